@@ -0,0 +1,22 @@
+use std::env;
+
+const MODEL: &str = "COMMIT_GPT_BUILTIN_MODEL";
+const TOKENIZER: &str = "COMMIT_GPT_BUILTIN_TOKENIZER";
+
+// The builtin feature compiles in the model these name; without them it still builds,
+// e.g. for `--all-features`, and `--provider builtin` reports what is missing.
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(builtin_model)");
+    println!("cargo::rerun-if-changed=build.rs");
+    println!("cargo::rerun-if-env-changed={}", MODEL);
+    println!("cargo::rerun-if-env-changed={}", TOKENIZER);
+    let builtin = env::var_os("CARGO_FEATURE_BUILTIN").is_some();
+    match (env::var_os(MODEL), env::var_os(TOKENIZER)) {
+        (Some(_), Some(_)) if builtin => println!("cargo::rustc-cfg=builtin_model"),
+        (Some(_), None) | (None, Some(_)) if builtin => println!(
+            "cargo::warning=the builtin model needs both {} and {}; building without it",
+            MODEL, TOKENIZER
+        ),
+        _ => {}
+    }
+}
@@ -0,0 +1,173 @@
+use std::env;
+
+use reqwest::blocking::RequestBuilder;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::openai::{ApiError, Message};
+use crate::provider::{Capabilities, Provider};
+use crate::Result;
+
+const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+// Anthropic requires max_tokens on every request
+const DEFAULT_MAX_TOKENS: usize = 1024;
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+/// One event of a streamed message.
+#[derive(Deserialize)]
+struct StreamEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    delta: Option<ContentBlock>,
+}
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+/// Anthropic's messages API, for Claude models.
+pub struct Anthropic;
+
+impl Provider for Anthropic {
+    // Same variable as the official SDKs
+    fn base_url(&self) -> String {
+        match env::var("ANTHROPIC_BASE_URL") {
+            Ok(base) if !base.is_empty() => base.trim_end_matches('/').to_string(),
+            _ => ANTHROPIC_API_URL.to_string(),
+        }
+    }
+
+    fn api_key_env(&self) -> Option<String> {
+        Some("ANTHROPIC_API_KEY".to_string())
+    }
+
+    fn chat_path(&self, _model: &str) -> String {
+        "messages".to_string()
+    }
+
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        request
+            .header("x-api-key", api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+    }
+
+    fn supports_n(&self) -> bool {
+        false
+    }
+
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn request_body(
+        &self,
+        model: &str,
+        messages: &[Message],
+        _n: usize,
+        max_tokens: Option<usize>,
+        extra_body: &Map<String, Value>,
+    ) -> Result<Value> {
+        Ok(request_body(model, messages, max_tokens, extra_body))
+    }
+
+    fn replies(&self, body: &str) -> Result<Vec<String>> {
+        let response: MessagesResponse = serde_json::from_str(body)?;
+        let text: String = response
+            .content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .map(|block| block.text)
+            .collect();
+        Ok(vec![text])
+    }
+
+    /// Text arrives in `content_block_delta` events; the others open and close the
+    /// message and its blocks.
+    fn stream_delta(&self, data: &str) -> Result<Option<String>> {
+        let event: StreamEvent = serde_json::from_str(data)?;
+        Ok(match (event.kind.as_str(), event.delta) {
+            ("content_block_delta", Some(delta)) if delta.kind == "text_delta" => Some(delta.text),
+            _ => None,
+        })
+    }
+
+    /// Anthropic has no code for an oversized prompt, so it is recognized by its message
+    /// and reported like OpenAI's, which the prompt shrinking looks for.
+    fn error(&self, status: StatusCode, body: &str) -> ApiError {
+        let mut error = ApiError::from_body(status, body);
+        if error
+            .message
+            .as_deref()
+            .is_some_and(|message| message.contains("prompt is too long"))
+        {
+            error.code = Some("context_length_exceeded".to_string());
+        }
+        error
+    }
+
+    fn models_path(&self) -> String {
+        "models".to_string()
+    }
+
+    fn models(&self, body: &str) -> Result<Vec<String>> {
+        let list: ModelList = serde_json::from_str(body)?;
+        Ok(list.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+/// The messages request body, with `extra_body` merged in except for `model` and `messages`.
+pub fn request_body(
+    model: &str,
+    messages: &[Message],
+    max_tokens: Option<usize>,
+    extra_body: &Map<String, Value>,
+) -> Value {
+    // The system prompt is a top-level field rather than a message
+    let system: Vec<&str> = messages
+        .iter()
+        .filter(|message| message.role == "system")
+        .map(|message| message.content.as_str())
+        .collect();
+    let conversation: Vec<&Message> = messages
+        .iter()
+        .filter(|message| message.role != "system")
+        .collect();
+
+    let mut body = json!({
+        "model": model,
+        "max_tokens": max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        "system": system.join("\n\n"),
+        "messages": conversation,
+    });
+    if let Some(body) = body.as_object_mut() {
+        for (key, value) in extra_body {
+            if key != "model" && key != "messages" {
+                body.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    body
+}
@@ -0,0 +1,91 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::digest;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::sigv4::hex;
+use crate::{CommitGPTError, Result};
+
+/// An append-only record of what was sent to external providers, one JSON object per
+/// line, set in the `[audit]` table of the user config.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLog {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub payloads: Payloads,
+}
+
+/// What the log keeps of every payload.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Payloads {
+    /// The SHA-256 and size of the payload, so a copy kept elsewhere can be matched
+    #[default]
+    Hash,
+    /// The payload itself, next to its hash
+    Full,
+}
+
+impl AuditLog {
+    /// Appends an entry for `payload` about to be sent to `destination`. The request is
+    /// not sent when this fails, so nothing goes out unrecorded.
+    pub fn record(&self, destination: &str, payload: &[u8]) -> Result<()> {
+        let hash = hex(digest::digest(&digest::SHA256, payload).as_ref());
+        let mut entry = json!({
+            "timestamp": timestamp(SystemTime::now()),
+            "destination": destination,
+            "sha256": hash,
+            "bytes": payload.len(),
+        });
+        if self.payloads == Payloads::Full {
+            entry["payload"] = json!(String::from_utf8_lossy(payload));
+        }
+
+        let path_display = self.path.display().to_string();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| CommitGPTError::AuditLogError(path_display.clone(), e))?;
+        writeln!(file, "{}", entry).map_err(|e| CommitGPTError::AuditLogError(path_display, e))
+    }
+}
+
+/// `time` as an RFC 3339 UTC timestamp with second precision.
+pub fn timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, rest) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since the epoch, after Howard Hinnant's algorithm
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
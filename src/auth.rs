@@ -0,0 +1,204 @@
+// `auth login` keeps the API key in the OS keychain, so that it need not lie on disk in
+// a file for `--api-key-path`. Runs then read it from there when neither the file nor the
+// provider's environment variable gives one. Keys are stored per provider, and per
+// endpoint for `--endpoint`. The keychain needs the `keychain` feature.
+
+use std::io::{self, BufRead, IsTerminal};
+#[cfg(unix)]
+use std::mem::MaybeUninit;
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+use clap::{Subcommand, ValueEnum};
+
+use crate::{Args, CommitGPTError, Result};
+
+#[cfg(feature = "keychain")]
+const SERVICE: &str = "commit-gpt";
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AuthAction {
+    /// Store the API key of --provider, or of --endpoint, in the OS keychain, read from
+    /// standard input
+    Login,
+
+    /// Remove the stored API key of --provider, or of --endpoint
+    Logout,
+}
+
+pub fn run(args: &Args, action: &AuthAction) -> Result<()> {
+    let account = account(args);
+    match action {
+        AuthAction::Login => {
+            let key = read_key(&account)?;
+            if key.is_empty() {
+                return Err(CommitGPTError::EmptyApiKeyError);
+            }
+            store(&account, &key)?;
+            eprintln!("Stored the API key for {} in the keychain", account);
+        }
+        AuthAction::Logout => {
+            if delete(&account)? {
+                eprintln!("Removed the API key for {} from the keychain", account);
+            } else {
+                eprintln!("No API key for {} in the keychain", account);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The keychain entry the key of the provider `args` pick is stored under.
+pub fn account(args: &Args) -> String {
+    match &args.endpoint {
+        Some(endpoint) => format!("endpoint:{}", endpoint),
+        None => args
+            .provider
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// A line from standard input, not echoed when typed in on a terminal.
+fn read_key(account: &str) -> Result<String> {
+    let hidden = if io::stdin().is_terminal() {
+        eprint!("API key for {}: ", account);
+        Some(HiddenInput::start())
+    } else {
+        None
+    };
+    let mut key = String::new();
+    let read = io::stdin().lock().read_line(&mut key);
+    if hidden.is_some() {
+        drop(hidden);
+        eprintln!();
+    }
+    read.map_err(|e| CommitGPTError::ApiKeyReadError("standard input".to_string(), e))?;
+    Ok(key.trim().to_string())
+}
+
+/// Keeps what is typed on the terminal from being echoed until dropped, or until the
+/// process is interrupted or terminated, so the shell does not stay silent afterwards.
+#[cfg(unix)]
+struct HiddenInput {
+    saved: Option<libc::termios>,
+}
+
+// The settings the signal handler goes back to; `auth login` reads a single key
+#[cfg(unix)]
+static SAVED_TERMINAL: OnceLock<libc::termios> = OnceLock::new();
+
+#[cfg(unix)]
+const RESTORED_ON: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+#[cfg(unix)]
+impl HiddenInput {
+    fn start() -> Self {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: tcgetattr fills in `termios` when it succeeds
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } != 0 {
+            return HiddenInput { saved: None };
+        }
+        // SAFETY: initialized by the successful tcgetattr
+        let saved = unsafe { termios.assume_init() };
+        SAVED_TERMINAL.get_or_init(|| saved);
+        let mut hidden = saved;
+        hidden.c_lflag &= !libc::ECHO;
+        // SAFETY: the handler only calls async-signal-safe functions, and the settings
+        // are valid ones from tcgetattr
+        unsafe {
+            for signal in RESTORED_ON {
+                libc::signal(
+                    signal,
+                    restore_and_reraise as *const () as libc::sighandler_t,
+                );
+            }
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &hidden);
+        }
+        HiddenInput { saved: Some(saved) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for HiddenInput {
+    fn drop(&mut self) {
+        let Some(saved) = &self.saved else {
+            return;
+        };
+        // SAFETY: `saved` came from tcgetattr
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            for signal in RESTORED_ON {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+        }
+    }
+}
+
+/// Turns echo back on, then lets `signal` end the process as it would have.
+#[cfg(unix)]
+extern "C" fn restore_and_reraise(signal: libc::c_int) {
+    // SAFETY: tcsetattr, signal and raise are async-signal-safe
+    unsafe {
+        if let Some(saved) = SAVED_TERMINAL.get() {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+#[cfg(not(unix))]
+struct HiddenInput;
+
+#[cfg(not(unix))]
+impl HiddenInput {
+    fn start() -> Self {
+        HiddenInput
+    }
+}
+
+/// The key stored for `account`, if any. A keychain that cannot be reached, as in CI,
+/// holds none.
+#[cfg(feature = "keychain")]
+pub fn stored_key(account: &str) -> Option<String> {
+    keyring::Entry::new(SERVICE, account)
+        .ok()?
+        .get_password()
+        .ok()
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn stored_key(_account: &str) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "keychain")]
+fn store(account: &str, key: &str) -> Result<()> {
+    keyring::Entry::new(SERVICE, account)
+        .and_then(|entry| entry.set_password(key))
+        .map_err(|e| CommitGPTError::KeychainError(e.to_string()))
+}
+
+#[cfg(not(feature = "keychain"))]
+fn store(_account: &str, _key: &str) -> Result<()> {
+    Err(CommitGPTError::KeychainUnavailable)
+}
+
+/// Deletes the key stored for `account`, returning whether there was one.
+#[cfg(feature = "keychain")]
+fn delete(account: &str) -> Result<bool> {
+    let entry = keyring::Entry::new(SERVICE, account)
+        .map_err(|e| CommitGPTError::KeychainError(e.to_string()))?;
+    match entry.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(CommitGPTError::KeychainError(e.to_string())),
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+fn delete(_account: &str) -> Result<bool> {
+    Err(CommitGPTError::KeychainUnavailable)
+}
@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::changes::FileChange;
+
+/// Value of `--model` that picks the model from the size of the change.
+pub const AUTO_MODEL: &str = "auto";
+
+// Extensions that do not count as a programming language of their own
+const NON_CODE_EXTENSIONS: &[&str] = &[
+    "md", "txt", "rst", "toml", "json", "yaml", "yml", "lock", "cfg", "ini",
+];
+
+// OpenAI's models, for providers without defaults of their own
+const SMALL_MODEL: &str = "gpt-4o-mini";
+const LARGE_MODEL: &str = "gpt-4o";
+
+/// Thresholds for `--model auto`, set in the `[auto_model]` table of the config.
+/// A change within all limits goes to `small`, anything bigger to `large`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoModel {
+    /// Defaults to the provider's small model
+    pub small: Option<String>,
+    /// Defaults to the provider's large model
+    pub large: Option<String>,
+    /// Most added and removed lines a small change has
+    pub max_lines: usize,
+    pub max_files: usize,
+    /// Most distinct languages, by file extension, a small change touches
+    pub max_languages: usize,
+}
+
+impl Default for AutoModel {
+    fn default() -> Self {
+        AutoModel {
+            small: None,
+            large: None,
+            max_lines: 200,
+            max_files: 8,
+            max_languages: 1,
+        }
+    }
+}
+
+impl AutoModel {
+    /// The model for `changes`, falling back on the provider's `(small, large)` models.
+    pub fn pick(&self, changes: &[FileChange], defaults: Option<(&str, &str)>) -> String {
+        let (small, large) = defaults.unwrap_or((SMALL_MODEL, LARGE_MODEL));
+        let lines: usize = changes
+            .iter()
+            .map(|change| change.added + change.removed)
+            .sum();
+        let languages: HashSet<String> = changes
+            .iter()
+            .filter_map(|change| Path::new(&change.file_path).extension())
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .filter(|extension| !NON_CODE_EXTENSIONS.contains(&extension.as_str()))
+            .collect();
+
+        if lines <= self.max_lines
+            && changes.len() <= self.max_files
+            && languages.len() <= self.max_languages
+        {
+            self.small.clone().unwrap_or_else(|| small.to_string())
+        } else {
+            self.large.clone().unwrap_or_else(|| large.to_string())
+        }
+    }
+}
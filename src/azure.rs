@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+use std::env;
+
+use reqwest::blocking::RequestBuilder;
+use serde::Deserialize;
+
+use crate::openai::OpenAICompatible;
+use crate::{CommitGPTError, Result};
+
+const DEFAULT_API_VERSION: &str = "2024-06-01";
+
+/// The `[azure]` table of the config, for OpenAI models deployed on Azure.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AzureConfig {
+    /// The resource URL, e.g. `https://example.openai.azure.com` (defaults to $AZURE_OPENAI_ENDPOINT)
+    pub endpoint: Option<String>,
+    /// The `api-version` query parameter (defaults to $OPENAI_API_VERSION, then 2024-06-01)
+    pub api_version: Option<String>,
+    /// Deployment names by model; a model without one is taken as the deployment name
+    pub deployments: BTreeMap<String, String>,
+}
+
+/// An Azure OpenAI resource, which addresses models by deployment and takes the key
+/// in an `api-key` header. Bodies and replies are OpenAI's.
+pub struct Azure {
+    endpoint: String,
+    api_version: String,
+    deployments: BTreeMap<String, String>,
+}
+
+impl Azure {
+    pub fn new(config: Option<&AzureConfig>) -> Result<Self> {
+        let config = config.cloned().unwrap_or_default();
+        let from_env = |name| {
+            env::var(name)
+                .ok()
+                .filter(|value: &String| !value.is_empty())
+        };
+        let endpoint = config.endpoint.or_else(|| from_env("AZURE_OPENAI_ENDPOINT")).ok_or_else(|| {
+            CommitGPTError::ProviderConfigError(
+                "--provider azure needs the resource URL as endpoint in [azure] or $AZURE_OPENAI_ENDPOINT".to_string(),
+            )
+        })?;
+        Ok(Azure {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            api_version: config
+                .api_version
+                .or_else(|| from_env("OPENAI_API_VERSION"))
+                .unwrap_or_else(|| DEFAULT_API_VERSION.to_string()),
+            deployments: config.deployments,
+        })
+    }
+}
+
+impl OpenAICompatible for Azure {
+    fn base_url(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    fn api_key_env(&self) -> Option<String> {
+        Some("AZURE_OPENAI_API_KEY".to_string())
+    }
+
+    fn chat_path(&self, model: &str) -> String {
+        let deployment = self.deployments.get(model).map_or(model, String::as_str);
+        format!(
+            "openai/deployments/{}/chat/completions?api-version={}",
+            deployment, self.api_version
+        )
+    }
+
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        request.header("api-key", api_key)
+    }
+
+    fn models_path(&self) -> String {
+        format!("openai/models?api-version={}", self.api_version)
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use reqwest::blocking::RequestBuilder;
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::audit;
+use crate::openai::{ApiError, Message};
+use crate::provider::{Provider, Sampling, Usage};
+use crate::sigv4::{self, Credentials};
+use crate::{CommitGPTError, Result};
+
+// The Converse API needs no max_tokens, but the models' own defaults can be tiny
+const DEFAULT_MAX_TOKENS: usize = 1024;
+
+#[derive(Deserialize)]
+struct ConverseResponse {
+    output: ConverseOutput,
+}
+
+#[derive(Deserialize)]
+struct ConverseOutput {
+    message: Option<ConverseMessage>,
+}
+
+#[derive(Deserialize)]
+struct ConverseMessage {
+    #[serde(default)]
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelList {
+    #[serde(default)]
+    model_summaries: Vec<ModelSummary>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelSummary {
+    model_id: String,
+    #[serde(default)]
+    output_modalities: Vec<String>,
+}
+
+/// Amazon Bedrock's Converse API, which takes the same body for every model family.
+/// Requests are signed with the IAM credentials the AWS CLI uses, instead of an API key.
+pub struct Bedrock {
+    region: String,
+    credentials: Credentials,
+}
+
+impl Bedrock {
+    /// Reads the credentials and region from the environment, then from the shared
+    /// AWS files for `$AWS_PROFILE`.
+    pub fn new() -> Result<Self> {
+        let profile = from_env("AWS_PROFILE").unwrap_or_else(|| "default".to_string());
+        let credentials = match (
+            from_env("AWS_ACCESS_KEY_ID"),
+            from_env("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Some(access_key_id), Some(secret_access_key)) => Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: from_env("AWS_SESSION_TOKEN"),
+            },
+            _ => {
+                let mut section = aws_file("AWS_SHARED_CREDENTIALS_FILE", "credentials")
+                    .and_then(|mut sections| sections.remove(&profile))
+                    .unwrap_or_default();
+                match (section.remove("aws_access_key_id"), section.remove("aws_secret_access_key")) {
+                    (Some(access_key_id), Some(secret_access_key)) => Credentials {
+                        access_key_id,
+                        secret_access_key,
+                        session_token: section.remove("aws_session_token"),
+                    },
+                    _ => {
+                        return Err(CommitGPTError::ProviderConfigError(format!(
+                            "--provider bedrock found no AWS credentials; set $AWS_ACCESS_KEY_ID and $AWS_SECRET_ACCESS_KEY or add the {} profile to ~/.aws/credentials",
+                            profile
+                        )))
+                    }
+                }
+            }
+        };
+
+        // The config file names every profile but the default one `profile <name>`
+        let config_section = if profile == "default" {
+            profile.clone()
+        } else {
+            format!("profile {}", profile)
+        };
+        let region = from_env("AWS_REGION")
+            .or_else(|| from_env("AWS_DEFAULT_REGION"))
+            .or_else(|| {
+                aws_file("AWS_CONFIG_FILE", "config")?
+                    .remove(&config_section)?
+                    .remove("region")
+            })
+            .ok_or_else(|| {
+                CommitGPTError::ProviderConfigError(
+                    "--provider bedrock needs a region in $AWS_REGION or ~/.aws/config".to_string(),
+                )
+            })?;
+        Ok(Bedrock {
+            region,
+            credentials,
+        })
+    }
+}
+
+impl Provider for Bedrock {
+    // Same variable as the AWS SDKs, e.g. for a VPC endpoint
+    fn base_url(&self) -> String {
+        match from_env("AWS_ENDPOINT_URL_BEDROCK_RUNTIME") {
+            Some(base) => base.trim_end_matches('/').to_string(),
+            None => format!("https://bedrock-runtime.{}.amazonaws.com", self.region),
+        }
+    }
+
+    /// Model ids contain colons and ARNs slashes, which belong in a single segment.
+    fn chat_path(&self, model: &str) -> String {
+        format!("model/{}/converse", sigv4::uri_encode(model, true))
+    }
+
+    /// The credentials go into the signature instead.
+    fn authorize(&self, request: RequestBuilder, _api_key: &str) -> RequestBuilder {
+        request
+    }
+
+    fn sign(&self, method: &str, url: &str, body: &[u8]) -> Result<Vec<(String, String)>> {
+        let url = Url::parse(url)
+            .map_err(|e| CommitGPTError::ProviderConfigError(format!("{}: {}", url, e)))?;
+        // The control plane listing the models is its own service
+        let service = match url
+            .host_str()
+            .and_then(|host| host.split('.').find(|label| label.starts_with("bedrock")))
+        {
+            Some(service) => service.to_string(),
+            None => "bedrock-runtime".to_string(),
+        };
+        let amz_date = audit::timestamp(SystemTime::now()).replace(['-', ':'], "");
+        Ok(sigv4::sign(
+            &self.credentials,
+            &self.region,
+            &service,
+            method,
+            &url,
+            body,
+            &amz_date,
+        ))
+    }
+
+    fn requires_api_key(&self) -> bool {
+        false
+    }
+
+    fn supports_n(&self) -> bool {
+        false
+    }
+
+    fn request_body(
+        &self,
+        _model: &str,
+        messages: &[Message],
+        _n: usize,
+        max_tokens: Option<usize>,
+        extra_body: &Map<String, Value>,
+    ) -> Result<Value> {
+        let system: Vec<Value> = messages
+            .iter()
+            .filter(|message| message.role == "system")
+            .map(|message| json!({ "text": message.content }))
+            .collect();
+        let conversation: Vec<Value> = messages
+            .iter()
+            .filter(|message| message.role != "system")
+            .map(
+                |message| json!({ "role": message.role, "content": [{ "text": message.content }] }),
+            )
+            .collect();
+        let mut body = json!({
+            "messages": conversation,
+            "inferenceConfig": { "maxTokens": max_tokens.unwrap_or(DEFAULT_MAX_TOKENS) },
+        });
+        if let Some(body) = body.as_object_mut() {
+            if !system.is_empty() {
+                body.insert("system".to_string(), Value::Array(system));
+            }
+            for (key, value) in extra_body {
+                if key != "messages" && key != "system" {
+                    body.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Ok(body)
+    }
+
+    fn sampling_body(&self, mut body: Value, sampling: Sampling) -> Value {
+        if let Some(temperature) = sampling.temperature {
+            body["inferenceConfig"]["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = sampling.top_p {
+            body["inferenceConfig"]["topP"] = json!(top_p);
+        }
+        body
+    }
+
+    fn usage(&self, body: &str) -> Option<Usage> {
+        Usage::read(body, "usage", "inputTokens", "outputTokens")
+    }
+
+    fn replies(&self, body: &str) -> Result<Vec<String>> {
+        let response: ConverseResponse = serde_json::from_str(body)?;
+        Ok(response
+            .output
+            .message
+            .map(|message| {
+                message
+                    .content
+                    .into_iter()
+                    .filter_map(|block| block.text)
+                    .collect()
+            })
+            .into_iter()
+            .collect())
+    }
+
+    /// Reports an oversized prompt like OpenAI does, which the prompt shrinking looks for.
+    fn error(&self, status: StatusCode, body: &str) -> ApiError {
+        let Ok(response) = serde_json::from_str::<ErrorResponse>(body) else {
+            return ApiError::from_body(status, body);
+        };
+        let too_long = ["Input is too long", "too many input tokens"]
+            .iter()
+            .any(|phrase| response.message.contains(phrase));
+        ApiError {
+            status,
+            code: too_long.then(|| "context_length_exceeded".to_string()),
+            message: Some(response.message),
+            kind: None,
+        }
+    }
+
+    /// The models are listed by the control plane, not the runtime the base URL points at.
+    fn models_path(&self) -> String {
+        format!(
+            "https://bedrock.{}.amazonaws.com/foundation-models",
+            self.region
+        )
+    }
+
+    fn models(&self, body: &str) -> Result<Vec<String>> {
+        let list: ModelList = serde_json::from_str(body)?;
+        // Embedding and image models put out something other than text
+        Ok(list
+            .model_summaries
+            .into_iter()
+            .filter(|model| {
+                model.output_modalities.is_empty()
+                    || model
+                        .output_modalities
+                        .iter()
+                        .any(|modality| modality == "TEXT")
+            })
+            .map(|model| model.model_id)
+            .collect())
+    }
+}
+
+fn from_env(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// The sections of a shared AWS file, `${variable}` or `~/.aws/<name>`, by name.
+fn aws_file(variable: &str, name: &str) -> Option<HashMap<String, HashMap<String, String>>> {
+    let path = from_env(variable)
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".aws").join(name)))?;
+    let contents = fs::read_to_string(path).ok()?;
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = None;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            current = Some(section.trim().to_string());
+        } else if let (Some(section), Some((key, value))) = (&current, line.split_once('=')) {
+            sections
+                .entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    Some(sections)
+}
@@ -0,0 +1,64 @@
+// Rough token accounting used to keep prompts inside a model's context window
+
+const DEFAULT_CONTEXT_WINDOW: usize = 8192;
+const COMPLETION_RESERVE: usize = 1024;
+const CHARS_PER_TOKEN: usize = 4;
+
+pub fn context_window(model: &str) -> usize {
+    known_context_window(model).unwrap_or(DEFAULT_CONTEXT_WINDOW)
+}
+
+pub fn known_context_window(model: &str) -> Option<usize> {
+    let windows: &[(&str, usize)] = &[
+        ("gpt-4o", 128_000),
+        ("gpt-4-turbo", 128_000),
+        ("gpt-4-32k", 32_768),
+        ("gpt-4", 8_192),
+        ("gpt-3.5-turbo", 16_385),
+        ("o1-mini", 128_000),
+        ("o1-preview", 128_000),
+        ("o1", 200_000),
+        ("o3-mini", 200_000),
+        ("claude-3", 200_000),
+        ("anthropic.claude-3", 200_000),
+        ("grok-2", 131_072),
+        ("grok-beta", 131_072),
+        ("deepseek-chat", 65_536),
+        ("gemini-1.5-pro", 2_097_152),
+        ("gemini-1.5-flash", 1_048_576),
+    ];
+    windows
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, window)| *window)
+}
+
+/// Number of prompt tokens available for `model` once room for the completion is reserved.
+pub fn prompt_budget(model: &str) -> usize {
+    reserve_completion(context_window(model))
+}
+
+/// Number of prompt tokens available in a context window of `tokens`, as a provider
+/// reports it, once room for the completion is reserved.
+pub fn reserve_completion(tokens: usize) -> usize {
+    tokens.saturating_sub(COMPLETION_RESERVE)
+}
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Splits `text` at the last line boundary that fits in `tokens`, returning the
+/// kept and the dropped part.
+pub fn split_at_budget(text: &str, tokens: usize) -> (&str, &str) {
+    let mut used = 0;
+    let mut end = 0;
+    for line in text.split_inclusive('\n') {
+        used += estimate_tokens(line);
+        if used > tokens {
+            break;
+        }
+        end += line.len();
+    }
+    text.split_at(end)
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use git2::Oid;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::sigv4::hex;
+use crate::{CommitGPTError, Result};
+
+const CACHE_DIR: &str = "commit-gpt";
+const SUMMARY_CACHE_FILE: &str = "summaries.json";
+const REJECTED_CACHE_FILE: &str = "rejected.json";
+const SALT_FILE: &str = "salt";
+const SALT_BYTES: usize = 16;
+const MAX_REJECTED_MESSAGES: usize = 5;
+const MAX_REJECTED_FINGERPRINTS: usize = 20;
+
+/// Files described by the previous run, keyed by their old and new blob OIDs,
+/// together with the message that was generated for them and, where the model
+/// summarized them one by one, their summaries.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SummaryCache {
+    pub message: String,
+    pub files: HashMap<String, CachedFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CachedFile {
+    pub file_path: String,
+    pub change_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+impl SummaryCache {
+    pub fn key(old_oid: Oid, new_oid: Oid) -> String {
+        format!("{}..{}", old_oid, new_oid)
+    }
+
+    /// Loads the cache from the repository's git directory. A missing or
+    /// unreadable cache is treated as empty.
+    pub fn load(git_dir: &Path) -> Self {
+        read_json(git_dir, SUMMARY_CACHE_FILE)
+    }
+
+    pub fn save(&self, git_dir: &Path) -> Result<()> {
+        write_json(git_dir, SUMMARY_CACHE_FILE, self)
+    }
+
+    pub fn contains(&self, old_oid: Oid, new_oid: Oid) -> bool {
+        self.files.contains_key(&Self::key(old_oid, new_oid))
+    }
+
+    pub fn summary(&self, old_oid: Oid, new_oid: Oid) -> Option<&str> {
+        self.files
+            .get(&Self::key(old_oid, new_oid))?
+            .summary
+            .as_deref()
+    }
+
+    /// The summaries by key, to reuse instead of summarizing the same changes again.
+    pub fn summaries(&self) -> HashMap<String, String> {
+        self.files
+            .iter()
+            .filter_map(|(key, file)| Some((key.clone(), file.summary.clone()?)))
+            .collect()
+    }
+}
+
+/// The salt of the hashed aliases of `--private-paths`, made at random once per
+/// repository, so aliases stay the same from run to run but cannot be recomputed from
+/// anything the model sees.
+pub fn path_salt(git_dir: &Path) -> Result<String> {
+    let path = cache_path(git_dir, SALT_FILE);
+    if let Ok(salt) = fs::read_to_string(&path) {
+        if !salt.trim().is_empty() {
+            return Ok(salt.trim().to_string());
+        }
+    }
+    let mut bytes = [0; SALT_BYTES];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io::Error::other("no source of randomness"))
+        .and_then(|()| {
+            fs::create_dir_all(git_dir.join(CACHE_DIR))?;
+            let salt = hex(&bytes);
+            // A concurrent run may have made one first, which is then used instead
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => io::Write::write_all(&mut file, salt.as_bytes()).map(|()| salt),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    fs::read_to_string(&path).map(|salt| salt.trim().to_string())
+                }
+                Err(e) => Err(e),
+            }
+        })
+        .map_err(|e| CommitGPTError::CacheWriteError(path.display().to_string(), e))
+}
+
+fn cache_path(git_dir: &Path, file_name: &str) -> PathBuf {
+    git_dir.join(CACHE_DIR).join(file_name)
+}
+
+/// Suggestions rejected in the interactive loop, per diff fingerprint, so later
+/// runs over the same changes can steer away from them.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RejectedCache {
+    entries: Vec<RejectedEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RejectedEntry {
+    fingerprint: String,
+    messages: Vec<String>,
+}
+
+impl RejectedCache {
+    pub fn load(git_dir: &Path) -> Self {
+        read_json(git_dir, REJECTED_CACHE_FILE)
+    }
+
+    pub fn save(&self, git_dir: &Path) -> Result<()> {
+        write_json(git_dir, REJECTED_CACHE_FILE, self)
+    }
+
+    pub fn messages(&self, fingerprint: &str) -> &[String] {
+        self.entries
+            .iter()
+            .find(|entry| entry.fingerprint == fingerprint)
+            .map(|entry| entry.messages.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn add(&mut self, fingerprint: &str, messages: Vec<String>) {
+        if messages.is_empty() {
+            return;
+        }
+        // Most recently touched fingerprint last; the oldest ones are dropped first
+        let position = self
+            .entries
+            .iter()
+            .position(|entry| entry.fingerprint == fingerprint);
+        let mut entry = match position {
+            Some(index) => self.entries.remove(index),
+            None => RejectedEntry {
+                fingerprint: fingerprint.to_string(),
+                messages: Vec::new(),
+            },
+        };
+        for message in messages {
+            if !entry.messages.contains(&message) {
+                entry.messages.push(message);
+            }
+        }
+        let excess = entry.messages.len().saturating_sub(MAX_REJECTED_MESSAGES);
+        entry.messages.drain(..excess);
+        self.entries.push(entry);
+
+        let excess = self.entries.len().saturating_sub(MAX_REJECTED_FINGERPRINTS);
+        self.entries.drain(..excess);
+    }
+}
+
+fn read_json<T: DeserializeOwned + Default>(git_dir: &Path, file_name: &str) -> T {
+    fs::read_to_string(cache_path(git_dir, file_name))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write_json<T: Serialize>(git_dir: &Path, file_name: &str, value: &T) -> Result<()> {
+    let path = cache_path(git_dir, file_name);
+    let contents = serde_json::to_string_pretty(value).map_err(io::Error::other);
+    contents
+        .and_then(|contents| {
+            fs::create_dir_all(git_dir.join(CACHE_DIR))?;
+            fs::write(&path, contents)
+        })
+        .map_err(|e| CommitGPTError::CacheWriteError(path.display().to_string(), e))
+}
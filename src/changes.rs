@@ -0,0 +1,684 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::io::BufRead;
+use std::path::{Component, Path, PathBuf};
+
+use git2::{Delta, DiffFindOptions, DiffLine, DiffOptions, ErrorCode, ObjectType, Oid, Repository};
+use serde_json::{json, Value};
+
+use crate::cache::{CachedFile, SummaryCache};
+use crate::{mass_edit, symbols};
+use crate::{CommitGPTError, Result};
+
+const GROUP_DEPTH: usize = 2;
+const GROUP_SAMPLE_FILES: usize = 3;
+const GROUP_SAMPLE_LINES: usize = 3;
+const INTENT_DOC_PREFIXES: &[&str] = &[
+    "CHANGELOG",
+    "CHANGES",
+    "HISTORY",
+    "NEWS",
+    "RELEASE_NOTES",
+    "RELEASE-NOTES",
+    "SPEC",
+    "DESIGN",
+];
+
+#[derive(Clone)]
+pub struct FileChange {
+    pub file_path: String,
+    pub change_type: String,
+    pub old_oid: Oid,
+    pub new_oid: Oid,
+    pub summaries: Vec<String>,
+    /// Full added lines of changelogs and design docs, kept as statements of intent
+    pub notes: Vec<String>,
+    /// The items added, removed and modified in source files, like "added fn `parse`"
+    pub symbols: Vec<String>,
+    /// The hunks of the diff, in order
+    pub hunks: Vec<Hunk>,
+    /// Lines added, over all hunks
+    pub added: usize,
+    /// Lines removed, over all hunks
+    pub removed: usize,
+    /// Whether git found the contents to be binary, so there are no lines to show
+    pub binary: bool,
+    /// Shown to the model as background for the rest, but not to be described
+    pub context_only: bool,
+}
+
+impl FileChange {
+    /// Counts the added and removed lines again from the hunks, after they were split
+    /// up.
+    pub fn recount(&mut self) {
+        self.added = self.hunks.iter().map(|hunk| hunk.added).sum();
+        self.removed = self.hunks.iter().map(|hunk| hunk.removed).sum();
+    }
+
+    /// The size of the change as the prompt gives it, like " (+120/-4, 3 hunks)", or
+    /// nothing for changes without lines, like pure renames.
+    fn stats(&self) -> String {
+        match self.hunks.len() {
+            _ if self.binary => " (binary)".to_string(),
+            0 => String::new(),
+            1 => format!(" (+{}/-{}, 1 hunk)", self.added, self.removed),
+            hunks => format!(" (+{}/-{}, {} hunks)", self.added, self.removed, hunks),
+        }
+    }
+}
+
+/// A hunk of a file's diff: its header, the index of its first line in `summaries` and
+/// the lines it adds and removes, which summaries leave out when they are blank.
+#[derive(Clone)]
+pub struct Hunk {
+    pub header: String,
+    pub start: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+pub fn get_changes(
+    repo: &Repository,
+    include_unstaged: bool,
+    base: Option<&git2::Tree>,
+    paths: Option<&[PathBuf]>,
+) -> Result<Vec<FileChange>> {
+    // An empty pathspec would match everything
+    if paths.is_some_and(|paths| paths.is_empty()) {
+        return Ok(Vec::new());
+    }
+    let renamed;
+    let paths = match paths {
+        Some(paths) => {
+            renamed = with_renamed_paths(repo, include_unstaged, base, paths)?;
+            Some(renamed.as_slice())
+        }
+        None => None,
+    };
+    let mut diff = get_combined_diff(repo, include_unstaged, base, paths)?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+    Ok(collect_changes(Some(repo), &diff))
+}
+
+/// `paths` and the other path of every rename they take part in, as a rename is only
+/// found when both of its paths are in the diff.
+fn with_renamed_paths(
+    repo: &Repository,
+    include_unstaged: bool,
+    base: Option<&git2::Tree>,
+    paths: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    // Only added and deleted files can turn out to be one side of a rename
+    let listed = get_combined_diff(repo, include_unstaged, base, Some(paths))?;
+    let candidates: HashSet<&Path> = listed
+        .deltas()
+        .filter(|delta| {
+            matches!(
+                delta.status(),
+                Delta::Added | Delta::Deleted | Delta::Untracked
+            )
+        })
+        .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+        .collect();
+    if candidates.is_empty() {
+        return Ok(paths.to_vec());
+    }
+
+    let mut all = get_combined_diff(repo, include_unstaged, base, None)?;
+    all.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+    let mut paths = paths.to_vec();
+    for delta in all
+        .deltas()
+        .filter(|delta| delta.status() == Delta::Renamed)
+    {
+        let (Some(old), Some(new)) = (delta.old_file().path(), delta.new_file().path()) else {
+            continue;
+        };
+        if candidates.contains(old) || candidates.contains(new) {
+            paths.extend([old.to_path_buf(), new.to_path_buf()]);
+        }
+    }
+    Ok(paths)
+}
+
+/// Reads one path per line, making them relative to the repository since pathspecs are
+/// matched against repository paths. Relative paths are taken from the current
+/// directory, as a shell in a subdirectory gives them.
+pub fn read_paths(repo: &Repository, reader: impl BufRead) -> Result<Vec<PathBuf>> {
+    let cwd = env::current_dir().unwrap_or_default();
+    // Tools may pass the paths either with or without symlinks resolved
+    let workdirs: Vec<PathBuf> = repo
+        .workdir()
+        .into_iter()
+        .flat_map(|dir| {
+            [
+                dir.to_path_buf(),
+                dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf()),
+            ]
+        })
+        .collect();
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(CommitGPTError::StdinReadError)?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let path = normalize(&cwd.join(line));
+        let relative = workdirs
+            .iter()
+            .find_map(|workdir| path.strip_prefix(workdir).ok());
+        paths.push(relative.map_or_else(|| PathBuf::from(line), Path::to_path_buf));
+    }
+    Ok(paths)
+}
+
+/// `path` without `.` and `..` components, which would keep it from matching the workdir.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+/// The changes `commit` made to its first parent, as `get_changes` saw them when they were staged.
+pub fn commit_changes(repo: &Repository, commit: &git2::Commit) -> Result<Vec<FileChange>> {
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    tree_changes(repo, parent_tree.as_ref(), &commit.tree()?)
+}
+
+/// The changes from `old` (an empty tree when `None`) to `new`.
+pub fn tree_changes(
+    repo: &Repository,
+    old: Option<&git2::Tree>,
+    new: &git2::Tree,
+) -> Result<Vec<FileChange>> {
+    let mut diff = repo.diff_tree_to_tree(old, Some(new), None)?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+    Ok(collect_changes(Some(repo), &diff))
+}
+
+/// The changes in the text of a unified diff, as `git diff` or another VCS prints it.
+pub fn patch_changes(patch: &[u8]) -> Result<Vec<FileChange>> {
+    let diff = git2::Diff::from_buffer(patch)?;
+    Ok(collect_changes(None, &diff))
+}
+
+fn get_combined_diff<'a>(
+    repo: &'a Repository,
+    include_unstaged: bool,
+    base: Option<&git2::Tree>,
+    paths: Option<&[PathBuf]>,
+) -> Result<git2::Diff<'a>> {
+    let mut diff_opts = DiffOptions::new();
+    if let Some(paths) = paths {
+        // Listed files are taken literally, not as glob patterns
+        diff_opts.disable_pathspec_match(true);
+        for path in paths {
+            diff_opts.pathspec(path);
+        }
+    }
+    if include_unstaged {
+        // Include both staged and unstaged changes
+        diff_opts
+            .include_untracked(true)
+            .recurse_untracked_dirs(true);
+    } else {
+        // Include only staged changes
+        diff_opts
+            .include_untracked(false)
+            .recurse_untracked_dirs(false);
+    }
+
+    // Get the HEAD tree unless another base was requested; before the first commit
+    // everything is new
+    let head = match base {
+        Some(tree) => Some(tree.clone()),
+        None => match repo.head() {
+            Ok(head) => Some(head.peel_to_tree()?),
+            Err(e) if e.code() == ErrorCode::UnbornBranch => None,
+            Err(e) => return Err(e.into()),
+        },
+    };
+
+    if include_unstaged {
+        // Diff between HEAD tree and workdir (staged and unstaged changes)
+        Ok(repo.diff_tree_to_workdir(head.as_ref(), Some(&mut diff_opts))?)
+    } else {
+        // Get the index
+        let index = repo.index()?;
+
+        // Diff between HEAD tree and index (staged changes)
+        Ok(repo.diff_tree_to_index(head.as_ref(), Some(&index), Some(&mut diff_opts))?)
+    }
+}
+
+fn collect_changes(repo: Option<&Repository>, diff: &git2::Diff) -> Vec<FileChange> {
+    // Files are recorded as they come so binary files and pure renames, which have no
+    // lines, are listed too
+    let changes_map: RefCell<HashMap<String, FileChange>> = RefCell::new(HashMap::new());
+
+    diff.foreach(
+        &mut |delta, _progress| {
+            file_entry(&mut changes_map.borrow_mut(), repo, &delta);
+            true
+        },
+        Some(&mut |delta, _binary| {
+            let mut changes_map = changes_map.borrow_mut();
+            let file_change = file_entry(&mut changes_map, repo, &delta);
+            file_change.binary = true;
+            if file_change.summaries.is_empty() {
+                file_change
+                    .summaries
+                    .push("Binary file changed".to_string());
+            }
+            true
+        }),
+        Some(&mut |delta, hunk| {
+            let mut changes_map = changes_map.borrow_mut();
+            let file_change = file_entry(&mut changes_map, repo, &delta);
+            file_change.hunks.push(Hunk {
+                header: String::from_utf8_lossy(hunk.header()).trim().to_string(),
+                start: file_change.summaries.len(),
+                added: 0,
+                removed: 0,
+            });
+            true
+        }),
+        Some(&mut |delta, _hunk, line| {
+            let mut changes_map = changes_map.borrow_mut();
+            let file_change = file_entry(&mut changes_map, repo, &delta);
+
+            let hunk = file_change.hunks.last_mut();
+            match line.origin() {
+                '+' => {
+                    file_change.added += 1;
+                    if let Some(hunk) = hunk {
+                        hunk.added += 1;
+                    }
+                }
+                '-' => {
+                    file_change.removed += 1;
+                    if let Some(hunk) = hunk {
+                        hunk.removed += 1;
+                    }
+                }
+                _ => {}
+            }
+            let summary = summarize_change(&line);
+            if !summary.is_empty() {
+                file_change.summaries.push(summary);
+            }
+            if line.origin() == '+' && is_intent_doc(&file_change.file_path) {
+                let content = String::from_utf8_lossy(line.content())
+                    .trim_end()
+                    .to_string();
+                if !content.trim().is_empty() {
+                    file_change.notes.push(content);
+                }
+            }
+
+            true
+        }),
+    )
+    .unwrap();
+
+    let mut changes: Vec<FileChange> = changes_map.into_inner().into_values().collect();
+    changes.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    if let Some(repo) = repo {
+        for change in changes
+            .iter_mut()
+            .filter(|change| symbols::supports(&change.file_path))
+        {
+            change.symbols = changed_symbols(repo, change);
+        }
+    }
+    changes
+}
+
+/// The items `change` touched, when both versions of the file can be read and parsed.
+fn changed_symbols(repo: &Repository, change: &FileChange) -> Vec<String> {
+    let text = |oid: Oid, path: Option<&str>| -> Option<Option<String>> {
+        if oid.is_zero() {
+            return Some(None);
+        }
+        // Workdir files are hashed but not written to the object database
+        let contents = match repo.find_blob(oid) {
+            Ok(blob) => blob.content().to_vec(),
+            Err(_) => std::fs::read(repo.workdir()?.join(path?)).ok()?,
+        };
+        String::from_utf8(contents).ok().map(Some)
+    };
+    let (Some(old), Some(new)) = (
+        text(change.old_oid, None),
+        text(change.new_oid, Some(&change.file_path)),
+    ) else {
+        return Vec::new();
+    };
+    symbols::changed_items(&change.file_path, old.as_deref(), new.as_deref()).unwrap_or_default()
+}
+
+fn file_entry<'m>(
+    changes_map: &'m mut HashMap<String, FileChange>,
+    repo: Option<&Repository>,
+    delta: &git2::DiffDelta,
+) -> &'m mut FileChange {
+    let file_path = delta
+        .new_file()
+        .path()
+        .or_else(|| delta.old_file().path())
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    changes_map.entry(file_path.clone()).or_insert_with(|| {
+        let change_type = match delta.status() {
+            Delta::Added => "Added",
+            Delta::Deleted => "Deleted",
+            Delta::Modified => "Modified",
+            Delta::Renamed => "Renamed",
+            Delta::Copied => "Copied",
+            _ => "Modified",
+        }
+        .to_string();
+
+        let mut summaries = Vec::new();
+        if matches!(delta.status(), Delta::Renamed | Delta::Copied) {
+            if let Some(old_path) = delta.old_file().path() {
+                summaries.push(format!(
+                    "{} from {}",
+                    change_type,
+                    old_path.to_string_lossy()
+                ));
+            }
+        }
+
+        FileChange {
+            old_oid: delta.old_file().id(),
+            new_oid: blob_oid(repo, &delta.new_file()),
+            file_path,
+            change_type,
+            summaries,
+            notes: Vec::new(),
+            symbols: Vec::new(),
+            hunks: Vec::new(),
+            added: 0,
+            removed: 0,
+            binary: false,
+            context_only: false,
+        }
+    })
+}
+
+/// Changelogs, release notes and design documents, whose added lines are
+/// written by the author to explain the change.
+fn is_intent_doc(file_path: &str) -> bool {
+    let path = Path::new(file_path);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_uppercase())
+        .unwrap_or_default();
+    let is_text = matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        None | Some("md" | "rst" | "txt" | "adoc")
+    );
+    let in_design_dir = path.components().any(|component| {
+        matches!(
+            component
+                .as_os_str()
+                .to_string_lossy()
+                .to_lowercase()
+                .as_str(),
+            "design" | "rfcs" | "rfc" | "adr" | "specs" | "spec"
+        )
+    });
+
+    let is_intent_name = INTENT_DOC_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix));
+
+    is_text && (is_intent_name || in_design_dir)
+}
+
+/// The added changelog and design doc lines, grouped per file, if any.
+pub fn intent_notes(changes: &[FileChange]) -> Option<String> {
+    let notes: String = changes
+        .iter()
+        .filter(|change| !change.notes.is_empty())
+        .map(|change| format!("{}:\n{}\n", change.file_path, change.notes.join("\n")))
+        .collect();
+    if notes.is_empty() {
+        None
+    } else {
+        Some(notes)
+    }
+}
+
+fn blob_oid(repo: Option<&Repository>, file: &git2::DiffFile) -> Oid {
+    // Workdir files are not always hashed by libgit2, so hash them ourselves
+    if !file.id().is_zero() {
+        return file.id();
+    }
+    file.path()
+        .zip(repo.and_then(Repository::workdir))
+        .and_then(|(path, workdir)| Oid::hash_file(ObjectType::Blob, workdir.join(path)).ok())
+        .unwrap_or_else(Oid::zero)
+}
+
+fn summarize_change(line: &DiffLine) -> String {
+    let content = String::from_utf8_lossy(line.content()).trim().to_string();
+
+    // Limit the length of the content to prevent excessively long summaries
+    let truncated_content = if content.chars().count() > 80 {
+        format!("{}...", content.chars().take(77).collect::<String>())
+    } else {
+        content.clone()
+    };
+
+    match line.origin() {
+        '+' => format!("Added: {}", truncated_content),
+        '-' => format!("Removed: {}", truncated_content),
+        _ => String::new(),
+    }
+}
+
+/// Formats the changes for the prompt. Files the previous draft already covers are
+/// only listed, files with the same mechanical edit are described once, and above
+/// `group_threshold` files the rest is aggregated per directory. Changes marked as
+/// context only come last, set apart.
+pub fn format_changes_for_prompt(
+    changes: &[FileChange],
+    previous: &SummaryCache,
+    group_threshold: usize,
+) -> String {
+    let (context, changes): (Vec<&FileChange>, Vec<&FileChange>) =
+        changes.iter().partition(|change| change.context_only);
+    let (unchanged, fresh): (Vec<&FileChange>, Vec<&FileChange>) = changes
+        .into_iter()
+        .partition(|change| previous.contains(change.old_oid, change.new_oid));
+
+    let (mass_edits, fresh) = mass_edit::find(&fresh);
+    let mut formatted = mass_edit::format(&mass_edits);
+    if fresh.len() > group_threshold {
+        formatted.push_str(&format_grouped(&fresh));
+    } else {
+        formatted.push_str(&format_detailed(&fresh));
+    }
+
+    if !unchanged.is_empty() {
+        let unchanged: String = unchanged
+            .iter()
+            .map(
+                |change| match previous.summary(change.old_oid, change.new_oid) {
+                    Some(summary) => format!(
+                        "- **{}** ({}): {}\n",
+                        change.file_path, change.change_type, summary
+                    ),
+                    None => format!("- **{}**: {}\n", change.file_path, change.change_type),
+                },
+            )
+            .collect();
+        formatted.push_str("\nUnchanged since the previous draft (already described by it):\n");
+        formatted.push_str(&unchanged);
+        formatted.push_str(&format!("\nPrevious draft:\n{}\n", previous.message));
+    }
+
+    if !context.is_empty() {
+        formatted.push_str("\nFor context only (do not describe these in the message):\n");
+        formatted.push_str(&format_detailed(&context));
+    }
+
+    formatted
+}
+
+fn format_detailed(changes: &[&FileChange]) -> String {
+    let mut formatted = String::new();
+
+    for change in changes {
+        formatted.push_str(&format!(
+            "- **{}**: {}{}\n",
+            change.file_path,
+            change.change_type,
+            change.stats()
+        ));
+        if !change.symbols.is_empty() {
+            formatted.push_str(&format!("  - Items: {}\n", change.symbols.join("; ")));
+        }
+        for summary in &change.summaries {
+            formatted.push_str(&format!("  - {}\n", summary));
+        }
+    }
+
+    formatted
+}
+
+fn format_grouped(changes: &[&FileChange]) -> String {
+    let mut groups: BTreeMap<String, Vec<&FileChange>> = BTreeMap::new();
+    for change in changes {
+        groups
+            .entry(group_key(&change.file_path))
+            .or_default()
+            .push(change);
+    }
+
+    let mut formatted = String::new();
+    for (directory, mut files) in groups {
+        let mut change_types: BTreeMap<&str, usize> = BTreeMap::new();
+        for file in &files {
+            *change_types.entry(file.change_type.as_str()).or_default() += 1;
+        }
+        let change_types: Vec<String> = change_types
+            .iter()
+            .map(|(change_type, count)| format!("{} {}", count, change_type.to_lowercase()))
+            .collect();
+        let changed_lines: usize = files.iter().map(|file| file.summaries.len()).sum();
+
+        formatted.push_str(&format!(
+            "- **{}**: {} files ({}), {} changed lines\n",
+            directory,
+            files.len(),
+            change_types.join(", "),
+            changed_lines
+        ));
+
+        // A few samples from the largest files show what kind of change dominates
+        files.sort_by_key(|file| std::cmp::Reverse(file.summaries.len()));
+        for file in files.iter().take(GROUP_SAMPLE_FILES) {
+            formatted.push_str(&format!(
+                "  - {} ({} lines)\n",
+                file.file_path,
+                file.summaries.len()
+            ));
+            for summary in file.summaries.iter().take(GROUP_SAMPLE_LINES) {
+                formatted.push_str(&format!("    - {}\n", summary));
+            }
+        }
+    }
+
+    formatted
+}
+
+fn group_key(file_path: &str) -> String {
+    let directory: Vec<String> = Path::new(file_path)
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components())
+        .take(GROUP_DEPTH)
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    if directory.is_empty() {
+        "./".to_string()
+    } else {
+        format!("{}/", directory.join("/"))
+    }
+}
+
+/// The changes as `analyze` prints them, an object per file.
+pub fn to_json(changes: &[FileChange]) -> Value {
+    changes
+        .iter()
+        .map(|change| {
+            json!({
+                "path": change.file_path,
+                "change_type": change.change_type,
+                "added": change.added,
+                "removed": change.removed,
+                "hunks": change.hunks.len(),
+                "binary": change.binary,
+                "symbols": change.symbols,
+            })
+        })
+        .collect()
+}
+
+/// Identifies a set of changes by the blob OIDs of its files.
+pub fn fingerprint(changes: &[FileChange]) -> String {
+    let keys: Vec<String> = changes
+        .iter()
+        .map(|change| {
+            format!(
+                "{} {}",
+                change.file_path,
+                SummaryCache::key(change.old_oid, change.new_oid)
+            )
+        })
+        .collect();
+    Oid::hash_object(ObjectType::Blob, keys.join("\n").as_bytes())
+        .map(|oid| oid.to_string())
+        .unwrap_or_default()
+}
+
+/// The cache recording `changes` as described by `message`, with the summaries of those
+/// the model summarized, keyed like the cache.
+pub fn summary_cache(
+    changes: &[FileChange],
+    message: &str,
+    summaries: &HashMap<String, String>,
+) -> SummaryCache {
+    let files = changes
+        .iter()
+        .map(|change| {
+            let key = SummaryCache::key(change.old_oid, change.new_oid);
+            let file = CachedFile {
+                file_path: change.file_path.clone(),
+                change_type: change.change_type.clone(),
+                summary: summaries.get(&key).cloned(),
+            };
+            (key, file)
+        })
+        .collect();
+    SummaryCache {
+        message: message.to_string(),
+        files,
+    }
+}
@@ -0,0 +1,100 @@
+use crate::openai::Message;
+
+/// Chat formats of the model families local models are commonly distributed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Family {
+    Llama3,
+    ChatML,
+    Mistral,
+    Gemma,
+    Phi3,
+}
+
+impl Family {
+    /// Guesses the family from the `general.architecture` of a GGUF file.
+    /// Unknown architectures get ChatML, which most recent fine-tunes understand.
+    pub fn detect(architecture: &str) -> Self {
+        match architecture {
+            "llama" => Family::Llama3,
+            "mistral" | "mixtral" => Family::Mistral,
+            "gemma" | "gemma2" | "gemma3" => Family::Gemma,
+            "phi3" => Family::Phi3,
+            _ => Family::ChatML,
+        }
+    }
+
+    /// Renders the conversation, ending with the opening of the assistant's turn.
+    pub fn render(self, messages: &[Message]) -> String {
+        let mut prompt = String::new();
+        match self {
+            Family::Llama3 => {
+                prompt.push_str("<|begin_of_text|>");
+                for message in messages {
+                    prompt.push_str(&format!(
+                        "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+                        message.role, message.content
+                    ));
+                }
+                prompt.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+            }
+            Family::ChatML => {
+                for message in messages {
+                    prompt.push_str(&format!(
+                        "<|im_start|>{}\n{}<|im_end|>\n",
+                        message.role, message.content
+                    ));
+                }
+                prompt.push_str("<|im_start|>assistant\n");
+            }
+            Family::Mistral => {
+                // No system role; the system prompt is prepended to the first user turn
+                prompt.push_str("<s>");
+                let mut pending_system = String::new();
+                for message in messages {
+                    match message.role.as_str() {
+                        "system" => pending_system.push_str(&format!("{}\n\n", message.content)),
+                        "assistant" => prompt.push_str(&format!("{}</s>", message.content)),
+                        _ => {
+                            prompt.push_str(&format!(
+                                "[INST] {}{} [/INST]",
+                                pending_system, message.content
+                            ));
+                            pending_system.clear();
+                        }
+                    }
+                }
+            }
+            Family::Gemma => {
+                prompt.push_str("<bos>");
+                let mut pending_system = String::new();
+                for message in messages {
+                    match message.role.as_str() {
+                        "system" => pending_system.push_str(&format!("{}\n\n", message.content)),
+                        "assistant" => prompt.push_str(&format!(
+                            "<start_of_turn>model\n{}<end_of_turn>\n",
+                            message.content
+                        )),
+                        _ => {
+                            prompt.push_str(&format!(
+                                "<start_of_turn>user\n{}{}<end_of_turn>\n",
+                                pending_system, message.content
+                            ));
+                            pending_system.clear();
+                        }
+                    }
+                }
+                prompt.push_str("<start_of_turn>model\n");
+            }
+            Family::Phi3 => {
+                for message in messages {
+                    prompt.push_str(&format!(
+                        "<|{}|>\n{}<|end|>\n",
+                        message.role, message.content
+                    ));
+                }
+                prompt.push_str("<|assistant|>\n");
+            }
+        }
+        prompt
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::deadline::Deadlines;
+#[cfg(feature = "candle")]
+use crate::local::LocalModel;
+#[cfg(feature = "local-llama")]
+use crate::local_llama::LlamaLocalModel;
+use crate::mock::{Recorder, Replies};
+use crate::openai::Message;
+use crate::provider::{ApiClient, Capabilities, Usage};
+use crate::transcript::Transcript;
+use crate::{CommitGPTError, Result};
+
+/// Where completions come from: a provider's API, a model running in-process, or a
+/// file of canned replies.
+pub enum Client {
+    Api(Box<ApiClient>),
+    #[cfg(feature = "candle")]
+    Local(Box<LocalModel>),
+    #[cfg(feature = "local-llama")]
+    Llama(Box<LlamaLocalModel>),
+    /// A client whose transient failures fall through to the next client and model in turn
+    Chain(Box<Client>, Vec<(Client, String)>),
+    /// A client whose requests and replies are written to a transcript
+    Recorded(Box<Client>, Transcript),
+    /// Canned replies, for `--provider mock`
+    Mock(Box<Replies>),
+    /// A client whose replies are recorded for replaying with `--provider mock`
+    Recording(Box<Client>, Recorder),
+}
+
+impl Client {
+    /// Sends the conversation to the model and returns the trimmed reply.
+    pub fn complete(&self, model: &str, messages: &[Message]) -> Result<String> {
+        let mut replies = self.complete_n(model, messages, 1, None)?;
+        Ok(replies.swap_remove(0))
+    }
+
+    /// Requests a single reply and hands its text to `on_text` as it arrives, or all at
+    /// once where the model cannot stream.
+    pub fn complete_streaming(
+        &self,
+        model: &str,
+        messages: &[Message],
+        max_tokens: Option<usize>,
+        on_text: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        match self {
+            Client::Api(client) if client.capabilities(model).streaming => {
+                client.complete_streaming(model, messages, max_tokens, on_text)
+            }
+            Client::Chain(first, fallbacks) => {
+                fall_back(model, first, fallbacks, |client, model| {
+                    client.complete_streaming(model, messages, max_tokens, &mut *on_text)
+                })
+            }
+            Client::Recorded(client, transcript) => {
+                let result = client.complete_streaming(model, messages, max_tokens, on_text);
+                transcript.exchange(
+                    model,
+                    messages,
+                    1,
+                    result.as_ref().map(std::slice::from_ref),
+                );
+                result
+            }
+            Client::Recording(client, recorder) => {
+                let reply = client.complete_streaming(model, messages, max_tokens, on_text)?;
+                recorder.record(model, messages, std::slice::from_ref(&reply));
+                Ok(reply)
+            }
+            _ => {
+                let mut replies = self.complete_n(model, messages, 1, max_tokens)?;
+                let reply = replies.swap_remove(0);
+                on_text(&reply);
+                Ok(reply)
+            }
+        }
+    }
+
+    /// Requests `n` replies that are JSON matching `schema`, held to it where the
+    /// provider can do that; elsewhere the prompt has to ask for it.
+    pub fn complete_structured(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        schema: &Value,
+    ) -> Result<Vec<String>> {
+        match self {
+            Client::Api(client) => {
+                client.complete_structured(model, messages, n, max_tokens, schema)
+            }
+            Client::Chain(first, fallbacks) => {
+                fall_back(model, first, fallbacks, |client, model| {
+                    client.complete_structured(model, messages, n, max_tokens, schema)
+                })
+            }
+            Client::Recorded(client, transcript) => {
+                let result = client.complete_structured(model, messages, n, max_tokens, schema);
+                transcript.exchange(model, messages, n, result.as_deref());
+                result
+            }
+            Client::Recording(client, recorder) => {
+                let replies = client.complete_structured(model, messages, n, max_tokens, schema)?;
+                recorder.record(model, messages, &replies);
+                Ok(replies)
+            }
+            _ => self.complete_n(model, messages, n, max_tokens),
+        }
+    }
+
+    /// Bounds every API request by `deadlines`; in-process models always run to the end.
+    pub fn set_deadlines(&mut self, deadlines: Deadlines) {
+        match self {
+            Client::Api(client) => client.set_deadlines(deadlines),
+            #[cfg(feature = "candle")]
+            Client::Local(_) => {}
+            #[cfg(feature = "local-llama")]
+            Client::Llama(_) => {}
+            Client::Chain(first, fallbacks) => {
+                first.set_deadlines(deadlines);
+                for (client, _) in fallbacks {
+                    client.set_deadlines(deadlines);
+                }
+            }
+            Client::Recorded(client, _) | Client::Recording(client, _) => {
+                client.set_deadlines(deadlines)
+            }
+            Client::Mock(_) => {}
+        }
+    }
+
+    /// What the model behind the client can do; a chain goes by its first model.
+    pub fn capabilities(&self, model: &str) -> Capabilities {
+        match self {
+            Client::Api(client) => client.capabilities(model),
+            // In-process models hand back whole replies and follow their own chat template
+            #[cfg(feature = "candle")]
+            Client::Local(_) => Capabilities::default(),
+            #[cfg(feature = "local-llama")]
+            Client::Llama(_) => Capabilities::default(),
+            Client::Chain(first, _) => first.capabilities(model),
+            Client::Recorded(client, _) | Client::Recording(client, _) => {
+                client.capabilities(model)
+            }
+            Client::Mock(_) => Capabilities::default(),
+        }
+    }
+
+    /// The tokens the API reported using so far, by model. In-process models and canned
+    /// replies cost nothing, so they report none.
+    pub fn usage(&self) -> BTreeMap<String, Usage> {
+        match self {
+            Client::Api(client) => client.usage(),
+            Client::Chain(first, fallbacks) => {
+                let mut usage = first.usage();
+                for (client, _) in fallbacks {
+                    for (model, used) in client.usage() {
+                        usage.entry(model).or_default().add(used);
+                    }
+                }
+                usage
+            }
+            Client::Recorded(client, _) | Client::Recording(client, _) => client.usage(),
+            _ => BTreeMap::new(),
+        }
+    }
+
+    /// Adds a note to the transcript, if there is one.
+    pub fn note(&self, title: &str, body: &str) {
+        match self {
+            Client::Recorded(_, transcript) => transcript.note(title, body),
+            Client::Recording(client, _) => client.note(title, body),
+            _ => {}
+        }
+    }
+
+    /// Requests `n` alternative replies of at most `max_tokens` each. A local model ignores `model`.
+    pub fn complete_n(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+    ) -> Result<Vec<String>> {
+        match self {
+            Client::Api(client) => client.complete_n(model, messages, n, max_tokens),
+            #[cfg(feature = "candle")]
+            Client::Local(local) => local.complete_n(messages, n, max_tokens),
+            #[cfg(feature = "local-llama")]
+            Client::Llama(local) => local.complete_n(messages, n, max_tokens),
+            Client::Chain(first, fallbacks) => {
+                fall_back(model, first, fallbacks, |client, model| {
+                    client.complete_n(model, messages, n, max_tokens)
+                })
+            }
+            Client::Recorded(client, transcript) => {
+                let result = client.complete_n(model, messages, n, max_tokens);
+                transcript.exchange(model, messages, n, result.as_deref());
+                result
+            }
+            Client::Mock(replies) => replies.complete_n(messages, n),
+            Client::Recording(client, recorder) => {
+                let replies = client.complete_n(model, messages, n, max_tokens)?;
+                recorder.record(model, messages, &replies);
+                Ok(replies)
+            }
+        }
+    }
+}
+
+/// Sends `request` to `first`, then to each fallback in turn for as long as the previous
+/// one failed transiently.
+fn fall_back<T>(
+    model: &str,
+    first: &Client,
+    fallbacks: &[(Client, String)],
+    mut request: impl FnMut(&Client, &str) -> Result<T>,
+) -> Result<T> {
+    let mut current = model;
+    let mut result = request(first, model);
+    for (client, fallback) in fallbacks {
+        match &result {
+            Err(e) if is_transient(e) => {
+                eprintln!(
+                    "Warning: {} failed ({}); falling back to {}",
+                    current, e, fallback
+                );
+            }
+            _ => break,
+        }
+        current = fallback;
+        result = request(client, fallback);
+    }
+    result
+}
+
+/// Whether another model may well succeed where one failed: it was rate limited, the
+/// server failed, or it took too long.
+fn is_transient(error: &CommitGPTError) -> bool {
+    match error {
+        CommitGPTError::ApiErrorStatus(error) => {
+            error.status == StatusCode::TOO_MANY_REQUESTS || error.status.is_server_error()
+        }
+        CommitGPTError::HttpRequestError(error) => error.is_timeout() || error.is_connect(),
+        _ => false,
+    }
+}
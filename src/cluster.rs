@@ -0,0 +1,66 @@
+use serde::Deserialize;
+
+use crate::changes::FileChange;
+use crate::client::Client;
+use crate::openai::{strip_code_fence, Message};
+use crate::Result;
+
+const CLUSTER_SYSTEM_PROMPT: &str =
+    "You group the files of a Git change into a few themes. Reply with JSON only.";
+const CLUSTER_PROMPT_TEMPLATE: &str = "\
+Group the changed files below into 2 to 6 themes that describe why they changed. Every file belongs to exactly one theme. Reply with JSON of the form {\"themes\": [{\"title\": \"short theme description\", \"files\": [\"path\"]}]}.
+
+Files:
+{files}";
+const CLUSTER_SAMPLE_LINES: usize = 5;
+
+#[derive(Deserialize, Debug)]
+pub struct Theme {
+    pub title: String,
+    pub files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Themes {
+    themes: Vec<Theme>,
+}
+
+/// Asks `model` to cluster the changes into themes.
+pub fn cluster_changes(client: &Client, model: &str, changes: &[FileChange]) -> Result<Vec<Theme>> {
+    let files: String = changes
+        .iter()
+        .map(|change| {
+            let samples: Vec<&str> = change
+                .summaries
+                .iter()
+                .take(CLUSTER_SAMPLE_LINES)
+                .map(String::as_str)
+                .collect();
+            format!(
+                "- {} ({}): {}\n",
+                change.file_path,
+                change.change_type,
+                samples.join("; ")
+            )
+        })
+        .collect();
+
+    let messages = [
+        Message::system(CLUSTER_SYSTEM_PROMPT),
+        Message::user(&CLUSTER_PROMPT_TEMPLATE.replace("{files}", &files)),
+    ];
+    let reply = client.complete(model, &messages)?;
+    let themes: Themes = serde_json::from_str(strip_code_fence(&reply))?;
+    Ok(themes.themes)
+}
+
+/// Prompt section telling the final model to mirror the themes in its bullets.
+pub fn prompt_section(themes: &[Theme]) -> String {
+    let mut section = String::from(
+        "Structure the body with one bullet per theme below, in this order, instead of one bullet per file.\n",
+    );
+    for theme in themes {
+        section.push_str(&format!("- {}: {}\n", theme.title, theme.files.join(", ")));
+    }
+    section
+}
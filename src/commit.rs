@@ -0,0 +1,64 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::{ErrorCode, Oid, Repository};
+
+use crate::{CommitGPTError, Result};
+
+/// Commits the current index with `message`, like `git commit` with default cleanup.
+/// In a linked worktree these are the worktree's own index and HEAD.
+pub fn create_commit(repo: &Repository, message: &str) -> Result<Oid> {
+    let signature = repo.signature()?;
+    let mut index = repo.index()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    // The first commit of a new repository or orphan branch has no parent
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(e) if e.code() == ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e.into()),
+    };
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    let message = git2::message_prettify(message, None)?;
+
+    Ok(repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &parents,
+    )?)
+}
+
+/// Refuses to commit in `repo` when run from another worktree of the same repository, as
+/// with `--workdir-path` pointing at a linked worktree: the commit would go to the branch
+/// checked out there, which is rarely the one meant.
+pub fn check_worktree(repo: &Repository) -> Result<()> {
+    let Ok(current) = Repository::discover(".") else {
+        return Ok(());
+    };
+    let (Some(target), Some(here)) = (repo.workdir(), current.workdir()) else {
+        return Ok(());
+    };
+    if common_dir(repo) != common_dir(&current) || canonical(target) == canonical(here) {
+        return Ok(());
+    }
+    Err(CommitGPTError::OtherWorktreeError(
+        target.display().to_string(),
+        here.display().to_string(),
+    ))
+}
+
+/// The Git directory the worktrees of a repository share: a linked worktree's own one
+/// names it in its `commondir` file.
+fn common_dir(repo: &Repository) -> PathBuf {
+    let common = match fs::read_to_string(repo.path().join("commondir")) {
+        Ok(common) if repo.is_worktree() => repo.path().join(common.trim()),
+        _ => repo.path().to_path_buf(),
+    };
+    canonical(&common)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
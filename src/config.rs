@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use git2::Repository;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::audit::AuditLog;
+use crate::auto_model::AutoModel;
+use crate::azure::AzureConfig;
+use crate::copilot::CopilotConfig;
+use crate::deadline::DeadlineConfig;
+use crate::hook::ChainOrder;
+use crate::openrouter::OpenRouterConfig;
+use crate::presets::Endpoint;
+use crate::secrets::RedactionConfig;
+use crate::{lock, validate, CommitGPTError, Result};
+
+const CONFIG_DIR: &str = "commit-gpt";
+const CONFIG_FILE: &str = "config.toml";
+const REPO_CONFIG_FILE: &str = ".commit-gpt.toml";
+const REPO_CONTEXT_FILE: &str = ".commit-gpt-context";
+
+/// Settings that do not fit on the command line. Read from the user config
+/// (`~/.config/commit-gpt/config.toml`), then `.commit-gpt.toml` in the
+/// repository, then the file given with `--config`, later ones overriding earlier ones.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Extra fields merged into every request body, e.g. `presence_penalty`
+    pub extra_body: Map<String, Value>,
+    /// Replaces the default `commit-gpt/<version>` User-Agent
+    pub user_agent: Option<String>,
+    /// Extra headers sent with every request, e.g. tags a gateway uses to attribute traffic
+    pub headers: BTreeMap<String, String>,
+    /// Thresholds for `--model auto`
+    pub auto_model: Option<AutoModel>,
+    /// Terms to avoid in messages, mapped to the preferred term, e.g. `customer = "tenant"`
+    pub glossary: BTreeMap<String, String>,
+    /// Models tried in order when the chosen one is rate limited, fails or times out, like `--fallback-model`
+    pub fallback_models: Vec<String>,
+    /// Commit messages whose tone and layout generated ones imitate
+    pub style_examples: Vec<String>,
+    /// How random-looking strings, likely keys and tokens, are masked in prompts
+    pub redaction: Option<RedactionConfig>,
+    /// Regular expressions that must not match the message, e.g. codenames or "hack"
+    pub banned: Vec<String>,
+    /// Dollars a single run may cost before asking for confirmation
+    pub max_cost: Option<f64>,
+    /// Where every payload sent to a provider is recorded; ignored in repository configs
+    pub audit: Option<AuditLog>,
+    /// The resource for `--provider azure`; ignored in repository configs
+    pub azure: Option<AzureConfig>,
+    /// Fallback models and attribution for `--provider openrouter`
+    pub openrouter: Option<OpenRouterConfig>,
+    /// The integration `--provider copilot` reports requests as coming from
+    pub copilot: Option<CopilotConfig>,
+    /// Seconds the run and each of its stages may take before the message is written offline
+    pub deadlines: Option<DeadlineConfig>,
+    /// Times a chat request is sent again after a rate limit, server error or dropped
+    /// connection, like `--retries`
+    pub retries: Option<u32>,
+    /// Seconds to wait for another run on the same repository before giving up on it
+    pub lock_timeout: Option<u64>,
+    /// Replaces the provider's API base, like `--api-base`; ignored in repository configs
+    pub api_base: Option<String>,
+    /// A PEM file of root certificates trusted besides the built-in ones, like `--ca-cert`;
+    /// ignored in repository configs
+    pub ca_cert: Option<PathBuf>,
+    /// Languages to write messages in, like `--languages`; without any, the language of
+    /// recent commit messages
+    pub languages: Vec<String>,
+    /// The HTTP(S) proxy requests go through instead of the one in `HTTPS_PROXY`, like
+    /// `--proxy`; ignored in repository configs
+    pub proxy: Option<String>,
+    /// OpenAI-compatible APIs by name, for `--endpoint`; ignored in repository configs
+    pub endpoints: BTreeMap<String, Endpoint>,
+    /// Whether a prepare-commit-msg hook that was there before commit-gpt's runs before
+    /// or after it, like `hook install --order`
+    pub hook_order: Option<ChainOrder>,
+    /// Whether the hook also writes a new message for `git commit --amend` and `-c`,
+    /// which otherwise keep the message of the commit they reuse
+    pub hook_amend: Option<bool>,
+}
+
+impl Config {
+    pub fn lock_timeout(&self) -> Duration {
+        Duration::from_secs(self.lock_timeout.unwrap_or(lock::DEFAULT_LOCK_TIMEOUT))
+    }
+
+    pub fn load(repo: &Repository, explicit: Option<&Path>) -> Result<Self> {
+        let mut config = Config::default();
+
+        let repo_config = repo.workdir().map(|workdir| workdir.join(REPO_CONFIG_FILE));
+        let mut paths: Vec<PathBuf> = user_config_path().into_iter().collect();
+        paths.extend(repo_config.clone());
+        for path in paths {
+            if path.is_file() {
+                let mut file_config = Self::read(&path)?;
+                // A cloned repository must not choose where its diffs get written, or
+                // which server receives the API key
+                if Some(&path) == repo_config.as_ref() {
+                    if file_config.audit.take().is_some() {
+                        eprintln!(
+                            "Warning: ignoring [audit] in {}; set it in the user config",
+                            path.display()
+                        );
+                    }
+                    if file_config.azure.take().is_some() {
+                        eprintln!(
+                            "Warning: ignoring [azure] in {}; set it in the user config",
+                            path.display()
+                        );
+                    }
+                    if file_config.api_base.take().is_some() {
+                        eprintln!(
+                            "Warning: ignoring api_base in {}; set it in the user config",
+                            path.display()
+                        );
+                    }
+                    if file_config.ca_cert.take().is_some() {
+                        eprintln!(
+                            "Warning: ignoring ca_cert in {}; set it in the user config",
+                            path.display()
+                        );
+                    }
+                    if file_config.proxy.take().is_some() {
+                        eprintln!(
+                            "Warning: ignoring proxy in {}; set it in the user config",
+                            path.display()
+                        );
+                    }
+                    if !std::mem::take(&mut file_config.endpoints).is_empty() {
+                        eprintln!(
+                            "Warning: ignoring [endpoints] in {}; set them in the user config",
+                            path.display()
+                        );
+                    }
+                }
+                config.merge(file_config);
+            }
+        }
+
+        if let Some(path) = explicit {
+            config.merge(Self::read(path)?);
+        }
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let path_display = path.display().to_string();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CommitGPTError::ConfigReadError(path_display.clone(), e))?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| CommitGPTError::ConfigParseError(path_display.clone(), e))?;
+        for pattern in &config.banned {
+            let compiled = validate::banned_pattern(pattern)
+                .map_err(|e| CommitGPTError::BannedPatternError(pattern.clone(), e))?;
+            if compiled.is_match("") {
+                return Err(CommitGPTError::EmptyBannedPatternError(
+                    pattern.clone(),
+                    path_display,
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    fn merge(&mut self, other: Config) {
+        self.extra_body.extend(other.extra_body);
+        if other.user_agent.is_some() {
+            self.user_agent = other.user_agent;
+        }
+        self.headers.extend(other.headers);
+        self.glossary.extend(other.glossary);
+        self.banned.extend(other.banned);
+        if !other.fallback_models.is_empty() {
+            self.fallback_models = other.fallback_models;
+        }
+        if !other.style_examples.is_empty() {
+            self.style_examples = other.style_examples;
+        }
+        if other.auto_model.is_some() {
+            self.auto_model = other.auto_model;
+        }
+        if other.max_cost.is_some() {
+            self.max_cost = other.max_cost;
+        }
+        if other.audit.is_some() {
+            self.audit = other.audit;
+        }
+        if other.azure.is_some() {
+            self.azure = other.azure;
+        }
+        if other.openrouter.is_some() {
+            self.openrouter = other.openrouter;
+        }
+        if other.copilot.is_some() {
+            self.copilot = other.copilot;
+        }
+        if other.redaction.is_some() {
+            self.redaction = other.redaction;
+        }
+        if other.deadlines.is_some() {
+            self.deadlines = other.deadlines;
+        }
+        if other.lock_timeout.is_some() {
+            self.lock_timeout = other.lock_timeout;
+        }
+        if other.retries.is_some() {
+            self.retries = other.retries;
+        }
+        if other.api_base.is_some() {
+            self.api_base = other.api_base;
+        }
+        if other.ca_cert.is_some() {
+            self.ca_cert = other.ca_cert;
+        }
+        if !other.languages.is_empty() {
+            self.languages = other.languages;
+        }
+        if other.proxy.is_some() {
+            self.proxy = other.proxy;
+        }
+        self.endpoints.extend(other.endpoints);
+        if other.hook_order.is_some() {
+            self.hook_order = other.hook_order;
+        }
+        if other.hook_amend.is_some() {
+            self.hook_amend = other.hook_amend;
+        }
+    }
+
+    /// The endpoint `name` from the `[endpoints]` table.
+    pub fn endpoint(&self, name: &str) -> Result<&Endpoint> {
+        self.endpoints
+            .get(name)
+            .ok_or_else(|| CommitGPTError::UnknownEndpointError(name.to_string()))
+    }
+}
+
+/// Durable project context (naming conventions, glossary) from `--context-file`, or
+/// else from `.commit-gpt-context` at the root of the working tree.
+pub fn project_context(repo: &Repository, explicit: Option<&Path>) -> Result<Option<String>> {
+    let path = match explicit {
+        Some(path) => path.to_path_buf(),
+        None => match repo
+            .workdir()
+            .map(|workdir| workdir.join(REPO_CONTEXT_FILE))
+        {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(None),
+        },
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| CommitGPTError::ContextFileReadError(path.display().to_string(), e))?;
+    let contents = contents.trim();
+    Ok((!contents.is_empty()).then(|| contents.to_string()))
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join(CONFIG_DIR).join(CONFIG_FILE))
+}
@@ -0,0 +1,150 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use reqwest::blocking::RequestBuilder;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::openai::OpenAICompatible;
+use crate::provider::{ApiClient, Session};
+use crate::{CommitGPTError, Result};
+
+const COPILOT_API_URL: &str = "https://api.githubcopilot.com";
+const GITHUB_API_URL: &str = "https://api.github.com";
+// Copilot only serves chat to integrations it knows, and commit-gpt is not one of them
+const DEFAULT_INTEGRATION_ID: &str = "vscode-chat";
+const EDITOR_VERSION: &str = concat!("commit-gpt/", env!("CARGO_PKG_VERSION"));
+
+/// The `[copilot]` table of the config.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CopilotConfig {
+    /// Sent as `Copilot-Integration-Id`, instead of VS Code's `vscode-chat`
+    pub integration_id: Option<String>,
+}
+
+/// A sign-in of an editor plugin, in `hosts.json` or `apps.json`.
+#[derive(Deserialize)]
+struct Host {
+    oauth_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CopilotToken {
+    token: String,
+    endpoints: Option<Endpoints>,
+}
+
+#[derive(Deserialize)]
+struct Endpoints {
+    api: Option<String>,
+}
+
+/// GitHub Copilot's chat API, signed in with the GitHub token in `--api-key-path`, the
+/// one a Copilot editor plugin stored, or the GitHub CLI's. That token is traded for a
+/// short-lived Copilot token on every run, the way the editor plugins do. Requests name
+/// VS Code's chat as the integration they come from, unless `integration_id` in the
+/// `[copilot]` config table names another one the account may use.
+pub struct Copilot {
+    config: CopilotConfig,
+}
+
+impl Copilot {
+    pub fn new(config: Option<&CopilotConfig>) -> Self {
+        Copilot {
+            config: config.cloned().unwrap_or_default(),
+        }
+    }
+}
+
+impl OpenAICompatible for Copilot {
+    fn base_url(&self) -> String {
+        COPILOT_API_URL.to_string()
+    }
+
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        request
+            .bearer_auth(api_key)
+            .header(
+                "Copilot-Integration-Id",
+                self.config
+                    .integration_id
+                    .as_deref()
+                    .unwrap_or(DEFAULT_INTEGRATION_ID),
+            )
+            .header("Editor-Version", EDITOR_VERSION)
+    }
+
+    fn requires_api_key(&self) -> bool {
+        false
+    }
+
+    fn sign_in(&self, client: &ApiClient, api_key: &str) -> Result<Option<Session>> {
+        let github_token = match api_key {
+            "" => plugin_token().or_else(gh_token).ok_or_else(|| {
+                CommitGPTError::ProviderConfigError(
+                    "--provider copilot found no GitHub sign-in; sign in to Copilot in an editor or with `gh auth login`, \
+                     or pass a GitHub token with --api-key-path"
+                        .to_string(),
+                )
+            })?,
+            key => key.to_string(),
+        };
+        // Same variable GitHub Actions sets, which also points this at GitHub Enterprise
+        let github_api = env::var("GITHUB_API_URL").unwrap_or_else(|_| GITHUB_API_URL.to_string());
+        let url = format!(
+            "{}/copilot_internal/v2/token",
+            github_api.trim_end_matches('/')
+        );
+        let body = client.fetch(&url, &format!("token {}", github_token))?;
+        let token: CopilotToken = serde_json::from_str(&body)?;
+        Ok(Some(Session {
+            api_key: token.token,
+            base_url: token.endpoints.and_then(|endpoints| endpoints.api),
+        }))
+    }
+}
+
+/// The GitHub token of the first signed-in Copilot editor plugin.
+fn plugin_token() -> Option<String> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let dir = config_home.map(|home| home.join("github-copilot"));
+    for file in ["hosts.json", "apps.json"] {
+        let Some(contents) = dir
+            .as_ref()
+            .and_then(|dir| fs::read_to_string(dir.join(file)).ok())
+        else {
+            continue;
+        };
+        // Keyed by `github.com`, or `github.com:<app id>` in apps.json
+        let Ok(hosts) = serde_json::from_str::<Map<String, Value>>(&contents) else {
+            continue;
+        };
+        let token = hosts
+            .iter()
+            .filter(|(host, _)| host.starts_with("github.com"))
+            .filter_map(|(_, host)| {
+                serde_json::from_value::<Host>(host.clone())
+                    .ok()?
+                    .oauth_token
+            })
+            .next();
+        if token.is_some() {
+            return token;
+        }
+    }
+    None
+}
+
+/// The token the GitHub CLI is signed in with, which Copilot takes as well for accounts
+/// with a subscription.
+fn gh_token() -> Option<String> {
+    let output = Command::new("gh").args(["auth", "token"]).output().ok()?;
+    let token = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !token.is_empty()).then_some(token)
+}
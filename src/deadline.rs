@@ -0,0 +1,98 @@
+// A run from a hook holds up the commit, so the run as a whole (`--deadline`) and each
+// of its stages (the `[deadlines]` config table) can be given a time limit. A stage that
+// runs out of time ends with `DeadlineExceeded`, and the message is then written from
+// the changed files alone, as with `--offline`.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::{CommitGPTError, Result};
+
+/// The `[deadlines]` table of the config, in seconds.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeadlineConfig {
+    /// The whole run, like `--deadline`
+    pub total: Option<u64>,
+    /// Collecting the changes
+    pub diff: Option<u64>,
+    /// Each request to the API, including its reply
+    pub api_call: Option<u64>,
+    /// Verifying and correcting the first draft, checked between the requests it makes
+    pub validation: Option<u64>,
+}
+
+/// A part of the run with a deadline of its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Diff,
+    ApiCall,
+    Validation,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Diff => "collecting the changes",
+            Stage::ApiCall => "the API request",
+            Stage::Validation => "validating the message",
+        })
+    }
+}
+
+/// When the run must be done by, and how long each stage may take.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Deadlines {
+    end: Option<Instant>,
+    diff: Option<Duration>,
+    api_call: Option<Duration>,
+    validation: Option<Duration>,
+}
+
+impl Deadlines {
+    /// Starts the clock of a run. `total` from the command line wins over the config's.
+    pub fn start(config: Option<&DeadlineConfig>, total: Option<u64>) -> Self {
+        let config = config.cloned().unwrap_or_default();
+        let seconds = |limit: Option<u64>| limit.map(Duration::from_secs);
+        Deadlines {
+            end: seconds(total.or(config.total)).map(|total| Instant::now() + total),
+            diff: seconds(config.diff),
+            api_call: seconds(config.api_call),
+            validation: seconds(config.validation),
+        }
+    }
+
+    /// When `stage`, starting now, must be done: at its own limit or at the end of the
+    /// run, whichever comes first. `None` when neither is set.
+    pub fn until(&self, stage: Stage) -> Option<Instant> {
+        let limit = match stage {
+            Stage::Diff => self.diff,
+            Stage::ApiCall => self.api_call,
+            Stage::Validation => self.validation,
+        };
+        match (limit.map(|limit| Instant::now() + limit), self.end) {
+            (Some(own), Some(end)) => Some(own.min(end)),
+            (own, end) => own.or(end),
+        }
+    }
+}
+
+/// Fails when `stage` is not done by `until`.
+pub fn check(until: Option<Instant>, stage: Stage) -> Result<()> {
+    match until {
+        Some(until) if Instant::now() >= until => Err(CommitGPTError::DeadlineExceeded(stage)),
+        _ => Ok(()),
+    }
+}
+
+/// The time left until `until`.
+pub fn remaining(until: Option<Instant>) -> Option<Duration> {
+    until.map(|until| until.saturating_duration_since(Instant::now()))
+}
+
+/// Whether the deadline `until`, if any, has passed.
+pub fn passed(until: Option<Instant>) -> bool {
+    until.is_some_and(|until| Instant::now() >= until)
+}
@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use git2::{Repository, Sort};
+
+use crate::cache::SummaryCache;
+use crate::changes::{commit_changes, format_changes_for_prompt};
+use crate::config::Config;
+use crate::openai::Message;
+use crate::prompt::{Prompt, PromptBuilder, USER_PROMPT_TEMPLATE};
+use crate::validate::{Detail, Rules};
+use crate::{budget, connect, spending, truncated, Args, CommitGPTError, Result};
+
+/// A prompt template paired with a model, and how its messages fared.
+struct Variant {
+    prompt: String,
+    template: String,
+    model: String,
+    generated: usize,
+    failed: usize,
+    with_problems: usize,
+    title_lengths: usize,
+    overlap: f64,
+}
+
+/// A commit from the range, with what its message is compared against.
+struct Replay {
+    id: String,
+    title: String,
+    rules: Rules,
+    changes: String,
+}
+
+/// Regenerates the messages of the commits in `range` with every combination of prompt
+/// template and model, then prints the titles side by side and a table comparing the
+/// variants: how many messages broke the rules, how long the titles were and how many
+/// of their words the committed titles share.
+pub fn run(
+    args: &Args,
+    config: &Config,
+    repo: &Repository,
+    prompts: &[PathBuf],
+    models: &[String],
+    range: &str,
+) -> Result<()> {
+    let mut templates = Vec::new();
+    for path in prompts {
+        let template = fs::read_to_string(path)
+            .map_err(|e| CommitGPTError::PromptReadError(path.display().to_string(), e))?;
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        templates.push((name, template));
+    }
+    if templates.is_empty() {
+        templates.push(("built-in".to_string(), USER_PROMPT_TEMPLATE.to_string()));
+    }
+    let models = if models.is_empty() {
+        vec![args.model.clone()]
+    } else {
+        models.to_vec()
+    };
+    let mut variants: Vec<Variant> = templates
+        .iter()
+        .flat_map(|(prompt, template)| {
+            models.iter().map(|model| Variant {
+                prompt: prompt.clone(),
+                template: template.clone(),
+                model: model.clone(),
+                generated: 0,
+                failed: 0,
+                with_problems: 0,
+                title_lengths: 0,
+                overlap: 0.0,
+            })
+        })
+        .collect();
+
+    let replays = replays(args, repo, range)?;
+    let prompt = PromptBuilder::new()
+        .context(&args.context.clone().unwrap_or_default())
+        .mood(args.mood)
+        .style_examples(&config.style_examples)
+        .build();
+
+    // Every commit is sent once per variant, so the limit applies to the whole experiment
+    if !args.yes && !args.in_process() {
+        let (mut cost, mut tokens) = (0.0, 0);
+        for replay in &replays {
+            let prompt = with_rules(&prompt, &replay.rules);
+            for variant in &variants {
+                let prompt_tokens = budget::estimate_tokens(&prompt.system())
+                    + budget::estimate_tokens(
+                        &prompt.render_template(&variant.template, &replay.changes),
+                    );
+                tokens += prompt_tokens;
+                cost += spending::estimate_cost(&variant.model, prompt_tokens, 1).unwrap_or(0.0);
+            }
+        }
+        let limit = config.max_cost.unwrap_or(spending::DEFAULT_MAX_COST);
+        spending::confirm(cost, limit, tokens, &[])?;
+    }
+
+    let client = connect(args, config)?;
+    for replay in &replays {
+        println!("{} {}", replay.id, replay.title);
+        let prompt = with_rules(&prompt, &replay.rules);
+        for variant in &mut variants {
+            let overhead = budget::estimate_tokens(&prompt.system())
+                + budget::estimate_tokens(&prompt.render_template(&variant.template, ""));
+            let budget = match client.capabilities(&variant.model).max_context {
+                Some(context) => budget::reserve_completion(context),
+                None => budget::prompt_budget(&variant.model),
+            };
+            let available = budget.saturating_sub(overhead);
+            let (kept, dropped) = budget::split_at_budget(&replay.changes, available);
+            let conversation = [
+                Message::system(&prompt.system()),
+                Message::user(
+                    &prompt.render_template(&variant.template, &truncated(kept, dropped)),
+                ),
+            ];
+
+            let label = format!("{} {}", variant.prompt, variant.model);
+            match client.complete_n(&variant.model, &conversation, 1, replay.rules.max_tokens()) {
+                Ok(mut replies) => {
+                    let message = replies.swap_remove(0);
+                    let title = message.lines().next().unwrap_or_default();
+                    variant.generated += 1;
+                    variant.with_problems +=
+                        usize::from(!replay.rules.problems(&message).is_empty());
+                    variant.title_lengths += title.chars().count();
+                    variant.overlap += overlap(title, &replay.title);
+                    println!("  {}: {}", label, title);
+                }
+                Err(e) => {
+                    variant.failed += 1;
+                    eprintln!("Warning: {} failed for {}: {}", label, replay.id, e);
+                }
+            }
+        }
+        println!();
+    }
+
+    let width = variants
+        .iter()
+        .map(|variant| variant.prompt.len())
+        .max()
+        .unwrap_or(0)
+        .max("PROMPT".len());
+    let model_width = variants
+        .iter()
+        .map(|variant| variant.model.len())
+        .max()
+        .unwrap_or(0)
+        .max("MODEL".len());
+    println!(
+        "{:<width$}  {:<model_width$}  {:>9}  {:>6}  {:>8}  {:>9}  {:>7}",
+        "PROMPT", "MODEL", "GENERATED", "FAILED", "PROBLEMS", "TITLE LEN", "OVERLAP"
+    );
+    for variant in &variants {
+        let generated = variant.generated.max(1) as f64;
+        println!(
+            "{:<width$}  {:<model_width$}  {:>9}  {:>6}  {:>8}  {:>9.1}  {:>7.2}",
+            variant.prompt,
+            variant.model,
+            variant.generated,
+            variant.failed,
+            variant.with_problems,
+            variant.title_lengths as f64 / generated,
+            variant.overlap / generated,
+        );
+    }
+    Ok(())
+}
+
+/// The commits in `range`, oldest first, leaving out merges and commits without changes.
+fn replays(args: &Args, repo: &Repository, range: &str) -> Result<Vec<Replay>> {
+    let mut walk = repo.revwalk()?;
+    walk.push_range(range)?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+
+    let mut replays = Vec::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let changes = commit_changes(repo, &commit)?;
+        if changes.is_empty() {
+            continue;
+        }
+        let short_id = commit.as_object().short_id()?;
+        replays.push(Replay {
+            id: short_id.as_str().unwrap_or_default().to_string(),
+            title: commit.summary().unwrap_or_default().to_string(),
+            rules: Rules {
+                detail: Some(args.detail.unwrap_or_else(|| Detail::for_changes(&changes))),
+                mood: args.mood,
+                ..Rules::default()
+            },
+            changes: format_changes_for_prompt(
+                &changes,
+                &SummaryCache::default(),
+                args.group_threshold,
+            ),
+        });
+    }
+    Ok(replays)
+}
+
+fn with_rules(prompt: &Prompt, rules: &Rules) -> Prompt {
+    let mut prompt = prompt.clone();
+    if let Some(section) = rules.prompt_section() {
+        prompt.section("Constraints", &section);
+    }
+    prompt
+}
+
+/// The share of the words in either title that appear in both, ignoring case.
+fn overlap(title: &str, original: &str) -> f64 {
+    let words = |title: &str| -> HashSet<String> {
+        title
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    };
+    let (generated, original) = (words(title), words(original));
+    let union = generated.union(&original).count();
+    if union == 0 {
+        return 0.0;
+    }
+    generated.intersection(&original).count() as f64 / union as f64
+}
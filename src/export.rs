@@ -0,0 +1,41 @@
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+use crate::openai::{self, Message, OpenAI};
+use crate::provider::{Provider, Sampling};
+use crate::{anthropic, Result};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Body for OpenAI's chat completions endpoint
+    Openai,
+    /// Body for Anthropic's messages endpoint
+    Anthropic,
+    /// The system and user prompts as plain text
+    Raw,
+}
+
+/// The fully assembled request for `format`, ready to be sent by other tooling.
+pub fn request(
+    format: ExportFormat,
+    model: &str,
+    messages: &[Message],
+    max_tokens: Option<usize>,
+    extra_body: &Map<String, Value>,
+    sampling: Sampling,
+) -> Result<String> {
+    let body = match format {
+        ExportFormat::Openai => openai::request_body(model, messages, 1, max_tokens, extra_body)?,
+        ExportFormat::Anthropic => anthropic::request_body(model, messages, max_tokens, extra_body),
+        ExportFormat::Raw => {
+            let prompts: Vec<&str> = messages
+                .iter()
+                .map(|message| message.content.as_str())
+                .collect();
+            return Ok(prompts.join("\n\n"));
+        }
+    };
+    // Both take the sampling parameters as top-level fields
+    let body = OpenAI.sampling_body(body, sampling);
+    Ok(serde_json::to_string_pretty(&body)?)
+}
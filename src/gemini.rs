@@ -0,0 +1,230 @@
+use std::env;
+
+use reqwest::blocking::RequestBuilder;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::openai::{ApiError, Message};
+use crate::provider::{Capabilities, Provider, Sampling, Usage};
+use crate::Result;
+
+const GEMINI_API_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+#[derive(Deserialize)]
+struct GenerateResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+}
+
+#[derive(Deserialize)]
+struct Candidate {
+    content: Option<Content>,
+}
+
+#[derive(Deserialize)]
+struct Content {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+struct Part {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct ModelList {
+    #[serde(default)]
+    models: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelEntry {
+    name: String,
+    #[serde(default)]
+    supported_generation_methods: Vec<String>,
+}
+
+/// Google's Gemini API, which takes the conversation as `contents` made of `parts`.
+pub struct Gemini;
+
+impl Provider for Gemini {
+    fn base_url(&self) -> String {
+        match env::var("GEMINI_BASE_URL") {
+            Ok(base) if !base.is_empty() => base.trim_end_matches('/').to_string(),
+            _ => GEMINI_API_URL.to_string(),
+        }
+    }
+
+    fn api_key_env(&self) -> Option<String> {
+        Some("GEMINI_API_KEY".to_string())
+    }
+
+    fn chat_path(&self, model: &str) -> String {
+        format!("models/{}:generateContent", model)
+    }
+
+    // JSON is asked for with `responseMimeType`
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            json_mode: true,
+            ..Capabilities::default()
+        }
+    }
+
+    // Gemini's schemas are a subset of JSON Schema without `additionalProperties`
+    fn json_body(&self, mut body: Value, schema: &Value) -> Value {
+        let mut schema = schema.clone();
+        if let Some(schema) = schema.as_object_mut() {
+            schema.remove("additionalProperties");
+        }
+        body["generationConfig"]["responseMimeType"] = json!("application/json");
+        body["generationConfig"]["responseSchema"] = schema;
+        body
+    }
+
+    fn sampling_body(&self, mut body: Value, sampling: Sampling) -> Value {
+        if let Some(temperature) = sampling.temperature {
+            body["generationConfig"]["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = sampling.top_p {
+            body["generationConfig"]["topP"] = json!(top_p);
+        }
+        body
+    }
+
+    fn usage(&self, body: &str) -> Option<Usage> {
+        Usage::read(
+            body,
+            "usageMetadata",
+            "promptTokenCount",
+            "candidatesTokenCount",
+        )
+    }
+
+    // Streaming is chosen by the endpoint rather than the body
+    fn stream_body(&self, body: Value) -> Value {
+        body
+    }
+
+    fn stream_path(&self, model: &str) -> String {
+        format!("models/{}:streamGenerateContent?alt=sse", model)
+    }
+
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        request.header("x-goog-api-key", api_key)
+    }
+
+    fn request_body(
+        &self,
+        _model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        extra_body: &Map<String, Value>,
+    ) -> Result<Value> {
+        // The system prompt is an instruction of its own, and the assistant is the `model` role
+        let system: Vec<Value> = messages
+            .iter()
+            .filter(|message| message.role == "system")
+            .map(|message| json!({ "text": message.content }))
+            .collect();
+        let contents: Vec<Value> = messages
+            .iter()
+            .filter(|message| message.role != "system")
+            .map(|message| {
+                let role = if message.role == "assistant" {
+                    "model"
+                } else {
+                    "user"
+                };
+                json!({ "role": role, "parts": [{ "text": message.content }] })
+            })
+            .collect();
+
+        let mut generation_config = Map::new();
+        if n > 1 {
+            generation_config.insert("candidateCount".to_string(), json!(n));
+        }
+        if let Some(max_tokens) = max_tokens {
+            generation_config.insert("maxOutputTokens".to_string(), json!(max_tokens));
+        }
+        let mut body = json!({ "contents": contents });
+        if let Some(body) = body.as_object_mut() {
+            if !system.is_empty() {
+                body.insert("systemInstruction".to_string(), json!({ "parts": system }));
+            }
+            if !generation_config.is_empty() {
+                body.insert(
+                    "generationConfig".to_string(),
+                    Value::Object(generation_config),
+                );
+            }
+            for (key, value) in extra_body {
+                if key != "contents" && key != "systemInstruction" {
+                    body.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Ok(body)
+    }
+
+    fn replies(&self, body: &str) -> Result<Vec<String>> {
+        let response: GenerateResponse = serde_json::from_str(body)?;
+        Ok(response
+            .candidates
+            .into_iter()
+            .filter_map(|candidate| candidate.content)
+            .map(|content| content.parts.into_iter().map(|part| part.text).collect())
+            .collect())
+    }
+
+    /// Every event is a response of its own with the next part of the text.
+    fn stream_delta(&self, data: &str) -> Result<Option<String>> {
+        Ok(self.replies(data)?.into_iter().next())
+    }
+
+    /// Reports an oversized prompt like OpenAI does, which the prompt shrinking looks for.
+    fn error(&self, status: StatusCode, body: &str) -> ApiError {
+        let mut error = ApiError::from_body(status, body);
+        if error
+            .message
+            .as_deref()
+            .is_some_and(|message| message.contains("exceeds the maximum number of tokens"))
+        {
+            error.code = Some("context_length_exceeded".to_string());
+        }
+        error
+    }
+
+    fn models_path(&self) -> String {
+        "models".to_string()
+    }
+
+    fn models(&self, body: &str) -> Result<Vec<String>> {
+        let list: ModelList = serde_json::from_str(body)?;
+        // Embedding and question-answering models list other methods only
+        Ok(list
+            .models
+            .into_iter()
+            .filter(|model| {
+                model.supported_generation_methods.is_empty()
+                    || model
+                        .supported_generation_methods
+                        .iter()
+                        .any(|method| method == "generateContent")
+            })
+            .map(|model| {
+                model
+                    .name
+                    .strip_prefix("models/")
+                    .map(String::from)
+                    .unwrap_or(model.name)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::{Subcommand, ValueEnum};
+use git2::{Repository, Sort};
+use rusqlite::{params, params_from_iter, Connection};
+use serde_json::json;
+
+use crate::audit::timestamp;
+use crate::changes::{commit_changes, fingerprint};
+use crate::prompt::system_prompt;
+use crate::trailers;
+use crate::validate::Mood;
+use crate::{CommitGPTError, Result};
+
+const DATA_DIR: &str = "commit-gpt";
+const HISTORY_FILE: &str = "history.sqlite";
+// How far back commits are searched for the changes of a pending suggestion
+const MAX_COMMITS_SEARCHED: usize = 100;
+// Suggestions not committed within this long are taken to have been dropped
+const PENDING_FOR: Duration = Duration::from_secs(14 * 86_400);
+
+// Schema changes after the first, applied in order; `user_version` counts those applied
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE messages ADD COLUMN prompt TEXT NOT NULL DEFAULT '';
+     CREATE TABLE feedback (
+         message_id INTEGER PRIMARY KEY REFERENCES messages (id),
+         commit_id TEXT NOT NULL,
+         final TEXT NOT NULL,
+         edited INTEGER NOT NULL
+     );",
+];
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum HistoryAction {
+    /// Find earlier messages containing every word of the query, in the message or the paths
+    Search {
+        #[arg(required = true)]
+        query: Vec<String>,
+
+        /// Most messages shown, newest first
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FeedbackAction {
+    /// Print a JSONL fine-tuning dataset of the changes and the messages committed for them
+    Export {
+        /// Also include suggestions that were committed unchanged
+        #[arg(long)]
+        include_unedited: bool,
+    },
+}
+
+/// A generated message, with the fingerprint of the changes it describes.
+pub struct Entry {
+    pub created_at: String,
+    pub repository: String,
+    pub fingerprint: String,
+    pub model: String,
+    pub files: Vec<String>,
+    pub message: String,
+    /// The user prompt the message was generated from
+    pub prompt: String,
+    /// Whether the message was accepted in the interactive loop or committed directly
+    pub accepted: bool,
+}
+
+/// Every message generated on this machine, across repositories, in an SQLite database
+/// under the user's data directory.
+pub struct History {
+    connection: Connection,
+}
+
+impl History {
+    pub fn open() -> Result<Self> {
+        let path = history_path().ok_or_else(|| {
+            CommitGPTError::HistoryIoError(
+                HISTORY_FILE.to_string(),
+                std::io::ErrorKind::NotFound.into(),
+            )
+        })?;
+        Self::open_at(&path)
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| CommitGPTError::HistoryIoError(parent.display().to_string(), e))?;
+        }
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY,
+                created_at TEXT NOT NULL,
+                repository TEXT NOT NULL,
+                fingerprint TEXT NOT NULL,
+                model TEXT NOT NULL,
+                files TEXT NOT NULL,
+                message TEXT NOT NULL,
+                accepted INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS messages_fingerprint ON messages (fingerprint);",
+        )?;
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            connection.execute_batch(&format!(
+                "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                migration,
+                index + 1
+            ))?;
+        }
+        Ok(History { connection })
+    }
+
+    pub fn record(&self, entry: &Entry) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO messages (created_at, repository, fingerprint, model, files, message, accepted, prompt)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                entry.created_at,
+                entry.repository,
+                entry.fingerprint,
+                entry.model,
+                entry.files.join("\n"),
+                entry.message,
+                entry.accepted,
+                entry.prompt,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Pairs the suggestions for `repo` that have no outcome yet with the commit that has
+    /// the same changes among those made since, recording whether the message was edited.
+    /// Suggestions older than [`PENDING_FOR`] are no longer looked for.
+    pub fn reconcile(&self, repo: &Repository) -> Result<()> {
+        let repository = repository_name(repo);
+        let cutoff = timestamp(SystemTime::now() - PENDING_FOR);
+        let mut statement = self.connection.prepare(
+            "SELECT id, fingerprint, message, created_at FROM messages
+             WHERE repository = ?1 AND created_at >= ?2
+               AND id NOT IN (SELECT message_id FROM feedback)",
+        )?;
+        let pending: Vec<(i64, String, String, String)> = statement
+            .query_map(params![repository, cutoff], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        let Some(oldest) = pending.iter().map(|(_, _, _, created_at)| created_at).min() else {
+            return Ok(());
+        };
+
+        let mut committed = HashMap::new();
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TIME)?;
+        if walk.push_head().is_err() {
+            return Ok(());
+        }
+        for oid in walk.take(MAX_COMMITS_SEARCHED) {
+            let commit = repo.find_commit(oid?)?;
+            // Commits made before the oldest suggestion cannot have taken it
+            let seconds = u64::try_from(commit.time().seconds()).unwrap_or_default();
+            if timestamp(UNIX_EPOCH + Duration::from_secs(seconds)) < *oldest {
+                break;
+            }
+            let changes = commit_changes(repo, &commit)?;
+            committed.entry(fingerprint(&changes)).or_insert_with(|| {
+                (
+                    commit.id().to_string(),
+                    commit.message().unwrap_or_default().trim().to_string(),
+                )
+            });
+        }
+
+        for (id, fingerprint, suggestion, _) in &pending {
+            if let Some((commit_id, message)) = committed.get(fingerprint) {
+                self.connection.execute(
+                    "INSERT INTO feedback (message_id, commit_id, final, edited) VALUES (?1, ?2, ?3, ?4)",
+                    params![id, commit_id, message, is_edited(suggestion, message)],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// One chat-format training example per commit, pairing the prompt of its latest
+    /// suggestion with the message that was committed.
+    pub fn training_examples(&self, include_unedited: bool) -> Result<Vec<serde_json::Value>> {
+        let mut statement = self.connection.prepare(
+            "SELECT messages.prompt, feedback.final FROM feedback
+             JOIN messages ON messages.id = feedback.message_id
+             WHERE feedback.message_id IN (SELECT MAX(message_id) FROM feedback GROUP BY commit_id)
+               AND messages.prompt != '' AND (feedback.edited OR ?1)
+             ORDER BY feedback.message_id",
+        )?;
+        let examples = statement
+            .query_map([include_unedited], |row| {
+                let prompt: String = row.get(0)?;
+                let message: String = row.get(1)?;
+                // The system prompt is not stored, but follows from the mood the prompt asks for
+                let mood = Mood::value_variants()
+                    .iter()
+                    .copied()
+                    .find(|mood| prompt.contains(mood.phrase()))
+                    .unwrap_or_default();
+                Ok(json!({
+                    "messages": [
+                        { "role": "system", "content": system_prompt(mood) },
+                        { "role": "user", "content": prompt },
+                        { "role": "assistant", "content": message },
+                    ]
+                }))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(examples)
+    }
+
+    /// The newest `limit` messages whose text or paths contain every word, ignoring case.
+    pub fn search(&self, words: &[String], limit: usize) -> Result<Vec<Entry>> {
+        let conditions =
+            vec!["(message LIKE ? ESCAPE '\\' OR files LIKE ? ESCAPE '\\')"; words.len()];
+        let sql = format!(
+            "SELECT created_at, repository, fingerprint, model, files, message, accepted FROM messages
+             WHERE {} ORDER BY id DESC LIMIT {}",
+            conditions.join(" AND "),
+            limit
+        );
+        let patterns: Vec<String> = words
+            .iter()
+            .map(|word| {
+                format!(
+                    "%{}%",
+                    word.replace('\\', "\\\\")
+                        .replace('%', "\\%")
+                        .replace('_', "\\_")
+                )
+            })
+            .flat_map(|pattern| [pattern.clone(), pattern])
+            .collect();
+
+        let mut statement = self.connection.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(patterns), |row| {
+            let files: String = row.get(4)?;
+            Ok(Entry {
+                created_at: row.get(0)?,
+                repository: row.get(1)?,
+                fingerprint: row.get(2)?,
+                model: row.get(3)?,
+                files: files.lines().map(String::from).collect(),
+                message: row.get(5)?,
+                prompt: String::new(),
+                accepted: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+impl Entry {
+    pub fn new(
+        repository: &Repository,
+        fingerprint: &str,
+        model: &str,
+        files: Vec<String>,
+        message: &str,
+        accepted: bool,
+    ) -> Self {
+        Entry {
+            created_at: timestamp(SystemTime::now()),
+            repository: repository_name(repository),
+            fingerprint: fingerprint.to_string(),
+            model: model.to_string(),
+            files,
+            message: message.to_string(),
+            prompt: String::new(),
+            accepted,
+        }
+    }
+}
+
+pub fn run(action: &HistoryAction) -> Result<()> {
+    match action {
+        HistoryAction::Search { query, limit } => {
+            let history = History::open()?;
+            for entry in history.search(query, *limit)? {
+                let accepted = if entry.accepted { " (accepted)" } else { "" };
+                println!(
+                    "{} {} {}{}",
+                    entry.created_at, entry.repository, entry.model, accepted
+                );
+                for line in entry.message.lines() {
+                    println!("{}", format!("    {}", line).trim_end());
+                }
+                println!();
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn run_feedback(repo: &Repository, action: &FeedbackAction) -> Result<()> {
+    match action {
+        FeedbackAction::Export { include_unedited } => {
+            let history = History::open()?;
+            history.reconcile(repo)?;
+            for example in history.training_examples(*include_unedited)? {
+                println!("{}", example);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Whether `committed` says something else than `suggestion`. Trailers added on the way,
+/// like `--signoff`'s, and the whitespace and comments git cleans up do not count.
+fn is_edited(suggestion: &str, committed: &str) -> bool {
+    let text = |message: &str| {
+        let cleaned = git2::message_prettify(message, Some(b'#')).unwrap_or_default();
+        let lines: Vec<&str> = cleaned.trim_end().lines().collect();
+        let end = trailers::trailer_block_start(&lines).unwrap_or(lines.len());
+        lines[..end].join("\n").trim().to_string()
+    };
+    text(suggestion) != text(committed)
+}
+
+fn repository_name(repo: &Repository) -> String {
+    repo.workdir().unwrap_or(repo.path()).display().to_string()
+}
+
+fn history_path() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data_home.join(DATA_DIR).join(HISTORY_FILE))
+}
@@ -0,0 +1,455 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Subcommand, ValueEnum};
+use git2::Repository;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::{
+    apply_signoff, diff_source, generate, offline, parse_args, template, trailers, Args,
+    CommitGPTError, Result,
+};
+
+const HOOK_NAME: &str = "prepare-commit-msg";
+const HOOK_MARKER: &str = "# Installed by commit-gpt";
+/// A hook that was there before ours is kept under its name with this suffix, and run by ours
+const CHAINED_SUFFIX: &str = ".chained";
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum HookAction {
+    /// Install commit-gpt as the repository's prepare-commit-msg hook, running any hook
+    /// already there along with it
+    Install {
+        /// When the hook already there runs (defaults to hook_order in the config, or before)
+        #[arg(long, value_enum)]
+        order: Option<ChainOrder>,
+    },
+
+    /// Remove commit-gpt's hook, putting back the hook it ran along with its own
+    Uninstall,
+
+    /// List the repository's hooks, and which of them commit-gpt installed
+    Status,
+
+    /// Fill in the commit message file (invoked by git as prepare-commit-msg)
+    Run {
+        /// File holding the commit message
+        message_file: PathBuf,
+
+        /// Source of the message (message, template, merge, squash or commit)
+        source: Option<String>,
+
+        /// Commit the message was taken from, when the source is commit
+        commit: Option<String>,
+    },
+}
+
+/// When the hook commit-gpt's replaced runs, relative to commit-gpt.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChainOrder {
+    /// The other hook runs first; the trailers it adds to the message are kept
+    #[default]
+    Before,
+    /// commit-gpt runs first, and the other hook can change the message it wrote
+    After,
+}
+
+pub fn run(args: &Args, config: &Config, repo: &Repository, action: &HookAction) -> Result<()> {
+    match action {
+        HookAction::Install { order } => {
+            install(args, repo, order.or(config.hook_order).unwrap_or_default())
+        }
+        HookAction::Uninstall => uninstall(repo),
+        HookAction::Status => status(repo),
+        HookAction::Run {
+            message_file,
+            source,
+            commit,
+        } => run_hook(
+            args,
+            config,
+            repo,
+            message_file,
+            source.as_deref(),
+            commit.as_deref(),
+        ),
+    }
+}
+
+/// Runs the hook the way the pre-commit framework invokes it: the options configured
+/// as the hook's `args` come first and the message file last, while the source and
+/// commit git passes to prepare-commit-msg are only available from the environment.
+pub fn pre_commit_entry(entry_args: &[String]) -> Result<()> {
+    let (message_file, options) = entry_args
+        .split_last()
+        .expect("clap requires the message file");
+    // Options given before the subcommand still apply, so reparse them together with the hook's
+    let global_options = env::args().take_while(|arg| arg != "pre-commit-entry");
+    let args = parse_args(global_options.chain(options.iter().cloned()));
+
+    let repo = Repository::open(&args.workdir_path)?;
+    let config = Config::load(&repo, args.config.as_deref())?;
+    let source = env::var("PRE_COMMIT_COMMIT_MSG_SOURCE")
+        .ok()
+        .filter(|source| !source.is_empty());
+    let commit = env::var("PRE_COMMIT_COMMIT_OBJECT_NAME")
+        .ok()
+        .filter(|commit| !commit.is_empty());
+    run_hook(
+        &args,
+        &config,
+        &repo,
+        Path::new(message_file),
+        source.as_deref(),
+        commit.as_deref(),
+    )
+}
+
+fn install(args: &Args, repo: &Repository, order: ChainOrder) -> Result<()> {
+    let path = hooks_dir(repo)?.join(HOOK_NAME);
+    let path_display = path.display().to_string();
+    let chained = chained_path(&path);
+
+    // A hook of another tool is kept and run too, unless one is kept already
+    if path.exists() && installed_version(&path).is_none() {
+        if chained.exists() {
+            return Err(CommitGPTError::ForeignHookExists(path_display));
+        }
+        fs::rename(&path, &chained)
+            .map_err(|e| CommitGPTError::HookIoError(path_display.clone(), e))?;
+        eprintln!(
+            "Moved the existing {} hook to {}, to run along with commit-gpt",
+            HOOK_NAME,
+            chained.display()
+        );
+    }
+
+    let exe =
+        env::current_exe().map_err(|e| CommitGPTError::HookIoError(path_display.clone(), e))?;
+    let mut command = vec![exe.display().to_string()];
+    if let Some(api_key_path) = &args.api_key_path {
+        let api_key_path = fs::canonicalize(api_key_path)
+            .map_err(|e| CommitGPTError::ApiKeyReadError(api_key_path.clone(), e))?;
+        command.push("--api-key-path".to_string());
+        command.push(api_key_path.display().to_string());
+    }
+    if let Some(local_model) = &args.local_model {
+        let local_model = std::path::absolute(local_model).unwrap_or_else(|_| local_model.clone());
+        command.push("--local-model".to_string());
+        command.push(local_model.display().to_string());
+    }
+    if let Some(context_file) = &args.context_file {
+        let context_file = fs::canonicalize(context_file).map_err(|e| {
+            CommitGPTError::ContextFileReadError(context_file.display().to_string(), e)
+        })?;
+        command.push("--context-file".to_string());
+        command.push(context_file.display().to_string());
+    }
+    // The model is named the way the provider it was picked for names it
+    if let Some(provider) = args.provider.to_possible_value() {
+        command.push("--provider".to_string());
+        command.push(provider.get_name().to_string());
+    }
+    if let Some(endpoint) = &args.endpoint {
+        command.push("--endpoint".to_string());
+        command.push(endpoint.clone());
+    }
+    if let Some(api_base) = &args.api_base {
+        command.push("--api-base".to_string());
+        command.push(api_base.clone());
+    }
+    command.push("--model".to_string());
+    command.push(args.model.clone());
+    if args.signoff {
+        command.push("--signoff".to_string());
+    }
+
+    let command: Vec<String> = command.iter().map(|arg| shell_quote(arg)).collect();
+    let own = format!("{} hook run \"$@\"", command.join(" "));
+    let other = format!(
+        "\"$(dirname \"$0\")/{}{}\" \"$@\"",
+        HOOK_NAME, CHAINED_SUFFIX
+    );
+    let (first, last) = match order {
+        _ if !chained.exists() => (None, own),
+        ChainOrder::Before => (Some(other), own),
+        ChainOrder::After => (Some(own), other),
+    };
+    let mut script = format!("#!/bin/sh\n{} {}\n", HOOK_MARKER, env!("CARGO_PKG_VERSION"));
+    if let Some(first) = first {
+        // Either failing aborts the commit, as a failing hook of its own would
+        script.push_str(&format!("{} || exit $?\n", first));
+    }
+    script.push_str(&format!("exec {}\n", last));
+
+    write_executable(&path, &script)
+        .map_err(|e| CommitGPTError::HookIoError(path_display.clone(), e))?;
+    eprintln!("Installed {} hook at {}", HOOK_NAME, path_display);
+    Ok(())
+}
+
+fn uninstall(repo: &Repository) -> Result<()> {
+    let path = hooks_dir(repo)?.join(HOOK_NAME);
+    let path_display = path.display().to_string();
+    if !path.exists() {
+        eprintln!("No {} hook is installed at {}", HOOK_NAME, path_display);
+        return Ok(());
+    }
+    if installed_version(&path).is_none() {
+        eprintln!(
+            "Left the {} hook at {} alone, as commit-gpt did not install it",
+            HOOK_NAME, path_display
+        );
+        return Ok(());
+    }
+
+    let chained = chained_path(&path);
+    let restored = if chained.exists() {
+        fs::rename(&chained, &path)
+    } else {
+        fs::remove_file(&path)
+    };
+    restored.map_err(|e| CommitGPTError::HookIoError(path_display.clone(), e))?;
+    if path.exists() {
+        eprintln!(
+            "Restored the {} hook commit-gpt ran along with its own at {}",
+            HOOK_NAME, path_display
+        );
+    } else {
+        eprintln!("Removed the {} hook at {}", HOOK_NAME, path_display);
+    }
+    Ok(())
+}
+
+/// Prints every hook of the repository: whether commit-gpt installed it, in which
+/// version, and the hook it runs first.
+fn status(repo: &Repository) -> Result<()> {
+    let dir = hooks_dir(repo)?;
+    let mut hooks: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_none_or(|extension| extension != "sample")
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    hooks.sort();
+
+    println!("Hooks in {}:", dir.display());
+    if hooks.is_empty() {
+        println!("  none");
+    }
+    for hook in hooks {
+        let name = hook.file_name().unwrap_or_default().to_string_lossy();
+        let description = match installed_version(&hook) {
+            Some(version) => {
+                let mut description = match version.as_str() {
+                    "" => "commit-gpt, an earlier version".to_string(),
+                    version => format!("commit-gpt {}", version),
+                };
+                if version != env!("CARGO_PKG_VERSION") {
+                    description.push_str(&format!(
+                        " (this is {}; install again to update)",
+                        env!("CARGO_PKG_VERSION")
+                    ));
+                }
+                let chained = chained_path(&hook);
+                if chained.exists() {
+                    let when = match chained_order(&hook) {
+                        ChainOrder::Before => "before",
+                        ChainOrder::After => "after",
+                    };
+                    description.push_str(&format!(", runs {} {} itself", chained.display(), when));
+                }
+                description
+            }
+            None if name.ends_with(CHAINED_SUFFIX) => {
+                "another tool's, run by commit-gpt's hook".to_string()
+            }
+            None => "another tool's".to_string(),
+        };
+        println!("  {}: {}", name, description);
+    }
+    Ok(())
+}
+
+/// The version of commit-gpt that installed the hook at `path`, or `None` when it did
+/// not. Hooks from before versions were recorded have an empty one.
+fn installed_version(path: &Path) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    let line = contents
+        .lines()
+        .find(|line| line.starts_with(HOOK_MARKER))?;
+    Some(line[HOOK_MARKER.len()..].trim().to_string())
+}
+
+/// When commit-gpt's hook at `path` runs the hook it replaced: last, with `exec`, when
+/// it runs it after itself.
+fn chained_order(path: &Path) -> ChainOrder {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let runs_last = format!("exec \"$(dirname \"$0\")/{}{}\"", HOOK_NAME, CHAINED_SUFFIX);
+    if contents.contains(&runs_last) {
+        ChainOrder::After
+    } else {
+        ChainOrder::Before
+    }
+}
+
+fn chained_path(hook: &Path) -> PathBuf {
+    let mut name = hook.file_name().unwrap_or_default().to_os_string();
+    name.push(CHAINED_SUFFIX);
+    hook.with_file_name(name)
+}
+
+fn run_hook(
+    args: &Args,
+    config: &Config,
+    repo: &Repository,
+    message_file: &Path,
+    source: Option<&str>,
+    commit: Option<&str>,
+) -> Result<()> {
+    // Messages given with -m/-F, merges and squashes are left untouched, and so are those
+    // of amended or reused commits unless the config asks for a new one
+    let regenerate_commit = config.hook_amend.unwrap_or(false);
+    if matches!(source, Some("message" | "merge" | "squash"))
+        || (source == Some("commit") && !regenerate_commit)
+    {
+        return Ok(());
+    }
+
+    let file_display = message_file.display().to_string();
+    let existing = fs::read_to_string(message_file)
+        .map_err(|e| CommitGPTError::HookIoError(file_display.clone(), e))?;
+    let comment_prefix = template::comment_prefix(repo, Some(&existing));
+    let (existing_message, comment_block) = split_comment_block(&existing, &comment_prefix);
+
+    // When amending, describe the amended commit as a whole
+    let base = match (source, commit) {
+        (Some("commit"), Some(rev)) => amend_base(repo, rev)?,
+        _ => None,
+    };
+
+    // A commit is never blocked by the model, so whatever fails only costs the draft
+    let generated = match generate(args, config, repo, base.as_ref()) {
+        Ok(message) => message,
+        Err(e) => {
+            eprintln!(
+                "Warning: {}; writing a message from the changed files instead",
+                e
+            );
+            let changes = diff_source(args, repo, base.as_ref())?.changes()?;
+            (!changes.is_empty()).then(|| offline::message(&changes))
+        }
+    };
+    let Some(message) = generated else {
+        return Ok(());
+    };
+    // Lines starting with the comment prefix would be dropped by git on commit
+    let message = template::strip_comments(&message, &comment_prefix);
+    let message = trailers::merge_trailers(&message, &trailers::parse_trailers(&existing_message));
+    let message = apply_signoff(args, repo, message)?;
+
+    // Keep git's standard comment block (and any verbose diff) below the message
+    let mut contents = format!("{}\n", message);
+    if !comment_block.is_empty() {
+        contents.push('\n');
+        contents.push_str(&comment_block);
+    }
+    fs::write(message_file, contents).map_err(|e| CommitGPTError::HookIoError(file_display, e))
+}
+
+/// The parent tree of `rev` when it is the commit being amended (HEAD).
+fn amend_base<'a>(repo: &'a Repository, rev: &str) -> Result<Option<git2::Tree<'a>>> {
+    let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+    if repo.head()?.peel_to_commit()?.id() != commit.id() {
+        return Ok(None);
+    }
+    match commit.parent(0) {
+        Ok(parent) => Ok(Some(parent.tree()?)),
+        Err(_) => {
+            let empty = repo.treebuilder(None)?.write()?;
+            Ok(Some(repo.find_tree(empty)?))
+        }
+    }
+}
+
+/// Splits COMMIT_EDITMSG into the message (without comment lines) and the
+/// trailing block of comments git appended, including everything below the
+/// scissors line of `git commit -v`.
+fn split_comment_block(contents: &str, comment_prefix: &str) -> (String, String) {
+    let lines: Vec<&str> = contents.lines().collect();
+    let scissors = format!("{} {}", comment_prefix, template::SCISSORS);
+    let head_end = lines
+        .iter()
+        .position(|line| *line == scissors)
+        .unwrap_or(lines.len());
+
+    let is_comment_or_blank =
+        |line: &&str| line.starts_with(comment_prefix) || line.trim().is_empty();
+    let block_start = lines[..head_end]
+        .iter()
+        .rposition(|line| !is_comment_or_blank(line))
+        .map_or(0, |last| last + 1);
+
+    let message: Vec<&str> = lines[..block_start]
+        .iter()
+        .filter(|line| !line.starts_with(comment_prefix))
+        .copied()
+        .collect();
+    let block: Vec<&str> = lines[block_start..]
+        .iter()
+        .copied()
+        .skip_while(|line| line.trim().is_empty())
+        .collect();
+
+    let join = |lines: &[&str]| {
+        if lines.is_empty() {
+            String::new()
+        } else {
+            format!("{}\n", lines.join("\n"))
+        }
+    };
+    (join(&message), join(&block))
+}
+
+fn hooks_dir(repo: &Repository) -> Result<PathBuf> {
+    if let Ok(path) = repo.config()?.get_path("core.hooksPath") {
+        if path.is_absolute() {
+            return Ok(path);
+        }
+        if let Some(workdir) = repo.workdir() {
+            return Ok(workdir.join(path));
+        }
+    }
+    // Linked worktrees share the hooks of the main repository
+    let common_dir = fs::read_to_string(repo.path().join("commondir"))
+        .map(|dir| repo.path().join(dir.trim()))
+        .unwrap_or_else(|_| repo.path().to_path_buf());
+    Ok(common_dir.join("hooks"))
+}
+
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+fn write_executable(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use std::io::{self, BufRead, Write};
+
+use crate::changes::{FileChange, Hunk};
+use crate::style::Painter;
+use crate::wdiff::word_diff;
+use crate::Result;
+
+const SHORTER_PROMPT: &str =
+    "Make the commit message shorter: keep the title and only the most important bullet points.";
+const LONGER_PROMPT: &str =
+    "Make the commit message more detailed: describe each change more thoroughly in the body.";
+const CHANGE_TYPE_PROMPT: &str = "Reconsider what kind of change this is (fix, feature, refactor, performance, docs or chore) and reframe the title and body accordingly.";
+const BREAKING_PROMPT: &str = "Add a `BREAKING CHANGE:` footer to the commit message that describes what is incompatible and how to migrate.";
+
+pub enum Action {
+    Accept,
+    Regenerate(Option<String>),
+    Quit,
+}
+
+/// Shows `message` and keeps regenerating it until it is accepted or the user quits.
+/// Every suggestion that was not accepted is collected in `rejected`.
+/// All interaction happens on stderr so stdout only ever carries the accepted message.
+pub fn review_loop(
+    painter: &Painter,
+    message: String,
+    rejected: &mut Vec<String>,
+    mut regenerate: impl FnMut(&str, Option<&str>) -> Result<String>,
+) -> Result<Option<String>> {
+    let mut current = message;
+    eprintln!("{}\n", painter.message(&current));
+
+    loop {
+        match ask_action()? {
+            Action::Accept => return Ok(Some(current)),
+            Action::Quit => {
+                rejected.push(current);
+                return Ok(None);
+            }
+            Action::Regenerate(feedback) => {
+                rejected.push(current.clone());
+                let next = regenerate(&current, feedback.as_deref())?;
+                eprintln!("{}\n", word_diff(painter, &current, &next));
+                current = next;
+            }
+        }
+    }
+}
+
+fn ask_action() -> Result<Action> {
+    loop {
+        eprint!("[a]ccept, [r]egenerate, [1/s]horter, [2/l]onger, [3/t]ype, [4/b]reaking, [q]uit, or type feedback: ");
+        io::stderr().flush().ok();
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+            return Ok(Action::Quit);
+        }
+
+        return Ok(match line.trim() {
+            "" => continue,
+            "a" | "accept" => Action::Accept,
+            "q" | "quit" => Action::Quit,
+            "r" | "regenerate" => Action::Regenerate(None),
+            "1" | "s" => Action::Regenerate(Some(SHORTER_PROMPT.to_string())),
+            "2" | "l" => Action::Regenerate(Some(LONGER_PROMPT.to_string())),
+            "3" | "t" => Action::Regenerate(Some(CHANGE_TYPE_PROMPT.to_string())),
+            "4" | "b" => Action::Regenerate(Some(BREAKING_PROMPT.to_string())),
+            feedback => Action::Regenerate(Some(feedback.to_string())),
+        });
+    }
+}
+
+/// Lists `candidates` numbered from 1, on stderr like the rest of the interaction.
+pub fn show_candidates(painter: &Painter, candidates: &[String]) {
+    for (index, candidate) in candidates.iter().enumerate() {
+        eprintln!("[{}]\n{}\n", index + 1, painter.message(candidate));
+    }
+}
+
+/// Asks which of `count` candidates to continue with, the first on an empty answer.
+pub fn pick_candidate(count: usize) -> usize {
+    loop {
+        eprint!("Use which candidate [1-{}]: ", count);
+        io::stderr().flush().ok();
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+            return 0;
+        }
+        match line.trim() {
+            "" => return 0,
+            answer => match answer.parse::<usize>() {
+                Ok(number) if (1..=count).contains(&number) => return number - 1,
+                _ => eprintln!("Answer with a number from 1 to {}", count),
+            },
+        }
+    }
+}
+
+/// What a file or hunk is to the message.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Describe,
+    /// Shown to the model, which is told not to describe it
+    Context,
+    /// Left out of the prompt
+    Exclude,
+}
+
+impl Mark {
+    fn label(self) -> &'static str {
+        match self {
+            Mark::Describe => "describe",
+            Mark::Context => "context",
+            Mark::Exclude => "exclude",
+        }
+    }
+}
+
+/// Lets the user mark files and hunks, numbered like `2` and `2.1`, as context only or
+/// excluded before the message is generated, and returns the changes as marked.
+pub fn mark_changes(changes: Vec<FileChange>) -> Vec<FileChange> {
+    let mut marks: Vec<(Mark, Vec<Mark>)> = changes
+        .iter()
+        .map(|change| (Mark::Describe, vec![Mark::Describe; change.hunks.len()]))
+        .collect();
+
+    loop {
+        eprintln!("Mark what the message describes:");
+        for (index, (change, (mark, hunk_marks))) in changes.iter().zip(&marks).enumerate() {
+            eprintln!(
+                "  {:<6} [{}] {} ({})",
+                index + 1,
+                mark.label(),
+                change.file_path,
+                change.change_type
+            );
+            // A file with one hunk is marked as a whole
+            if change.hunks.len() > 1 {
+                for (hunk, (hunk_mark, Hunk { header, .. })) in
+                    hunk_marks.iter().zip(&change.hunks).enumerate()
+                {
+                    let number = format!("{}.{}", index + 1, hunk + 1);
+                    eprintln!("    {:<6} [{}] {}", number, hunk_mark.label(), header);
+                }
+            }
+        }
+        eprint!("[c]ontext only, e[x]clude or [i]nclude, followed by numbers like 2 or 2.1, or [d]one: ");
+        io::stderr().flush().ok();
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let mark = match words.next() {
+            None | Some("d" | "done") => break,
+            Some("c" | "context") => Mark::Context,
+            Some("x" | "exclude") => Mark::Exclude,
+            Some("i" | "include") => Mark::Describe,
+            Some(other) => {
+                eprintln!("Unknown command {}", other);
+                continue;
+            }
+        };
+        for number in words {
+            let target = match number.split_once('.') {
+                Some((file, hunk)) => file
+                    .parse::<usize>()
+                    .ok()
+                    .zip(hunk.parse::<usize>().ok().map(Some)),
+                None => number.parse::<usize>().ok().map(|file| (file, None)),
+            };
+            let entry = target.and_then(|(file, hunk)| {
+                let (file_mark, hunk_marks) = marks.get_mut(file.checked_sub(1)?)?;
+                match hunk {
+                    Some(hunk) => hunk_marks.get_mut(hunk.checked_sub(1)?),
+                    None => Some(file_mark),
+                }
+            });
+            match entry {
+                Some(entry) => *entry = mark,
+                None => eprintln!("No file or hunk {}", number),
+            }
+        }
+    }
+
+    changes
+        .into_iter()
+        .zip(marks)
+        .flat_map(|(change, (mark, hunk_marks))| apply(change, mark, &hunk_marks))
+        .collect()
+}
+
+/// `change` as marked: left out, as a whole, or split into the hunks to describe and
+/// those for context.
+fn apply(change: FileChange, mark: Mark, hunk_marks: &[Mark]) -> Vec<FileChange> {
+    match mark {
+        Mark::Exclude => return Vec::new(),
+        Mark::Context => {
+            return vec![FileChange {
+                context_only: true,
+                ..change
+            }]
+        }
+        Mark::Describe if hunk_marks.iter().all(|mark| *mark == Mark::Describe) => {
+            return vec![change]
+        }
+        Mark::Describe => {}
+    }
+
+    let mut described = FileChange {
+        summaries: Vec::new(),
+        hunks: Vec::new(),
+        ..change.clone()
+    };
+    let mut context = FileChange {
+        summaries: Vec::new(),
+        hunks: Vec::new(),
+        notes: Vec::new(),
+        context_only: true,
+        ..change.clone()
+    };
+    // Lines before the first hunk, like where a file was renamed from, belong to the file
+    let first = change
+        .hunks
+        .first()
+        .map_or(change.summaries.len(), |hunk| hunk.start);
+    described
+        .summaries
+        .extend_from_slice(&change.summaries[..first]);
+    for (index, hunk) in change.hunks.iter().enumerate() {
+        let end = change
+            .hunks
+            .get(index + 1)
+            .map_or(change.summaries.len(), |next| next.start);
+        let target = match hunk_marks[index] {
+            Mark::Describe => &mut described,
+            Mark::Context => &mut context,
+            Mark::Exclude => continue,
+        };
+        target.hunks.push(Hunk {
+            header: hunk.header.clone(),
+            start: target.summaries.len(),
+            added: hunk.added,
+            removed: hunk.removed,
+        });
+        target
+            .summaries
+            .extend_from_slice(&change.summaries[hunk.start..end]);
+    }
+
+    described.recount();
+    context.recount();
+    let mut marked = Vec::new();
+    if !described.hunks.is_empty() || !described.summaries.is_empty() {
+        marked.push(described);
+    }
+    if !context.hunks.is_empty() {
+        marked.push(context);
+    }
+    marked
+}
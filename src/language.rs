@@ -0,0 +1,390 @@
+// Messages are written in English unless `--languages` names others. With two or more the
+// message has a section per language, each with its own title and body: the first gives
+// the commit its title and the others open with a heading of their own, like `[中文]`.
+// Languages are named by their ISO 639-1 codes. Without any configured, the language most
+// recent commit messages are written in is used.
+
+use git2::Repository;
+
+/// The languages messages can be written in: code, English name and own name
+const LANGUAGES: &[(&str, &str, &str)] = &[
+    ("en", "English", "English"),
+    ("zh", "Chinese", "中文"),
+    ("ja", "Japanese", "日本語"),
+    ("ko", "Korean", "한국어"),
+    ("de", "German", "Deutsch"),
+    ("fr", "French", "Français"),
+    ("es", "Spanish", "Español"),
+    ("pt", "Portuguese", "Português"),
+    ("it", "Italian", "Italiano"),
+    ("nl", "Dutch", "Nederlands"),
+    ("sv", "Swedish", "Svenska"),
+    ("fi", "Finnish", "Suomi"),
+    ("pl", "Polish", "Polski"),
+    ("ru", "Russian", "Русский"),
+    ("uk", "Ukrainian", "Українська"),
+];
+
+/// Recent commits whose messages are looked at to detect the language
+const RECENT_COMMITS: usize = 50;
+/// Fewer messages in a language do not make it the repository's
+const MIN_MESSAGES: usize = 3;
+
+/// Words common in commit messages in each language written in the Latin script
+const COMMON_WORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "of", "to", "for", "with", "is", "from", "add", "fix", "remove",
+            "update", "when",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der",
+            "die",
+            "das",
+            "und",
+            "für",
+            "mit",
+            "nicht",
+            "ist",
+            "von",
+            "zu",
+            "auf",
+            "wenn",
+            "hinzugefügt",
+            "entfernt",
+            "behoben",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le",
+            "les",
+            "des",
+            "et",
+            "pour",
+            "avec",
+            "dans",
+            "du",
+            "une",
+            "est",
+            "ajout",
+            "ajoute",
+            "correction",
+            "supprime",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "del", "y", "para", "con", "una", "por", "añade", "agrega",
+            "corrige", "elimina",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "os", "do", "da", "para", "com", "em", "uma", "não", "adiciona", "corrige",
+            "remove",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "gli", "della", "per", "di", "una", "che", "aggiunge", "aggiunto", "corretto",
+            "rimuove",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "het",
+            "een",
+            "en",
+            "voor",
+            "met",
+            "van",
+            "niet",
+            "bij",
+            "toegevoegd",
+            "verwijderd",
+            "opgelost",
+        ],
+    ),
+    (
+        "sv",
+        &[
+            "och", "för", "med", "att", "av", "till", "den", "det", "inte", "lägg", "fixa", "ta",
+        ],
+    ),
+    (
+        "fi",
+        &[
+            "ja",
+            "on",
+            "ei",
+            "että",
+            "kun",
+            "lisää",
+            "korjaa",
+            "poista",
+            "päivitä",
+        ],
+    ),
+    (
+        "pl",
+        &[
+            "i", "w", "z", "na", "dla", "nie", "się", "dodaj", "popraw", "usuń",
+        ],
+    ),
+];
+
+/// The writing systems the languages are told apart by.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Cyrillic,
+    Han,
+    Kana,
+    Hangul,
+}
+
+/// A language from [`LANGUAGES`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Language {
+    code: &'static str,
+    name: &'static str,
+    native: &'static str,
+}
+
+impl Language {
+    /// The language with the ISO 639-1 `code`, as `--languages` takes it.
+    pub fn parse(code: &str) -> Result<Self, String> {
+        let code = code.trim().to_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|(known, _, _)| *known == code)
+            .map(|&(code, name, native)| Language { code, name, native })
+            .ok_or_else(|| {
+                let known: Vec<&str> = LANGUAGES.iter().map(|(code, _, _)| *code).collect();
+                format!(
+                    "unknown language `{}`; expected one of {}",
+                    code,
+                    known.join(", ")
+                )
+            })
+    }
+
+    /// The English name of the language.
+    pub fn name(self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_english(self) -> bool {
+        self.code == "en"
+    }
+
+    /// The line that opens the section in this language, after the first.
+    pub fn heading(self) -> String {
+        format!("[{}]", self.native)
+    }
+
+    /// Whether `text` is mostly written in the script of this language, going by words,
+    /// so that the names of files and functions count little. Languages that share a
+    /// script, like English and German, are not told apart.
+    fn is_written_in(self, text: &str) -> bool {
+        let words = words(text);
+        let matching = words
+            .iter()
+            .filter(|script| self.scripts().contains(script))
+            .count();
+        matching * 2 >= words.len()
+    }
+
+    fn scripts(self) -> &'static [Script] {
+        match self.code {
+            "zh" => &[Script::Han],
+            "ja" => &[Script::Han, Script::Kana],
+            "ko" => &[Script::Hangul, Script::Han],
+            "ru" | "uk" => &[Script::Cyrillic],
+            _ => &[Script::Latin],
+        }
+    }
+}
+
+/// The script of a letter; anything else has none.
+fn script(c: char) -> Option<Script> {
+    Some(match c {
+        'a'..='z' | 'A'..='Z' | '\u{c0}'..='\u{24f}' => Script::Latin,
+        '\u{400}'..='\u{4ff}' => Script::Cyrillic,
+        '\u{3040}'..='\u{30ff}' => Script::Kana,
+        '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' => Script::Han,
+        '\u{ac00}'..='\u{d7af}' | '\u{1100}'..='\u{11ff}' => Script::Hangul,
+        _ => return None,
+    })
+}
+
+/// The script of every word of `text`. Han and kana are written without spaces, so each
+/// of their characters counts as a word.
+fn words(text: &str) -> Vec<Script> {
+    let mut words = Vec::new();
+    let mut previous = None;
+    for c in text.chars() {
+        let current = script(c);
+        if let Some(current) = current {
+            if matches!(current, Script::Han | Script::Kana) || previous != Some(current) {
+                words.push(current);
+            }
+        }
+        previous = current;
+    }
+    words
+}
+
+/// The language most of the recent commit messages of `repo` are written in, if most
+/// of those it can tell are in the same one.
+pub fn detect(repo: &Repository) -> Option<Language> {
+    let mut walk = repo.revwalk().ok()?;
+    walk.push_head().ok()?;
+    let mut counts: Vec<(Language, usize)> = Vec::new();
+    let mut detected = 0;
+    for oid in walk.take(RECENT_COMMITS) {
+        let Ok(commit) = oid.and_then(|oid| repo.find_commit(oid)) else {
+            continue;
+        };
+        let Some(language) = commit.message().and_then(detect_message) else {
+            continue;
+        };
+        detected += 1;
+        match counts.iter_mut().find(|(counted, _)| *counted == language) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((language, 1)),
+        }
+    }
+    let (language, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+    (count >= MIN_MESSAGES && count * 2 > detected).then_some(language)
+}
+
+/// The language of a single message, by its script and then by its common words.
+fn detect_message(message: &str) -> Option<Language> {
+    let words = words(message);
+    let count = |script| words.iter().filter(|word| **word == script).count();
+    let code = if count(Script::Hangul) * 2 > words.len() {
+        "ko"
+    } else if count(Script::Kana) > 0
+        && (count(Script::Kana) + count(Script::Han)) * 2 > words.len()
+    {
+        "ja"
+    } else if count(Script::Han) * 2 > words.len() {
+        "zh"
+    } else if count(Script::Cyrillic) * 2 > words.len() {
+        if message.chars().any(|c| matches!(c, 'і' | 'ї' | 'є' | 'ґ')) {
+            "uk"
+        } else {
+            "ru"
+        }
+    } else {
+        let lowercase = message.to_lowercase();
+        let text: Vec<&str> = lowercase.split(|c: char| !c.is_alphabetic()).collect();
+        let scores: Vec<(&str, usize)> = COMMON_WORDS
+            .iter()
+            .map(|(code, common)| {
+                (
+                    *code,
+                    text.iter().filter(|word| common.contains(word)).count(),
+                )
+            })
+            .collect();
+        let best = scores.iter().map(|(_, score)| *score).max()?;
+        let mut leaders = scores.iter().filter(|(_, score)| *score == best);
+        match (leaders.next(), leaders.next()) {
+            (Some(&(code, _)), None) if best > 0 => code,
+            _ => return None,
+        }
+    };
+    Language::parse(code).ok()
+}
+
+/// Prompt instruction for writing in `languages`, or `None` for English alone.
+pub fn instruction(languages: &[Language]) -> Option<String> {
+    match languages {
+        [] => None,
+        [language] if language.is_english() => None,
+        [language] => Some(format!("Write the message in {}.", language.name)),
+        [first, others @ ..] => {
+            let sections: Vec<String> = others
+                .iter()
+                .map(|language| {
+                    format!(
+                        "a line of only `{}`, then the message in {}",
+                        language.heading(),
+                        language.name
+                    )
+                })
+                .collect();
+            Some(format!(
+                "Write the message in {} first. After it, leave a blank line and add {}, each with its own title and body \
+saying the same as the first, separated by blank lines.",
+                first.name,
+                sections.join(", then ")
+            ))
+        }
+    }
+}
+
+/// The sections of `message` in each of `languages`, in order. The first language has
+/// the text before the first heading; a language whose heading is missing is left out.
+pub fn sections(message: &str, languages: &[Language]) -> Vec<(Language, String)> {
+    let mut sections: Vec<(Language, String)> = Vec::new();
+    let mut current = languages.first().copied();
+    let mut text = String::new();
+    for line in message.lines() {
+        if let Some(&language) = languages
+            .iter()
+            .skip(1)
+            .find(|language| line.trim() == language.heading())
+        {
+            if let Some(previous) = current {
+                sections.push((previous, text.trim().to_string()));
+            }
+            current = Some(language);
+            text.clear();
+            continue;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    if let Some(language) = current {
+        sections.push((language, text.trim().to_string()));
+    }
+    sections
+}
+
+/// What is missing from the sections of `message`, or written in the wrong language.
+pub fn problems(message: &str, languages: &[Language]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let sections = sections(message, languages);
+    for &language in languages {
+        match sections.iter().find(|(section, _)| *section == language) {
+            None => problems.push(format!(
+                "Add the message in {} after a line of only `{}`.",
+                language.name,
+                language.heading()
+            )),
+            Some((_, text)) if text.is_empty() => problems.push(format!(
+                "The {} section is empty; write the message in {} there.",
+                language.name, language.name
+            )),
+            Some((_, text)) if !language.is_written_in(text) => problems.push(format!(
+                "Write the {} section in {}.",
+                language.name, language.name
+            )),
+            Some(_) => {}
+        }
+    }
+    problems
+}
@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+use std::fs::File;
+#[cfg(builtin_model)]
+use std::io::Cursor;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use candle_core::quantized::gguf_file;
+use candle_core::{Device, Tensor};
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::{quantized_llama, quantized_qwen2};
+use tokenizers::Tokenizer;
+
+use crate::chat_template::Family;
+use crate::openai::Message;
+use crate::{CommitGPTError, Result};
+
+const TOKENIZER_FILE: &str = "tokenizer.json";
+const MAX_NEW_TOKENS: usize = 512;
+const CANDIDATE_TEMPERATURE: f64 = 0.7;
+const CANDIDATE_TOP_P: f64 = 0.9;
+const STOP_TOKENS: [&str; 4] = ["<|im_end|>", "<|eot_id|>", "<|endoftext|>", "</s>"];
+
+// Chosen at build time, so the binary needs nothing else to run; see build.rs
+#[cfg(builtin_model)]
+const BUILTIN_NAME: &str = "the builtin model";
+#[cfg(builtin_model)]
+static BUILTIN_WEIGHTS: &[u8] = include_bytes!(env!("COMMIT_GPT_BUILTIN_MODEL"));
+#[cfg(builtin_model)]
+static BUILTIN_TOKENIZER: &[u8] = include_bytes!(env!("COMMIT_GPT_BUILTIN_TOKENIZER"));
+
+enum Weights {
+    Llama(quantized_llama::ModelWeights),
+    Qwen2(quantized_qwen2::ModelWeights),
+}
+
+impl Weights {
+    fn forward(&mut self, input: &Tensor, position: usize) -> candle_core::Result<Tensor> {
+        match self {
+            Weights::Llama(model) => model.forward(input, position),
+            Weights::Qwen2(model) => model.forward(input, position),
+        }
+    }
+}
+
+/// A quantized GGUF model run on the CPU, for machines that cannot reach any API.
+/// The tokenizer is read from `tokenizer.json` next to the model file.
+pub struct LocalModel {
+    weights: RefCell<Weights>,
+    family: Family,
+    tokenizer: Tokenizer,
+    stop_tokens: Vec<u32>,
+    device: Device,
+}
+
+impl LocalModel {
+    /// Loads a llama or qwen2 family model, e.g. a quantized Qwen2.5-Coder-3B-Instruct.
+    pub fn load(path: &Path) -> Result<Self> {
+        let path_display = path.display().to_string();
+        let mut file = File::open(path).map_err(|e| {
+            CommitGPTError::LocalModelLoadError(path_display.clone(), e.to_string())
+        })?;
+        let tokenizer_path = path.with_file_name(TOKENIZER_FILE);
+        let tokenizer = Tokenizer::from_file(&tokenizer_path).map_err(|e| {
+            CommitGPTError::LocalModelLoadError(tokenizer_path.display().to_string(), e.to_string())
+        })?;
+        Self::from_gguf(&path_display, &mut file, tokenizer)
+    }
+
+    /// The model compiled into the binary, for `--provider builtin`.
+    #[cfg(builtin_model)]
+    pub fn builtin() -> Result<Self> {
+        let tokenizer = Tokenizer::from_bytes(BUILTIN_TOKENIZER).map_err(|e| {
+            CommitGPTError::LocalModelLoadError(BUILTIN_NAME.to_string(), e.to_string())
+        })?;
+        Self::from_gguf(BUILTIN_NAME, &mut Cursor::new(BUILTIN_WEIGHTS), tokenizer)
+    }
+
+    /// Built with the builtin feature but without a model to compile in.
+    #[cfg(all(feature = "builtin", not(builtin_model)))]
+    pub fn builtin() -> Result<Self> {
+        Err(CommitGPTError::BuiltinModelMissing)
+    }
+
+    fn from_gguf<R: Read + Seek>(name: &str, reader: &mut R, tokenizer: Tokenizer) -> Result<Self> {
+        let load_error = |e: String| CommitGPTError::LocalModelLoadError(name.to_string(), e);
+        let device = Device::Cpu;
+
+        let content = gguf_file::Content::read(reader).map_err(|e| load_error(e.to_string()))?;
+        let architecture = content
+            .metadata
+            .get("general.architecture")
+            .and_then(|value| value.to_string().ok())
+            .cloned()
+            .unwrap_or_default();
+        let weights = match architecture.as_str() {
+            "llama" => quantized_llama::ModelWeights::from_gguf(content, reader, &device)
+                .map(Weights::Llama),
+            "qwen2" => quantized_qwen2::ModelWeights::from_gguf(content, reader, &device)
+                .map(Weights::Qwen2),
+            other => return Err(load_error(format!("unsupported architecture {:?}", other))),
+        }
+        .map_err(|e| load_error(e.to_string()))?;
+
+        let stop_tokens = STOP_TOKENS
+            .iter()
+            .filter_map(|token| tokenizer.token_to_id(token))
+            .collect();
+
+        Ok(LocalModel {
+            weights: RefCell::new(weights),
+            family: Family::detect(&architecture),
+            tokenizer,
+            stop_tokens,
+            device,
+        })
+    }
+
+    /// Generates `n` replies, sampled when more than one is requested and greedy otherwise.
+    pub fn complete_n(
+        &self,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+    ) -> Result<Vec<String>> {
+        let max_tokens =
+            max_tokens.map_or(MAX_NEW_TOKENS, |max_tokens| max_tokens.min(MAX_NEW_TOKENS));
+        let prompt = self.family.render(messages);
+        let encoding = self
+            .tokenizer
+            .encode(prompt, false)
+            .map_err(|e| CommitGPTError::LocalInferenceError(e.to_string()))?;
+
+        let mut replies = Vec::with_capacity(n);
+        for seed in 0..n as u64 {
+            let sampling = (n > 1).then_some(CANDIDATE_TEMPERATURE);
+            let mut logits =
+                LogitsProcessor::new(seed, sampling, sampling.map(|_| CANDIDATE_TOP_P));
+            let tokens = self
+                .generate(encoding.get_ids(), &mut logits, max_tokens)
+                .map_err(|e| CommitGPTError::LocalInferenceError(e.to_string()))?;
+            let reply = self
+                .tokenizer
+                .decode(&tokens, true)
+                .map_err(|e| CommitGPTError::LocalInferenceError(e.to_string()))?;
+            let reply = reply.trim().to_string();
+            if !reply.is_empty() {
+                replies.push(reply);
+            }
+        }
+        if replies.is_empty() {
+            return Err(CommitGPTError::NoCommitMessage);
+        }
+        Ok(replies)
+    }
+
+    fn generate(
+        &self,
+        prompt: &[u32],
+        logits: &mut LogitsProcessor,
+        max_tokens: usize,
+    ) -> candle_core::Result<Vec<u32>> {
+        let mut weights = self.weights.borrow_mut();
+        // Position 0 discards the key-value cache of the previous call
+        let input = Tensor::new(prompt, &self.device)?.unsqueeze(0)?;
+        let mut next = logits.sample(&weights.forward(&input, 0)?.squeeze(0)?)?;
+
+        let mut tokens = Vec::new();
+        while !self.stop_tokens.contains(&next) && tokens.len() < max_tokens {
+            tokens.push(next);
+            let input = Tensor::new(&[next], &self.device)?.unsqueeze(0)?;
+            let output = weights.forward(&input, prompt.len() + tokens.len() - 1)?;
+            next = logits.sample(&output.squeeze(0)?)?;
+        }
+        Ok(tokens)
+    }
+}
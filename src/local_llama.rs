@@ -0,0 +1,152 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::model::{LlamaChatMessage, LlamaModel};
+use llama_cpp_2::sampling::LlamaSampler;
+use llama_cpp_2::token::LlamaToken;
+
+use crate::chat_template::Family;
+use crate::openai::Message;
+use crate::{CommitGPTError, Result};
+
+const MAX_NEW_TOKENS: usize = 512;
+const MAX_BATCH: usize = 2048;
+const CANDIDATE_TEMPERATURE: f32 = 0.7;
+const CANDIDATE_TOP_P: f32 = 0.9;
+
+/// A user-provided GGUF model run through llama.cpp, which covers more architectures
+/// and quantizations than the candle backend.
+pub struct LlamaLocalModel {
+    backend: LlamaBackend,
+    model: LlamaModel,
+    family: Family,
+}
+
+impl LlamaLocalModel {
+    pub fn load(path: &Path) -> Result<Self> {
+        let load_error =
+            |e: String| CommitGPTError::LocalModelLoadError(path.display().to_string(), e);
+        if !path.is_file() {
+            return Err(load_error("no such file".to_string()));
+        }
+
+        let backend = LlamaBackend::init().map_err(|e| load_error(e.to_string()))?;
+        let model = LlamaModel::load_from_file(&backend, path, &LlamaModelParams::default())
+            .map_err(|e| load_error(e.to_string()))?;
+        let architecture = model
+            .meta_val_str("general.architecture")
+            .unwrap_or_default();
+        Ok(LlamaLocalModel {
+            backend,
+            model,
+            family: Family::detect(&architecture),
+        })
+    }
+
+    /// Generates `n` replies, sampled when more than one is requested and greedy otherwise.
+    pub fn complete_n(
+        &self,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+    ) -> Result<Vec<String>> {
+        let max_tokens =
+            max_tokens.map_or(MAX_NEW_TOKENS, |max_tokens| max_tokens.min(MAX_NEW_TOKENS));
+        let inference_error = |e: String| CommitGPTError::LocalInferenceError(e);
+
+        let prompt = self.render(messages);
+        let tokens = self.model.vocab().tokenize(prompt.as_bytes(), false, true);
+        let context_size = NonZeroU32::new((tokens.len() + max_tokens) as u32);
+        let params = LlamaContextParams::default()
+            .with_n_ctx(context_size)
+            .with_n_batch(tokens.len().clamp(1, MAX_BATCH) as u32);
+        let mut context = self
+            .model
+            .new_context(&self.backend, params)
+            .map_err(|e| inference_error(e.to_string()))?;
+
+        let mut replies = Vec::with_capacity(n);
+        for seed in 0..n as u32 {
+            let mut sampler = if n > 1 {
+                LlamaSampler::chain_simple([
+                    LlamaSampler::temp(CANDIDATE_TEMPERATURE),
+                    LlamaSampler::top_p(CANDIDATE_TOP_P, 1),
+                    LlamaSampler::dist(seed),
+                ])
+            } else {
+                LlamaSampler::greedy()
+            };
+            let reply = self
+                .generate(&mut context, &tokens, &mut sampler, max_tokens)
+                .map_err(inference_error)?;
+            let reply = reply.trim().to_string();
+            if !reply.is_empty() {
+                replies.push(reply);
+            }
+        }
+        if replies.is_empty() {
+            return Err(CommitGPTError::NoCommitMessage);
+        }
+        Ok(replies)
+    }
+
+    /// Prefers the chat template embedded in the GGUF file and falls back to the family's format.
+    fn render(&self, messages: &[Message]) -> String {
+        let embedded = self.model.chat_template(None).ok().and_then(|template| {
+            let chat = messages
+                .iter()
+                .map(|message| LlamaChatMessage::new(message.role.clone(), message.content.clone()))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .ok()?;
+            self.model.apply_chat_template(&template, &chat, true).ok()
+        });
+        embedded.unwrap_or_else(|| self.family.render(messages))
+    }
+
+    fn generate(
+        &self,
+        context: &mut LlamaContext<'_>,
+        prompt: &[LlamaToken],
+        sampler: &mut LlamaSampler,
+        max_tokens: usize,
+    ) -> std::result::Result<String, String> {
+        let vocab = self.model.vocab();
+        context.clear_kv_cache();
+
+        let mut batch = LlamaBatch::new(prompt.len().max(1), 1);
+        for (chunk_start, chunk) in prompt.chunks(MAX_BATCH).enumerate() {
+            batch.clear();
+            for (offset, &token) in chunk.iter().enumerate() {
+                let position = chunk_start * MAX_BATCH + offset;
+                let is_last = position + 1 == prompt.len();
+                batch
+                    .add(token, position as i32, &[0], is_last)
+                    .map_err(|e| e.to_string())?;
+            }
+            context.decode(&mut batch).map_err(|e| e.to_string())?;
+        }
+
+        let mut reply = Vec::new();
+        let mut position = prompt.len();
+        for _ in 0..max_tokens {
+            let token = sampler.sample(context, batch.n_tokens() - 1);
+            if vocab.is_eog(token) {
+                break;
+            }
+            reply.extend(vocab.token_to_piece(token, false, None));
+
+            batch.clear();
+            batch
+                .add(token, position as i32, &[0], true)
+                .map_err(|e| e.to_string())?;
+            context.decode(&mut batch).map_err(|e| e.to_string())?;
+            position += 1;
+        }
+        Ok(String::from_utf8_lossy(&reply).into_owned())
+    }
+}
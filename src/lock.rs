@@ -0,0 +1,187 @@
+// Hooks can run commit-gpt several times at once, e.g. during a rebase with autosquash.
+// Writes to the caches are serialized with a lock file, and runs over the same changes
+// share one request to the model instead of each making their own.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use git2::{ObjectType, Oid};
+
+use crate::{CommitGPTError, Result};
+
+const LOCK_DIR: &str = "commit-gpt";
+const LOCK_FILE: &str = "lock";
+const IN_FLIGHT_DIR: &str = "in-flight";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Results are only read by runs that were already waiting, so they need not last long
+const RESULT_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Seconds a run waits for another one on the same repository, unless the config sets `lock_timeout`.
+pub const DEFAULT_LOCK_TIMEOUT: u64 = 30;
+
+/// Exclusive access to the caches in the git directory, released when dropped.
+pub struct RepoLock {
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// Waits up to `timeout` for other runs to release the lock. A lock older than that
+    /// is left over from a run that crashed, and is taken over.
+    pub fn acquire(git_dir: &Path, timeout: Duration) -> Result<Self> {
+        let dir = git_dir.join(LOCK_DIR);
+        let path = dir.join(LOCK_FILE);
+        fs::create_dir_all(&dir)
+            .map_err(|e| CommitGPTError::LockError(dir.display().to_string(), e))?;
+        let started = SystemTime::now();
+        loop {
+            match create_marker(&path) {
+                Ok(()) => return Ok(RepoLock { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if age(&path).is_some_and(|age| age > timeout) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed().unwrap_or_default() > timeout {
+                        return Err(CommitGPTError::LockTimeout(path.display().to_string()));
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Err(e) => return Err(CommitGPTError::LockError(path.display().to_string(), e)),
+            }
+        }
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// What a run does about a request another run may already be making.
+pub enum InFlight {
+    /// Make the request and publish the result with [`Claim::finish`]
+    Owner(Claim),
+    /// Use the message a concurrent run generated for the same request
+    Reused(String),
+}
+
+/// The right to make a request, released (without a result) when dropped.
+pub struct Claim {
+    pending: Option<PathBuf>,
+    result: PathBuf,
+}
+
+impl Claim {
+    /// Hands `message` to the runs waiting for this request.
+    pub fn finish(mut self, message: &str) {
+        let partial = self.result.with_extension("partial");
+        // Renamed into place so waiters never read half a message
+        if fs::write(&partial, message).is_ok() {
+            let _ = fs::rename(&partial, &self.result);
+        }
+        if let Some(pending) = self.pending.take() {
+            let _ = fs::remove_file(pending);
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            let _ = fs::remove_file(pending);
+        }
+    }
+}
+
+/// Identifies a request by everything that decides its reply.
+pub fn request_key(model: &str, prompt: &str) -> String {
+    Oid::hash_object(
+        ObjectType::Blob,
+        format!("{}\n{}", model, prompt).as_bytes(),
+    )
+    .map(|oid| oid.to_string())
+    .unwrap_or_default()
+}
+
+/// Claims the request `key`, or waits up to `timeout` for the run that already claimed
+/// it and reuses its message. When that run fails or takes too long, this one makes the
+/// request itself.
+pub fn claim(git_dir: &Path, key: &str, timeout: Duration) -> Result<InFlight> {
+    let dir = git_dir.join(LOCK_DIR).join(IN_FLIGHT_DIR);
+    fs::create_dir_all(&dir)
+        .map_err(|e| CommitGPTError::LockError(dir.display().to_string(), e))?;
+    remove_old_results(&dir);
+
+    let pending = dir.join(format!("{}.pending", key));
+    let result = dir.join(format!("{}.message", key));
+    let started = SystemTime::now();
+    loop {
+        match create_marker(&pending) {
+            Ok(()) => {
+                let _ = fs::remove_file(&result);
+                return Ok(InFlight::Owner(Claim {
+                    pending: Some(pending),
+                    result,
+                }));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                // Left over from a run that crashed
+                if age(&pending).is_some_and(|age| age > timeout) {
+                    let _ = fs::remove_file(&pending);
+                    continue;
+                }
+                if started.elapsed().unwrap_or_default() > timeout {
+                    return Ok(InFlight::Owner(Claim {
+                        pending: None,
+                        result,
+                    }));
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(e) => return Err(CommitGPTError::LockError(pending.display().to_string(), e)),
+        }
+        // Only a result that appeared while this run waited answers its request
+        if !pending.exists() {
+            if let Ok(message) = fs::read_to_string(&result) {
+                if age(&result).is_some_and(|age| age <= started.elapsed().unwrap_or_default()) {
+                    return Ok(InFlight::Reused(message));
+                }
+            }
+        }
+    }
+}
+
+/// Creates `path` unless it exists, recording the process that holds it.
+fn create_marker(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    writeln!(file, "{}", process::id())
+}
+
+fn age(path: &Path) -> Option<Duration> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?
+        .elapsed()
+        .ok()
+}
+
+fn remove_old_results(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "message")
+            && age(&path).is_some_and(|age| age > RESULT_LIFETIME)
+        {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -1,77 +1,442 @@
-use std::fs;
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use reqwest::blocking::Client;
-use git2::{Repository, DiffOptions, DiffLine, Delta};
+mod anthropic;
+mod audit;
+mod auth;
+mod auto_model;
+mod azure;
+mod bedrock;
+mod budget;
+mod cache;
+mod changes;
+#[cfg(any(feature = "candle", feature = "local-llama"))]
+mod chat_template;
+mod client;
+mod cluster;
+mod commit;
+mod config;
+mod copilot;
+mod deadline;
+mod experiment;
+mod export;
+mod gemini;
+mod history;
+mod hook;
+mod injection;
+mod interactive;
+mod language;
+#[cfg(feature = "candle")]
+mod local;
+#[cfg(feature = "local-llama")]
+mod local_llama;
+mod lock;
+mod mass_edit;
+mod mock;
+mod models;
+mod offline;
+mod ollama;
+mod openai;
+mod openrouter;
+mod ping;
+#[cfg(feature = "polyglot")]
+mod polyglot;
+mod presets;
+mod privacy;
+mod prompt;
+mod provider;
+mod render;
+mod response;
+mod retry;
+mod secrets;
+mod select;
+mod server;
+mod sigv4;
+mod source;
+mod spending;
+mod structured;
+mod style;
+mod summarize;
+mod symbols;
+mod template;
+mod trailers;
+mod transcript;
+mod validate;
+mod verify;
+mod wdiff;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use git2::Repository;
+use regex::Regex;
 use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
-const OPENAI_API_URL: &str = "https://api.openai.com/v1/chat/completions";
-const SYSTEM_PROMPT: &str = "You are a helpful assistant that writes clear and concise Git commit messages in the imperative mood, without any speculation.";
-const USER_PROMPT_TEMPLATE: &str = "\
-Write a Git commit message with a short title and a detailed body, using the imperative mood. Do not include any speculation or guesses. Be concise and precise. Use bullet points in the body to list changes. Format the message as a git commit message with no extra metadata, symbols or quotes in a way that it can be directly copy pasted to the commit.
+use auto_model::AUTO_MODEL;
+use cache::{RejectedCache, SummaryCache};
+use changes::{format_changes_for_prompt, FileChange};
+use client::Client;
+use config::Config;
+use deadline::{Deadlines, Stage};
+use export::ExportFormat;
+use language::Language;
+use openai::Message;
+use prompt::{Intent, Prompt, PromptBuilder, REGENERATE_PROMPT};
+use provider::{ApiClient, ClientOptions, Provider, ProviderKind, Sampling};
+use render::OutputFormat;
+use select::{SelectStrategy, Selector};
+use source::DiffSource;
+use style::{ColorChoice, Painter};
+use transcript::Transcript;
+use validate::{Detail, Mood, Rules};
 
-Context: {context}
+const MAX_CORRECTIONS: usize = 2;
+const MAX_CONTEXT_RETRIES: usize = 3;
 
-Changes:
-{structured_changes}
-";
-
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to the OpenAI API key file
+    /// Path to the API key file, instead of the provider's environment variable like OPENAI_API_KEY
     #[arg(short, long, value_name = "FILE")]
-    api_key_path: String,
+    api_key_path: Option<String>,
+
+    /// API the prompts are sent to
+    #[arg(long, value_enum, default_value_t = ProviderKind::Openai)]
+    provider: ProviderKind,
+
+    /// Base URL of the provider's API, e.g. http://localhost:8000/v1 for vLLM or a LiteLLM proxy
+    #[arg(long, value_name = "URL")]
+    api_base: Option<String>,
+
+    /// Named endpoint from the `[endpoints]` config table, used instead of --provider
+    #[arg(long, value_name = "NAME", conflicts_with = "provider")]
+    endpoint: Option<String>,
+
+    /// Extra header sent with every request, e.g. `X-Team: tools` (repeatable)
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
 
     /// Additional context for the commit message
     #[arg(short, long, value_name = "CONTEXT")]
     context: Option<String>,
 
+    /// File with project context included in every prompt (defaults to .commit-gpt-context)
+    #[arg(long, value_name = "FILE")]
+    context_file: Option<PathBuf>,
+
     /// Path to the working directory (defaults to current directory)
     #[arg(short, long, value_name = "DIR", default_value = ".")]
     workdir_path: String,
 
-    /// OpenAI model to use (defaults to gpt-4)
+    /// Model to use (defaults to gpt-4, or the provider's own), or `auto` to pick one by the size of the change
     #[arg(short, long, value_name = "MODEL", default_value = "gpt-4")]
     model: String,
 
     /// Include unstaged changes (default is false)
     #[arg(short = 'u', long)]
     include_unstaged: bool,
-}
 
-#[derive(Serialize)]
-struct OpenAIRequest {
-    model: String,
-    messages: Vec<Message>,
-}
+    /// Only describe files in detail that changed since the previous run
+    #[arg(short = 'i', long)]
+    incremental: bool,
 
-#[derive(Serialize)]
-struct Message {
-    role: String,
-    content: String,
-}
+    /// Print the prompt instead of sending it, marking the parts over the model's budget
+    #[arg(long)]
+    show_prompt: bool,
 
-#[derive(Deserialize)]
-struct OpenAIResponse {
-    choices: Vec<Choice>,
-}
+    /// When to color the output (NO_COLOR is honored in auto mode)
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
 
-#[derive(Deserialize)]
-struct Choice {
-    message: MessageContent,
-}
+    /// Review the message and regenerate it with optional feedback before printing it
+    #[arg(short = 'I', long)]
+    interactive: bool,
+
+    /// Before generating, mark files and hunks as context only or to leave out (with --interactive)
+    #[arg(long, requires = "interactive")]
+    mark: bool,
+
+    /// Append a Signed-off-by trailer using the git config identity
+    #[arg(short, long)]
+    signoff: bool,
+
+    /// How the message is printed
+    #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+    output: OutputFormat,
+
+    /// Shorthand for `--output porcelain`, for git clients parsing the output
+    #[arg(long, conflicts_with = "output")]
+    porcelain: bool,
+
+    /// Commit the staged changes with the generated message
+    #[arg(long, conflicts_with = "include_unstaged")]
+    commit: bool,
+
+    /// Only describe the files listed on stdin, one per line (as lint-staged passes them)
+    #[arg(long, conflicts_with = "interactive")]
+    stdin_files: bool,
+
+    /// Describe the changes of a commit or a range like main..feature instead of the staged ones
+    #[arg(long, value_name = "RANGE", conflicts_with_all = ["include_unstaged", "stdin_files", "commit"])]
+    range: Option<String>,
+
+    /// Describe the changes of a unified diff (`-` for stdin) instead of the staged ones, e.g. from another VCS
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["include_unstaged", "stdin_files", "commit", "range"])]
+    diff: Option<PathBuf>,
+
+    /// Write the message to this file instead of printing it
+    #[arg(long, value_name = "FILE")]
+    output_file: Option<PathBuf>,
+
+    /// Disguise directory and file names in prompts, restoring them in the message
+    #[arg(long, value_name = "MODE", value_enum)]
+    private_paths: Option<privacy::PathPrivacy>,
+
+    /// Summarize changes per directory when more than this many files changed
+    #[arg(long, value_name = "N", default_value_t = 30)]
+    group_threshold: usize,
+
+    /// Let a cheaper model cluster the changes into themes that the body follows
+    #[arg(long)]
+    cluster: bool,
+
+    /// Model used for clustering (defaults to gpt-4o-mini, or the provider's own small one)
+    #[arg(long, value_name = "MODEL", default_value = "gpt-4o-mini")]
+    cluster_model: String,
+
+    /// Maximum number of bullet points in the body, enforced by regenerating
+    #[arg(long, value_name = "N")]
+    max_bullets: Option<usize>,
+
+    /// How detailed the message is (scaled to the size of the change by default)
+    #[arg(long, value_enum)]
+    detail: Option<Detail>,
+
+    /// Sampling temperature sent to the model; 0 makes the message as repeatable as it allows
+    #[arg(long, value_name = "T")]
+    temperature: Option<f64>,
+
+    /// Nucleus sampling: only the most likely tokens making up this share of probability are picked from
+    #[arg(long, value_name = "P")]
+    top_p: Option<f64>,
+
+    /// Grammatical mood of the title, which the prompt asks for and replies are checked against
+    #[arg(long, value_enum, default_value_t = Mood::Imperative)]
+    mood: Mood,
+
+    /// Languages to write the message in, like en,zh; with several, the message has a
+    /// section in each, the first giving the commit its title
+    #[arg(long, value_delimiter = ',', value_parser = Language::parse, value_name = "CODES", conflicts_with = "structured")]
+    languages: Vec<Language>,
+
+    /// Upper limit on the tokens of each reply, instead of the one that goes with --detail
+    #[arg(long, value_name = "N")]
+    max_tokens: Option<usize>,
+
+    /// Kind of change, steering the framing and the Conventional Commit type
+    #[arg(long, value_enum)]
+    intent: Option<Intent>,
+
+    /// Number of candidate messages to request in one call, listed to pick from with
+    /// --interactive, or picked from with --auto-select
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    candidates: usize,
+
+    /// Pick the best candidate automatically
+    #[arg(long)]
+    auto_select: bool,
+
+    /// How the best candidate is picked
+    #[arg(long, value_enum, default_value_t = SelectStrategy::Heuristic)]
+    select_strategy: SelectStrategy,
+
+    /// Model used to judge candidates, verify messages and summarize files for a last
+    /// attempt at a message it keeps finding inaccurate (defaults to gpt-4o-mini, or the
+    /// provider's own small one)
+    #[arg(long, value_name = "MODEL", default_value = "gpt-4o-mini")]
+    judge_model: String,
+
+    /// Have the judge model verify the message against the changes and correct it
+    #[arg(long)]
+    verify: bool,
 
-#[derive(Deserialize)]
-struct MessageContent {
-    content: String,
+    /// Model that writes the last attempt from summaries of every file when corrections
+    /// do not satisfy the judge, ideally one with a larger context (defaults to --model)
+    #[arg(long, value_name = "MODEL", requires = "verify")]
+    escalation_model: Option<String>,
+
+    /// Generate with a quantized GGUF model on this machine instead of the API (needs the `candle` or `local-llama` feature)
+    #[arg(long, value_name = "FILE")]
+    local_model: Option<PathBuf>,
+
+    /// Send prompts that exceed the spending limit without asking
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Fail instead of warning when the message still contains banned words (implied by $CI)
+    #[arg(long)]
+    ci: bool,
+
+    /// Seconds any request to the API may take before the run fails (defaults to 30)
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Times a request is sent again after a rate limit, server error or dropped connection,
+    /// waiting longer each time (defaults to retries in the config, or 2)
+    #[arg(long, value_name = "N")]
+    retries: Option<u32>,
+
+    /// Send requests through this HTTP(S) proxy, like http://proxy.example:3128, instead of
+    /// the one in HTTPS_PROXY or HTTP_PROXY (defaults to proxy in the config)
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Trust the root certificates in this PEM file besides the built-in ones, as
+    /// TLS-intercepting proxies need (defaults to ca_cert in the config)
+    #[arg(long, value_name = "FILE")]
+    ca_cert: Option<PathBuf>,
+
+    /// Report the latency and token usage of every API call on stderr
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Report the tokens the API reported using in all on stderr, with their cost where known
+    #[arg(long)]
+    usage: bool,
+
+    /// Do not record the message in the history database
+    #[arg(long)]
+    no_history: bool,
+
+    /// Fail instead of making any network request, e.g. in CI jobs that must stay offline
+    #[arg(long)]
+    forbid_network: bool,
+
+    /// Model to try next when the previous one is rate limited, fails or times out, as
+    /// `model`, `provider:model`, `endpoint:model` or `builtin` (repeatable; replaces `fallback_models` in the config)
+    #[arg(long = "fallback-model", value_name = "MODEL")]
+    fallback_models: Vec<String>,
+
+    /// The model options left at their default, which the default model of the provider
+    /// or endpoint replaces
+    #[arg(skip)]
+    defaulted_models: Vec<&'static str>,
+
+    /// Write a plain message from the changed files and their line counts, without any model
+    /// (also the fallback when the API cannot be reached)
+    #[arg(long, conflicts_with_all = ["local_model", "warmup", "verify", "cluster", "auto_select"])]
+    offline: bool,
+
+    /// Write every prompt, reply and check of the run to this Markdown file, e.g. for a bug report
+    #[arg(long, value_name = "FILE")]
+    save_transcript: Option<PathBuf>,
+
+    /// Replies for --provider mock, as JSON lines with a `replies` array each, e.g. from --record-replies
+    #[arg(long, value_name = "FILE")]
+    mock_replies: Option<PathBuf>,
+
+    /// Append the replies of every request to FILE, for replaying them with --provider mock
+    #[arg(long, value_name = "FILE")]
+    record_replies: Option<PathBuf>,
+
+    /// Print the first draft on stderr as the model writes it, where the provider can stream
+    #[arg(long, conflicts_with = "structured")]
+    stream: bool,
+
+    /// Have the model answer with the type, scope, subject and bullets as JSON, held to a
+    /// schema where the provider supports that, and put the message together from them
+    #[arg(long)]
+    structured: bool,
+
+    /// Seconds the run may take before the message is written as with --offline, e.g. so a
+    /// hook never holds up a commit for long (replaces `total` in the `[deadlines]` config)
+    #[arg(long, value_name = "SECONDS")]
+    deadline: Option<u64>,
+
+    /// Load the model with a tiny request while the changes are collected (automatic with a local provider in server mode)
+    #[arg(long, conflicts_with = "local_model")]
+    warmup: bool,
+
+    /// Keep running and answer generate requests from an editor over this transport
+    #[arg(long, value_name = "TRANSPORT", value_enum, conflicts_with_all = ["interactive", "stdin_files", "commit"])]
+    server: Option<server::Transport>,
+
+    /// Additional configuration file, applied after the user and repository ones
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Set by the server to revise its last draft instead of starting over
+    #[arg(skip)]
+    revision: Option<server::Revision>,
+
+    /// Set by the server once the request this run answers is cancelled
+    #[arg(skip)]
+    cancelled: Option<Arc<AtomicBool>>,
 }
 
-struct FileChange {
-    file_path: String,
-    change_type: String,
-    summaries: Vec<String>,
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Store the API key in the OS keychain, or remove it from there
+    Auth {
+        #[command(subcommand)]
+        action: auth::AuthAction,
+    },
+    /// Install or run the prepare-commit-msg hook
+    Hook {
+        #[command(subcommand)]
+        action: hook::HookAction,
+    },
+    /// Fill in the commit message file when run by the pre-commit framework
+    PreCommitEntry {
+        /// Options from the hook's `args`, followed by the message file pre-commit passes
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// List the chat models available to the API key, with context sizes and prices where known
+    Models,
+    /// Search the messages generated earlier
+    History {
+        #[command(subcommand)]
+        action: history::HistoryAction,
+    },
+    /// Export suggestions paired with the messages that were committed
+    Feedback {
+        #[command(subcommand)]
+        action: history::FeedbackAction,
+    },
+    /// Check that the provider is reachable and accepts the credentials
+    Ping,
+    /// Regenerate the messages of past commits with each prompt template and model, and compare them
+    Experiment {
+        /// Prompt template files with the placeholders of the built-in prompt (defaults to the built-in one)
+        #[arg(long, value_name = "FILES", value_delimiter = ',')]
+        prompts: Vec<PathBuf>,
+
+        /// Models to compare (defaults to --model)
+        #[arg(long, value_name = "MODELS", value_delimiter = ',')]
+        models: Vec<String>,
+
+        /// Commits to replay
+        #[arg(long, value_name = "RANGE", default_value = "HEAD~10..HEAD")]
+        range: String,
+    },
+    /// Print the staged changes as JSON, with their line and hunk counts, instead of
+    /// generating a message
+    Analyze,
+    /// Print the assembled request body for the staged changes instead of sending it
+    ExportPrompt {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Openai)]
+        format: ExportFormat,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -79,6 +444,20 @@ enum CommitGPTError {
     #[error("Failed to read API key from {0}: {1}")]
     ApiKeyReadError(String, #[source] std::io::Error),
 
+    #[error("No API key given; pass --api-key-path{0}")]
+    MissingApiKeyError(String),
+
+    #[error("No API key given on standard input")]
+    EmptyApiKeyError,
+
+    #[cfg(feature = "keychain")]
+    #[error("Keychain error: {0}")]
+    KeychainError(String),
+
+    #[cfg(not(feature = "keychain"))]
+    #[error("auth needs commit-gpt built with the keychain feature")]
+    KeychainUnavailable,
+
     #[error("Git error: {0}")]
     GitError(#[from] git2::Error),
 
@@ -86,13 +465,155 @@ enum CommitGPTError {
     HttpRequestError(#[from] reqwest::Error),
 
     #[error("API responded with error status: {0}")]
-    ApiErrorStatus(reqwest::StatusCode),
+    ApiErrorStatus(openai::ApiError),
 
     #[error("Failed to parse API response: {0}")]
     ApiResponseParseError(#[from] serde_json::Error),
 
+    #[error("Unexpected API response: {0}")]
+    UnexpectedResponse(response::Mismatch),
+
+    #[error("Failed to read the streamed reply: {0}")]
+    StreamReadError(#[source] std::io::Error),
+
+    #[error("Failed to write transcript {0}: {1}")]
+    TranscriptWriteError(String, #[source] std::io::Error),
+
     #[error("No commit message generated")]
     NoCommitMessage,
+
+    #[error("Failed to write cache {0}: {1}")]
+    CacheWriteError(String, #[source] std::io::Error),
+
+    #[error("Failed to read config file {0}: {1}")]
+    ConfigReadError(String, #[source] std::io::Error),
+
+    #[error("Failed to read the file list from stdin: {0}")]
+    StdinReadError(#[source] std::io::Error),
+
+    #[error("Failed to read diff {0}: {1}")]
+    DiffReadError(String, #[source] std::io::Error),
+
+    #[error("Not a commit or range: {0}")]
+    InvalidRangeError(String),
+
+    #[error("Failed to write message to {0}: {1}")]
+    OutputWriteError(String, #[source] std::io::Error),
+
+    #[error("Failed to read prompt template {0}: {1}")]
+    PromptReadError(String, #[source] std::io::Error),
+
+    #[error("Failed to read context file {0}: {1}")]
+    ContextFileReadError(String, #[source] std::io::Error),
+
+    #[error("Invalid config file {0}: {1}")]
+    ConfigParseError(String, #[source] toml::de::Error),
+
+    #[error("History database error: {0}")]
+    HistoryError(#[from] rusqlite::Error),
+
+    #[error("Failed to access history database {0}: {1}")]
+    HistoryIoError(String, #[source] std::io::Error),
+
+    #[error("Failed to write audit log {0}: {1}")]
+    AuditLogError(String, #[source] std::io::Error),
+
+    #[error("Refusing to request {0} because of --forbid-network")]
+    NetworkForbidden(String),
+
+    #[error("Failed to pull model {0}: {1}")]
+    ModelPullError(String, String),
+
+    #[error("Incomplete provider setup: {0}")]
+    ProviderConfigError(String),
+
+    #[error("Failed to access lock {0}: {1}")]
+    LockError(String, #[source] std::io::Error),
+
+    #[error(
+        "Timed out waiting for {0} held by another commit-gpt run; remove it if none is running"
+    )]
+    LockTimeout(String),
+
+    #[error("Invalid request header {0} in config")]
+    InvalidHeaderError(String),
+
+    #[error("Invalid languages in config: {0}")]
+    LanguageConfigError(String),
+
+    #[error("Failed to read CA certificate {0}: {1}")]
+    CaCertReadError(String, #[source] std::io::Error),
+
+    #[error("No PEM certificate in {0}")]
+    InvalidCaCertError(String),
+
+    #[error("Invalid proxy {0}: {1}")]
+    InvalidProxyError(String, #[source] reqwest::Error),
+
+    #[error("No endpoint named {0} in the [endpoints] config table")]
+    UnknownEndpointError(String),
+
+    #[cfg(any(feature = "candle", feature = "local-llama"))]
+    #[error("Failed to load local model {0}: {1}")]
+    LocalModelLoadError(String, String),
+
+    #[cfg(any(feature = "candle", feature = "local-llama"))]
+    #[error("Local inference failed: {0}")]
+    LocalInferenceError(String),
+
+    #[cfg(not(any(feature = "candle", feature = "local-llama")))]
+    #[error("--local-model needs commit-gpt built with the candle or local-llama feature")]
+    LocalModelUnavailable,
+
+    #[cfg(not(feature = "builtin"))]
+    #[error("--provider builtin needs commit-gpt built with the builtin feature")]
+    BuiltinModelUnavailable,
+
+    #[cfg(all(feature = "builtin", not(builtin_model)))]
+    #[error("commit-gpt was built without a model for --provider builtin; set COMMIT_GPT_BUILTIN_MODEL and COMMIT_GPT_BUILTIN_TOKENIZER to the model and its tokenizer.json when building")]
+    BuiltinModelMissing,
+
+    #[error("--provider builtin runs in-process and has no API for this command")]
+    BuiltinModelApi,
+
+    #[error("--provider mock has no API for this command")]
+    MockProviderApi,
+
+    #[error("Failed to access mock replies {0}: {1}")]
+    MockRepliesError(String, #[source] std::io::Error),
+
+    #[error("No mock reply was recorded for this request; record the replies again with --record-replies")]
+    MockReplyMissing,
+
+    #[error("The API did not answer within the --timeout of {0} s; raise it, or stage fewer changes for a shorter prompt")]
+    RequestTimeout(u64),
+
+    #[error("{0} ran past its deadline")]
+    DeadlineExceeded(deadline::Stage),
+
+    #[error("The request was cancelled")]
+    Cancelled,
+
+    #[error("Estimated cost ${0:.2} exceeds the spending limit of ${1:.2}; pass --yes or raise max_cost in the config")]
+    SpendingLimitExceeded(f64, f64),
+
+    #[error("Invalid banned pattern {0}: {1}")]
+    BannedPatternError(String, #[source] regex::Error),
+
+    #[error("Banned pattern {0:?} in {1} matches an empty string, so it would ban every message")]
+    EmptyBannedPatternError(String, String),
+
+    #[error("The message contains banned words: {0}")]
+    BannedWordsError(String),
+
+    #[error("Failed to access hook file {0}: {1}")]
+    HookIoError(String, #[source] std::io::Error),
+
+    #[error("A prepare-commit-msg hook not installed by commit-gpt already exists at {0}")]
+    ForeignHookExists(String),
+
+    #[error("Refusing to commit in {0} from {1}, another worktree of the same repository; run commit-gpt from {0} instead")]
+    OtherWorktreeError(String, String),
 }
 
 type Result<T> = std::result::Result<T, CommitGPTError>;
@@ -104,196 +625,1127 @@ fn main() {
     }
 }
 
+impl Args {
+    /// Whether the model runs in this process, where it costs nothing.
+    fn in_process(&self) -> bool {
+        self.local_model.is_some()
+            || matches!(self.provider, ProviderKind::Builtin | ProviderKind::Mock)
+    }
+
+    /// Swaps every model option left at its default for `large`, or for `small` where a
+    /// small model does.
+    fn use_default_models(&mut self, large: &str, small: &str) {
+        for (id, option, model) in [
+            ("model", &mut self.model, large),
+            ("cluster_model", &mut self.cluster_model, small),
+            ("judge_model", &mut self.judge_model, small),
+        ] {
+            if self.defaulted_models.contains(&id) {
+                *option = model.to_string();
+            }
+        }
+    }
+}
+
+/// Parses `arguments` like [`Parser::parse_from`], then swaps every model option left at
+/// its default for the provider's default models.
+fn parse_args<I, T>(arguments: I) -> Args
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = Args::command().get_matches_from(arguments);
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let defaulted = |id: &str, matches: &ArgMatches| {
+        matches.value_source(id) == Some(ValueSource::DefaultValue)
+    };
+    args.defaulted_models = ["model", "cluster_model", "judge_model"]
+        .into_iter()
+        .filter(|id| defaulted(id, &matches))
+        .collect();
+    if let Some(models) = args.provider.default_models() {
+        args.use_default_models(models.large, models.small);
+    }
+    args
+}
+
 fn run() -> Result<()> {
     // Parse command-line arguments
-    let args = Args::parse();
-
-    // Read the API key
-    let api_key = fs::read_to_string(&args.api_key_path)
-        .map_err(|e| CommitGPTError::ApiKeyReadError(args.api_key_path.clone(), e))?
-        .trim()
-        .to_string();
+    let mut args = parse_args(env::args_os());
+    if args.porcelain {
+        args.output = OutputFormat::Porcelain;
+    }
+    if let Some(Command::PreCommitEntry { args: entry_args }) = &args.command {
+        return hook::pre_commit_entry(entry_args);
+    }
+    if let Some(Command::History { action }) = &args.command {
+        return history::run(action);
+    }
+    if let Some(Command::Auth { action }) = &args.command {
+        return auth::run(&args, action);
+    }
 
     // Open the Git repository at the specified working directory path
     let repo = Repository::open(&args.workdir_path)?;
+    if args.commit {
+        commit::check_worktree(&repo)?;
+    }
+    let config = Config::load(&repo, args.config.as_deref())?;
+    if let Some(name) = &args.endpoint {
+        if let Some(model) = config.endpoint(name)?.default_model.clone() {
+            args.use_default_models(&model, &model);
+        }
+    }
 
-    // Prepare git information
-    let structured_changes = get_structured_changes(&repo, args.include_unstaged)?;
-    if structured_changes.is_empty() {
-        if args.include_unstaged {
-            println!("No changes detected. Nothing to generate a commit message for.");
-        } else {
-            println!("No staged changes detected. Nothing to generate a commit message for.");
+    if let Some(Command::Hook { action }) = &args.command {
+        return hook::run(&args, &config, &repo, action);
+    }
+    if let Some(Command::Analyze) = &args.command {
+        let changes = diff_source(&args, &repo, None)?.changes()?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&changes::to_json(&changes))?
+        );
+        return Ok(());
+    }
+    if let Some(Command::Ping) = &args.command {
+        ping::run(&args, &config);
+    }
+    if let Some(Command::Feedback { action }) = &args.command {
+        return history::run_feedback(&repo, action);
+    }
+    if let Some(Command::Experiment {
+        prompts,
+        models,
+        range,
+    }) = &args.command
+    {
+        return experiment::run(&args, &config, &repo, prompts, models, range);
+    }
+    if let Some(Command::Models) = &args.command {
+        return models::run(&args, &config);
+    }
+    if let Some(transport) = args.server {
+        return server::serve(&args, &config, &repo, transport);
+    }
+
+    let generated = thread::scope(|scope| {
+        if args.warmup {
+            scope.spawn(|| ping::warm_up(&args, &config));
+        }
+        generate(&args, &config, &repo, None)
+    });
+    if args.output == OutputFormat::Porcelain {
+        match &generated {
+            Ok(None) => println!("{}", render::porcelain_status("empty", None)),
+            Err(e) => println!(
+                "{}",
+                render::porcelain_status("error", Some(&e.to_string()))
+            ),
+            Ok(Some(_)) => {}
         }
+    }
+    let Some(commit_message) = generated? else {
         return Ok(());
+    };
+    let commit_message = apply_signoff(&args, &repo, commit_message)?;
+
+    // Output the commit message without extra text
+    match &args.output_file {
+        Some(path) => {
+            let painter = Painter::new(ColorChoice::Never, false);
+            let contents = format!(
+                "{}\n",
+                render::renderer(args.output, &painter).render(&commit_message)
+            );
+            fs::write(path, contents)
+                .map_err(|e| CommitGPTError::OutputWriteError(path.display().to_string(), e))?;
+        }
+        None => {
+            let painter = Painter::new(args.color, std::io::stdout().is_terminal());
+            println!(
+                "{}",
+                render::renderer(args.output, &painter).render(&commit_message)
+            );
+        }
     }
 
-    let context = args.context.unwrap_or_default();
+    if args.commit {
+        let oid = commit::create_commit(&repo, &commit_message)?;
+        eprintln!("Created commit {}", oid);
+    }
 
-    let prompt = USER_PROMPT_TEMPLATE
-        .replace("{structured_changes}", &structured_changes)
-        .replace("{context}", &context);
+    Ok(())
+}
 
-    // Prepare OpenAI API request
-    let request_body = OpenAIRequest {
-        model: args.model.clone(),
-        messages: vec![
-            Message {
-                role: "system".to_string(),
-                content: SYSTEM_PROMPT.to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: prompt,
-            },
-        ],
+/// Where the changes to describe come from, per the command line.
+fn diff_source<'a>(
+    args: &Args,
+    repo: &'a Repository,
+    base: Option<&'a git2::Tree<'a>>,
+) -> Result<Box<dyn DiffSource + 'a>> {
+    let index = source::Index {
+        repo,
+        include_unstaged: args.include_unstaged,
+        base,
     };
+    Ok(if let Some(path) = &args.diff {
+        Box::new(source::UnifiedDiff::read(path)?)
+    } else if let Some(spec) = &args.range {
+        Box::new(source::Range {
+            repo,
+            spec: spec.clone(),
+        })
+    } else if args.stdin_files {
+        let paths = changes::read_paths(repo, std::io::stdin().lock())?;
+        Box::new(source::FileList { index, paths })
+    } else {
+        Box::new(index)
+    })
+}
+
+/// Generates a message for the changes since `base` (HEAD when `None`). Returns
+/// `None` when there is nothing to describe or nothing was accepted.
+fn generate(
+    args: &Args,
+    config: &Config,
+    repo: &Repository,
+    base: Option<&git2::Tree>,
+) -> Result<Option<String>> {
+    let deadlines = Deadlines::start(config.deadlines.as_ref(), args.deadline);
+    let collecting = deadlines.until(Stage::Diff);
+    let changes = diff_source(args, repo, base)?.changes()?;
+    if changes.is_empty() {
+        let notice = if args.include_unstaged || args.range.is_some() || args.diff.is_some() {
+            "No changes detected. Nothing to generate a commit message for."
+        } else {
+            "No staged changes detected. Nothing to generate a commit message for."
+        };
+        // Porcelain output and the server report this in their own format instead
+        if args.output == OutputFormat::Porcelain || args.server.is_some() {
+            eprintln!("{}", notice);
+        } else {
+            println!("{}", notice);
+        }
+        return Ok(None);
+    }
 
-    // Create a client with rustls TLS backend
-    let client = Client::builder()
-        .use_rustls_tls()
-        .build()?;
-
-    // Send request to OpenAI API
-    let response = client
-        .post(OPENAI_API_URL)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request_body)
-        .send()?;
-
-    if response.status().is_success() {
-        let resp_json: OpenAIResponse = response.json()?;
-        let commit_message = resp_json
-            .choices
-            .get(0)
-            .ok_or(CommitGPTError::NoCommitMessage)?
-            .message
-            .content
-            .trim()
-            .to_string();
-        if commit_message.is_empty() {
-            return Err(CommitGPTError::NoCommitMessage);
-        }
-        // Output the commit message without extra text
-        println!("{}", commit_message);
+    let changes = if args.mark {
+        let marked = interactive::mark_changes(changes);
+        if marked.iter().all(|change| change.context_only) {
+            eprintln!("Everything was marked as context only or left out. Nothing to generate a commit message for.");
+            return Ok(None);
+        }
+        marked
     } else {
-        return Err(CommitGPTError::ApiErrorStatus(response.status()));
+        changes
+    };
+
+    if args.offline {
+        return Ok(Some(offline::message(&changes)));
+    }
+    if let Err(e) = deadline::check(collecting, Stage::Diff) {
+        eprintln!(
+            "Warning: {}; writing a message from the changed files instead",
+            e
+        );
+        return Ok(Some(offline::message(&changes)));
     }
 
-    Ok(())
-}
+    // Identifies the changes in the history and the rejected cache, by their real paths
+    let fingerprint = changes::fingerprint(&changes);
 
-fn get_structured_changes(repo: &Repository, include_unstaged: bool) -> Result<String> {
-    let diff = get_combined_diff(repo, include_unstaged)?;
-    let changes = collect_changes(&diff);
-    Ok(format_changes_for_prompt(&changes))
-}
+    // Files already described by the previous run are only listed briefly
+    let previous = if args.incremental {
+        SummaryCache::load(repo.path())
+    } else {
+        SummaryCache::default()
+    };
 
-fn get_combined_diff(repo: &Repository, include_unstaged: bool) -> Result<git2::Diff> {
-    let mut diff_opts = DiffOptions::new();
-    if include_unstaged {
-        // Include both staged and unstaged changes
-        diff_opts
-            .include_untracked(true)
-            .recurse_untracked_dirs(true);
+    // Disguise the paths before anything sent to the model is built from them
+    let path_map = match args.private_paths {
+        Some(privacy) => {
+            let salt = cache::path_salt(repo.path())?;
+            let mut path_map = privacy::PathMap::new(privacy, &salt, &changes);
+            // The previous draft can mention files that are no longer part of the change
+            path_map.add_paths(previous.files.values().map(|file| file.file_path.as_str()));
+            Some(path_map)
+        }
+        None => None,
+    };
+    let hide = |text: &str| match &path_map {
+        Some(path_map) => path_map.hide_text(text),
+        None => text.to_string(),
+    };
+    let reveal = |text: &str| match &path_map {
+        Some(path_map) => path_map.reveal(text),
+        None => text.to_string(),
+    };
+    let (changes, previous) = match &path_map {
+        Some(path_map) => (path_map.hide(&changes), path_map.hide_cache(&previous)),
+        None => (changes, previous),
+    };
+    let (changes, masked) =
+        secrets::redact(&changes, &config.redaction.clone().unwrap_or_default());
+    match masked {
+        0 => {}
+        1 => eprintln!("Masked a random-looking string, likely a key or token, in the prompt"),
+        masked => eprintln!(
+            "Masked {} random-looking strings, likely keys or tokens, in the prompt",
+            masked
+        ),
+    }
+
+    // Resolve `--model auto` now that the size of the change is known
+    let resolved;
+    let args = if args.model == AUTO_MODEL {
+        let defaults = default_auto_models(args, config)?;
+        let model = config.auto_model.clone().unwrap_or_default().pick(
+            &changes,
+            defaults
+                .as_ref()
+                .map(|(small, large)| (small.as_str(), large.as_str())),
+        );
+        if args.verbose {
+            eprintln!("Using {} for this change", model);
+        }
+        resolved = Args {
+            model,
+            ..args.clone()
+        };
+        &resolved
     } else {
-        // Include only staged changes
-        diff_opts
-            .include_untracked(false)
-            .recurse_untracked_dirs(false);
+        args
+    };
+
+    let structured_changes = format_changes_for_prompt(&changes, &previous, args.group_threshold);
+    let mut file_summaries = previous.summaries();
+
+    let languages = languages(args, config, repo)?;
+    let mut builder = PromptBuilder::new()
+        .context(&hide(&args.context.clone().unwrap_or_default()))
+        .mood(args.mood);
+    if let Some(project_context) = config::project_context(repo, args.context_file.as_deref())? {
+        builder = builder.project_context(&hide(&project_context));
+    }
+    let comment_prefix = template::comment_prefix(repo, None);
+    let commit_template = template::load(repo);
+    if let Some(commit_template) = &commit_template {
+        builder = builder.format(&hide(&template::prompt_section(
+            commit_template,
+            &comment_prefix,
+        )));
+    }
+    let style_examples: Vec<String> = config
+        .style_examples
+        .iter()
+        .map(|example| hide(example))
+        .collect();
+    builder = builder.style_examples(&style_examples);
+
+    // Changelog and design doc entries are the author's own description of the change
+    if let Some(notes) = changes::intent_notes(&changes) {
+        builder = builder.section(
+            "Author's notes (added to changelogs or design docs in this change; treat them as the primary statement of intent and base the title on them)",
+            &injection::escape(&notes),
+        );
+    }
+
+    // Steer away from suggestions rejected earlier for the same changes
+    let avoid = RejectedCache::load(repo.path())
+        .messages(&fingerprint)
+        .to_vec();
+    if !avoid.is_empty() {
+        builder = builder.section("Rejected suggestions", &hide(&avoid_section(&avoid)));
     }
 
-    // Get the HEAD tree
-    let head = repo.head()?.peel_to_tree()?;
+    if let Some(intent) = args.intent {
+        builder = builder.section("Intent", &intent.prompt_section());
+    }
+    let mut prompt = builder.build();
+
+    let rules = Rules {
+        max_bullets: args.max_bullets,
+        conventional_type: args.intent.map(Intent::conventional_type),
+        glossary: config.glossary.clone(),
+        banned: config
+            .banned
+            .iter()
+            .map(|pattern| {
+                validate::banned_pattern(pattern)
+                    .map_err(|e| CommitGPTError::BannedPatternError(pattern.clone(), e))
+            })
+            .collect::<Result<_>>()?,
+        detail: Some(args.detail.unwrap_or_else(|| Detail::for_changes(&changes))),
+        mood: args.mood,
+        languages,
+        max_tokens: args.max_tokens,
+        structured: args.structured,
+    };
+    if let Some(section) = rules.prompt_section() {
+        prompt.section("Constraints", &section);
+    }
+    if rules.structured {
+        prompt.section("Answer format", &structured::prompt_section(args.mood));
+    }
 
-    if include_unstaged {
-        // Diff between HEAD tree and workdir (staged and unstaged changes)
-        Ok(repo.diff_tree_to_workdir(Some(&head), Some(&mut diff_opts))?)
+    // Clustering needs an API call, so it is skipped when only showing the prompt
+    let export_format = match &args.command {
+        Some(Command::ExportPrompt { format }) => Some(*format),
+        _ => None,
+    };
+    let client = if args.show_prompt || export_format.is_some() {
+        None
     } else {
-        // Get the index
-        let index = repo.index()?;
+        let mut client = connect_with_fallbacks(args, config)?;
+        client.set_deadlines(deadlines);
+        if let Some(path) = &args.record_replies {
+            client = Client::Recording(Box::new(client), mock::Recorder::create(path)?);
+        }
+        if let Some(path) = &args.save_transcript {
+            client = Client::Recorded(Box::new(client), Transcript::create(path, &args.model)?);
+        }
+        Some(client)
+    };
+    if let (true, Some(client)) = (args.cluster, &client) {
+        match cluster::cluster_changes(client, &args.cluster_model, &changes) {
+            Ok(themes) => prompt.section("Themes", &cluster::prompt_section(&themes)),
+            Err(e) => eprintln!(
+                "Warning: clustering failed, continuing without themes: {}",
+                e
+            ),
+        }
+    }
+
+    // Drop whatever does not fit in the model's context window
+    let overhead =
+        budget::estimate_tokens(&prompt.system()) + budget::estimate_tokens(&prompt.render(""));
+    let context = client
+        .as_ref()
+        .and_then(|client| client.capabilities(&args.model).max_context);
+    let budget = context.map_or_else(
+        || budget::prompt_budget(&args.model),
+        budget::reserve_completion,
+    );
+    let mut available = budget.saturating_sub(overhead);
 
-        // Diff between HEAD tree and index (staged changes)
-        Ok(repo.diff_tree_to_index(Some(&head), Some(&index), Some(&mut diff_opts))?)
+    let Some(client) = client else {
+        let (kept, dropped) = budget::split_at_budget(&structured_changes, available);
+        let painter = Painter::new(args.color, std::io::stdout().is_terminal());
+        match export_format {
+            Some(format) => {
+                let conversation = [
+                    Message::system(&prompt.system()),
+                    Message::user(&prompt.render(&truncated(kept, dropped))),
+                ];
+                let request = export::request(
+                    format,
+                    &args.model,
+                    &conversation,
+                    rules.max_tokens(),
+                    &config.extra_body,
+                    client_options(args).sampling,
+                )?;
+                println!("{}", request);
+            }
+            None => show_prompt(&painter, &args.model, &prompt, kept, dropped),
+        }
+        return Ok(None);
+    };
+
+    // Ask before an expensive run; local models cost nothing
+    if !args.yes && !args.in_process() {
+        let (kept, _) = budget::split_at_budget(&structured_changes, available);
+        let prompt_tokens = overhead + budget::estimate_tokens(kept);
+        let mut cost = spending::estimate_cost(&args.model, prompt_tokens, args.candidates);
+        if args.verify {
+            cost = cost
+                .zip(spending::estimate_cost(&args.judge_model, prompt_tokens, 1))
+                .map(|(a, b)| a + b);
+        }
+        if let Some(cost) = cost {
+            let limit = config.max_cost.unwrap_or(spending::DEFAULT_MAX_COST);
+            spending::confirm(cost, limit, prompt_tokens, &changes)?;
+        }
     }
-}
 
-fn collect_changes(diff: &git2::Diff) -> Vec<FileChange> {
-    let mut changes_map: HashMap<String, FileChange> = HashMap::new();
+    // A concurrent run over the same changes, e.g. from a hook during a rebase, already
+    // asks the model; its message is reused. Interactive runs and revisions are the
+    // author's own, so they always ask.
+    let claim = if args.interactive || args.revision.is_some() {
+        None
+    } else {
+        let key = lock::request_key(&args.model, &prompt.render(&structured_changes));
+        match lock::claim(repo.path(), &key, config.lock_timeout())? {
+            lock::InFlight::Reused(message) => {
+                eprintln!("Reusing the message a concurrent run generated for the same changes");
+                return Ok(Some(message));
+            }
+            lock::InFlight::Owner(claim) => Some(claim),
+        }
+    };
 
-    diff.foreach(
-        &mut |_delta, _progress| {
-            true // No mutation of changes_map here
-        },
-        None,
-        Some(&mut |_delta, _hunk| true),
-        Some(&mut |delta, _hunk, line| {
-            let file_path = delta
-                .new_file()
-                .path()
-                .or_else(|| delta.old_file().path())
-                .map(|p| p.to_string_lossy().to_string())
-                .unwrap_or_else(|| "Unknown file".to_string());
-
-            let change_type = match delta.status() {
-                Delta::Added => "Added",
-                Delta::Deleted => "Deleted",
-                Delta::Modified => "Modified",
-                Delta::Renamed => "Renamed",
-                Delta::Copied => "Copied",
-                _ => "Modified",
+    // The context window estimate can be off, so shrink the prompt until the model accepts it
+    let mut structured_changes = structured_changes;
+    let mut retries = 0;
+    let mut fell_back = false;
+    let (conversation, sent_changes, commit_message) = loop {
+        let (kept, dropped) = budget::split_at_budget(&structured_changes, available);
+        if retries > 0 && !dropped.is_empty() {
+            eprintln!(
+                "Dropped from the prompt: {}",
+                dropped_files(dropped).join(", ")
+            );
+        }
+
+        let sent_changes = truncated(kept, dropped);
+        let conversation = vec![
+            Message::system(&prompt.system()),
+            Message::user(&prompt.render(&sent_changes)),
+        ];
+
+        let draft = match &args.revision {
+            Some(revision) => {
+                let mut messages = conversation.clone();
+                // The previous draft and the feedback name the real files
+                messages.push(Message::assistant(&hide(&revision.previous)));
+                messages.push(Message::user(&hide(
+                    revision.feedback.as_deref().unwrap_or(REGENERATE_PROMPT),
+                )));
+                complete_valid(&client, &args.model, &rules, messages)
+            }
+            None => first_draft(
+                &client,
+                args,
+                &rules,
+                &changes,
+                &conversation,
+                &sent_changes,
+            ),
+        };
+        match draft {
+            Err(CommitGPTError::ApiErrorStatus(error))
+                if error.is_context_length_exceeded() && retries < MAX_CONTEXT_RETRIES =>
+            {
+                retries += 1;
+                let grouped = format_changes_for_prompt(&changes, &previous, 0);
+                if grouped != structured_changes {
+                    eprintln!(
+                        "Context length exceeded; retrying with changes grouped per directory"
+                    );
+                    structured_changes = grouped;
+                } else {
+                    available /= 2;
+                    eprintln!(
+                        "Context length exceeded; retrying with a budget of {} tokens",
+                        available
+                    );
+                }
+            }
+            // Better a plain message than none, e.g. on a plane
+            Err(CommitGPTError::HttpRequestError(e)) if e.is_connect() || e.is_timeout() => {
+                eprintln!("Warning: could not reach the API ({}); writing a message from the changed files instead", e);
+                fell_back = true;
+                break (conversation, sent_changes, offline::message(&changes));
             }
-            .to_string();
+            Err(e @ CommitGPTError::DeadlineExceeded(_)) => {
+                eprintln!(
+                    "Warning: {}; writing a message from the changed files instead",
+                    e
+                );
+                fell_back = true;
+                break (conversation, sent_changes, offline::message(&changes));
+            }
+            result => break (conversation, sent_changes, result?),
+        }
+    };
 
-            let summary = summarize_change(&line);
+    let commit_message = if args.verify && !fell_back {
+        let validating = deadlines.until(Stage::Validation);
+        let verified = verify_message(&client, args, &rules, &conversation, &sent_changes, commit_message.clone(), validating)
+            .and_then(|(message, issues)| {
+                if issues.is_empty() {
+                    return Ok((message, issues));
+                }
+                eprintln!("The judge still finds inaccuracies; summarizing the files one by one for a last attempt");
+                match last_attempt(&client, args, &rules, &prompt, &changes, validating, &mut file_summaries) {
+                    Ok((escalated, remaining)) if remaining.len() <= issues.len() => Ok((escalated, remaining)),
+                    Ok(_) => Ok((message, issues)),
+                    Err(e @ CommitGPTError::DeadlineExceeded(_)) => Err(e),
+                    Err(e) => {
+                        eprintln!("Warning: the last attempt failed: {}", e);
+                        Ok((message, issues))
+                    }
+                }
+            })
+            .map(|(message, issues)| {
+                for issue in issues {
+                    eprintln!("Warning: possible inaccuracy: {}", issue);
+                }
+                message
+            });
+        match verified {
+            // An unverified message still beats one written from the file list
+            Err(e @ CommitGPTError::DeadlineExceeded(_)) => {
+                eprintln!("Warning: {}; keeping the message unverified", e);
+                commit_message
+            }
+            result => result?,
+        }
+    } else {
+        commit_message
+    };
+
+    // The author reviews, and the rejected cache keeps, the real names
+    let commit_message = reveal(&commit_message);
+
+    let commit_message = if args.interactive {
+        let stderr_painter = Painter::new(args.color, std::io::stderr().is_terminal());
+        let regenerate = |previous: &str, feedback: Option<&str>| {
+            let mut messages = conversation.clone();
+            messages.push(Message::assistant(&hide(previous)));
+            messages.push(Message::user(&hide(feedback.unwrap_or(REGENERATE_PROMPT))));
+            complete_valid(&client, &args.model, &rules, messages).map(|message| reveal(&message))
+        };
+        let mut rejected = Vec::new();
+        let accepted =
+            interactive::review_loop(&stderr_painter, commit_message, &mut rejected, regenerate);
+        // Reloaded under the lock, so rejections recorded by other runs meanwhile are kept
+        {
+            let _lock = lock::RepoLock::acquire(repo.path(), config.lock_timeout())?;
+            let mut rejected_cache = RejectedCache::load(repo.path());
+            rejected_cache.add(&fingerprint, rejected);
+            rejected_cache.save(repo.path())?;
+        }
+        match accepted? {
+            Some(message) => message,
+            None => return Ok(None),
+        }
+    } else {
+        commit_message
+    };
+
+    let commit_message = match &commit_template {
+        Some(_) => template::strip_comments(&commit_message, &comment_prefix),
+        None => commit_message,
+    };
+
+    // Instructions smuggled in through the diff can surface in the reply
+    let (commit_message, injected) = injection::strip_artifacts(&commit_message);
+    for line in injected {
+        eprintln!(
+            "Warning: removed a line that looks like injected instructions: {}",
+            line
+        );
+    }
+    // Compliance failures must not slip into commits made by automation
+    let banned = rules.banned_matches(&commit_message);
+    if !banned.is_empty() && is_ci(args) {
+        return Err(CommitGPTError::BannedWordsError(banned.join(", ")));
+    }
+
+    if args.incremental {
+        let _lock = lock::RepoLock::acquire(repo.path(), config.lock_timeout())?;
+        // Kept with the real names, which the next run disguises again
+        let cache = changes::summary_cache(&changes, &commit_message, &file_summaries);
+        let cache = match &path_map {
+            Some(path_map) => path_map.reveal_cache(cache),
+            None => cache,
+        };
+        cache.save(repo.path())?;
+    }
+    if !args.no_history {
+        let files = changes
+            .iter()
+            .map(|change| reveal(&change.file_path))
+            .collect();
+        let accepted = args.interactive || args.commit;
+        let entry = history::Entry {
+            prompt: conversation[1].content.clone(),
+            ..history::Entry::new(
+                repo,
+                &fingerprint,
+                &args.model,
+                files,
+                &commit_message,
+                accepted,
+            )
+        };
+        // The history is a convenience, so failing to keep it does not fail the run
+        let recorded = history::History::open().and_then(|history| {
+            // Pair earlier suggestions with what was committed in the meantime
+            history.reconcile(repo)?;
+            history.record(&entry)
+        });
+        if let Err(e) = recorded {
+            eprintln!(
+                "Warning: could not record the message in the history: {}",
+                e
+            );
+        }
+    }
+    if let Some(claim) = claim {
+        claim.finish(&commit_message);
+    }
+    client.note("Final message", &transcript::fenced(&commit_message));
+    if args.usage {
+        report_usage(&client);
+    }
+
+    Ok(Some(commit_message))
+}
 
-            let file_change = changes_map.entry(file_path.clone()).or_insert(FileChange {
-                file_path,
-                change_type,
-                summaries: Vec::new(),
+fn report_usage(client: &Client) {
+    let usage = client.usage();
+    if usage.is_empty() {
+        eprintln!("The API reported no token usage");
+    }
+    for (model, used) in usage {
+        let cost = spending::cost(&model, used.prompt_tokens, used.completion_tokens)
+            .map(|cost| format!(", about ${:.4}", cost))
+            .unwrap_or_default();
+        eprintln!(
+            "{}: {} prompt and {} completion tokens{}",
+            model, used.prompt_tokens, used.completion_tokens, cost
+        );
+    }
+}
+
+/// The first message for the conversation, picked from several candidates when requested:
+/// by the selector with --auto-select, by the user with --interactive, or else the first.
+fn first_draft(
+    client: &Client,
+    args: &Args,
+    rules: &Rules,
+    changes: &[FileChange],
+    conversation: &[Message],
+    structured_changes: &str,
+) -> Result<String> {
+    if args.candidates < 2 && args.stream {
+        let mut stderr = std::io::stderr();
+        let message =
+            client.complete_streaming(&args.model, conversation, rules.max_tokens(), &mut |text| {
+                let _ = write!(stderr, "{}", text);
+                let _ = stderr.flush();
             });
+        eprintln!();
+        return fix_violations(client, &args.model, rules, conversation.to_vec(), message?);
+    }
+    if args.candidates < 2 {
+        return complete_valid(client, &args.model, rules, conversation.to_vec());
+    }
 
-            if !summary.is_empty() {
-                file_change.summaries.push(summary);
+    let mut candidates = drafts(client, &args.model, rules, conversation, args.candidates)?;
+    let picked = if args.auto_select {
+        let selector = Selector {
+            strategy: args.select_strategy,
+            client,
+            judge_model: &args.judge_model,
+            rules,
+        };
+        selector.select(&candidates, changes, structured_changes)
+    } else {
+        let painter = Painter::new(args.color, std::io::stderr().is_terminal());
+        interactive::show_candidates(&painter, &candidates);
+        if args.interactive {
+            interactive::pick_candidate(candidates.len())
+        } else {
+            eprintln!("Using candidate 1; pick another with --interactive, or the best with --auto-select");
+            0
+        }
+    };
+    fix_violations(
+        client,
+        &args.model,
+        rules,
+        conversation.to_vec(),
+        candidates.swap_remove(picked),
+    )
+}
+
+fn dropped_files(dropped: &str) -> Vec<&str> {
+    let file = Regex::new(r"(?m)^\s*- \*\*(.+?)\*\*").unwrap();
+    file.captures_iter(dropped)
+        .filter_map(|captures| captures.get(1))
+        .map(|path| path.as_str())
+        .collect()
+}
+
+/// Completes `messages`, asking the model to fix any rule violations a bounded number of times.
+fn complete_valid(
+    client: &Client,
+    model: &str,
+    rules: &Rules,
+    messages: Vec<Message>,
+) -> Result<String> {
+    let message = draft(client, model, rules, &messages)?;
+    fix_violations(client, model, rules, messages, message)
+}
+
+/// One reply to `messages`, within the length budget of the rules.
+fn draft(client: &Client, model: &str, rules: &Rules, messages: &[Message]) -> Result<String> {
+    let mut replies = drafts(client, model, rules, messages, 1)?;
+    Ok(replies.swap_remove(0))
+}
+
+/// `n` replies to `messages`, put together from their parts when they are structured.
+fn drafts(
+    client: &Client,
+    model: &str,
+    rules: &Rules,
+    messages: &[Message],
+    n: usize,
+) -> Result<Vec<String>> {
+    if !rules.structured {
+        return client.complete_n(model, messages, n, rules.max_tokens());
+    }
+    let replies = client.complete_structured(
+        model,
+        messages,
+        n,
+        rules.max_tokens(),
+        &structured::schema(),
+    )?;
+    Ok(replies
+        .into_iter()
+        .map(|reply| match structured::parse(&reply) {
+            Ok(parts) => structured::render(&parts),
+            Err(e) => {
+                eprintln!(
+                    "Warning: the reply is not the requested JSON ({}); using it as it is",
+                    e
+                );
+                reply
             }
+        })
+        .collect())
+}
 
-            true
-        }),
-    )
-    .unwrap();
+fn fix_violations(
+    client: &Client,
+    model: &str,
+    rules: &Rules,
+    mut messages: Vec<Message>,
+    mut message: String,
+) -> Result<String> {
+    for _ in 0..MAX_CORRECTIONS {
+        let problems = rules.problems(&message);
+        if problems.is_empty() {
+            return Ok(message);
+        }
+        client.note(
+            "Validation",
+            &format!("The reply breaks the rules:\n\n- {}", problems.join("\n- ")),
+        );
+        messages.push(Message::assistant(&message));
+        messages.push(Message::user(&format!(
+            "Rewrite the commit message to fix these problems:\n- {}",
+            problems.join("\n- ")
+        )));
+        message = draft(client, model, rules, &messages)?;
+    }
+
+    // Terminology the model keeps getting wrong is fixed up directly
+    let message = rules.substitute_glossary(&message);
+    for problem in rules.problems(&message) {
+        eprintln!("Warning: {}", problem);
+    }
+    Ok(message)
+}
+
+/// Lets the judge model check `message` against the changes and has the main
+/// model correct whatever it flags, unless that goes on past `until`. Returns the
+/// message with the inaccuracies the judge still finds in it.
+fn verify_message(
+    client: &Client,
+    args: &Args,
+    rules: &Rules,
+    conversation: &[Message],
+    structured_changes: &str,
+    mut message: String,
+    until: Option<Instant>,
+) -> Result<(String, Vec<String>)> {
+    let mut messages = conversation.to_vec();
+    for _ in 0..MAX_CORRECTIONS {
+        deadline::check(until, Stage::Validation)?;
+        let issues =
+            verify::find_inaccuracies(client, &args.judge_model, structured_changes, &message)?;
+        if issues.is_empty() {
+            return Ok((message, issues));
+        }
+        client.note(
+            "Verification",
+            &format!("The judge found inaccuracies:\n\n- {}", issues.join("\n- ")),
+        );
+        messages.push(Message::assistant(&message));
+        messages.push(Message::user(&format!(
+            "A reviewer compared the commit message with the changes and found these inaccuracies. Rewrite the message to fix them:\n- {}",
+            issues.join("\n- ")
+        )));
+        message = complete_valid(client, &args.model, rules, messages.clone())?;
+    }
 
-    changes_map.into_iter().map(|(_, v)| v).collect()
+    deadline::check(until, Stage::Validation)?;
+    let issues =
+        verify::find_inaccuracies(client, &args.judge_model, structured_changes, &message)?;
+    Ok((message, issues))
 }
 
-fn summarize_change(line: &DiffLine) -> String {
-    let content = String::from_utf8_lossy(line.content()).trim().to_string();
+/// A message written by `--escalation-model` from summaries of every file rather than
+/// from the changes cut to fit the prompt, with the inaccuracies the judge finds in it.
+/// Files already in `file_summaries` are not summarized again.
+fn last_attempt(
+    client: &Client,
+    args: &Args,
+    rules: &Rules,
+    prompt: &Prompt,
+    changes: &[FileChange],
+    until: Option<Instant>,
+    file_summaries: &mut HashMap<String, String>,
+) -> Result<(String, Vec<String>)> {
+    let summaries =
+        summarize::summarize_files(client, &args.judge_model, changes, until, file_summaries)?;
+    let model = args.escalation_model.as_deref().unwrap_or(&args.model);
+    let conversation = vec![
+        Message::system(&prompt.system()),
+        Message::user(&prompt.render(&summaries)),
+    ];
+    deadline::check(until, Stage::Validation)?;
+    let message = complete_valid(client, model, rules, conversation)?;
+    deadline::check(until, Stage::Validation)?;
+    let issues = verify::find_inaccuracies(client, &args.judge_model, &summaries, &message)?;
+    Ok((message, issues))
+}
 
-    // Limit the length of the content to prevent excessively long summaries
-    let truncated_content = if content.len() > 80 {
-        format!("{}...", &content[..77])
+/// Connects to `--model`'s provider, and to those of the fallback models, which are given
+/// as `model`, `provider:model` or `builtin`.
+fn connect_with_fallbacks(args: &Args, config: &Config) -> Result<Client> {
+    let client = connect(args, config)?;
+    let entries = if args.fallback_models.is_empty() {
+        &config.fallback_models
     } else {
-        content.clone()
+        &args.fallback_models
+    };
+    if entries.is_empty() {
+        return Ok(client);
+    }
+    let mut fallbacks = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let (provider, endpoint, model) = match entry.split_once(':') {
+            _ if entry == "builtin" => (ProviderKind::Builtin, None, entry.clone()),
+            Some((name, model)) if config.endpoints.contains_key(name) => {
+                (args.provider, Some(name.to_string()), model.to_string())
+            }
+            // Ollama tags also contain a colon, as in llama3:8b
+            Some((provider, model)) => match ProviderKind::from_str(provider, true) {
+                Ok(provider) => (provider, None, model.to_string()),
+                Err(_) => (args.provider, args.endpoint.clone(), entry.clone()),
+            },
+            None => (args.provider, args.endpoint.clone(), entry.clone()),
+        };
+        let fallback_args = Args {
+            provider,
+            endpoint,
+            model: model.clone(),
+            local_model: None,
+            ..args.clone()
+        };
+        fallbacks.push((connect(&fallback_args, config)?, model));
+    }
+    Ok(Client::Chain(Box::new(client), fallbacks))
+}
+
+fn connect(args: &Args, config: &Config) -> Result<Client> {
+    if args.provider == ProviderKind::Mock {
+        return Ok(Client::Mock(Box::new(mock::Replies::load(
+            args.mock_replies.as_deref(),
+        )?)));
+    }
+    if args.provider == ProviderKind::Builtin && args.local_model.is_none() {
+        #[cfg(feature = "builtin")]
+        return Ok(Client::Local(Box::new(local::LocalModel::builtin()?)));
+        #[cfg(not(feature = "builtin"))]
+        return Err(CommitGPTError::BuiltinModelUnavailable);
+    }
+    if let Some(path) = &args.local_model {
+        #[cfg(feature = "local-llama")]
+        return Ok(Client::Llama(Box::new(local_llama::LlamaLocalModel::load(
+            path,
+        )?)));
+        #[cfg(all(feature = "candle", not(feature = "local-llama")))]
+        return Ok(Client::Local(Box::new(local::LocalModel::load(path)?)));
+        #[cfg(not(any(feature = "candle", feature = "local-llama")))]
+        {
+            let _ = path;
+            return Err(CommitGPTError::LocalModelUnavailable);
+        }
+    }
+
+    Ok(Client::Api(Box::new(api_client(args, config)?)))
+}
+
+fn api_client(args: &Args, config: &Config) -> Result<ApiClient> {
+    let provider: Box<dyn Provider> = match &args.endpoint {
+        Some(name) => Box::new(config.endpoint(name)?.clone()),
+        None => args.provider.provider(config)?,
     };
+    let api_key = read_api_key(args, provider.as_ref())?;
+    ApiClient::new(provider, api_key, config, client_options(args))
+}
 
-    match line.origin() {
-        '+' => format!("Added: {}", truncated_content),
-        '-' => format!("Removed: {}", truncated_content),
-        _ => String::new(),
+fn client_options(args: &Args) -> ClientOptions {
+    ClientOptions {
+        verbose: args.verbose,
+        forbid_network: args.forbid_network,
+        api_base: args.api_base.clone(),
+        headers: args.headers.clone(),
+        sampling: Sampling {
+            temperature: args.temperature,
+            top_p: args.top_p,
+        },
+        timeout: args.timeout.map(Duration::from_secs),
+        retries: args.retries,
+        proxy: args.proxy.clone(),
+        ca_cert: args.ca_cert.clone(),
+        cancelled: args.cancelled.clone(),
     }
 }
 
-fn format_changes_for_prompt(changes: &[FileChange]) -> String {
-    let mut formatted = String::new();
+fn parse_header(header: &str) -> std::result::Result<(String, String), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| "expected NAME: VALUE".to_string())?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
 
-    for change in changes {
-        formatted.push_str(&format!(
-            "- **{}**: {}\n",
-            change.file_path, change.change_type
-        ));
-        for summary in &change.summaries {
-            formatted.push_str(&format!("  - {}\n", summary));
+/// The key in the `--api-key-path` file, or else in the provider's environment variable,
+/// like OPENAI_API_KEY, or else the one `auth login` stored. Providers that can go
+/// without one get an empty key.
+fn read_api_key(args: &Args, provider: &dyn Provider) -> Result<String> {
+    let Some(api_key_path) = args.api_key_path.clone() else {
+        let variable = provider.api_key_env();
+        if let Some(api_key) = variable.as_ref().and_then(|name| env::var(name).ok()) {
+            if !api_key.trim().is_empty() {
+                return Ok(api_key.trim().to_string());
+            }
+        }
+        if let Some(api_key) = auth::stored_key(&auth::account(args)) {
+            return Ok(api_key);
         }
+        if !provider.requires_api_key() {
+            return Ok(String::new());
+        }
+        let mut hint = variable
+            .map(|name| format!(" or set {}", name))
+            .unwrap_or_default();
+        if cfg!(feature = "keychain") {
+            hint.push_str(", or run commit-gpt auth login");
+        }
+        return Err(CommitGPTError::MissingApiKeyError(hint));
+    };
+    Ok(fs::read_to_string(&api_key_path)
+        .map_err(|e| CommitGPTError::ApiKeyReadError(api_key_path.clone(), e))?
+        .trim()
+        .to_string())
+}
+
+/// The `(small, large)` models `--model auto` picks from without any configured: the
+/// endpoint's default model, or the provider's models.
+fn default_auto_models(args: &Args, config: &Config) -> Result<Option<(String, String)>> {
+    if let Some(name) = &args.endpoint {
+        if let Some(model) = config.endpoint(name)?.default_model.clone() {
+            return Ok(Some((model.clone(), model)));
+        }
+    }
+    Ok(args
+        .provider
+        .default_models()
+        .map(|models| (models.small.to_string(), models.large.to_string())))
+}
+
+fn is_ci(args: &Args) -> bool {
+    args.ci || env::var("CI").is_ok_and(|ci| !ci.is_empty() && ci != "false" && ci != "0")
+}
+
+fn avoid_section(rejected: &[String]) -> String {
+    let mut section = String::from(
+        "The author rejected these earlier suggestions. Avoid their phrasings and do not repeat them:\n",
+    );
+    for message in rejected {
+        section.push_str(&format!("---\n{}\n", message));
+    }
+    section
+}
+
+fn apply_signoff(args: &Args, repo: &Repository, message: String) -> Result<String> {
+    if args.signoff {
+        Ok(trailers::append_trailer(
+            &message,
+            &trailers::signoff_line(repo)?,
+        ))
+    } else {
+        Ok(message)
+    }
+}
+
+/// The languages to write in: those given, or configured, or else the one recent commit
+/// messages are written in. English needs no instructions, so it is left out when detected.
+fn languages(args: &Args, config: &Config, repo: &Repository) -> Result<Vec<Language>> {
+    if !args.languages.is_empty() {
+        return Ok(args.languages.clone());
+    }
+    if !config.languages.is_empty() {
+        return config
+            .languages
+            .iter()
+            .map(|code| Language::parse(code).map_err(CommitGPTError::LanguageConfigError))
+            .collect();
+    }
+    match language::detect(repo) {
+        Some(language) if !language.is_english() => {
+            eprintln!(
+                "Writing the message in {}, like recent commits; set languages in the config to choose",
+                language.name()
+            );
+            Ok(vec![language])
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// The changes that fit the budget with a note on how much was left out.
+fn truncated(kept: &str, dropped: &str) -> String {
+    let mut changes = kept.to_string();
+    if !dropped.is_empty() {
+        changes.push_str(&format!(
+            "[{} more lines truncated]\n",
+            dropped.lines().count()
+        ));
+    }
+    changes
+}
+
+fn show_prompt(painter: &Painter, model: &str, prompt: &Prompt, kept: &str, dropped: &str) {
+    let total = budget::estimate_tokens(&prompt.system())
+        + budget::estimate_tokens(&prompt.render(kept))
+        + budget::estimate_tokens(dropped);
+    eprintln!(
+        "Estimated prompt tokens: {} (budget {} for {})",
+        total,
+        budget::prompt_budget(model),
+        model
+    );
+
+    println!("{}\n", prompt.system());
+    print!("{}", prompt.render(kept));
+    if dropped.is_empty() {
+        return;
     }
 
-    formatted
+    let marker = format!(
+        "----- over budget: the following {} lines would be truncated -----",
+        dropped.lines().count()
+    );
+    println!("{}", painter.warning(&marker));
+    print!("{}", painter.warning(dropped));
 }
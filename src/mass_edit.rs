@@ -0,0 +1,162 @@
+// A rename or an updated import path applied across many files reads as many changes in
+// the prompt, and the message then lists them one by one. Files whose every changed line
+// is the same substitution, like `old_name` to `new_name`, are described once instead,
+// with the number of files it was applied to.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::changes::FileChange;
+
+/// Fewer files with the same edit are described each on its own
+const MIN_FILES: usize = 3;
+const SAMPLE_FILES: usize = 3;
+const SAMPLE_SUBSTITUTIONS: usize = 5;
+
+/// What a changed line replaced, narrowed to the words that differ: before and after.
+type Substitution = (String, String);
+
+/// The same substitutions, made in every one of `files`.
+pub struct MassEdit<'a> {
+    pub substitutions: BTreeSet<Substitution>,
+    pub files: Vec<&'a FileChange>,
+}
+
+/// The mass edits among `changes`, and the changes that are not part of one.
+pub fn find<'a>(changes: &[&'a FileChange]) -> (Vec<MassEdit<'a>>, Vec<&'a FileChange>) {
+    let mut by_edit: BTreeMap<BTreeSet<Substitution>, Vec<&'a FileChange>> = BTreeMap::new();
+    let mut rest = Vec::new();
+    for &change in changes {
+        match substitutions(change) {
+            Some(substitutions) => by_edit.entry(substitutions).or_default().push(change),
+            None => rest.push(change),
+        }
+    }
+
+    let mut edits = Vec::new();
+    for (substitutions, files) in by_edit {
+        if files.len() >= MIN_FILES {
+            edits.push(MassEdit {
+                substitutions,
+                files,
+            });
+        } else {
+            rest.extend(files);
+        }
+    }
+    rest.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    (edits, rest)
+}
+
+/// Formats the mass edits for the prompt, like the other changes.
+pub fn format(edits: &[MassEdit]) -> String {
+    let mut formatted = String::new();
+    for edit in edits {
+        let files: Vec<&str> = edit
+            .files
+            .iter()
+            .take(SAMPLE_FILES)
+            .map(|change| change.file_path.as_str())
+            .collect();
+        let more = edit.files.len().saturating_sub(SAMPLE_FILES);
+        let more = if more > 0 {
+            format!(" and {} more", more)
+        } else {
+            String::new()
+        };
+        formatted.push_str(&format!(
+            "- **The same edit in {} files**: {}{}\n",
+            edit.files.len(),
+            files.join(", "),
+            more
+        ));
+        for (before, after) in edit.substitutions.iter().take(SAMPLE_SUBSTITUTIONS) {
+            let line = match (before.as_str(), after.as_str()) {
+                ("", after) => format!("Inserted `{}`", after),
+                (before, "") => format!("Deleted `{}`", before),
+                (before, after) => format!("Replaced `{}` with `{}`", before, after),
+            };
+            formatted.push_str(&format!("  - {}\n", line));
+        }
+        if edit.substitutions.len() > SAMPLE_SUBSTITUTIONS {
+            formatted.push_str(&format!(
+                "  - and {} more substitutions\n",
+                edit.substitutions.len() - SAMPLE_SUBSTITUTIONS
+            ));
+        }
+    }
+    formatted
+}
+
+/// The substitutions `change` is made of, or `None` when some of its lines were added or
+/// removed without a counterpart, so that it is more than a mechanical edit.
+fn substitutions(change: &FileChange) -> Option<BTreeSet<Substitution>> {
+    if change.change_type != "Modified" {
+        return None;
+    }
+    let mut substitutions = BTreeSet::new();
+    let mut removed: Vec<&str> = Vec::new();
+    let mut added: Vec<&str> = Vec::new();
+    // A hunk lists its removed lines before the added ones that replace them
+    let mut pair = |removed: &mut Vec<&str>, added: &mut Vec<&str>| {
+        if removed.len() != added.len() {
+            return false;
+        }
+        for (before, after) in removed.drain(..).zip(added.drain(..)) {
+            match substitution(before, after) {
+                Some(substitution) => substitutions.insert(substitution),
+                None => return false,
+            };
+        }
+        true
+    };
+    for summary in &change.summaries {
+        if let Some(line) = summary.strip_prefix("Removed:") {
+            if !added.is_empty() && !pair(&mut removed, &mut added) {
+                return None;
+            }
+            removed.push(line.trim_start());
+        } else if let Some(line) = summary.strip_prefix("Added:") {
+            added.push(line.trim_start());
+        } else {
+            return None;
+        }
+    }
+    if !pair(&mut removed, &mut added) {
+        return None;
+    }
+    (!substitutions.is_empty()).then_some(substitutions)
+}
+
+/// The part of `before` that `after` replaced, widened to whole words. `None` when the
+/// lines do not differ, as when only their indentation changed.
+fn substitution(before: &str, after: &str) -> Option<Substitution> {
+    let before: Vec<char> = before.chars().collect();
+    let after: Vec<char> = after.chars().collect();
+    let mut start = before
+        .iter()
+        .zip(&after)
+        .take_while(|(b, a)| b == a)
+        .count();
+    let room = before.len().min(after.len()) - start;
+    let mut end = before
+        .iter()
+        .rev()
+        .zip(after.iter().rev())
+        .take(room)
+        .take_while(|(b, a)| b == a)
+        .count();
+    if start + end == before.len() && start + end == after.len() {
+        return None;
+    }
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    while start > 0 && is_word(before[start - 1]) {
+        start -= 1;
+    }
+    while end > 0 && is_word(before[before.len() - end]) {
+        end -= 1;
+    }
+    Some((
+        before[start..before.len() - end].iter().collect(),
+        after[start..after.len() - end].iter().collect(),
+    ))
+}
@@ -0,0 +1,153 @@
+// `--provider mock` answers from a file of canned replies instead of a model, so runs can
+// be tested end to end and demonstrated without a network or an API key. The file is
+// JSON lines, one request per line; `--record-replies` writes it from a real run, and one
+// can be written by hand with just the replies.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use git2::{ObjectType, Oid};
+use serde::{Deserialize, Serialize};
+
+use crate::openai::Message;
+use crate::{CommitGPTError, Result};
+
+/// The reply without a file of replies, enough to try commit-gpt out
+const DEFAULT_REPLY: &str = "Update the changed files\n\n- Canned reply from --provider mock";
+
+/// The replies to one request, as a line of the file.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Identifies the request by its messages; entries without one are replayed in order
+    /// for any request no entry has the key of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    /// The model that wrote the replies, for whoever reads the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    replies: Vec<String>,
+}
+
+/// Canned replies, from a file or the default one.
+pub struct Replies {
+    entries: Vec<Entry>,
+    /// The next entry without a key to answer with
+    next: Mutex<usize>,
+}
+
+impl Replies {
+    /// The replies in `path`, or only the default reply without one.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut entries = Vec::new();
+        if let Some(path) = path {
+            let read_error = |e| CommitGPTError::MockRepliesError(path.display().to_string(), e);
+            let contents = fs::read_to_string(path).map_err(read_error)?;
+            for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                let entry = serde_json::from_str(line)
+                    .map_err(|e| read_error(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+                entries.push(entry);
+            }
+        } else {
+            entries.push(Entry {
+                key: None,
+                model: None,
+                replies: vec![DEFAULT_REPLY.to_string()],
+            });
+        }
+        Ok(Replies {
+            entries,
+            next: Mutex::new(0),
+        })
+    }
+
+    /// `n` replies to `messages`: the recorded ones for the same messages, or else those
+    /// of the next entry without a key, repeated as needed to make `n`.
+    pub fn complete_n(&self, messages: &[Message], n: usize) -> Result<Vec<String>> {
+        let key = request_key(messages);
+        let recorded = self
+            .entries
+            .iter()
+            .find(|entry| entry.key.as_deref() == Some(key.as_str()));
+        let entry = match recorded {
+            Some(entry) => entry,
+            None => {
+                let unkeyed: Vec<&Entry> = self
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.key.is_none())
+                    .collect();
+                if unkeyed.is_empty() {
+                    return Err(CommitGPTError::MockReplyMissing);
+                }
+                // After the last entry the file starts over, so a single reply answers every request
+                let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+                let entry = unkeyed[*next % unkeyed.len()];
+                *next += 1;
+                entry
+            }
+        };
+        if entry.replies.is_empty() {
+            return Err(CommitGPTError::NoCommitMessage);
+        }
+        Ok(entry
+            .replies
+            .iter()
+            .cycle()
+            .take(n.max(1))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Appends the replies of every request to a file `--provider mock` can replay.
+pub struct Recorder {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| CommitGPTError::MockRepliesError(path.display().to_string(), e))?;
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Records the `replies` of `model` to `messages`. Failing to write only warns, as
+    /// the run itself went well.
+    pub fn record(&self, model: &str, messages: &[Message], replies: &[String]) {
+        let entry = Entry {
+            key: Some(request_key(messages)),
+            model: Some(model.to_string()),
+            replies: replies.to_vec(),
+        };
+        let Ok(line) = serde_json::to_string(&entry) else {
+            return;
+        };
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(file, "{}", line) {
+            eprintln!(
+                "Warning: {}",
+                CommitGPTError::MockRepliesError(self.path.display().to_string(), e)
+            );
+        }
+    }
+}
+
+/// Identifies a request by its messages, so a recording replays under another model too.
+fn request_key(messages: &[Message]) -> String {
+    let text: String = messages
+        .iter()
+        .map(|message| format!("{}\n{}\n", message.role, message.content))
+        .collect();
+    Oid::hash_object(ObjectType::Blob, text.as_bytes())
+        .map(|oid| oid.to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,108 @@
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::provider::{Capabilities, ProviderKind};
+use crate::{api_client, budget, spending, Args, Result};
+
+/// Prints the provider's chat models, the ones `--model` can name, with the context
+/// window and prices commit-gpt knows of, which are also what `--model auto`, budgeting
+/// and the spending limit go by, and what the API can do for each.
+pub fn run(args: &Args, config: &Config) -> Result<()> {
+    if let Some(path) = &args.local_model {
+        println!("{} (local)", path.display());
+        return Ok(());
+    }
+    if args.provider == ProviderKind::Builtin {
+        println!("builtin (compiled in)");
+        return Ok(());
+    }
+    if args.provider == ProviderKind::Mock {
+        println!("mock (canned replies)");
+        return Ok(());
+    }
+
+    let client = api_client(args, config)?;
+    let models = client.list_models()?;
+    let width = models
+        .iter()
+        .map(String::len)
+        .max()
+        .unwrap_or(0)
+        .max("MODEL".len());
+    println!(
+        "{:<width$}  {:>8}  {:>10}  {:>10}  FEATURES",
+        "MODEL", "CONTEXT", "INPUT $/M", "OUTPUT $/M"
+    );
+    for model in &models {
+        let capabilities = client.capabilities(model);
+        let context = capabilities
+            .max_context
+            .or_else(|| budget::known_context_window(model))
+            .map_or("-".to_string(), |tokens| tokens.to_string());
+        let (input, output) = spending::prices(model)
+            .map_or(("-".to_string(), "-".to_string()), |(input, output)| {
+                (format!("{:.2}", input), format!("{:.2}", output))
+            });
+        println!(
+            "{:<width$}  {:>8}  {:>10}  {:>10}  {}",
+            model,
+            context,
+            input,
+            output,
+            features(&capabilities)
+        );
+    }
+    Ok(())
+}
+
+/// The models `run` prints, as the JSON the server answers `listModels` with; a context
+/// window or price commit-gpt does not know of is `null`.
+pub fn value(args: &Args, config: &Config) -> Result<Value> {
+    if let Some(path) = &args.local_model {
+        return Ok(json!([{ "id": path.display().to_string(), "local": true }]));
+    }
+    if args.provider == ProviderKind::Builtin {
+        return Ok(json!([{ "id": "builtin", "local": true }]));
+    }
+    if args.provider == ProviderKind::Mock {
+        return Ok(json!([{ "id": "mock", "local": true }]));
+    }
+
+    let client = api_client(args, config)?;
+    let models = client
+        .list_models()?
+        .into_iter()
+        .map(|model| {
+            let capabilities = client.capabilities(&model);
+            let context = capabilities
+                .max_context
+                .or_else(|| budget::known_context_window(&model));
+            let prices = spending::prices(&model);
+            json!({
+                "id": model,
+                "contextWindow": context,
+                "inputPrice": prices.map(|(input, _)| input),
+                "outputPrice": prices.map(|(_, output)| output),
+                "streaming": capabilities.streaming,
+                "jsonMode": capabilities.json_mode,
+                "systemRole": capabilities.system_role,
+            })
+        })
+        .collect();
+    Ok(Value::Array(models))
+}
+
+fn features(capabilities: &Capabilities) -> String {
+    let features: Vec<&str> = [
+        (capabilities.streaming, "streaming"),
+        (capabilities.json_mode, "json"),
+        (capabilities.system_role, "system"),
+    ]
+    .into_iter()
+    .filter_map(|(supported, feature)| supported.then_some(feature))
+    .collect();
+    if features.is_empty() {
+        return "-".to_string();
+    }
+    features.join(",")
+}
@@ -0,0 +1,126 @@
+// Without a model, a message is put together from what the diff itself says: which
+// files changed how, and by how many lines. It is plain, but better than no message
+// when there is no network.
+
+use std::collections::BTreeSet;
+
+use crate::changes::FileChange;
+
+const MAX_TITLE_CHARS: usize = 72;
+const MAX_LISTED_FILES: usize = 10;
+const MAX_NAMED_SCOPES: usize = 3;
+
+/// A title naming what changed, and a bullet with the diffstat of every file. Changes
+/// marked as context only are left out.
+pub fn message(changes: &[FileChange]) -> String {
+    let changes: Vec<FileChange> = changes
+        .iter()
+        .filter(|change| !change.context_only)
+        .cloned()
+        .collect();
+    let changes = changes.as_slice();
+    let mut lines = vec![title(changes), String::new()];
+    for change in changes.iter().take(MAX_LISTED_FILES) {
+        let stat = match (change.added, change.removed) {
+            (0, 0) => String::new(),
+            (added, 0) => format!(" (+{})", added),
+            (0, removed) => format!(" (-{})", removed),
+            (added, removed) => format!(" (+{} -{})", added, removed),
+        };
+        lines.push(format!("- {} {}{}", verb(change), subject(change), stat));
+    }
+    if changes.len() > MAX_LISTED_FILES {
+        lines.push(format!(
+            "- and {} more files",
+            changes.len() - MAX_LISTED_FILES
+        ));
+    }
+    lines.join("\n")
+}
+
+fn title(changes: &[FileChange]) -> String {
+    let verbs: BTreeSet<&str> = changes.iter().map(verb).collect();
+    // Mixed kinds of changes are all updates as far as the title is concerned
+    let verb = match verbs.len() {
+        1 => verbs.into_iter().next().unwrap_or("Update"),
+        _ => "Update",
+    };
+    let title = match changes {
+        [change] => format!("{} {}", verb, subject(change)),
+        _ => {
+            let directory = common_directory(changes);
+            let scopes: BTreeSet<&str> = changes
+                .iter()
+                .map(|change| change.file_path.split('/').next().unwrap_or_default())
+                .collect();
+            if !directory.is_empty() {
+                format!("{} {} files in {}", verb, changes.len(), directory)
+            } else if scopes.len() <= MAX_NAMED_SCOPES {
+                format!(
+                    "{} {}",
+                    verb,
+                    join_and(&scopes.into_iter().collect::<Vec<_>>())
+                )
+            } else {
+                format!("{} {} files", verb, changes.len())
+            }
+        }
+    };
+    if title.chars().count() <= MAX_TITLE_CHARS {
+        return title;
+    }
+    let cut: String = title.chars().take(MAX_TITLE_CHARS - 3).collect();
+    format!("{}...", cut.trim_end())
+}
+
+fn verb(change: &FileChange) -> &'static str {
+    match change.change_type.as_str() {
+        "Added" => "Add",
+        "Deleted" => "Remove",
+        "Renamed" => "Rename",
+        "Copied" => "Copy",
+        _ => "Update",
+    }
+}
+
+/// The path, with where it came from for renames and copies.
+fn subject(change: &FileChange) -> String {
+    let source = format!("{} from ", change.change_type);
+    match change
+        .summaries
+        .iter()
+        .find_map(|summary| summary.strip_prefix(&source))
+    {
+        Some(old_path) => format!("{} to {}", old_path, change.file_path),
+        None => change.file_path.clone(),
+    }
+}
+
+/// The deepest directory every changed file is in, or an empty string.
+fn common_directory(changes: &[FileChange]) -> String {
+    let mut paths = changes
+        .iter()
+        .map(|change| change.file_path.split('/').collect::<Vec<_>>());
+    let Some(first) = paths.next() else {
+        return String::new();
+    };
+    // The file name itself is never part of the directory
+    let mut common = &first[..first.len().saturating_sub(1)];
+    for path in paths {
+        let shared = common
+            .iter()
+            .zip(&path[..path.len().saturating_sub(1)])
+            .take_while(|(a, b)| a == b)
+            .count();
+        common = &common[..shared];
+    }
+    common.join("/")
+}
+
+fn join_and(items: &[&str]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.to_string(),
+        [init @ .., last] => format!("{} and {}", init.join(", "), last),
+    }
+}
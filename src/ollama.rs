@@ -0,0 +1,271 @@
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use reqwest::blocking::RequestBuilder;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::budget;
+use crate::openai::{self, ApiError, Message};
+use crate::provider::{ApiClient, Capabilities, Provider, Sampling, Usage};
+use crate::{CommitGPTError, Result};
+
+const OLLAMA_URL: &str = "http://localhost:11434";
+const PROGRESS_WIDTH: u64 = 30;
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    // Ollama streams unless told otherwise
+    stream: bool,
+    options: ChatOptions,
+}
+
+#[derive(Serialize)]
+struct ChatOptions {
+    // Ollama's own default is smaller than the window the prompt is budgeted for, and it
+    // drops the start of longer prompts without saying so
+    num_ctx: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct TagList {
+    models: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// One line of the progress `/api/pull` streams.
+#[derive(Deserialize)]
+struct PullProgress {
+    #[serde(default)]
+    status: String,
+    total: Option<u64>,
+    completed: Option<u64>,
+    error: Option<String>,
+}
+
+/// A local Ollama server, so the changes never leave the machine.
+pub struct Ollama;
+
+impl Provider for Ollama {
+    // Same variable as the Ollama CLI, which also accepts a bare host:port
+    fn base_url(&self) -> String {
+        match env::var("OLLAMA_HOST") {
+            Ok(host) if host.contains("://") => host.trim_end_matches('/').to_string(),
+            Ok(host) if !host.is_empty() => format!("http://{}", host.trim_end_matches('/')),
+            _ => OLLAMA_URL.to_string(),
+        }
+    }
+
+    fn chat_path(&self, _model: &str) -> String {
+        "api/chat".to_string()
+    }
+
+    /// Ollama needs no credentials, but a key is passed on for servers behind a proxy.
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        if api_key.is_empty() {
+            request
+        } else {
+            request.bearer_auth(api_key)
+        }
+    }
+
+    fn requires_api_key(&self) -> bool {
+        false
+    }
+
+    fn supports_n(&self) -> bool {
+        false
+    }
+
+    // JSON is asked for with `format`
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            json_mode: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn request_body(
+        &self,
+        model: &str,
+        messages: &[Message],
+        _n: usize,
+        max_tokens: Option<usize>,
+        extra_body: &Map<String, Value>,
+    ) -> Result<Value> {
+        let request = ChatRequest {
+            model,
+            messages,
+            stream: false,
+            options: ChatOptions {
+                num_ctx: budget::context_window(model),
+                num_predict: max_tokens,
+            },
+        };
+        let mut body = serde_json::to_value(request)?;
+        if let Some(body) = body.as_object_mut() {
+            for (key, value) in extra_body {
+                if key != "model" && key != "messages" && key != "stream" {
+                    body.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Ok(body)
+    }
+
+    /// Reads a single response, or joins the chunks of a streamed one, one JSON object
+    /// per line, for servers that stream regardless.
+    fn replies(&self, body: &str) -> Result<Vec<String>> {
+        if let Ok(response) = serde_json::from_str::<ChatResponse>(body) {
+            return Ok(vec![response.message.content]);
+        }
+        let mut content = String::new();
+        for line in body.lines().filter(|line| !line.trim().is_empty()) {
+            let chunk: ChatResponse = serde_json::from_str(line)?;
+            content.push_str(&chunk.message.content);
+        }
+        Ok(vec![content])
+    }
+
+    fn json_body(&self, mut body: Value, schema: &Value) -> Value {
+        body["format"] = schema.clone();
+        body
+    }
+
+    /// The counts are top-level fields of the last response of a stream, or the only one.
+    fn usage(&self, body: &str) -> Option<Usage> {
+        Usage::read(body, "", "prompt_eval_count", "eval_count")
+    }
+
+    fn sampling_body(&self, mut body: Value, sampling: Sampling) -> Value {
+        if let Some(temperature) = sampling.temperature {
+            body["options"]["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = sampling.top_p {
+            body["options"]["top_p"] = json!(top_p);
+        }
+        body
+    }
+
+    /// A stream is one JSON object per line, without SSE framing.
+    fn stream_delta(&self, data: &str) -> Result<Option<String>> {
+        let chunk: ChatResponse = serde_json::from_str(data)?;
+        Ok(Some(chunk.message.content))
+    }
+
+    fn error(&self, status: StatusCode, body: &str) -> ApiError {
+        match serde_json::from_str::<ErrorResponse>(body) {
+            Ok(response) => ApiError {
+                status,
+                message: Some(response.error),
+                kind: None,
+                code: None,
+            },
+            Err(_) => ApiError::from_body(status, body),
+        }
+    }
+
+    fn models_path(&self) -> String {
+        "api/tags".to_string()
+    }
+
+    fn models(&self, body: &str) -> Result<Vec<String>> {
+        let list: TagList = serde_json::from_str(body)?;
+        // Embedding models are pulled alongside chat models but cannot answer
+        Ok(list
+            .models
+            .into_iter()
+            .map(|model| model.name)
+            .filter(|name| openai::is_chat_model_name(name))
+            .collect())
+    }
+
+    /// Offers to pull a model the server does not have, when there is a terminal to ask on.
+    fn install_model(&self, client: &ApiClient, model: &str, error: &ApiError) -> Result<bool> {
+        let missing = error.status == StatusCode::NOT_FOUND
+            && error
+                .message
+                .as_deref()
+                .is_some_and(|message| message.contains("not found"));
+        if !missing || !io::stdin().is_terminal() || !confirm_pull(model) {
+            return Ok(false);
+        }
+        pull(client, model)?;
+        Ok(true)
+    }
+}
+
+fn confirm_pull(model: &str) -> bool {
+    let mut stderr = io::stderr();
+    let _ = write!(
+        stderr,
+        "Ollama does not have {}. Pull it now? [Y/n] ",
+        model
+    );
+    let _ = stderr.flush();
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).unwrap_or(0);
+    matches!(answer.trim(), "" | "y" | "Y" | "yes")
+}
+
+/// Downloads `model` into the server, drawing its progress on stderr.
+fn pull(client: &ApiClient, model: &str) -> Result<()> {
+    let pull_error = |message: String| CommitGPTError::ModelPullError(model.to_string(), message);
+    let progress = client.post_streaming("api/pull", &json!({ "model": model, "stream": true }))?;
+    let mut stderr = io::stderr();
+    for line in progress.lines() {
+        let line = line.map_err(|e| pull_error(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let update: PullProgress = serde_json::from_str(&line)?;
+        if let Some(message) = update.error {
+            let _ = writeln!(stderr);
+            return Err(pull_error(message));
+        }
+        match (update.completed, update.total) {
+            (Some(completed), Some(total)) if total > 0 => {
+                let filled = (completed.min(total) * PROGRESS_WIDTH / total) as usize;
+                let _ = write!(
+                    stderr,
+                    "\r\x1b[K{} [{}{}] {:>3}%",
+                    update.status,
+                    "#".repeat(filled),
+                    " ".repeat(PROGRESS_WIDTH as usize - filled),
+                    completed.min(total) * 100 / total
+                );
+            }
+            _ => {
+                let _ = write!(stderr, "\r\x1b[K{}", update.status);
+            }
+        }
+        let _ = stderr.flush();
+    }
+    let _ = writeln!(stderr);
+    Ok(())
+}
@@ -0,0 +1,459 @@
+use std::env;
+use std::fmt;
+
+use reqwest::blocking::RequestBuilder;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::provider::{
+    fold_system_messages, top_level_sampling, ApiClient, Capabilities, Provider, Sampling, Session,
+};
+use crate::response::Field;
+use crate::Result;
+
+const OPENAI_API_URL: &str = "https://api.openai.com/v1";
+/// Completion tokens a reasoning model gets on top of the message, since its hidden
+/// reasoning counts against the same limit
+const REASONING_ALLOWANCE: usize = 4096;
+/// Parts of the names of models for embeddings, speech, images and moderation, and of
+/// the completion models that predate chat
+const NOT_CHAT_MODELS: &[&str] = &[
+    "embed",
+    "whisper",
+    "tts",
+    "transcribe",
+    "realtime",
+    "dall-e",
+    "image",
+    "sora",
+    "moderation",
+    "davinci",
+    "babbage",
+    "-instruct",
+];
+
+#[derive(Serialize)]
+struct OpenAIRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    #[serde(skip_serializing_if = "is_one")]
+    n: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<usize>,
+    /// Replaces `max_tokens` for reasoning models, counting their reasoning too
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_completion_tokens: Option<usize>,
+}
+
+fn is_one(n: &usize) -> bool {
+    *n == 1
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+}
+
+impl Message {
+    pub fn system(content: &str) -> Self {
+        Message {
+            role: "system".to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    pub fn user(content: &str) -> Self {
+        Message {
+            role: "user".to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Message {
+            role: "assistant".to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    code: Option<Value>,
+}
+
+/// A non-2xx response, with the provider's error details when its body could be parsed.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: Option<String>,
+    pub kind: Option<String>,
+    pub code: Option<String>,
+}
+
+impl ApiError {
+    pub fn from_body(status: StatusCode, body: &str) -> Self {
+        let parsed = serde_json::from_str::<ErrorResponse>(body)
+            .ok()
+            .map(|response| response.error);
+        let Some(error) = parsed else {
+            return ApiError {
+                status,
+                message: None,
+                kind: None,
+                code: None,
+            };
+        };
+        let code = error.code.and_then(|code| match code {
+            Value::String(code) => Some(code),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        });
+        ApiError {
+            status,
+            message: error.message,
+            kind: error.kind,
+            code,
+        }
+    }
+
+    fn is(&self, name: &str) -> bool {
+        self.code.as_deref() == Some(name) || self.kind.as_deref() == Some(name)
+    }
+
+    /// Whether the same request may well succeed shortly: it was rate limited, though
+    /// not for running out of quota, or the server failed.
+    pub fn is_transient(&self) -> bool {
+        (self.status == StatusCode::TOO_MANY_REQUESTS && !self.is("insufficient_quota"))
+            || self.status.is_server_error()
+    }
+
+    pub fn is_context_length_exceeded(&self) -> bool {
+        self.is("context_length_exceeded")
+    }
+
+    /// What the user can do about the error, when it is a well-known one.
+    pub fn hint(&self) -> Option<&'static str> {
+        if self.is_context_length_exceeded() {
+            Some("the diff is too large for the model; stage fewer files, lower --group-threshold or pick a model with a larger context window")
+        } else if self.is("insufficient_quota") {
+            Some("the account has no remaining quota; check the plan and billing settings of the provider")
+        } else if self.is("invalid_api_key") || self.status == StatusCode::UNAUTHORIZED {
+            // Any of them may have been read, so all are named
+            Some(if cfg!(feature = "keychain") {
+                "the API key was rejected; check the file passed with --api-key-path, the provider's environment variable, like OPENAI_API_KEY, or the key stored with `commit-gpt auth login`"
+            } else {
+                "the API key was rejected; check the file passed with --api-key-path or the provider's environment variable, like OPENAI_API_KEY"
+            })
+        } else if self.is("model_not_found") || self.status == StatusCode::NOT_FOUND {
+            Some("the model does not exist or is not available to this key; check --model")
+        } else if self.is("rate_limit_exceeded") || self.status == StatusCode::TOO_MANY_REQUESTS {
+            Some("requests are being rate limited; wait a moment and try again, or raise --retries")
+        } else if self.status.is_server_error() {
+            Some("the provider had an internal problem; try again later")
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.status)?;
+        if let Some(code) = self.code.as_ref().or(self.kind.as_ref()) {
+            write!(f, " ({})", code)?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        if let Some(hint) = self.hint() {
+            write!(f, "\nHint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// An API in OpenAI's chat completions format, which many vendors, gateways and local
+/// servers speak too. Its implementors are [`Provider`]s that send OpenAI's bodies and
+/// read OpenAI's replies, streams and model listings, overriding only where they differ:
+/// the address, the credentials and a few paths.
+pub trait OpenAICompatible {
+    /// See [`Provider::base_url`].
+    fn base_url(&self) -> String;
+
+    /// See [`Provider::authorize`].
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        request.bearer_auth(api_key)
+    }
+
+    /// See [`Provider::api_key_env`].
+    fn api_key_env(&self) -> Option<String> {
+        None
+    }
+
+    /// See [`Provider::requires_api_key`].
+    fn requires_api_key(&self) -> bool {
+        true
+    }
+
+    /// See [`Provider::sign_in`].
+    fn sign_in(&self, _client: &ApiClient, _api_key: &str) -> Result<Option<Session>> {
+        Ok(None)
+    }
+
+    /// See [`Provider::capabilities`]. JSON is asked for with `response_format`, and
+    /// reasoning models take no system messages.
+    fn capabilities(&self, model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            json_mode: true,
+            system_role: !is_reasoning_model(model),
+            ..Capabilities::default()
+        }
+    }
+
+    /// See [`Provider::chat_path`].
+    fn chat_path(&self, _model: &str) -> String {
+        "chat/completions".to_string()
+    }
+
+    /// See [`Provider::request_body`].
+    fn request_body(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        extra_body: &Map<String, Value>,
+    ) -> Result<Value> {
+        request_body(model, messages, n, max_tokens, extra_body)
+    }
+
+    /// See [`Provider::json_body`].
+    fn json_body(&self, mut body: Value, schema: &Value) -> Value {
+        body["response_format"] = json!({
+            "type": "json_schema",
+            "json_schema": { "name": "commit_message", "strict": true, "schema": schema },
+        });
+        body
+    }
+
+    /// See [`Provider::models_path`].
+    fn models_path(&self) -> String {
+        "models".to_string()
+    }
+}
+
+impl<T: OpenAICompatible> Provider for T {
+    fn base_url(&self) -> String {
+        OpenAICompatible::base_url(self)
+    }
+
+    fn chat_path(&self, model: &str) -> String {
+        OpenAICompatible::chat_path(self, model)
+    }
+
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        OpenAICompatible::authorize(self, request, api_key)
+    }
+
+    fn requires_api_key(&self) -> bool {
+        OpenAICompatible::requires_api_key(self)
+    }
+
+    fn api_key_env(&self) -> Option<String> {
+        OpenAICompatible::api_key_env(self)
+    }
+
+    fn sign_in(&self, client: &ApiClient, api_key: &str) -> Result<Option<Session>> {
+        OpenAICompatible::sign_in(self, client, api_key)
+    }
+
+    fn capabilities(&self, model: &str) -> Capabilities {
+        OpenAICompatible::capabilities(self, model)
+    }
+
+    /// Reasoning models reject `temperature` and `top_p`, so they are left out for them.
+    fn sampling_body(&self, body: Value, sampling: Sampling) -> Value {
+        if body["model"].as_str().is_some_and(is_reasoning_model) {
+            return body;
+        }
+        top_level_sampling(body, sampling)
+    }
+
+    fn request_body(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        extra_body: &Map<String, Value>,
+    ) -> Result<Value> {
+        OpenAICompatible::request_body(self, model, messages, n, max_tokens, extra_body)
+    }
+
+    /// Read leniently, since this format is spoken by many servers besides OpenAI's.
+    fn replies(&self, body: &str) -> Result<Vec<String>> {
+        let body = Field::parse(body)?;
+        let mut replies = Vec::new();
+        for choice in Field::root(&body).require("choices")?.items()? {
+            let reply = match choice.get("message") {
+                // Null content is a refusal or a tool call, neither of which is a message
+                Some(message) => message
+                    .get("content")
+                    .map(|content| content.text())
+                    .transpose()?,
+                // Servers built on the legacy completions API put the text on the choice
+                None => Some(choice.require("text")?.text()?),
+            };
+            replies.push(reply.unwrap_or_default());
+        }
+        Ok(replies)
+    }
+
+    fn json_body(&self, body: Value, schema: &Value) -> Value {
+        OpenAICompatible::json_body(self, body, schema)
+    }
+
+    /// The last chunk of a stream may have no choices, only usage.
+    fn stream_delta(&self, data: &str) -> Result<Option<String>> {
+        let chunk = Field::parse(data)?;
+        let Some(choices) = Field::root(&chunk).get("choices") else {
+            return Ok(None);
+        };
+        let Some(choice) = choices.items()?.into_iter().next() else {
+            return Ok(None);
+        };
+        match choice.get("delta").and_then(|delta| delta.get("content")) {
+            Some(content) => Ok(Some(content.text()?)),
+            None => Ok(None),
+        }
+    }
+
+    fn models_path(&self) -> String {
+        OpenAICompatible::models_path(self)
+    }
+
+    fn models(&self, body: &str) -> Result<Vec<String>> {
+        let body = Field::parse(body)?;
+        let root = Field::root(&body);
+        let list = if body.is_array() {
+            root
+        } else {
+            root.first_of(&["data", "models"])?
+        };
+        let mut models = Vec::new();
+        for model in list.items()? {
+            let id = model.first_of(&["id", "name"])?.text()?;
+            if takes_chat(&model, &id) {
+                models.push(id);
+            }
+        }
+        Ok(models)
+    }
+}
+
+/// The OpenAI chat completions API itself.
+pub struct OpenAI;
+
+impl OpenAICompatible for OpenAI {
+    // Same variable as the official SDKs, e.g. for gateways and test servers
+    fn base_url(&self) -> String {
+        match env::var("OPENAI_BASE_URL") {
+            Ok(base) if !base.is_empty() => base.trim_end_matches('/').to_string(),
+            _ => OPENAI_API_URL.to_string(),
+        }
+    }
+
+    fn api_key_env(&self) -> Option<String> {
+        Some("OPENAI_API_KEY".to_string())
+    }
+}
+
+/// Whether a listed model answers chat requests. Azure and Copilot say so in the
+/// listing, while OpenAI lists embedding, speech and image models and the older
+/// completion models alongside, telling them apart only by name.
+fn takes_chat(model: &Field, id: &str) -> bool {
+    if let Some(capabilities) = model.get("capabilities") {
+        if let Some(chat) = capabilities
+            .get("chat_completion")
+            .and_then(|chat| chat.flag())
+        {
+            return chat;
+        }
+        if let Some(kind) = capabilities.get("type").and_then(|kind| kind.text().ok()) {
+            return kind == "chat";
+        }
+    }
+    is_chat_model_name(id)
+}
+
+/// Whether `model` is not named as a model for anything other than chat.
+pub fn is_chat_model_name(model: &str) -> bool {
+    !NOT_CHAT_MODELS.iter().any(|part| model.contains(part))
+}
+
+/// Whether `model` is one of the o-series reasoning models, which take no system
+/// messages and limit replies with `max_completion_tokens`. Gateways often prefix the
+/// model with its vendor, as in `openai/o1-mini`.
+pub fn is_reasoning_model(model: &str) -> bool {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    let mut chars = name.chars();
+    chars.next() == Some('o') && chars.next().is_some_and(|c| c.is_ascii_digit())
+}
+
+/// The chat completions request body, shaped for reasoning models where `model` is one.
+pub fn request_body(
+    model: &str,
+    messages: &[Message],
+    n: usize,
+    max_tokens: Option<usize>,
+    extra_body: &Map<String, Value>,
+) -> Result<Value> {
+    let reasoning = is_reasoning_model(model);
+    let folded;
+    let messages = if reasoning {
+        folded = fold_system_messages(messages);
+        &folded
+    } else {
+        messages
+    };
+    let request = OpenAIRequest {
+        model,
+        messages,
+        n,
+        max_tokens: max_tokens.filter(|_| !reasoning),
+        max_completion_tokens: max_tokens
+            .filter(|_| reasoning)
+            .map(|tokens| tokens + REASONING_ALLOWANCE),
+    };
+    let mut body = serde_json::to_value(request)?;
+    if let Some(body) = body.as_object_mut() {
+        for (key, value) in extra_body {
+            if key != "model" && key != "messages" {
+                body.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Ok(body)
+}
+
+/// Unwraps a reply the model put in a Markdown code fence, as it often does for JSON.
+pub fn strip_code_fence(reply: &str) -> &str {
+    let reply = reply.trim();
+    let Some(inner) = reply.strip_prefix("```") else {
+        return reply;
+    };
+    let inner = inner.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    inner.strip_suffix("```").unwrap_or(inner).trim()
+}
@@ -0,0 +1,84 @@
+use reqwest::blocking::RequestBuilder;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::openai::{self, Message, OpenAICompatible};
+use crate::Result;
+
+const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1";
+// OpenRouter attributes requests to an app by these two headers
+const DEFAULT_SITE_URL: &str = "https://github.com/juliuskoskela/commit-gpt";
+const DEFAULT_APP_NAME: &str = "commit-gpt";
+
+/// The `[openrouter]` table of the config.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenRouterConfig {
+    /// Models OpenRouter tries in order when `--model` is down, rate limited or refuses the prompt
+    pub fallback_models: Vec<String>,
+    /// Sent as `HTTP-Referer`, which OpenRouter lists the app under
+    pub site_url: Option<String>,
+    /// Sent as `X-Title`
+    pub app_name: Option<String>,
+}
+
+/// OpenRouter, which serves many vendors' models in the OpenAI format under one key
+/// and fails over between them within a single request.
+pub struct OpenRouter {
+    config: OpenRouterConfig,
+}
+
+impl OpenRouter {
+    pub fn new(config: Option<&OpenRouterConfig>) -> Self {
+        OpenRouter {
+            config: config.cloned().unwrap_or_default(),
+        }
+    }
+}
+
+impl OpenAICompatible for OpenRouter {
+    fn base_url(&self) -> String {
+        OPENROUTER_API_URL.to_string()
+    }
+
+    fn api_key_env(&self) -> Option<String> {
+        Some("OPENROUTER_API_KEY".to_string())
+    }
+
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        request
+            .bearer_auth(api_key)
+            .header(
+                "HTTP-Referer",
+                self.config.site_url.as_deref().unwrap_or(DEFAULT_SITE_URL),
+            )
+            .header(
+                "X-Title",
+                self.config.app_name.as_deref().unwrap_or(DEFAULT_APP_NAME),
+            )
+    }
+
+    fn request_body(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        extra_body: &Map<String, Value>,
+    ) -> Result<Value> {
+        let mut body = openai::request_body(model, messages, n, max_tokens, extra_body)?;
+        let fallbacks: Vec<&str> = self
+            .config
+            .fallback_models
+            .iter()
+            .map(String::as_str)
+            .filter(|fallback| *fallback != model)
+            .collect();
+        if let (false, Some(body)) = (fallbacks.is_empty(), body.as_object_mut()) {
+            // The models are tried in the order listed, starting with the requested one
+            let models: Vec<&str> = std::iter::once(model).chain(fallbacks).collect();
+            body.insert("models".to_string(), json!(models));
+        }
+        Ok(body)
+    }
+}
@@ -0,0 +1,72 @@
+use std::process;
+use std::time::Instant;
+
+use reqwest::StatusCode;
+
+use crate::client::Client;
+use crate::config::Config;
+use crate::openai::Message;
+use crate::{connect, Args, CommitGPTError, Result};
+
+// Exit codes, so setup scripts can tell the failures apart
+const EXIT_FAILED: i32 = 1;
+const EXIT_UNREACHABLE: i32 = 2;
+const EXIT_UNAUTHORIZED: i32 = 3;
+
+/// Sends the smallest possible completion request to `--model` and exits with 0 when it
+/// was answered, 2 when the provider could not be reached, 3 when the credentials were
+/// rejected and 1 on any other failure.
+pub fn run(args: &Args, config: &Config) -> ! {
+    let started = Instant::now();
+    let result = connect(args, config).and_then(|client| smallest_request(&client, &args.model));
+    let elapsed = started.elapsed().as_millis();
+
+    let error = match result {
+        Ok(()) => {
+            println!("{} responded in {} ms", args.model, elapsed);
+            process::exit(0);
+        }
+        Err(error) => error,
+    };
+    eprintln!("Error: {} (after {} ms)", error, elapsed);
+    let code = match error {
+        CommitGPTError::HttpRequestError(_) => EXIT_UNREACHABLE,
+        CommitGPTError::MissingApiKeyError(_) | CommitGPTError::ApiKeyReadError(..) => {
+            EXIT_UNAUTHORIZED
+        }
+        CommitGPTError::ApiErrorStatus(error)
+            if matches!(
+                error.status,
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+            ) =>
+        {
+            EXIT_UNAUTHORIZED
+        }
+        _ => EXIT_FAILED,
+    };
+    process::exit(code);
+}
+
+/// Loads `--model` on the provider with a one-token request, so the first real
+/// generation does not wait for it. Failures are only reported, since generation
+/// reports them again.
+pub fn warm_up(args: &Args, config: &Config) {
+    let started = Instant::now();
+    match connect(args, config).and_then(|client| smallest_request(&client, &args.model)) {
+        Ok(()) if args.verbose => eprintln!(
+            "Warmed up {} in {} ms",
+            args.model,
+            started.elapsed().as_millis()
+        ),
+        Ok(()) => {}
+        Err(e) => eprintln!("Warning: warming up {} failed: {}", args.model, e),
+    }
+}
+
+fn smallest_request(client: &Client, model: &str) -> Result<()> {
+    match client.complete_n(model, &[Message::user("ping")], 1, Some(1)) {
+        // Any reply, even an empty one, shows the model is being served
+        Ok(_) | Err(CommitGPTError::NoCommitMessage) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,206 @@
+// The items of Python, JavaScript, TypeScript and Go files, parsed with tree-sitter, for
+// the symbol-level summary `symbols.rs` makes of Rust files with syn. Items are compared
+// by their text with the whitespace collapsed, so reindenting does not count as a change.
+
+use tree_sitter::{Language, Node, Parser};
+
+use crate::symbols::Items;
+
+#[derive(Clone, Copy)]
+enum Grammar {
+    Python,
+    /// JavaScript and TypeScript, whose declarations share their node kinds
+    Script,
+    Go,
+}
+
+fn language(extension: &str) -> Option<(Language, Grammar)> {
+    Some(match extension {
+        "py" | "pyi" => (tree_sitter_python::LANGUAGE.into(), Grammar::Python),
+        "js" | "jsx" | "mjs" | "cjs" => (tree_sitter_javascript::LANGUAGE.into(), Grammar::Script),
+        "ts" | "mts" | "cts" => (
+            tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Grammar::Script,
+        ),
+        "tsx" => (tree_sitter_typescript::LANGUAGE_TSX.into(), Grammar::Script),
+        "go" => (tree_sitter_go::LANGUAGE.into(), Grammar::Go),
+        _ => return None,
+    })
+}
+
+/// Whether files with `extension` have a grammar here.
+pub fn supports(extension: &str) -> bool {
+    language(extension).is_some()
+}
+
+/// The items of `source`, or `None` when it has syntax errors. tree-sitter parses past
+/// them, but what it makes of the code around them is guesswork.
+pub fn parse(extension: &str, source: &str) -> Option<Items> {
+    let (language, grammar) = language(extension)?;
+    let mut parser = Parser::new();
+    parser.set_language(&language).ok()?;
+    let tree = parser.parse(source, None)?;
+    let root = tree.root_node();
+    if root.has_error() {
+        return None;
+    }
+
+    let file = File { source, grammar };
+    let mut items = Items::default();
+    let mut cursor = root.walk();
+    for node in root.named_children(&mut cursor) {
+        file.item(node, node, &mut items);
+    }
+    Some(items)
+}
+
+struct File<'a> {
+    source: &'a str,
+    grammar: Grammar,
+}
+
+impl File<'_> {
+    /// Adds the item `node` declares, if any. `whole` is the node with what wraps the
+    /// declaration, like decorators or `export`, which count as part of the item.
+    fn item(&self, node: Node, whole: Node, items: &mut Items) {
+        let tokens = self.tokens(whole);
+        let named = |kind: &str| self.name(node).map(|name| format!("{} `{}`", kind, name));
+        match (self.grammar, node.kind()) {
+            (Grammar::Python, "decorated_definition") | (Grammar::Script, "export_statement") => {
+                if let Some(definition) = node
+                    .child_by_field_name("definition")
+                    .or_else(|| node.child_by_field_name("declaration"))
+                {
+                    self.item(definition, whole, items);
+                }
+            }
+            (Grammar::Python, "function_definition")
+            | (Grammar::Script, "function_declaration" | "generator_function_declaration")
+            | (Grammar::Go, "function_declaration") => {
+                if let Some(key) = named("function") {
+                    items.add(key, &tokens, Vec::new());
+                }
+            }
+            (Grammar::Python, "class_definition")
+            | (Grammar::Script, "class_declaration" | "abstract_class_declaration") => {
+                if let Some(class) = self.name(node) {
+                    let members = self.members(node, &class);
+                    items.add(format!("class `{}`", class), &tokens, members);
+                }
+            }
+            (Grammar::Script, "interface_declaration") => {
+                if let Some(key) = named("interface") {
+                    items.add(key, &tokens, Vec::new());
+                }
+            }
+            (Grammar::Script, "type_alias_declaration") => {
+                if let Some(key) = named("type") {
+                    items.add(key, &tokens, Vec::new());
+                }
+            }
+            (Grammar::Script, "enum_declaration") => {
+                if let Some(key) = named("enum") {
+                    items.add(key, &tokens, Vec::new());
+                }
+            }
+            // `const login = () => ...` is as much a function as a declared one
+            (Grammar::Script, "lexical_declaration" | "variable_declaration") => {
+                let mut cursor = node.walk();
+                for declarator in node.named_children(&mut cursor) {
+                    let is_function =
+                        declarator
+                            .child_by_field_name("value")
+                            .is_some_and(|value| {
+                                matches!(
+                                    value.kind(),
+                                    "arrow_function" | "function_expression" | "function"
+                                )
+                            });
+                    if let (true, Some(name)) = (is_function, self.name(declarator)) {
+                        items.add(
+                            format!("function `{}`", name),
+                            &self.tokens(declarator),
+                            Vec::new(),
+                        );
+                    }
+                }
+            }
+            // Go methods are declared outside their type, so they are items of their own
+            (Grammar::Go, "method_declaration") => {
+                if let (Some(receiver), Some(name)) = (self.receiver(node), self.name(node)) {
+                    items.add(
+                        format!("method `{}.{}`", receiver, name),
+                        &tokens,
+                        Vec::new(),
+                    );
+                }
+            }
+            (Grammar::Go, "type_declaration") => {
+                let mut cursor = node.walk();
+                for spec in node.named_children(&mut cursor) {
+                    if let Some(name) = self.name(spec) {
+                        items.add(format!("type `{}`", name), &self.tokens(spec), Vec::new());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The methods and fields in the body of `class`, named like "method `User.login`".
+    fn members(&self, class: Node, name: &str) -> Vec<(String, String)> {
+        let Some(body) = class.child_by_field_name("body") else {
+            return Vec::new();
+        };
+        let mut members = Vec::new();
+        let mut cursor = body.walk();
+        for whole in body.named_children(&mut cursor) {
+            let node = match whole.kind() {
+                "decorated_definition" => whole.child_by_field_name("definition").unwrap_or(whole),
+                _ => whole,
+            };
+            let kind = match node.kind() {
+                "function_definition"
+                | "method_definition"
+                | "method_signature"
+                | "abstract_method_signature" => "method",
+                "field_definition" | "public_field_definition" => "field",
+                _ => continue,
+            };
+            if let Some(member) = self.name(node) {
+                members.push((
+                    format!("{} `{}.{}`", kind, name, member),
+                    self.tokens(whole),
+                ));
+            }
+        }
+        members
+    }
+
+    /// The type a Go method is declared on, without pointer or type parameters.
+    fn receiver(&self, method: Node) -> Option<String> {
+        let receiver = method.child_by_field_name("receiver")?;
+        let mut cursor = receiver.walk();
+        let parameter = receiver.named_children(&mut cursor).next()?;
+        let kind = self.text(parameter.child_by_field_name("type")?);
+        let kind = kind.trim_start_matches('*');
+        Some(kind.split('[').next().unwrap_or(kind).to_string())
+    }
+
+    fn name(&self, node: Node) -> Option<String> {
+        node.child_by_field_name("name")
+            .or_else(|| node.child_by_field_name("property"))
+            .map(|name| self.text(name).to_string())
+    }
+
+    fn text(&self, node: Node) -> &str {
+        node.utf8_text(self.source.as_bytes()).unwrap_or_default()
+    }
+
+    fn tokens(&self, node: Node) -> String {
+        self.text(node)
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
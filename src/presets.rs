@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use reqwest::blocking::RequestBuilder;
+use reqwest::header::AUTHORIZATION;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::openai::OpenAICompatible;
+
+/// A vendor whose API is OpenAI's at another address, so it needs no config beyond the key.
+pub struct Preset {
+    pub base_url: &'static str,
+    /// Used for every model option left at its default
+    pub default_model: &'static str,
+    /// Where the key is read from without `--api-key-path`
+    pub api_key_env: &'static str,
+}
+
+pub const XAI: Preset = Preset {
+    base_url: "https://api.x.ai/v1",
+    default_model: "grok-2-latest",
+    api_key_env: "XAI_API_KEY",
+};
+
+pub const DEEPSEEK: Preset = Preset {
+    base_url: "https://api.deepseek.com/v1",
+    default_model: "deepseek-chat",
+    api_key_env: "DEEPSEEK_API_KEY",
+};
+
+/// How an [`Endpoint`] is sent the API key.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthStyle {
+    /// `Authorization: Bearer <key>`, as OpenAI takes it
+    #[default]
+    Bearer,
+    /// The bare key in the header named by `auth_header`
+    Header,
+    /// No key at all, as for a gateway that authenticates by network
+    None,
+}
+
+/// A named endpoint from the `[endpoints]` config table, picked with `--endpoint`, for
+/// an API in OpenAI's format that has no preset, like Groq, Mistral or a company gateway.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Endpoint {
+    pub base_url: String,
+    #[serde(default)]
+    pub auth: AuthStyle,
+    /// The header the key goes in with `auth = "header"`
+    #[serde(default = "default_auth_header")]
+    pub auth_header: String,
+    /// Used for every model option left at its default
+    pub default_model: Option<String>,
+    /// Sent with every request to this endpoint, after the `headers` of the config
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// The environment variable the key is read from without `--api-key-path`
+    pub api_key_env: Option<String>,
+}
+
+fn default_auth_header() -> String {
+    "api-key".to_string()
+}
+
+impl From<&Preset> for Endpoint {
+    fn from(preset: &Preset) -> Self {
+        Endpoint {
+            base_url: preset.base_url.to_string(),
+            auth: AuthStyle::Bearer,
+            auth_header: default_auth_header(),
+            default_model: Some(preset.default_model.to_string()),
+            headers: BTreeMap::new(),
+            api_key_env: Some(preset.api_key_env.to_string()),
+        }
+    }
+}
+
+impl OpenAICompatible for Endpoint {
+    fn base_url(&self) -> String {
+        self.base_url.trim_end_matches('/').to_string()
+    }
+
+    fn requires_api_key(&self) -> bool {
+        self.auth != AuthStyle::None
+    }
+
+    fn api_key_env(&self) -> Option<String> {
+        self.api_key_env.clone()
+    }
+
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        let mut request = match self.auth {
+            AuthStyle::Bearer => request.header(AUTHORIZATION, format!("Bearer {}", api_key)),
+            AuthStyle::Header => request.header(self.auth_header.as_str(), api_key),
+            AuthStyle::None => request,
+        };
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        request
+    }
+
+    // Few compatible servers take a schema, but most take a request for any JSON
+    fn json_body(&self, mut body: Value, _schema: &Value) -> Value {
+        body["response_format"] = json!({ "type": "json_object" });
+        body
+    }
+}
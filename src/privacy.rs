@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use git2::{ObjectType, Oid};
+use regex::Regex;
+
+use crate::cache::{CachedFile, SummaryCache};
+use crate::changes::FileChange;
+
+const HASH_LENGTH: usize = 8;
+
+/// How file paths are disguised in prompts for repositories whose names are sensitive.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathPrivacy {
+    /// Replace every directory and file name with a salted hash
+    Hash,
+    /// Number the directories and files, e.g. `dir2/file5.rs`
+    Bucket,
+}
+
+/// The aliases of the path components of a change, so the reply can be translated back.
+/// Extensions are kept, since they tell the model what kind of file changed.
+pub struct PathMap {
+    privacy: PathPrivacy,
+    salt: String,
+    // Directories and files numbered so far
+    counts: (usize, usize),
+    // Alias to real name, per directory name and file stem
+    reveal: BTreeMap<String, String>,
+    // Real name to alias
+    hide: BTreeMap<String, String>,
+    // Any of the real names, longest first
+    names: Option<Regex>,
+}
+
+impl PathMap {
+    /// Hashes are salted with `salt`, which never leaves the machine, so short names
+    /// cannot be recovered by hashing a dictionary.
+    pub fn new(privacy: PathPrivacy, salt: &str, changes: &[FileChange]) -> Self {
+        let mut map = PathMap {
+            privacy,
+            salt: salt.to_string(),
+            counts: (0, 0),
+            reveal: BTreeMap::new(),
+            hide: BTreeMap::new(),
+            names: None,
+        };
+        map.add_paths(changes.iter().flat_map(|change| paths_of(change)));
+        map
+    }
+
+    /// Gives the components of `paths` aliases too, e.g. those of files an earlier draft
+    /// described, so they are hidden wherever they are mentioned.
+    pub fn add_paths<'a>(&mut self, paths: impl IntoIterator<Item = &'a str>) {
+        for path in paths {
+            let components: Vec<&str> = path.split('/').collect();
+            for (index, component) in components.iter().enumerate() {
+                let is_file = index + 1 == components.len();
+                let name = if is_file { stem(component) } else { component };
+                if name.is_empty() || self.hide.contains_key(name) {
+                    continue;
+                }
+                let alias = match self.privacy {
+                    PathPrivacy::Hash => {
+                        let oid = Oid::hash_object(
+                            ObjectType::Blob,
+                            format!("{}\n{}", self.salt, name).as_bytes(),
+                        )
+                        .map(|oid| oid.to_string())
+                        .unwrap_or_default();
+                        oid[..HASH_LENGTH.min(oid.len())].to_string()
+                    }
+                    PathPrivacy::Bucket if is_file => {
+                        self.counts.1 += 1;
+                        format!("file{}", self.counts.1)
+                    }
+                    PathPrivacy::Bucket => {
+                        self.counts.0 += 1;
+                        format!("dir{}", self.counts.0)
+                    }
+                };
+                self.hide.insert(name.to_string(), alias.clone());
+                self.reveal.insert(alias, name.to_string());
+            }
+        }
+        self.names = names_pattern(self.hide.keys());
+    }
+
+    /// `changes` with their paths replaced by aliases, and every mention of a directory
+    /// or file name in their lines, notes, items and hunk headers as well, since code
+    /// refers to the files it lives in, as in `mod radar;`.
+    pub fn hide(&self, changes: &[FileChange]) -> Vec<FileChange> {
+        changes
+            .iter()
+            .map(|change| {
+                let mut change = change.clone();
+                change.file_path = self.hide_path(&change.file_path);
+                for text in change
+                    .summaries
+                    .iter_mut()
+                    .chain(&mut change.notes)
+                    .chain(&mut change.symbols)
+                    .chain(change.hunks.iter_mut().map(|hunk| &mut hunk.header))
+                {
+                    *text = self.hide_text(text);
+                }
+                change
+            })
+            .collect()
+    }
+
+    /// `text` with the real names that stand as words of their own replaced by aliases,
+    /// for anything else that ends up in the prompt.
+    pub fn hide_text(&self, text: &str) -> String {
+        let Some(names) = &self.names else {
+            return text.to_string();
+        };
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let mut hidden = String::new();
+        let (mut copied, mut at) = (0, 0);
+        while let Some(found) = names.find_at(text, at) {
+            let before = text[..found.start()].chars().next_back();
+            let after = text[found.end()..].chars().next();
+            if is_word(before) || is_word(after) {
+                // Part of a longer word; a shorter name may still start later in it
+                at = found.start()
+                    + text[found.start()..]
+                        .chars()
+                        .next()
+                        .map_or(1, char::len_utf8);
+                continue;
+            }
+            hidden.push_str(&text[copied..found.start()]);
+            hidden.push_str(&self.hide[found.as_str()]);
+            (copied, at) = (found.end(), found.end());
+        }
+        hidden.push_str(&text[copied..]);
+        hidden
+    }
+
+    fn hide_path(&self, path: &str) -> String {
+        let components: Vec<&str> = path.split('/').collect();
+        let hidden: Vec<String> = components
+            .iter()
+            .enumerate()
+            .map(|(index, component)| {
+                let is_file = index + 1 == components.len();
+                let name = if is_file { stem(component) } else { component };
+                let alias = self.hide.get(name).map_or(name, String::as_str);
+                format!("{}{}", alias, &component[name.len()..])
+            })
+            .collect();
+        hidden.join("/")
+    }
+
+    /// `cache` as the prompt shows it, with the paths, summaries and message disguised.
+    pub fn hide_cache(&self, cache: &SummaryCache) -> SummaryCache {
+        SummaryCache {
+            message: self.hide_text(&cache.message),
+            files: cache
+                .files
+                .iter()
+                .map(|(key, file)| {
+                    let file = CachedFile {
+                        file_path: self.hide_path(&file.file_path),
+                        change_type: file.change_type.clone(),
+                        summary: file
+                            .summary
+                            .as_deref()
+                            .map(|summary| self.hide_text(summary)),
+                    };
+                    (key.clone(), file)
+                })
+                .collect(),
+        }
+    }
+
+    /// `cache` with the real paths and summaries, to be disguised again by the next run.
+    /// The message is kept, since it is revealed before anything else is done with it.
+    pub fn reveal_cache(&self, cache: SummaryCache) -> SummaryCache {
+        SummaryCache {
+            files: cache
+                .files
+                .into_iter()
+                .map(|(key, file)| {
+                    let file = CachedFile {
+                        file_path: self.reveal(&file.file_path),
+                        summary: file.summary.as_deref().map(|summary| self.reveal(summary)),
+                        ..file
+                    };
+                    (key, file)
+                })
+                .collect(),
+            ..cache
+        }
+    }
+
+    /// Replaces the aliases in `message` with the real names.
+    pub fn reveal(&self, message: &str) -> String {
+        if self.reveal.is_empty() {
+            return message.to_string();
+        }
+        let mut aliases: Vec<&String> = self.reveal.keys().collect();
+        aliases.sort_by_key(|alias| std::cmp::Reverse(alias.len()));
+        let pattern = aliases
+            .iter()
+            .map(|alias| regex::escape(alias))
+            .collect::<Vec<_>>()
+            .join("|");
+        let pattern = Regex::new(&format!(r"\b({})\b", pattern)).unwrap();
+        pattern
+            .replace_all(message, |captures: &regex::Captures| {
+                self.reveal[&captures[1]].clone()
+            })
+            .into_owned()
+    }
+}
+
+/// A pattern matching any of `names`, preferring the longest, or `None` without any.
+fn names_pattern<'a>(names: impl Iterator<Item = &'a String>) -> Option<Regex> {
+    let mut names: Vec<&String> = names.collect();
+    if names.is_empty() {
+        return None;
+    }
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+    let pattern = names
+        .iter()
+        .map(|name| regex::escape(name))
+        .collect::<Vec<_>>()
+        .join("|");
+    Regex::new(&pattern).ok()
+}
+
+/// The current and, for renames, previous path of a change.
+fn paths_of(change: &FileChange) -> Vec<&str> {
+    let mut paths = vec![change.file_path.as_str()];
+    paths.extend(
+        change
+            .summaries
+            .iter()
+            .filter_map(|summary| summary.strip_prefix("Renamed from ")),
+    );
+    paths
+}
+
+/// A file name without its extensions, e.g. `schema` for `schema.d.ts`; dotfiles are kept whole.
+fn stem(name: &str) -> &str {
+    match name.find('.') {
+        Some(0) | None => name,
+        Some(dot) => &name[..dot],
+    }
+}
@@ -0,0 +1,206 @@
+use clap::ValueEnum;
+
+use crate::injection;
+use crate::validate::Mood;
+
+const SYSTEM_PROMPT_TEMPLATE: &str = "You are a helpful assistant that writes clear and concise Git commit messages in {mood}, without any speculation.";
+pub const USER_PROMPT_TEMPLATE: &str = "\
+Write a Git commit message with a short title and a detailed body, using {mood}. Do not include any speculation or guesses. Be concise and precise. Use bullet points in the body to list changes. Format the message as a git commit message with no extra metadata, symbols or quotes in a way that it can be directly copy pasted to the commit.
+
+Context: {context}
+
+{sections}{sandbox_instruction}
+
+Changes:
+{structured_changes}";
+pub const REGENERATE_PROMPT: &str =
+    "Write a different commit message for the same changes, following the same rules.";
+
+/// The system prompt asking for messages in `mood`.
+pub fn system_prompt(mood: Mood) -> String {
+    fill(SYSTEM_PROMPT_TEMPLATE, &[("mood", mood.phrase())])
+}
+
+/// The user prompt minus the structured changes, which are rendered in last so
+/// they can be cut to fit the model's budget.
+#[derive(Clone, Default)]
+pub struct Prompt {
+    context: String,
+    sections: Vec<(String, String)>,
+    mood: Mood,
+}
+
+impl Prompt {
+    /// Adds a titled block of extra instructions or material between the context and the changes.
+    pub fn section(&mut self, title: &str, body: &str) {
+        self.sections
+            .push((title.to_string(), body.trim_end().to_string()));
+    }
+
+    /// The system prompt to send along, see [`system_prompt`].
+    pub fn system(&self) -> String {
+        system_prompt(self.mood)
+    }
+
+    pub fn render(&self, structured_changes: &str) -> String {
+        self.render_template(USER_PROMPT_TEMPLATE, structured_changes)
+    }
+
+    /// Renders a template with the same `{context}`, `{sections}`, `{mood}`,
+    /// `{sandbox_instruction}` and `{structured_changes}` placeholders as the built-in prompt.
+    pub fn render_template(&self, template: &str, structured_changes: &str) -> String {
+        let sections: String = self
+            .sections
+            .iter()
+            .map(|(title, body)| format!("{}:\n{}\n\n", title, body))
+            .collect();
+
+        fill(
+            template,
+            &[
+                ("context", &self.context),
+                ("sections", &sections),
+                ("mood", self.mood.phrase()),
+                ("sandbox_instruction", injection::SANDBOX_INSTRUCTION),
+                (
+                    "structured_changes",
+                    &injection::sandbox(structured_changes),
+                ),
+            ],
+        )
+    }
+}
+
+/// Assembles a [`Prompt`] from its usual parts, in the order they are added, so callers
+/// never format sections by hand.
+#[derive(Default)]
+pub struct PromptBuilder {
+    prompt: Prompt,
+}
+
+impl PromptBuilder {
+    pub fn new() -> Self {
+        PromptBuilder::default()
+    }
+
+    /// What the author says about the change.
+    pub fn context(mut self, context: &str) -> Self {
+        self.prompt.context = context.to_string();
+        self
+    }
+
+    /// The grammatical mood the message is written in.
+    pub fn mood(mut self, mood: Mood) -> Self {
+        self.prompt.mood = mood;
+        self
+    }
+
+    /// Conventions and terminology of the project.
+    pub fn project_context(self, context: &str) -> Self {
+        self.section(
+            "Project context (conventions and terminology to follow)",
+            context,
+        )
+    }
+
+    /// The layout the message must follow, e.g. from [`crate::template::prompt_section`].
+    pub fn format(self, format: &str) -> Self {
+        self.section("Message format", format)
+    }
+
+    /// Messages whose tone and layout to imitate. They are escaped, since they may come
+    /// from anyone who can commit.
+    pub fn style_examples(self, examples: &[String]) -> Self {
+        if examples.is_empty() {
+            return self;
+        }
+        let mut body = String::from(
+            "Match the tone, length and layout of these messages, not their content:\n",
+        );
+        for example in examples {
+            body.push_str(&format!("---\n{}\n", injection::escape(example.trim_end())));
+        }
+        self.section("Style examples", &body)
+    }
+
+    /// Adds any other titled section.
+    pub fn section(mut self, title: &str, body: &str) -> Self {
+        self.prompt.section(title, body);
+        self
+    }
+
+    pub fn build(self) -> Prompt {
+        self.prompt
+    }
+}
+
+/// Substitutes the `{name}` placeholders of `template` in a single pass, so braces in
+/// the substituted text, e.g. from a diff, are never expanded themselves.
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = values.iter().find(|(name, _)| {
+            rest.strip_prefix('{')
+                .and_then(|after| after.strip_prefix(name))
+                .is_some_and(|after| after.starts_with('}'))
+        });
+        match placeholder {
+            Some((name, value)) => {
+                output.push_str(value);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                output.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// What kind of change the author says this is, steering framing and the Conventional Commit type.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Intent {
+    Fix,
+    Feature,
+    Refactor,
+    Perf,
+    Docs,
+    Chore,
+}
+
+impl Intent {
+    pub fn conventional_type(self) -> &'static str {
+        match self {
+            Intent::Fix => "fix",
+            Intent::Feature => "feat",
+            Intent::Refactor => "refactor",
+            Intent::Perf => "perf",
+            Intent::Docs => "docs",
+            Intent::Chore => "chore",
+        }
+    }
+
+    fn framing(self) -> &'static str {
+        match self {
+            Intent::Fix => "a bug fix. Describe the problem that was fixed and how",
+            Intent::Feature => "a new feature. Describe the capability that was added and how to use it",
+            Intent::Refactor => "a refactoring without behavior changes. Describe the structural change and why it helps",
+            Intent::Perf => "a performance improvement. Describe what got faster or cheaper and how",
+            Intent::Docs => "a documentation change. Describe what is now documented",
+            Intent::Chore => "maintenance work. Describe the housekeeping briefly",
+        }
+    }
+
+    pub fn prompt_section(self) -> String {
+        format!(
+            "This change is {}. Start the title with the Conventional Commit type `{}: ` (an optional scope in parentheses is allowed).",
+            self.framing(),
+            self.conventional_type()
+        )
+    }
+}
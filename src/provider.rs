@@ -0,0 +1,896 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Certificate, NoProxy, Proxy, StatusCode, Url};
+use serde_json::{json, Map, Value};
+
+use crate::anthropic::Anthropic;
+use crate::audit::AuditLog;
+use crate::azure::Azure;
+use crate::bedrock::Bedrock;
+use crate::config::Config;
+use crate::copilot::Copilot;
+use crate::deadline::{self, Deadlines, Stage};
+use crate::gemini::Gemini;
+use crate::ollama::Ollama;
+use crate::openai::{ApiError, Message, OpenAI};
+use crate::openrouter::OpenRouter;
+use crate::presets::{Endpoint, DEEPSEEK, XAI};
+use crate::response;
+use crate::retry;
+use crate::{CommitGPTError, Result};
+
+const USER_AGENT: &str = concat!("commit-gpt/", env!("CARGO_PKG_VERSION"));
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// The wire format of a chat API: where requests go, how they are authorized and
+/// encoded, and how replies are read back. Prompts, budgeting and validation are shared
+/// by every provider; only this part differs.
+pub trait Provider {
+    /// The API base URL, without a trailing slash.
+    fn base_url(&self) -> String;
+
+    /// The path of the chat endpoint under the base URL.
+    fn chat_path(&self, model: &str) -> String;
+
+    /// Adds the credentials to a request.
+    fn authorize(&self, request: RequestBuilder, api_key: &str) -> RequestBuilder;
+
+    /// Headers computed from the whole request of `method` to `url` with `body`, for
+    /// providers that sign requests instead of sending a key.
+    fn sign(&self, _method: &str, _url: &str, _body: &[u8]) -> Result<Vec<(String, String)>> {
+        Ok(Vec::new())
+    }
+
+    /// Whether requests fail without an API key, rather than merely go without one.
+    fn requires_api_key(&self) -> bool {
+        true
+    }
+
+    /// The environment variable the key is read from without `--api-key-path`.
+    fn api_key_env(&self) -> Option<String> {
+        None
+    }
+
+    /// Trades `api_key` for the credentials requests are sent with, for providers whose
+    /// keys are short-lived. Called once, before any other request.
+    fn sign_in(&self, _client: &ApiClient, _api_key: &str) -> Result<Option<Session>> {
+        Ok(None)
+    }
+
+    /// Whether one request can ask for several replies; otherwise one is sent per reply.
+    fn supports_n(&self) -> bool {
+        true
+    }
+
+    /// What the API can do for `model`.
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// `body` from [`Provider::request_body`], asking for a reply that is JSON matching
+    /// `schema`, for providers with [`Capabilities::json_mode`].
+    fn json_body(&self, body: Value, _schema: &Value) -> Value {
+        body
+    }
+
+    /// `body` from [`Provider::request_body`], asking for the reply to be streamed.
+    fn stream_body(&self, mut body: Value) -> Value {
+        if let Some(body) = body.as_object_mut() {
+            body.insert("stream".to_string(), Value::Bool(true));
+        }
+        body
+    }
+
+    /// `body` from [`Provider::request_body`], with the sampling parameters that were
+    /// given. Most APIs take them as top-level `temperature` and `top_p` fields.
+    fn sampling_body(&self, body: Value, sampling: Sampling) -> Value {
+        top_level_sampling(body, sampling)
+    }
+
+    /// The path of the streaming chat endpoint, which most APIs share with the other one.
+    fn stream_path(&self, model: &str) -> String {
+        self.chat_path(model)
+    }
+
+    /// The text one event of a streamed reply adds, from its payload. `None` for events
+    /// without text, like the ones that open or close the stream.
+    fn stream_delta(&self, _data: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// The body asking `model` for `n` replies of at most `max_tokens` tokens, with the
+    /// configured `extra_body` fields merged in.
+    fn request_body(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        extra_body: &Map<String, Value>,
+    ) -> Result<Value>;
+
+    /// The text of every reply in a successful response body.
+    fn replies(&self, body: &str) -> Result<Vec<String>>;
+
+    /// The tokens a successful response body says the request took. OpenAI-style APIs
+    /// report them as `usage` with prompt and completion tokens, Anthropic's as input and
+    /// output tokens.
+    fn usage(&self, body: &str) -> Option<Usage> {
+        Usage::read(body, "usage", "prompt_tokens", "completion_tokens")
+            .or_else(|| Usage::read(body, "usage", "input_tokens", "output_tokens"))
+    }
+
+    /// The details of a non-2xx response.
+    fn error(&self, status: StatusCode, body: &str) -> ApiError {
+        ApiError::from_body(status, body)
+    }
+
+    /// The path listing the available models under the base URL, or its own URL.
+    fn models_path(&self) -> String;
+
+    /// The model ids in a successful response from the models endpoint.
+    fn models(&self, body: &str) -> Result<Vec<String>>;
+
+    /// Offers to make `model` available after a request for it failed with `error`,
+    /// returning whether the request is worth repeating.
+    fn install_model(&self, _client: &ApiClient, _model: &str, _error: &ApiError) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// What a provider's API can do for a model, so that budgeting, validation and output
+/// formats adapt to it instead of checking which provider they talk to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Replies can be streamed as they are written, read with [`Provider::stream_delta`]
+    pub streaming: bool,
+    /// Replies can be constrained to valid JSON
+    pub json_mode: bool,
+    /// System messages are accepted; otherwise they are folded into the first user message
+    pub system_role: bool,
+    /// The context window in tokens, when the provider knows it better than the model's name does
+    pub max_context: Option<usize>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            streaming: false,
+            json_mode: false,
+            system_role: true,
+            max_context: None,
+        }
+    }
+}
+
+/// Credentials a provider handed out in exchange for the configured ones.
+pub struct Session {
+    pub api_key: String,
+    /// Where the requests with this key go, when the provider says so
+    pub base_url: Option<String>,
+}
+
+/// The providers `--provider` can pick.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProviderKind {
+    /// The OpenAI chat completions API, or a server compatible with it (see OPENAI_BASE_URL)
+    #[default]
+    Openai,
+    /// A local Ollama server (see OLLAMA_HOST), which needs no API key
+    Ollama,
+    /// Anthropic's messages API for Claude models (see ANTHROPIC_BASE_URL)
+    Anthropic,
+    /// OpenAI models deployed on an Azure resource, set up in the `[azure]` config table
+    Azure,
+    /// Google's Gemini API (see GEMINI_BASE_URL)
+    Gemini,
+    /// GitHub Copilot's chat API, signed in through a Copilot editor plugin's or the
+    /// GitHub CLI's token, and sent as VS Code's chat unless the `[copilot]` config table
+    /// names another integration
+    Copilot,
+    /// Amazon Bedrock's Converse API, signed with the AWS CLI's credentials and region
+    Bedrock,
+    /// xAI's API for Grok models
+    Xai,
+    /// DeepSeek's API
+    Deepseek,
+    /// OpenRouter, with the fallback models in the `[openrouter]` config table
+    Openrouter,
+    /// The model compiled into commit-gpt with the `builtin` feature, run in-process
+    Builtin,
+    /// Canned replies from --mock-replies, for tests and demos without a network or API key
+    Mock,
+}
+
+impl ProviderKind {
+    /// Whether the provider runs on this machine, where the model may first have to be loaded.
+    pub fn is_local(self) -> bool {
+        matches!(self, ProviderKind::Ollama)
+    }
+
+    /// The models used where none are given, for providers that serve no OpenAI models
+    /// or name them otherwise. The rest take the OpenAI defaults of the options.
+    pub fn default_models(self) -> Option<DefaultModels> {
+        let (large, small) = match self {
+            ProviderKind::Anthropic => ("claude-3-5-sonnet-latest", "claude-3-5-haiku-latest"),
+            ProviderKind::Gemini => ("gemini-1.5-pro", "gemini-1.5-flash"),
+            // Every model has to be pulled first, so one does for everything
+            ProviderKind::Ollama => ("llama3.1", "llama3.1"),
+            ProviderKind::Bedrock => (
+                "anthropic.claude-3-5-sonnet-20240620-v1:0",
+                "anthropic.claude-3-haiku-20240307-v1:0",
+            ),
+            ProviderKind::Openrouter => ("openai/gpt-4o", "openai/gpt-4o-mini"),
+            ProviderKind::Xai => (XAI.default_model, XAI.default_model),
+            ProviderKind::Deepseek => (DEEPSEEK.default_model, DEEPSEEK.default_model),
+            _ => return None,
+        };
+        Some(DefaultModels { large, small })
+    }
+
+    pub fn provider(self, config: &Config) -> Result<Box<dyn Provider>> {
+        Ok(match self {
+            ProviderKind::Openai => Box::new(OpenAI),
+            ProviderKind::Ollama => Box::new(Ollama),
+            ProviderKind::Anthropic => Box::new(Anthropic),
+            ProviderKind::Azure => Box::new(Azure::new(config.azure.as_ref())?),
+            ProviderKind::Gemini => Box::new(Gemini),
+            ProviderKind::Copilot => Box::new(Copilot::new(config.copilot.as_ref())),
+            ProviderKind::Bedrock => Box::new(Bedrock::new()?),
+            ProviderKind::Xai => Box::new(Endpoint::from(&XAI)),
+            ProviderKind::Deepseek => Box::new(Endpoint::from(&DEEPSEEK)),
+            ProviderKind::Openrouter => Box::new(OpenRouter::new(config.openrouter.as_ref())),
+            // Has no API; `connect` runs it in-process instead
+            ProviderKind::Builtin => return Err(CommitGPTError::BuiltinModelApi),
+            ProviderKind::Mock => return Err(CommitGPTError::MockProviderApi),
+        })
+    }
+}
+
+/// The models a provider falls back on for the model options left at their defaults.
+#[derive(Clone, Copy, Debug)]
+pub struct DefaultModels {
+    /// For `--model`, and for large changes with `--model auto`
+    pub large: &'static str,
+    /// For clustering and judging, and for small changes with `--model auto`
+    pub small: &'static str,
+}
+
+/// How requests are sent, from the command line.
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+    /// Report the latency of every call on stderr
+    pub verbose: bool,
+    /// Fail every request instead of sending it
+    pub forbid_network: bool,
+    /// Replaces the provider's API base, e.g. for a proxy or a self-hosted server
+    pub api_base: Option<String>,
+    /// Sent with every request in addition to the configured headers, overriding them
+    pub headers: Vec<(String, String)>,
+    pub sampling: Sampling,
+    /// Longest a request may take, instead of reqwest's 30 seconds
+    pub timeout: Option<Duration>,
+    /// Times a chat request is sent again after a transient failure, instead of `retries`
+    /// from the config
+    pub retries: Option<u32>,
+    /// The proxy to send requests through, instead of `proxy` from the config
+    pub proxy: Option<String>,
+    /// Root certificates to trust besides the built-in ones, instead of `ca_cert` from
+    /// the config
+    pub ca_cert: Option<PathBuf>,
+    /// Set once the answer is no longer wanted, which stops the run before its next request
+    pub cancelled: Option<Arc<AtomicBool>>,
+}
+
+/// `body` with the sampling parameters that were given as top-level fields.
+pub fn top_level_sampling(mut body: Value, sampling: Sampling) -> Value {
+    if let Some(temperature) = sampling.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = sampling.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    body
+}
+
+/// How the model picks the tokens of its reply, where the provider's defaults are not
+/// wanted. A temperature of 0 makes replies as repeatable as the model allows.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sampling {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+}
+
+/// Tokens the API reported using, summed over requests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+impl Usage {
+    /// The counts named `prompt` and `completion` in the object at `field` of `body`,
+    /// or at its top level without a field.
+    pub fn read(body: &str, field: &str, prompt: &str, completion: &str) -> Option<Usage> {
+        let body: Value = serde_json::from_str(body).ok()?;
+        let usage = if field.is_empty() {
+            &body
+        } else {
+            body.get(field)?
+        };
+        let count = |name: &str| {
+            usage
+                .get(name)
+                .and_then(Value::as_u64)
+                .map(|count| count as usize)
+        };
+        Some(Usage {
+            prompt_tokens: count(prompt)?,
+            completion_tokens: count(completion).unwrap_or_default(),
+        })
+    }
+
+    pub fn add(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Sends chat requests over HTTP in the format of a [`Provider`].
+pub struct ApiClient {
+    client: Client,
+    provider: Box<dyn Provider>,
+    base_url: String,
+    api_key: String,
+    extra_body: Map<String, Value>,
+    audit: Option<AuditLog>,
+    options: ClientOptions,
+    deadlines: Deadlines,
+    /// What the responses so far reported using, by model
+    usage: Mutex<BTreeMap<String, Usage>>,
+}
+
+impl ApiClient {
+    /// The configured `extra_body` fields are merged into every request, except `model`
+    /// and `messages`. An API base from the options wins over the config's, which wins
+    /// over the provider's own.
+    pub fn new(
+        provider: Box<dyn Provider>,
+        api_key: String,
+        config: &Config,
+        mut options: ClientOptions,
+    ) -> Result<Self> {
+        options.retries = options.retries.or(config.retries);
+        let mut headers = HeaderMap::new();
+        let extra_headers = options.headers.iter().map(|(name, value)| (name, value));
+        for (name, value) in config.headers.iter().chain(extra_headers) {
+            let name = HeaderName::try_from(name.as_str())
+                .map_err(|_| CommitGPTError::InvalidHeaderError(name.clone()))?;
+            let value = HeaderValue::try_from(value.as_str())
+                .map_err(|_| CommitGPTError::InvalidHeaderError(name.to_string()))?;
+            headers.insert(name, value);
+        }
+
+        // One pooled client for every call of a run, so chunking, candidates and
+        // verification reuse the same keep-alive (HTTP/2 where offered) connection
+        let mut builder = Client::builder();
+        if let Some(timeout) = options.timeout {
+            builder = builder.timeout(timeout);
+        }
+        // reqwest goes through HTTPS_PROXY or HTTP_PROXY, except for the hosts in NO_PROXY,
+        // by itself; a proxy given here replaces those but still leaves out NO_PROXY's hosts
+        if let Some(proxy) = options.proxy.as_deref().or(config.proxy.as_deref()) {
+            let proxy = Proxy::all(proxy)
+                .map_err(|e| CommitGPTError::InvalidProxyError(proxy.to_string(), e))?;
+            builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+        }
+        // TLS-intercepting proxies sign with a root of their own
+        if let Some(path) = options.ca_cert.as_ref().or(config.ca_cert.as_ref()) {
+            for certificate in root_certificates(path)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        let client = builder
+            .use_rustls_tls()
+            .user_agent(config.user_agent.as_deref().unwrap_or(USER_AGENT))
+            .default_headers(headers)
+            .gzip(true)
+            .pool_idle_timeout(KEEP_ALIVE)
+            .tcp_keepalive(KEEP_ALIVE)
+            .build()?;
+        let api_base = options.api_base.clone().or_else(|| config.api_base.clone());
+        let mut client = ApiClient {
+            client,
+            base_url: api_base.clone().unwrap_or_else(|| provider.base_url()),
+            provider,
+            api_key,
+            extra_body: config.extra_body.clone(),
+            audit: config.audit.clone(),
+            options,
+            deadlines: Deadlines::default(),
+            usage: Mutex::default(),
+        };
+        if let Some(session) = client.provider.sign_in(&client, &client.api_key)? {
+            client.api_key = session.api_key;
+            if let (None, Some(base_url)) = (api_base, session.base_url) {
+                client.base_url = base_url;
+            }
+        }
+        Ok(client)
+    }
+
+    /// Requests `n` alternative replies from the chat endpoint, in a single call where
+    /// the provider allows it.
+    pub fn complete_n(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+    ) -> Result<Vec<String>> {
+        self.complete(model, messages, n, max_tokens, None)
+    }
+
+    /// Same, asking for replies that are JSON matching `schema`, where the provider can
+    /// hold them to one.
+    pub fn complete_structured(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        schema: &Value,
+    ) -> Result<Vec<String>> {
+        let schema = Some(schema).filter(|_| self.provider.capabilities(model).json_mode);
+        self.complete(model, messages, n, max_tokens, schema)
+    }
+
+    fn complete(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        schema: Option<&Value>,
+    ) -> Result<Vec<String>> {
+        let (calls, n) = if self.provider.supports_n() {
+            (1, n)
+        } else {
+            (n.max(1), 1)
+        };
+        let mut replies = Vec::with_capacity(calls * n);
+        for _ in 0..calls {
+            let batch = match self.request(model, messages, n, max_tokens, schema) {
+                Err(CommitGPTError::ApiErrorStatus(error))
+                    if self.provider.install_model(self, model, &error)? =>
+                {
+                    self.request(model, messages, n, max_tokens, schema)?
+                }
+                result => result?,
+            };
+            replies.extend(batch);
+        }
+        Ok(replies)
+    }
+
+    /// Gives every later chat request until the API call deadline, or the end of the
+    /// run, whichever comes first.
+    pub fn set_deadlines(&mut self, deadlines: Deadlines) {
+        self.deadlines = deadlines;
+    }
+
+    pub fn capabilities(&self, model: &str) -> Capabilities {
+        self.provider.capabilities(model)
+    }
+
+    /// The tokens the responses so far reported using, by model.
+    pub fn usage(&self) -> BTreeMap<String, Usage> {
+        self.usage.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn request(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        max_tokens: Option<usize>,
+        schema: Option<&Value>,
+    ) -> Result<Vec<String>> {
+        let messages = self.conversation(model, messages);
+        let request_body =
+            self.provider
+                .request_body(model, &messages, n, max_tokens, &self.extra_body)?;
+        let mut request_body = self
+            .provider
+            .sampling_body(request_body, self.options.sampling);
+        if let Some(schema) = schema {
+            request_body = self.provider.json_body(request_body, schema);
+        }
+        let (response, until) =
+            self.send_chat(model, n, &self.provider.chat_path(model), &request_body)?;
+
+        // Parsed separately so a malformed body is reported as such, not as a transport error
+        let body = response.text().map_err(|e| self.expired(until, e))?;
+        if let Some(usage) = self.provider.usage(&body) {
+            if self.options.verbose {
+                eprintln!(
+                    "{}: {} prompt and {} completion tokens",
+                    model, usage.prompt_tokens, usage.completion_tokens
+                );
+            }
+            let mut used = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+            used.entry(model.to_string()).or_default().add(usage);
+        }
+        let replies: Vec<String> = self
+            .provider
+            .replies(&body)
+            .map_err(|e| self.diagnose(e))?
+            .into_iter()
+            .map(|reply| reply.trim().to_string())
+            .filter(|reply| !reply.is_empty())
+            .collect();
+        if replies.is_empty() {
+            return Err(CommitGPTError::NoCommitMessage);
+        }
+        Ok(replies)
+    }
+
+    /// Requests a single reply as a stream, handing every piece of text to `on_text` as
+    /// it arrives, for providers with [`Capabilities::streaming`].
+    pub fn complete_streaming(
+        &self,
+        model: &str,
+        messages: &[Message],
+        max_tokens: Option<usize>,
+        on_text: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        let messages = self.conversation(model, messages);
+        let request_body =
+            self.provider
+                .request_body(model, &messages, 1, max_tokens, &self.extra_body)?;
+        let request_body = self
+            .provider
+            .sampling_body(request_body, self.options.sampling);
+        let request_body = self.provider.stream_body(request_body);
+        let (response, until) =
+            self.send_chat(model, 1, &self.provider.stream_path(model), &request_body)?;
+
+        let mut reply = String::new();
+        for line in BufReader::new(response).lines() {
+            let line = line.map_err(|e| match (until, self.options.timeout) {
+                _ if e.kind() != io::ErrorKind::TimedOut => CommitGPTError::StreamReadError(e),
+                (until, Some(timeout)) if !deadline::passed(until) => {
+                    CommitGPTError::RequestTimeout(timeout.as_secs())
+                }
+                (Some(_), _) => CommitGPTError::DeadlineExceeded(Stage::ApiCall),
+                _ => CommitGPTError::StreamReadError(e),
+            })?;
+            // Server-sent events carry their payload on `data:` lines; streams without that
+            // framing, like Ollama's, send one payload per line
+            let data = match line.strip_prefix("data:") {
+                Some(data) => data.trim(),
+                None if line.starts_with(':')
+                    || line.starts_with("event:")
+                    || line.starts_with("id:") =>
+                {
+                    continue
+                }
+                None => line.trim(),
+            };
+            if data.is_empty() {
+                continue;
+            }
+            if data == "[DONE]" {
+                break;
+            }
+            if let Some(text) = self
+                .provider
+                .stream_delta(data)
+                .map_err(|e| self.diagnose(e))?
+            {
+                on_text(&text);
+                reply.push_str(&text);
+            }
+        }
+        let reply = reply.trim().to_string();
+        if reply.is_empty() {
+            return Err(CommitGPTError::NoCommitMessage);
+        }
+        Ok(reply)
+    }
+
+    /// The conversation as the provider takes it for `model`.
+    fn conversation<'m>(&self, model: &str, messages: &'m [Message]) -> Cow<'m, [Message]> {
+        if self.provider.capabilities(model).system_role {
+            Cow::Borrowed(messages)
+        } else {
+            Cow::Owned(fold_system_messages(messages))
+        }
+    }
+
+    /// Posts a chat request for `model` to `path`, within the API call deadline, which is
+    /// returned with the successful response for reading it.
+    fn send_chat(
+        &self,
+        model: &str,
+        n: usize,
+        path: &str,
+        body: &Value,
+    ) -> Result<(Response, Option<Instant>)> {
+        let url = self.endpoint(path)?;
+        let payload = serde_json::to_vec(body)?;
+        if let Some(audit) = &self.audit {
+            audit.record(&url, &payload)?;
+        }
+        let until = self.deadlines.until(Stage::ApiCall);
+        let retries = self.options.retries.unwrap_or(retry::DEFAULT_RETRIES);
+        // The wait before the next attempt, if there is one
+        let retry_in = |attempt: u32, retry_after: Option<Duration>| {
+            (attempt < retries)
+                .then(|| retry::delay(attempt, retry_after, until))
+                .flatten()
+        };
+        let mut attempt = 0;
+        loop {
+            deadline::check(until, Stage::ApiCall)?;
+            if let Some(cancelled) = &self.options.cancelled {
+                if cancelled.load(Ordering::SeqCst) {
+                    return Err(CommitGPTError::Cancelled);
+                }
+            }
+            let started = Instant::now();
+            let mut request = self.client.post(&url);
+            // Whichever comes first, the deadline or the request timeout
+            let timeout = match (deadline::remaining(until), self.options.timeout) {
+                (Some(remaining), Some(timeout)) => Some(remaining.min(timeout)),
+                (remaining, timeout) => remaining.or(timeout),
+            };
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+            let request = self.authorized(request, "POST", &url, &payload)?;
+            let response = match request
+                .header(CONTENT_TYPE, "application/json")
+                .body(payload.clone())
+                .send()
+            {
+                Ok(response) => response,
+                Err(e) => match retry_in(attempt, None).filter(|_| retry::is_reset(&e)) {
+                    Some(delay) => {
+                        eprintln!(
+                            "Warning: {} dropped the connection; retrying in {:.1} s",
+                            model,
+                            delay.as_secs_f64()
+                        );
+                        std::thread::sleep(delay);
+                        attempt += 1;
+                        continue;
+                    }
+                    None => return Err(self.expired(until, e)),
+                },
+            };
+
+            let status = response.status();
+            if self.options.verbose {
+                eprintln!(
+                    "{} (n={}): {} over {:?} in {} ms",
+                    model,
+                    n,
+                    status,
+                    response.version(),
+                    started.elapsed().as_millis()
+                );
+            }
+            if status.is_success() {
+                return Ok((response, until));
+            }
+            let retry_after = retry::retry_after(&response);
+            let body = response.text().unwrap_or_default();
+            let error = self.provider.error(status, &body);
+            match retry_in(attempt, retry_after).filter(|_| error.is_transient()) {
+                Some(delay) => {
+                    eprintln!(
+                        "Warning: {} responded {}; retrying in {:.1} s",
+                        model,
+                        status,
+                        delay.as_secs_f64()
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                None => return Err(CommitGPTError::ApiErrorStatus(error)),
+            }
+        }
+    }
+
+    /// Reports a response body of the wrong shape as coming from the API base's host,
+    /// which matters most for servers that only claim to be compatible. A body that is
+    /// not even JSON is left as it is.
+    fn diagnose(&self, error: CommitGPTError) -> CommitGPTError {
+        let mut mismatch = match error {
+            CommitGPTError::UnexpectedResponse(mismatch) => mismatch,
+            CommitGPTError::ApiResponseParseError(e) if e.is_data() => response::from_serde(e),
+            error => return error,
+        };
+        mismatch.server = Url::parse(&self.base_url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .or_else(|| Some(self.base_url.clone()));
+        CommitGPTError::UnexpectedResponse(mismatch)
+    }
+
+    /// A request cut short by the deadline `until` or by `--timeout` is reported as such,
+    /// not as a slow server.
+    fn expired(&self, until: Option<Instant>, error: reqwest::Error) -> CommitGPTError {
+        match (until, self.options.timeout) {
+            _ if !error.is_timeout() => CommitGPTError::from(error),
+            (until, Some(timeout)) if !deadline::passed(until) => {
+                CommitGPTError::RequestTimeout(timeout.as_secs())
+            }
+            (Some(_), _) => CommitGPTError::DeadlineExceeded(Stage::ApiCall),
+            _ => CommitGPTError::from(error),
+        }
+    }
+
+    /// Adds the provider's credentials and signature to a request of `method` to `url`
+    /// with `payload`.
+    fn authorized(
+        &self,
+        request: RequestBuilder,
+        method: &str,
+        url: &str,
+        payload: &[u8],
+    ) -> Result<RequestBuilder> {
+        let mut request = self.provider.authorize(request, &self.api_key);
+        for (name, value) in self.provider.sign(method, url, payload)? {
+            request = request.header(name, value);
+        }
+        Ok(request)
+    }
+
+    /// The URL of `path` under the API base, or `path` itself when it is a URL, unless
+    /// the network is off limits.
+    fn endpoint(&self, path: &str) -> Result<String> {
+        let url = if path.contains("://") {
+            path.to_string()
+        } else {
+            join_url(&self.base_url, path)
+        };
+        if self.options.forbid_network {
+            return Err(CommitGPTError::NetworkForbidden(url));
+        }
+        Ok(url)
+    }
+
+    /// The ids of the models available to the API key, sorted.
+    pub fn list_models(&self) -> Result<Vec<String>> {
+        let url = self.endpoint(&self.provider.models_path())?;
+        let response = self
+            .authorized(self.client.get(&url), "GET", &url, &[])?
+            .send()?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            return Err(CommitGPTError::ApiErrorStatus(
+                self.provider.error(status, &body),
+            ));
+        }
+
+        let mut models = self
+            .provider
+            .models(&response.text()?)
+            .map_err(|e| self.diagnose(e))?;
+        models.sort();
+        Ok(models)
+    }
+
+    /// The body of a GET request to `url`, outside the API base, with `authorization`
+    /// instead of the provider's credentials.
+    pub fn fetch(&self, url: &str, authorization: &str) -> Result<String> {
+        if self.options.forbid_network {
+            return Err(CommitGPTError::NetworkForbidden(url.to_string()));
+        }
+        let response = self
+            .client
+            .get(url)
+            .header(AUTHORIZATION, authorization)
+            .send()?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            return Err(CommitGPTError::ApiErrorStatus(
+                self.provider.error(status, &body),
+            ));
+        }
+        Ok(response.text()?)
+    }
+
+    /// Posts `body` to `path` under the API base, for providers that answer with a stream
+    /// of lines, such as progress reports.
+    pub fn post_streaming(&self, path: &str, body: &Value) -> Result<BufReader<Response>> {
+        let url = self.endpoint(path)?;
+        let payload = serde_json::to_vec(body)?;
+        if let Some(audit) = &self.audit {
+            audit.record(&url, &payload)?;
+        }
+        let response = self.authorized(self.client.post(&url), "POST", &url, &payload)?;
+        let response = response
+            .header(CONTENT_TYPE, "application/json")
+            .body(payload)
+            .send()?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            return Err(CommitGPTError::ApiErrorStatus(
+                self.provider.error(status, &body),
+            ));
+        }
+        Ok(BufReader::new(response))
+    }
+}
+
+/// The conversation without system messages, whose instructions lead the first user
+/// message instead, for models that take no system role.
+pub fn fold_system_messages(messages: &[Message]) -> Vec<Message> {
+    let system: Vec<&str> = messages
+        .iter()
+        .filter(|message| message.role == "system")
+        .map(|message| message.content.as_str())
+        .collect();
+    let mut conversation: Vec<Message> = messages
+        .iter()
+        .filter(|message| message.role != "system")
+        .cloned()
+        .collect();
+    match conversation
+        .iter_mut()
+        .find(|message| message.role == "user")
+    {
+        Some(first) if !system.is_empty() => {
+            first.content = format!("{}\n\n{}", system.join("\n\n"), first.content)
+        }
+        Some(_) => {}
+        None if !system.is_empty() => conversation.insert(0, Message::user(&system.join("\n\n"))),
+        None => {}
+    }
+    conversation
+}
+
+/// The certificates in the PEM file at `path`.
+fn root_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let display = path.display().to_string();
+    let pem = fs::read(path).map_err(|e| CommitGPTError::CaCertReadError(display.clone(), e))?;
+    match Certificate::from_pem_bundle(&pem) {
+        Ok(certificates) if !certificates.is_empty() => Ok(certificates),
+        _ => Err(CommitGPTError::InvalidCaCertError(display)),
+    }
+}
+
+/// Appends `path` to `base` with exactly one slash between them. A query in `base`, as
+/// some gateways need for their key, is moved after the path and merged with its own.
+pub fn join_url(base: &str, path: &str) -> String {
+    let (base, base_query) = match base.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (base, None),
+    };
+    let mut url = format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    if let Some(query) = base_query.filter(|query| !query.is_empty()) {
+        url.push(if url.contains('?') { '&' } else { '?' });
+        url.push_str(query);
+    }
+    url
+}
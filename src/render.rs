@@ -0,0 +1,213 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::style::Painter;
+use crate::trailers;
+
+/// Bumped whenever the porcelain output changes incompatibly.
+const PORCELAIN_VERSION: u32 = 1;
+const GENERATED_BY: &str = concat!("Generated-by: commit-gpt ", env!("CARGO_PKG_VERSION"));
+
+/// How the generated message is printed.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The message as git stores it, colored on a terminal
+    #[default]
+    Plain,
+    /// An object with the title, body and trailers
+    Json,
+    /// Markdown for pull request descriptions
+    Markdown,
+    /// The message with a trailer recording that commit-gpt wrote it
+    Trailers,
+    /// Stable, versioned `key: value` lines for scripts and git clients
+    Porcelain,
+    /// A property list with the title, body and trailers, read with `read` in Emacs
+    Emacs,
+}
+
+pub trait Renderer {
+    fn render(&self, message: &str) -> String;
+}
+
+pub fn renderer<'a>(format: OutputFormat, painter: &'a Painter) -> Box<dyn Renderer + 'a> {
+    match format {
+        OutputFormat::Plain => Box::new(Plain { painter }),
+        OutputFormat::Json => Box::new(Json),
+        OutputFormat::Markdown => Box::new(Markdown),
+        OutputFormat::Trailers => Box::new(Trailers { painter }),
+        OutputFormat::Porcelain => Box::new(Porcelain),
+        OutputFormat::Emacs => Box::new(Emacs),
+    }
+}
+
+/// The title, body and trailers of `message`, as the JSON and Emacs output carry them.
+pub fn message_value(message: &str) -> Value {
+    let parts = split(message);
+    let trailers: Vec<_> = parts
+        .trailers
+        .iter()
+        .filter_map(|trailer| trailer.split_once(": "))
+        .map(|(key, value)| json!({ "key": key, "value": value }))
+        .collect();
+    json!({
+        "title": parts.title,
+        "body": parts.body,
+        "trailers": trailers,
+        "message": message,
+    })
+}
+
+/// Writes `value` as an Emacs Lisp form: objects become property lists with keyword
+/// keys, arrays lists, `null` and `false` nil, and `true` t.
+pub fn sexp(value: &Value) -> String {
+    match value {
+        Value::Null | Value::Bool(false) => "nil".to_string(),
+        Value::Bool(true) => "t".to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::String(string) => sexp_string(string),
+        Value::Array(items) => {
+            format!("({})", items.iter().map(sexp).collect::<Vec<_>>().join(" "))
+        }
+        Value::Object(fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(key, value)| format!(":{} {}", key, sexp(value)))
+                .collect();
+            format!("({})", fields.join(" "))
+        }
+    }
+}
+
+/// `string` as an Emacs Lisp string on a single line, so a multi-line message does not
+/// break the one form per line of the server.
+fn sexp_string(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len() + 2);
+    escaped.push('"');
+    for c in string.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_control() => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// The porcelain output for runs that produced no message: `status` is `empty` when
+/// there was nothing to describe, or `error` followed by an `error:` line.
+pub fn porcelain_status(status: &str, error: Option<&str>) -> String {
+    let mut output = format!("version: {}\nstatus: {}", PORCELAIN_VERSION, status);
+    if let Some(error) = error {
+        for line in error.lines() {
+            output.push_str(&format!("\nerror: {}", line));
+        }
+    }
+    output
+}
+
+/// A message split into its title, body and trailing trailer block.
+struct Parts<'a> {
+    title: &'a str,
+    body: String,
+    trailers: Vec<String>,
+}
+
+fn split(message: &str) -> Parts<'_> {
+    let message = message.trim();
+    let (title, rest) = message.split_once('\n').unwrap_or((message, ""));
+    let lines: Vec<&str> = rest.lines().collect();
+    let body_end = trailers::trailer_block_start(&lines).unwrap_or(lines.len());
+    Parts {
+        title: title.trim(),
+        body: lines[..body_end].join("\n").trim().to_string(),
+        trailers: trailers::parse_trailers(rest),
+    }
+}
+
+struct Plain<'a> {
+    painter: &'a Painter,
+}
+
+impl Renderer for Plain<'_> {
+    fn render(&self, message: &str) -> String {
+        self.painter.message(message)
+    }
+}
+
+struct Json;
+
+impl Renderer for Json {
+    fn render(&self, message: &str) -> String {
+        serde_json::to_string_pretty(&message_value(message)).unwrap_or_default()
+    }
+}
+
+struct Emacs;
+
+impl Renderer for Emacs {
+    fn render(&self, message: &str) -> String {
+        sexp(&message_value(message))
+    }
+}
+
+struct Markdown;
+
+impl Renderer for Markdown {
+    fn render(&self, message: &str) -> String {
+        let parts = split(message);
+        let mut output = format!("## {}\n", parts.title);
+        if !parts.body.is_empty() {
+            output.push_str(&format!("\n{}\n", parts.body));
+        }
+        if !parts.trailers.is_empty() {
+            output.push('\n');
+            for trailer in &parts.trailers {
+                match trailer.split_once(": ") {
+                    Some((key, value)) => output.push_str(&format!("**{}:** {}  \n", key, value)),
+                    None => output.push_str(&format!("{}  \n", trailer)),
+                }
+            }
+        }
+        output.trim_end().to_string()
+    }
+}
+
+/// One `key: value` per line: `title:` once, then a `body:` line per body line
+/// (blank ones included), a `trailer:` line per trailer, and `status: ok` last.
+struct Porcelain;
+
+impl Renderer for Porcelain {
+    fn render(&self, message: &str) -> String {
+        let parts = split(message);
+        let mut output = format!("version: {}\ntitle: {}\n", PORCELAIN_VERSION, parts.title);
+        for line in parts.body.lines() {
+            if line.is_empty() {
+                output.push_str("body:\n");
+            } else {
+                output.push_str(&format!("body: {}\n", line));
+            }
+        }
+        for trailer in &parts.trailers {
+            output.push_str(&format!("trailer: {}\n", trailer));
+        }
+        output.push_str("status: ok");
+        output
+    }
+}
+
+struct Trailers<'a> {
+    painter: &'a Painter,
+}
+
+impl Renderer for Trailers<'_> {
+    fn render(&self, message: &str) -> String {
+        self.painter
+            .message(&trailers::append_trailer(message, GENERATED_BY))
+    }
+}
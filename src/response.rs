@@ -0,0 +1,149 @@
+// Servers that claim OpenAI compatibility differ in the details: some leave out fields,
+// send null for them, or nest them differently. Their responses are read from a JSON
+// value with fallbacks instead of a strict schema, and whatever still does not fit is
+// reported by its path in the response.
+
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::{CommitGPTError, Result};
+
+/// A response that did not have the expected shape.
+#[derive(Debug)]
+pub struct Mismatch {
+    /// The host that sent the response, filled in by the client
+    pub server: Option<String>,
+    /// Where in the response, e.g. `choices[0].message.content`; empty for the whole body
+    pub path: String,
+    pub problem: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(server) = &self.server {
+            write!(f, "{} sent ", server)?;
+        }
+        match self.path.as_str() {
+            "" => write!(f, "a response that {}", self.problem),
+            path => write!(f, "a response whose {} {}", path, self.problem),
+        }
+    }
+}
+
+/// A value in a response, with its path for reporting.
+pub struct Field<'a> {
+    value: &'a Value,
+    path: String,
+}
+
+impl<'a> Field<'a> {
+    /// The whole of `body`, which must at least be JSON.
+    pub fn parse(body: &str) -> Result<Value> {
+        Ok(serde_json::from_str(body)?)
+    }
+
+    pub fn root(value: &'a Value) -> Self {
+        Field {
+            value,
+            path: String::new(),
+        }
+    }
+
+    /// The member `name`, when it is there and not null.
+    pub fn get(&self, name: &str) -> Option<Field<'a>> {
+        let value = self.value.get(name).filter(|value| !value.is_null())?;
+        let path = match self.path.as_str() {
+            "" => name.to_string(),
+            path => format!("{}.{}", path, name),
+        };
+        Some(Field { value, path })
+    }
+
+    /// The member `name`, failing when it is missing.
+    pub fn require(&self, name: &str) -> Result<Field<'a>> {
+        self.get(name).ok_or_else(|| match self.path.as_str() {
+            "" => mismatch(name, "is missing".to_string()),
+            path => mismatch(&format!("{}.{}", path, name), "is missing".to_string()),
+        })
+    }
+
+    /// The first of the members `names` that is there.
+    pub fn first_of(&self, names: &[&str]) -> Result<Field<'a>> {
+        names
+            .iter()
+            .find_map(|name| self.get(name))
+            .ok_or_else(|| mismatch(&self.path, format!("has none of {}", names.join(", "))))
+    }
+
+    /// The elements of an array.
+    pub fn items(&self) -> Result<Vec<Field<'a>>> {
+        let items = self
+            .value
+            .as_array()
+            .ok_or_else(|| self.wrong_type("an array"))?;
+        Ok(items
+            .iter()
+            .enumerate()
+            .map(|(index, value)| Field {
+                value,
+                path: format!("{}[{}]", self.path, index),
+            })
+            .collect())
+    }
+
+    /// Text, also accepted as an array of parts with a `text` each, as some servers
+    /// send message content.
+    pub fn text(&self) -> Result<String> {
+        match self.value {
+            Value::String(text) => Ok(text.clone()),
+            Value::Array(_) => {
+                let mut text = String::new();
+                for part in self.items()? {
+                    match part.get("text") {
+                        Some(part) => text.push_str(&part.text()?),
+                        // Parts that are not text, like images, say nothing about the commit
+                        None => continue,
+                    }
+                }
+                Ok(text)
+            }
+            _ => Err(self.wrong_type("text")),
+        }
+    }
+
+    /// A boolean, or `None` for any other value.
+    pub fn flag(&self) -> Option<bool> {
+        self.value.as_bool()
+    }
+
+    fn wrong_type(&self, expected: &str) -> CommitGPTError {
+        let actual = match self.value {
+            Value::Null => "null",
+            Value::Bool(_) => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "text",
+            Value::Array(_) => "an array",
+            Value::Object(_) => "an object",
+        };
+        mismatch(&self.path, format!("is {} instead of {}", actual, expected))
+    }
+}
+
+fn mismatch(path: &str, problem: String) -> CommitGPTError {
+    CommitGPTError::UnexpectedResponse(Mismatch {
+        server: None,
+        path: path.to_string(),
+        problem,
+    })
+}
+
+/// Turns a failure to read a response with a strict schema into a [`Mismatch`], whose
+/// message names the field serde stopped at.
+pub fn from_serde(error: serde_json::Error) -> Mismatch {
+    Mismatch {
+        server: None,
+        path: String::new(),
+        problem: format!("does not fit ({})", error),
+    }
+}
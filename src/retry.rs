@@ -0,0 +1,78 @@
+// Rate limits, overloaded servers and dropped keep-alive connections fail a request that
+// would succeed moments later, so chat requests are sent again a few times (`--retries`,
+// or `retries` in the config) before the failure counts. The waits double each time, with
+// jitter so that runs limited together do not retry together, unless the server says
+// how long to wait with `Retry-After`.
+
+use std::error::Error as _;
+use std::io;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::Response;
+use reqwest::header::RETRY_AFTER;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::deadline;
+
+pub const DEFAULT_RETRIES: u32 = 2;
+const FIRST_DELAY: Duration = Duration::from_millis(500);
+/// A server asking for a longer wait is not waited for
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How long to wait before retry number `attempt`, counted from 0, or `None` when it
+/// would be too long or run past `until`. `retry_after` is the wait the server asked for.
+pub fn delay(
+    attempt: u32,
+    retry_after: Option<Duration>,
+    until: Option<Instant>,
+) -> Option<Duration> {
+    let delay = match retry_after {
+        Some(retry_after) => retry_after,
+        None => {
+            let base = FIRST_DELAY.saturating_mul(2u32.saturating_pow(attempt));
+            base + jitter(base / 2)
+        }
+    };
+    if delay > MAX_DELAY {
+        return None;
+    }
+    match deadline::remaining(until) {
+        Some(remaining) if remaining <= delay => None,
+        _ => Some(delay),
+    }
+}
+
+/// The wait a response asks for in seconds with `Retry-After`. Dates are not parsed.
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Whether a request failed because the connection was dropped under it, as servers do
+/// with keep-alive connections they have closed on their side.
+pub fn is_reset(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            if matches!(
+                error.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            ) {
+                return true;
+            }
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// A random duration up to `max`.
+fn jitter(max: Duration) -> Duration {
+    let mut bytes = [0; 4];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return Duration::ZERO;
+    }
+    max.mul_f64(f64::from(u32::from_le_bytes(bytes)) / f64::from(u32::MAX))
+}
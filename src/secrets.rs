@@ -0,0 +1,151 @@
+// Keys and tokens that slipped into a change must not be sent along with it. Those of a
+// known format could be matched by pattern, but new ones turn up all the time, so any
+// long string that looks random is masked instead: its Shannon entropy per character
+// is close to what a string of random characters from its alphabet, of the same length,
+// would have on average. Short strings cannot reach the entropy of their alphabet, since
+// a string of n characters has at most log2(n) bits per character, so the bar is set by
+// length as well.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::changes::FileChange;
+
+const DEFAULT_MIN_LENGTH: usize = 20;
+/// Share of the expected entropy of random characters, which all but a few in a hundred
+/// random keys of 20 characters or more reach and most identifiers made of words stay
+/// below
+const DEFAULT_MIN_ENTROPY_RATIO: f64 = 0.9;
+/// Longer strings are measured against the expected entropy at this length, which is
+/// within a few hundredths of a bit of that of any longer one
+const MAX_EXPECTED_LENGTH: usize = 1000;
+const MASK: &str = "[redacted]";
+
+/// The `[redaction]` table of the config.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactionConfig {
+    /// Whether random-looking strings are masked at all
+    pub enabled: bool,
+    /// Shorter strings are left alone
+    pub min_length: usize,
+    /// Share of the entropy a random string of the same length and alphabet has on
+    /// average, from which a string counts as random
+    pub min_entropy_ratio: f64,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        RedactionConfig {
+            enabled: true,
+            min_length: DEFAULT_MIN_LENGTH,
+            min_entropy_ratio: DEFAULT_MIN_ENTROPY_RATIO,
+        }
+    }
+}
+
+/// `changes` with every random-looking string in their added and removed lines
+/// replaced by [`MASK`], and how many were.
+pub fn redact(changes: &[FileChange], config: &RedactionConfig) -> (Vec<FileChange>, usize) {
+    let mut masked = 0;
+    if !config.enabled {
+        return (changes.to_vec(), masked);
+    }
+    let changes = changes
+        .iter()
+        .map(|change| {
+            let mut change = change.clone();
+            for summary in change.summaries.iter_mut().chain(change.notes.iter_mut()) {
+                let (redacted, count) = redact_line(summary, config);
+                *summary = redacted;
+                masked += count;
+            }
+            change
+        })
+        .collect();
+    (changes, masked)
+}
+
+fn redact_line(line: &str, config: &RedactionConfig) -> (String, usize) {
+    let mut output = String::with_capacity(line.len());
+    let mut count = 0;
+    let mut rest = line;
+    while let Some(start) = rest.find(is_token_char) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_token_char(c)).unwrap_or(rest.len());
+        // Padding ends a token, so `KEY=value` is two
+        let end = end + rest[end..].len() - rest[end..].trim_start_matches('=').len();
+        let token = &rest[..end];
+        if looks_random(token, config) {
+            output.push_str(MASK);
+            count += 1;
+        } else {
+            output.push_str(token);
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    (output, count)
+}
+
+/// The characters keys and tokens are written with: base64, base64url and hex, apart
+/// from the `=` of padding.
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '_' | '-')
+}
+
+fn looks_random(token: &str, config: &RedactionConfig) -> bool {
+    let token = token.trim_end_matches('=');
+    if token.len() < config.min_length {
+        return false;
+    }
+    // Numbers are not secrets, or at least not ones that look random
+    if !token.chars().any(|c| c.is_ascii_alphabetic()) || !token.chars().any(|c| c.is_ascii_digit())
+    {
+        return false;
+    }
+    let alphabet = if token.chars().all(|c| c.is_ascii_hexdigit()) {
+        16
+    } else if token.chars().all(|c| c.is_ascii_alphanumeric()) {
+        62
+    } else {
+        64
+    };
+    let expected = expected_entropy(token.len().min(MAX_EXPECTED_LENGTH), alphabet);
+    entropy(token) >= config.min_entropy_ratio * expected
+}
+
+/// The average Shannon entropy, in bits per character, of strings of `length` characters
+/// drawn at random from `alphabet` of them. Each character occurs a binomially
+/// distributed number of times, which gives the expected sum of `count * log2(count)`.
+fn expected_entropy(length: usize, alphabet: usize) -> f64 {
+    let n = length as f64;
+    let p = 1.0 / alphabet as f64;
+    // The probability that a character occurs `count` times, starting from none
+    let mut probability = (1.0 - p).powf(n);
+    let mut sum = 0.0;
+    for count in 1..=length {
+        let k = count as f64;
+        probability *= (n - k + 1.0) / k * p / (1.0 - p);
+        sum += probability * k * k.log2();
+    }
+    n.log2() - alphabet as f64 * sum / n
+}
+
+/// Shannon entropy of `text` in bits per character.
+fn entropy(text: &str) -> f64 {
+    let mut counts: BTreeMap<char, usize> = BTreeMap::new();
+    for c in text.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let length = text.chars().count() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / length;
+            -p * p.log2()
+        })
+        .sum()
+}
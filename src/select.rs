@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use regex::Regex;
+
+use crate::changes::FileChange;
+use crate::client::Client;
+use crate::injection;
+use crate::openai::Message;
+use crate::prompt::fill;
+use crate::validate::Rules;
+
+const JUDGE_SYSTEM_PROMPT: &str =
+    "You judge Git commit messages for accuracy against a change. Reply with a single number.";
+const JUDGE_PROMPT_TEMPLATE: &str = "\
+Below are the changes of a commit followed by numbered candidate commit messages. Pick the candidate that describes the changes most accurately and clearly, without claims the changes do not support. Reply with the candidate's number only.
+
+{sandbox_instruction}
+
+Changes:
+{structured_changes}{candidates}";
+const MAX_TITLE_LENGTH: usize = 72;
+const MAX_MENTION_SCORE: i64 = 5;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SelectStrategy {
+    /// Score candidates locally against the diff
+    #[default]
+    Heuristic,
+    /// Ask a cheap model to pick the most accurate candidate
+    Judge,
+}
+
+pub struct Selector<'a> {
+    pub strategy: SelectStrategy,
+    pub client: &'a Client,
+    pub judge_model: &'a str,
+    pub rules: &'a Rules,
+}
+
+impl Selector<'_> {
+    /// Index of the best of `candidates`. A failed judging call falls back to the heuristic.
+    pub fn select(
+        &self,
+        candidates: &[String],
+        changes: &[FileChange],
+        structured_changes: &str,
+    ) -> usize {
+        if candidates.len() < 2 {
+            return 0;
+        }
+        if self.strategy == SelectStrategy::Judge {
+            match self.judge(candidates, structured_changes) {
+                Some(index) => return index,
+                None => eprintln!(
+                    "Warning: judging candidates failed, falling back to heuristic scoring"
+                ),
+            }
+        }
+
+        (0..candidates.len())
+            .max_by_key(|&index| {
+                (
+                    self.score(&candidates[index], changes),
+                    std::cmp::Reverse(index),
+                )
+            })
+            .unwrap_or(0)
+    }
+
+    fn judge(&self, candidates: &[String], structured_changes: &str) -> Option<usize> {
+        let numbered: String = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| format!("\nCandidate {}:\n{}\n", index + 1, candidate))
+            .collect();
+        let prompt = fill(
+            JUDGE_PROMPT_TEMPLATE,
+            &[
+                ("sandbox_instruction", injection::SANDBOX_INSTRUCTION),
+                (
+                    "structured_changes",
+                    &injection::sandbox(structured_changes),
+                ),
+                ("candidates", &numbered),
+            ],
+        );
+        let messages = [Message::system(JUDGE_SYSTEM_PROMPT), Message::user(&prompt)];
+
+        let reply = self.client.complete(self.judge_model, &messages).ok()?;
+        let number: usize = Regex::new(r"\d+")
+            .unwrap()
+            .find(&reply)?
+            .as_str()
+            .parse()
+            .ok()?;
+        (1..=candidates.len()).contains(&number).then(|| number - 1)
+    }
+
+    fn score(&self, candidate: &str, changes: &[FileChange]) -> i64 {
+        let mut score = -10 * self.rules.problems(candidate).len() as i64;
+
+        let title = candidate.lines().next().unwrap_or_default();
+        let title_length = title.chars().count();
+        if title_length <= MAX_TITLE_LENGTH {
+            score += 2;
+        } else {
+            score -= ((title_length - MAX_TITLE_LENGTH) / 10 + 1) as i64;
+        }
+
+        // Naming the touched files or modules suggests the message is grounded in the diff
+        let lowercase = candidate.to_lowercase();
+        let mentions = changes
+            .iter()
+            .filter_map(|change| Path::new(&change.file_path).file_stem())
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+            .filter(|stem| stem.len() >= 3 && lowercase.contains(stem.as_str()))
+            .count() as i64;
+        score += mentions.min(MAX_MENTION_SCORE);
+
+        if candidate.contains("```") {
+            score -= 5;
+        }
+        if candidate.starts_with(['"', '\'']) {
+            score -= 3;
+        }
+        score
+    }
+}
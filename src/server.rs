@@ -0,0 +1,327 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use clap::ValueEnum;
+use git2::Repository;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::render::{self, OutputFormat};
+use crate::{apply_signoff, generate, models, ping, spending, Args, CommitGPTError, Result};
+
+// JSON-RPC 2.0 error codes, and the one LSP uses for cancelled requests
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const GENERATION_FAILED: i64 = -32000;
+const LISTING_FAILED: i64 = -32001;
+const REQUEST_CANCELLED: i64 = -32800;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// One request per line on stdin, one response or notification per line on stdout
+    Stdio,
+}
+
+/// A follow-up to a previous draft, sent to the model in the same conversation.
+#[derive(Clone, Debug)]
+pub struct Revision {
+    pub previous: String,
+    pub feedback: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct GenerateParams {
+    context: Option<String>,
+    /// Revises the last draft with this feedback instead of starting over
+    feedback: Option<String>,
+    model: Option<String>,
+    cancellation_token: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct CancelParams {
+    id: Option<Value>,
+    cancellation_token: Option<String>,
+}
+
+/// Serves JSON-RPC 2.0 requests until stdin closes or `shutdown` is called. The methods
+/// are a stable contract for editor integrations:
+///
+/// - `generateMessage` drafts a message for the staged changes; `context` adds to the
+///   prompt, `feedback` revises the last draft and `model` overrides `--model`
+/// - `cancel` abandons a pending `generateMessage` by its `id` or `cancellationToken`,
+///   which is then answered with error -32800 and stops before its next API request
+/// - `listModels` returns the provider's models, as `commit-gpt models` lists them
+/// - `getConfig` returns the settings generation runs with
+/// - `shutdown` ends the session once pending requests are answered
+///
+/// Generation runs in the background, so requests keep being read while a draft is pending.
+/// A `status` notification reports `generating` with the id of each request, and `idle`
+/// with the id of the last one once none is in flight.
+/// With `--output emacs`, every line is written as a property list instead of JSON.
+pub fn serve(args: &Args, config: &Config, repo: &Repository, transport: Transport) -> Result<()> {
+    let Transport::Stdio = transport;
+    let session = Session {
+        args,
+        config,
+        repo_path: repo.workdir().unwrap_or(repo.path()).to_path_buf(),
+        sexp: args.output == OutputFormat::Emacs,
+        last: Mutex::new(None),
+        pending: Mutex::new(Vec::new()),
+    };
+    thread::scope(|scope| {
+        // Editors start the server ahead of time, so a local model can load before the first request
+        if args.warmup || (args.provider.is_local() && args.local_model.is_none()) {
+            scope.spawn(|| ping::warm_up(args, config));
+        }
+        for line in io::stdin().lock().lines() {
+            let line = line.map_err(CommitGPTError::StdinReadError)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value: Value = match serde_json::from_str(&line) {
+                Ok(value) => value,
+                Err(e) => {
+                    session.send(&error_response(&Value::Null, PARSE_ERROR, &e.to_string()));
+                    continue;
+                }
+            };
+            // Valid JSON that is not a request, such as an object without a method
+            let request: Request = match serde_json::from_value(value.clone()) {
+                Ok(request) => request,
+                Err(e) => {
+                    let id = value.get("id").cloned().unwrap_or(Value::Null);
+                    session.send(&error_response(&id, INVALID_REQUEST, &e.to_string()));
+                    continue;
+                }
+            };
+            let id = request.id.clone().unwrap_or(Value::Null);
+            match request.method.as_str() {
+                "generateMessage" => match parse_params::<GenerateParams>(request.params) {
+                    Ok(params) => {
+                        let cancelled = session.start(&id, params.cancellation_token.clone());
+                        let session = &session;
+                        scope.spawn(move || session.generate(id, params, cancelled));
+                    }
+                    Err(e) => session.send(&error_response(&id, INVALID_PARAMS, &e)),
+                },
+                "cancel" => match parse_params::<CancelParams>(request.params) {
+                    Ok(params) => {
+                        session.cancel(params);
+                        // A notification, without an id, is not answered
+                        if request.id.is_some() {
+                            session.send(&result_response(&id, Value::Null));
+                        }
+                    }
+                    Err(e) => session.send(&error_response(&id, INVALID_PARAMS, &e)),
+                },
+                "listModels" => {
+                    let session = &session;
+                    scope.spawn(move || session.list_models(&id));
+                }
+                "getConfig" => session.send(&result_response(&id, session.config_value())),
+                "shutdown" => {
+                    session.send(&result_response(&id, Value::Null));
+                    break;
+                }
+                method => session.send(&error_response(
+                    &id,
+                    METHOD_NOT_FOUND,
+                    &format!("unknown method {}", method),
+                )),
+            }
+        }
+        Ok(())
+    })
+}
+
+/// A `generateMessage` request whose generation has not finished yet.
+struct Pending {
+    id: Value,
+    token: Option<String>,
+    /// Set once the request was answered as cancelled
+    cancelled: Arc<AtomicBool>,
+}
+
+struct Session<'a> {
+    args: &'a Args,
+    config: &'a Config,
+    repo_path: std::path::PathBuf,
+    sexp: bool,
+    last: Mutex<Option<String>>,
+    pending: Mutex<Vec<Pending>>,
+}
+
+impl Session<'_> {
+    /// Tracks a request until its generation finishes, returning the flag that cancels it.
+    fn start(&self, id: &Value, token: Option<String>) -> Arc<AtomicBool> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.pending.lock().unwrap().push(Pending {
+            id: id.clone(),
+            token,
+            cancelled: cancelled.clone(),
+        });
+        self.status(id, "generating");
+        cancelled
+    }
+
+    /// Answers a cancelled request right away and stops its generation before the next
+    /// API request; a draft that still arrives is discarded.
+    fn cancel(&self, params: CancelParams) {
+        let pending = self.pending.lock().unwrap();
+        let matches = |request: &&Pending| {
+            !request.cancelled.load(Ordering::SeqCst)
+                && (params.id.as_ref() == Some(&request.id)
+                    || (params.cancellation_token.is_some()
+                        && params.cancellation_token == request.token))
+        };
+        let Some(request) = pending.iter().find(matches) else {
+            return;
+        };
+        request.cancelled.store(true, Ordering::SeqCst);
+        self.send(&error_response(
+            &request.id,
+            REQUEST_CANCELLED,
+            "request cancelled",
+        ));
+    }
+
+    fn generate(&self, id: Value, params: GenerateParams, cancelled: Arc<AtomicBool>) {
+        let mut args = self.args.clone();
+        args.cancelled = Some(cancelled);
+        if params.context.is_some() {
+            args.context = params.context;
+        }
+        if let Some(model) = params.model {
+            args.model = model;
+        }
+        if let Some(feedback) = params.feedback {
+            let previous = self.last.lock().unwrap().clone();
+            let Some(previous) = previous else {
+                self.finish(
+                    &id,
+                    error_response(
+                        &id,
+                        INVALID_PARAMS,
+                        "no draft to revise; call generateMessage without feedback first",
+                    ),
+                );
+                return;
+            };
+            args.revision = Some(Revision {
+                previous,
+                feedback: Some(feedback),
+            });
+        }
+
+        // Repositories cannot be shared between threads, so every request opens its own
+        let generated = Repository::open(&self.repo_path)
+            .map_err(CommitGPTError::from)
+            .and_then(|repo| {
+                let message = generate(&args, self.config, &repo, None)?;
+                message
+                    .map(|message| apply_signoff(&args, &repo, message))
+                    .transpose()
+            });
+        let response = match generated {
+            Ok(Some(message)) => {
+                *self.last.lock().unwrap() = Some(message.clone());
+                result_response(&id, render::message_value(&message))
+            }
+            Ok(None) => result_response(&id, Value::Null),
+            Err(e) => error_response(&id, GENERATION_FAILED, &e.to_string()),
+        };
+        self.finish(&id, response);
+    }
+
+    fn finish(&self, id: &Value, response: Value) {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(index) = pending.iter().position(|request| request.id == *id) else {
+            return;
+        };
+        let request = pending.remove(index);
+        if !request.cancelled.load(Ordering::SeqCst) {
+            self.send(&response);
+        }
+        if pending.is_empty() {
+            self.status(id, "idle");
+        }
+    }
+
+    /// Answers `listModels` in the background, since the provider is asked for its models.
+    fn list_models(&self, id: &Value) {
+        let response = match models::value(self.args, self.config) {
+            Ok(models) => result_response(id, models),
+            Err(e) => error_response(id, LISTING_FAILED, &e.to_string()),
+        };
+        self.send(&response);
+    }
+
+    fn config_value(&self) -> Value {
+        let name = |value: Option<clap::builder::PossibleValue>| {
+            value.map(|value| value.get_name().to_string())
+        };
+        json!({
+            "provider": name(self.args.provider.to_possible_value()),
+            "model": self.args.model,
+            "judgeModel": self.args.judge_model,
+            "candidates": self.args.candidates,
+            "detail": name(self.args.detail.and_then(|detail| detail.to_possible_value())),
+            "intent": name(self.args.intent.and_then(|intent| intent.to_possible_value())),
+            "maxBullets": self.args.max_bullets,
+            "signoff": self.args.signoff,
+            "maxCost": self.config.max_cost.unwrap_or(spending::DEFAULT_MAX_COST),
+            "glossary": self.config.glossary,
+            "banned": self.config.banned,
+            // Header values often carry credentials
+            "headers": self.config.headers.keys().collect::<Vec<_>>(),
+        })
+    }
+
+    fn status(&self, id: &Value, status: &str) {
+        self.send(&json!({ "jsonrpc": "2.0", "method": "status", "params": { "id": id, "status": status } }));
+    }
+
+    fn send(&self, message: &Value) {
+        let line = if self.sexp {
+            render::sexp(message)
+        } else {
+            message.to_string()
+        };
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", line).ok();
+        stdout.flush().ok();
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de> + Default>(
+    params: Value,
+) -> std::result::Result<T, String> {
+    if params.is_null() {
+        return Ok(T::default());
+    }
+    serde_json::from_value(params).map_err(|e| e.to_string())
+}
+
+fn result_response(id: &Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error_response(id: &Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
@@ -0,0 +1,99 @@
+// Where the changes to describe come from. Everything after collecting them works on
+// `FileChange`s alone, so another version control system only needs a source of its own.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use git2::{Repository, RevparseMode, Tree};
+
+use crate::changes::{self, FileChange};
+use crate::{CommitGPTError, Result};
+
+/// A set of changes to write a message for.
+pub trait DiffSource {
+    /// The changes, one per file, sorted by path.
+    fn changes(&self) -> Result<Vec<FileChange>>;
+}
+
+/// The staged changes, with or without the unstaged ones, since HEAD or another tree.
+pub struct Index<'a> {
+    pub repo: &'a Repository,
+    pub include_unstaged: bool,
+    pub base: Option<&'a Tree<'a>>,
+}
+
+impl DiffSource for Index<'_> {
+    fn changes(&self) -> Result<Vec<FileChange>> {
+        changes::get_changes(self.repo, self.include_unstaged, self.base, None)
+    }
+}
+
+/// The changes of the listed files only, as lint-staged passes them.
+pub struct FileList<'a> {
+    pub index: Index<'a>,
+    pub paths: Vec<PathBuf>,
+}
+
+impl DiffSource for FileList<'_> {
+    fn changes(&self) -> Result<Vec<FileChange>> {
+        let index = &self.index;
+        changes::get_changes(
+            index.repo,
+            index.include_unstaged,
+            index.base,
+            Some(&self.paths),
+        )
+    }
+}
+
+/// The changes of a commit, or between the two ends of a range like `main..feature`
+/// (or `main...feature`, from where they diverged).
+pub struct Range<'a> {
+    pub repo: &'a Repository,
+    pub spec: String,
+}
+
+impl DiffSource for Range<'_> {
+    fn changes(&self) -> Result<Vec<FileChange>> {
+        let revspec = self.repo.revparse(&self.spec)?;
+        let (Some(from), to) = (revspec.from(), revspec.to()) else {
+            return Err(CommitGPTError::InvalidRangeError(self.spec.clone()));
+        };
+        let Some(to) = to else {
+            return changes::commit_changes(self.repo, &from.peel_to_commit()?);
+        };
+        let from = if revspec.mode().contains(RevparseMode::MERGE_BASE) {
+            let base = self.repo.merge_base(from.id(), to.id())?;
+            self.repo.find_commit(base)?.tree()?
+        } else {
+            from.peel_to_tree()?
+        };
+        changes::tree_changes(self.repo, Some(&from), &to.peel_to_tree()?)
+    }
+}
+
+/// The text of a unified diff, e.g. from another VCS or a patch file.
+pub struct UnifiedDiff {
+    pub text: Vec<u8>,
+}
+
+impl UnifiedDiff {
+    /// Reads the diff from `path`, or stdin for `-`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut text = Vec::new();
+        let read = if path == Path::new("-") {
+            io::stdin().lock().read_to_end(&mut text).map(|_| ())
+        } else {
+            fs::read(path).map(|contents| text = contents)
+        };
+        read.map_err(|e| CommitGPTError::DiffReadError(path.display().to_string(), e))?;
+        Ok(UnifiedDiff { text })
+    }
+}
+
+impl DiffSource for UnifiedDiff {
+    fn changes(&self) -> Result<Vec<FileChange>> {
+        changes::patch_changes(&self.text)
+    }
+}
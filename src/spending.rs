@@ -0,0 +1,111 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::budget;
+use crate::changes::FileChange;
+use crate::{CommitGPTError, Result};
+
+/// Spending limit per run, in dollars, unless the config sets `max_cost`.
+pub const DEFAULT_MAX_COST: f64 = 0.25;
+
+// Tokens a commit message usually takes, for pricing the completion
+const EXPECTED_COMPLETION_TOKENS: usize = 300;
+const LARGEST_FILES_SHOWN: usize = 5;
+
+/// Dollars per million input and output tokens, by model name prefix.
+pub const PRICES: &[(&str, (f64, f64))] = &[
+    ("gpt-4o-mini", (0.15, 0.60)),
+    ("gpt-4o", (2.50, 10.00)),
+    ("gpt-4-turbo", (10.00, 30.00)),
+    ("gpt-4-32k", (60.00, 120.00)),
+    ("gpt-4", (30.00, 60.00)),
+    ("gpt-3.5-turbo", (0.50, 1.50)),
+    ("o1-mini", (1.10, 4.40)),
+    ("o1-preview", (15.00, 60.00)),
+    ("o1", (15.00, 60.00)),
+    ("o3-mini", (1.10, 4.40)),
+    ("gemini-1.5-flash", (0.075, 0.30)),
+    ("gemini-1.5-pro", (1.25, 5.00)),
+    ("claude-3-5-haiku", (0.80, 4.00)),
+    ("claude-3-5-sonnet", (3.00, 15.00)),
+    ("claude-3-haiku", (0.25, 1.25)),
+    ("claude-3-opus", (15.00, 75.00)),
+    ("grok-2", (2.00, 10.00)),
+    ("grok-beta", (5.00, 15.00)),
+    ("deepseek-chat", (0.14, 0.28)),
+    ("anthropic.claude-3-5-sonnet", (3.00, 15.00)),
+    ("anthropic.claude-3-haiku", (0.25, 1.25)),
+];
+
+pub fn prices(model: &str) -> Option<(f64, f64)> {
+    PRICES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, prices)| *prices)
+}
+
+/// Estimated cost of one request with `prompt_tokens` returning `completions` messages,
+/// or `None` when the model's prices are unknown.
+pub fn estimate_cost(model: &str, prompt_tokens: usize, completions: usize) -> Option<f64> {
+    cost(
+        model,
+        prompt_tokens,
+        EXPECTED_COMPLETION_TOKENS * completions,
+    )
+}
+
+/// What `prompt_tokens` and `completion_tokens` cost with `model`, or `None` when its
+/// prices are unknown.
+pub fn cost(model: &str, prompt_tokens: usize, completion_tokens: usize) -> Option<f64> {
+    let (input, output) = prices(model)?;
+    Some((prompt_tokens as f64 * input + completion_tokens as f64 * output) / 1_000_000.0)
+}
+
+/// Asks before spending more than `limit` on a run. Without a terminal to ask on,
+/// the run is aborted instead.
+pub fn confirm(cost: f64, limit: f64, prompt_tokens: usize, changes: &[FileChange]) -> Result<()> {
+    if cost <= limit {
+        return Ok(());
+    }
+
+    let mut stderr = io::stderr();
+    let _ = writeln!(
+        stderr,
+        "Estimated cost ${:.2} (about {} prompt tokens) exceeds the limit of ${:.2}.",
+        cost, prompt_tokens, limit
+    );
+    let mut largest: Vec<(usize, &str)> = changes
+        .iter()
+        .map(|change| {
+            let tokens = change
+                .summaries
+                .iter()
+                .map(|line| budget::estimate_tokens(line))
+                .sum();
+            (tokens, change.file_path.as_str())
+        })
+        .collect();
+    largest.sort_by_key(|&(tokens, _)| std::cmp::Reverse(tokens));
+    if !largest.is_empty() {
+        let _ = writeln!(
+            stderr,
+            "Largest changes, which could be unstaged or committed separately:"
+        );
+    }
+    for (tokens, path) in largest.iter().take(LARGEST_FILES_SHOWN) {
+        let _ = writeln!(stderr, "  {} (~{} tokens)", path, tokens);
+    }
+
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Err(CommitGPTError::SpendingLimitExceeded(cost, limit));
+    }
+    let _ = write!(stderr, "Send anyway? [y/N] ");
+    let _ = stderr.flush();
+    let mut answer = String::new();
+    stdin.lock().read_line(&mut answer).unwrap_or(0);
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        Ok(())
+    } else {
+        Err(CommitGPTError::SpendingLimitExceeded(cost, limit))
+    }
+}
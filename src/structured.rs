@@ -0,0 +1,80 @@
+// With `--structured` the model answers with the parts of the message as JSON instead of
+// the message itself, so it has no room for code fences or commentary around it. Where
+// the provider can hold replies to a schema it is asked to; elsewhere the prompt alone
+// asks for the JSON. The message is put together here.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::validate::Mood;
+
+/// The parts of a commit message, as the model sends them.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Reply {
+    /// The Conventional Commit type, empty for a plain title
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub scope: String,
+    pub subject: String,
+    pub body_bullets: Vec<String>,
+}
+
+/// The prompt section asking for the parts of the message, with the subject in `mood`.
+pub fn prompt_section(mood: Mood) -> String {
+    format!(
+        "Answer with a JSON object only, with these fields: \
+`type` (the Conventional Commit type, such as feat or fix, or an empty string), \
+`scope` (the area of the code, or an empty string), \
+`subject` (the title without type and scope, in {}) and \
+`body_bullets` (the points of the body as an array of strings without bullet markers; empty for no body).",
+        mood.phrase()
+    )
+}
+
+/// The JSON schema of a [`Reply`], strict enough for OpenAI's structured outputs: every
+/// field required and nothing else allowed.
+pub fn schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "type": { "type": "string" },
+            "scope": { "type": "string" },
+            "subject": { "type": "string" },
+            "body_bullets": { "type": "array", "items": { "type": "string" } },
+        },
+        "required": ["type", "scope", "subject", "body_bullets"],
+        "additionalProperties": false,
+    })
+}
+
+/// Reads a reply, also when a model without a JSON mode wrapped it in a code fence or
+/// in a sentence.
+pub fn parse(reply: &str) -> serde_json::Result<Reply> {
+    let object = match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => reply,
+    };
+    serde_json::from_str(object)
+}
+
+/// The commit message with the parts of `reply`.
+pub fn render(reply: &Reply) -> String {
+    let subject = reply.subject.trim();
+    let mut message = match (reply.kind.trim(), reply.scope.trim()) {
+        ("", _) => subject.to_string(),
+        (kind, "") => format!("{}: {}", kind, subject),
+        (kind, scope) => format!("{}({}): {}", kind, scope, subject),
+    };
+    let bullets: Vec<&str> = reply
+        .body_bullets
+        .iter()
+        .map(|bullet| bullet.trim().trim_start_matches(['-', '*']).trim_start())
+        .filter(|bullet| !bullet.is_empty())
+        .collect();
+    if !bullets.is_empty() {
+        message.push_str("\n\n- ");
+        message.push_str(&bullets.join("\n- "));
+    }
+    message
+}
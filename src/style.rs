@@ -0,0 +1,149 @@
+use std::env;
+
+use clap::ValueEnum;
+use regex::Regex;
+
+use crate::trailers::trailer_block_start;
+
+const THEME_ENV: &str = "COMMIT_GPT_COLORS";
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether output to a stream should be colored. `auto` colors terminals
+    /// unless `NO_COLOR` is set, while the explicit choices always win.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                is_terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
+/// SGR parameters for each part of a rendered message, overridable through
+/// `COMMIT_GPT_COLORS`, e.g. `subject=1;33:bullet=2:trailer=36`.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub subject: String,
+    pub bullet: String,
+    pub trailer: String,
+    pub warning: String,
+    pub added: String,
+    pub removed: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            subject: "1".to_string(),
+            bullet: "2".to_string(),
+            trailer: "36".to_string(),
+            warning: "31".to_string(),
+            added: "32".to_string(),
+            removed: "31;9".to_string(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn from_env() -> Self {
+        let mut theme = Theme::default();
+        let Ok(spec) = env::var(THEME_ENV) else {
+            return theme;
+        };
+        for entry in spec.split(':') {
+            let Some((name, code)) = entry.split_once('=') else {
+                continue;
+            };
+            let slot = match name.trim() {
+                "subject" => &mut theme.subject,
+                "bullet" => &mut theme.bullet,
+                "trailer" => &mut theme.trailer,
+                "warning" => &mut theme.warning,
+                "added" => &mut theme.added,
+                "removed" => &mut theme.removed,
+                _ => continue,
+            };
+            *slot = code.trim().to_string();
+        }
+        theme
+    }
+}
+
+pub struct Painter {
+    enabled: bool,
+    theme: Theme,
+}
+
+impl Painter {
+    pub fn new(choice: ColorChoice, is_terminal: bool) -> Self {
+        Painter {
+            enabled: choice.enabled(is_terminal),
+            theme: Theme::from_env(),
+        }
+    }
+
+    pub fn paint(&self, code: &str, text: &str) -> String {
+        if !self.enabled || code.is_empty() || text.is_empty() {
+            return text.to_string();
+        }
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+
+    pub fn warning(&self, text: &str) -> String {
+        self.paint(&self.theme.warning, text)
+    }
+
+    pub fn added(&self, text: &str) -> String {
+        if self.enabled {
+            self.paint(&self.theme.added, text)
+        } else {
+            format!("{{+{}+}}", text)
+        }
+    }
+
+    pub fn removed(&self, text: &str) -> String {
+        if self.enabled {
+            self.paint(&self.theme.removed, text)
+        } else {
+            format!("[-{}-]", text)
+        }
+    }
+
+    /// Colors the subject, the body bullets and the trailing trailer block of a commit message.
+    pub fn message(&self, message: &str) -> String {
+        if !self.enabled {
+            return message.to_string();
+        }
+
+        let lines: Vec<&str> = message.lines().collect();
+        let trailer_start = trailer_block_start(&lines);
+        let bullet = Regex::new(r"^\s*[-*]\s").unwrap();
+
+        lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                if index == 0 {
+                    self.paint(&self.theme.subject, line)
+                } else if trailer_start.is_some_and(|start| index >= start) {
+                    self.paint(&self.theme.trailer, line)
+                } else if bullet.is_match(line) {
+                    self.paint(&self.theme.bullet, line)
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
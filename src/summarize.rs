@@ -0,0 +1,86 @@
+// When the judge keeps flagging a message, the prompt has usually lost part of the change
+// to the context budget. Each file is then summarized on its own, in a request small
+// enough to hold all of it, and a last message is written from the summaries.
+
+use std::collections::HashMap;
+use std::slice;
+use std::time::Instant;
+
+use crate::budget;
+use crate::cache::SummaryCache;
+use crate::changes::{format_changes_for_prompt, FileChange};
+use crate::client::Client;
+use crate::deadline::{self, Stage};
+use crate::injection;
+use crate::openai::Message;
+use crate::prompt::fill;
+use crate::Result;
+
+const SUMMARY_SYSTEM_PROMPT: &str =
+    "You summarize changes to a single file of a Git change, without any speculation.";
+const SUMMARY_PROMPT_TEMPLATE: &str = "\
+Summarize what the change to this file does in one or two sentences. Reply with the summary only.
+
+{sandbox_instruction}
+
+Changes:
+{structured_changes}";
+
+/// A line per changed file with what `model` makes of its change, in the layout of the
+/// changes in the prompt. Changes marked as context only are left out. Summaries are
+/// looked up in and added to `known`, keyed by the blob OIDs like the summary cache, so
+/// the same change is only summarized once.
+pub fn summarize_files(
+    client: &Client,
+    model: &str,
+    changes: &[FileChange],
+    until: Option<Instant>,
+    known: &mut HashMap<String, String>,
+) -> Result<String> {
+    let budget = client
+        .capabilities(model)
+        .max_context
+        .map_or_else(|| budget::prompt_budget(model), budget::reserve_completion);
+    let available = budget.saturating_sub(
+        budget::estimate_tokens(SUMMARY_SYSTEM_PROMPT)
+            + budget::estimate_tokens(SUMMARY_PROMPT_TEMPLATE),
+    );
+
+    let mut summaries = String::new();
+    for change in changes.iter().filter(|change| !change.context_only) {
+        let key = SummaryCache::key(change.old_oid, change.new_oid);
+        if let Some(summary) = known.get(&key) {
+            summaries.push_str(&format!(
+                "- **{}** ({}): {}\n",
+                change.file_path, change.change_type, summary
+            ));
+            continue;
+        }
+        deadline::check(until, Stage::Validation)?;
+        let formatted = format_changes_for_prompt(
+            slice::from_ref(change),
+            &SummaryCache::default(),
+            usize::MAX,
+        );
+        let (kept, _) = budget::split_at_budget(&formatted, available);
+        let prompt = fill(
+            SUMMARY_PROMPT_TEMPLATE,
+            &[
+                ("sandbox_instruction", injection::SANDBOX_INSTRUCTION),
+                ("structured_changes", &injection::sandbox(kept)),
+            ],
+        );
+        let messages = [
+            Message::system(SUMMARY_SYSTEM_PROMPT),
+            Message::user(&prompt),
+        ];
+        let summary = client.complete(model, &messages)?;
+        let summary = summary.split_whitespace().collect::<Vec<_>>().join(" ");
+        summaries.push_str(&format!(
+            "- **{}** ({}): {}\n",
+            change.file_path, change.change_type, summary
+        ));
+        known.insert(key, summary);
+    }
+    Ok(summaries)
+}
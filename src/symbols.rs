@@ -0,0 +1,222 @@
+// Line summaries say what text changed but not what it belongs to, so for Rust files the
+// old and new versions are parsed and compared item by item: functions, types, traits and
+// the methods of impl blocks. Items are compared by their tokens, so reformatting or
+// editing a comment does not count as a change, while editing a doc comment does. With
+// the `polyglot` feature, other languages are parsed with tree-sitter (see `polyglot.rs`).
+
+use std::collections::HashMap;
+
+use quote::ToTokens;
+use syn::{ImplItem, Item};
+
+/// Above this many changed items the rest are only counted
+const MAX_ITEMS: usize = 20;
+
+/// An item of a file, and for impl blocks and classes their members, as token text.
+#[derive(Default)]
+struct Entry {
+    tokens: String,
+    members: Vec<(String, String)>,
+}
+
+/// The items of a file in the order they appear, keyed by kind and name.
+#[derive(Default)]
+pub struct Items {
+    order: Vec<String>,
+    entries: HashMap<String, Entry>,
+}
+
+impl Items {
+    /// The items of `source`, or `None` when it is not valid Rust, as work in progress
+    /// often is.
+    fn parse_rust(source: &str) -> Option<Self> {
+        let file = syn::parse_file(source).ok()?;
+        let mut items = Items::default();
+        items.add_all(&file.items, "");
+        Some(items)
+    }
+
+    /// Records the item `key`, like "fn `parse`", with its `tokens` and `members`, each
+    /// a key and tokens too. Items with the same key, like several `impl Foo` blocks,
+    /// are taken together.
+    pub fn add(&mut self, key: String, tokens: &str, members: Vec<(String, String)>) {
+        if !self.entries.contains_key(&key) {
+            self.order.push(key.clone());
+        }
+        let entry = self.entries.entry(key).or_default();
+        entry.tokens.push_str(tokens);
+        entry.members.extend(members);
+    }
+
+    fn add_all(&mut self, items: &[Item], prefix: &str) {
+        for item in items {
+            // Inline modules are looked into, so a change in `mod tests` names the test
+            if let Item::Mod(module) = item {
+                if let Some((_, content)) = &module.content {
+                    self.add_all(content, &format!("{}{}::", prefix, module.ident));
+                    continue;
+                }
+            }
+            let Some(key) = key(item, prefix) else {
+                continue;
+            };
+            let members = match item {
+                Item::Impl(block) => block.items.iter().filter_map(member).collect(),
+                _ => Vec::new(),
+            };
+            self.add(key, &item.to_token_stream().to_string(), members);
+        }
+    }
+}
+
+/// Whether the items of `path` can be told apart, by its extension.
+pub fn supports(path: &str) -> bool {
+    match extension(path) {
+        "rs" => true,
+        #[cfg(feature = "polyglot")]
+        extension => crate::polyglot::supports(extension),
+        #[cfg(not(feature = "polyglot"))]
+        _ => false,
+    }
+}
+
+fn extension(path: &str) -> &str {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+}
+
+fn parse(path: &str, source: &str) -> Option<Items> {
+    match extension(path) {
+        "rs" => Items::parse_rust(source),
+        #[cfg(feature = "polyglot")]
+        extension => crate::polyglot::parse(extension, source),
+        #[cfg(not(feature = "polyglot"))]
+        _ => None,
+    }
+}
+
+/// The items added, removed and modified from `old` to `new` of the file at `path`,
+/// like "added fn `parse`", or `None` when either version does not parse. A missing
+/// version has no items.
+pub fn changed_items(path: &str, old: Option<&str>, new: Option<&str>) -> Option<Vec<String>> {
+    let parse = |source: Option<&str>| {
+        source.map_or_else(|| Some(Items::default()), |source| parse(path, source))
+    };
+    let old = parse(old)?;
+    let new = parse(new)?;
+
+    let mut changed = Vec::new();
+    for key in &new.order {
+        let entry = &new.entries[key];
+        match old.entries.get(key) {
+            None => changed.push(format!("added {}", key)),
+            Some(previous) if previous.tokens != entry.tokens => {
+                let members = changed_members(&previous.members, &entry.members);
+                if members.is_empty() {
+                    changed.push(format!("modified {}", key));
+                } else {
+                    changed.push(format!("modified {} ({})", key, members.join(", ")));
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    for key in &old.order {
+        if !new.entries.contains_key(key) {
+            changed.push(format!("removed {}", key));
+        }
+    }
+
+    if changed.len() > MAX_ITEMS {
+        let more = changed.len() - MAX_ITEMS;
+        changed.truncate(MAX_ITEMS);
+        changed.push(format!("{} more", more));
+    }
+    Some(changed)
+}
+
+fn changed_members(old: &[(String, String)], new: &[(String, String)]) -> Vec<String> {
+    let find = |members: &[(String, String)], key: &str| {
+        members
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, tokens)| tokens.clone())
+    };
+    let mut changed = Vec::new();
+    for (key, tokens) in new {
+        match find(old, key) {
+            None => changed.push(format!("added {}", key)),
+            Some(previous) if &previous != tokens => changed.push(format!("modified {}", key)),
+            Some(_) => {}
+        }
+    }
+    for (key, _) in old {
+        if find(new, key).is_none() {
+            changed.push(format!("removed {}", key));
+        }
+    }
+    changed
+}
+
+/// Kind and name of an item, like "fn `parse`" or "impl `Display for Stage`". Imports
+/// and other items without a name of their own are left out.
+fn key(item: &Item, prefix: &str) -> Option<String> {
+    let (kind, name) = match item {
+        Item::Fn(item) => ("fn", item.sig.ident.to_string()),
+        Item::Struct(item) => ("struct", item.ident.to_string()),
+        Item::Enum(item) => ("enum", item.ident.to_string()),
+        Item::Union(item) => ("union", item.ident.to_string()),
+        Item::Trait(item) => ("trait", item.ident.to_string()),
+        Item::TraitAlias(item) => ("trait", item.ident.to_string()),
+        Item::Type(item) => ("type", item.ident.to_string()),
+        Item::Const(item) => ("const", item.ident.to_string()),
+        Item::Static(item) => ("static", item.ident.to_string()),
+        Item::Mod(item) => ("mod", item.ident.to_string()),
+        Item::Macro(item) => ("macro", item.ident.as_ref()?.to_string()),
+        Item::Impl(item) => {
+            let self_ty = compact(&item.self_ty.to_token_stream().to_string());
+            let name = match &item.trait_ {
+                Some((negative, path, _)) => format!(
+                    "{}{} for {}",
+                    if negative.is_some() { "!" } else { "" },
+                    compact(&path.to_token_stream().to_string()),
+                    self_ty
+                ),
+                None => self_ty,
+            };
+            return Some(format!("impl `{}`", name));
+        }
+        _ => return None,
+    };
+    Some(format!("{} `{}{}`", kind, prefix, name))
+}
+
+fn member(item: &ImplItem) -> Option<(String, String)> {
+    let key = match item {
+        ImplItem::Fn(item) => format!("fn `{}`", item.sig.ident),
+        ImplItem::Const(item) => format!("const `{}`", item.ident),
+        ImplItem::Type(item) => format!("type `{}`", item.ident),
+        _ => return None,
+    };
+    Some((key, item.to_token_stream().to_string()))
+}
+
+/// Token text with the spaces between tokens taken out where Rust is not written with
+/// them, so `Vec < String >` reads `Vec<String>`.
+fn compact(tokens: &str) -> String {
+    [
+        (" < ", "<"),
+        ("< ", "<"),
+        (" >", ">"),
+        (" :: ", "::"),
+        (":: ", "::"),
+        (" ,", ","),
+        ("& ", "&"),
+    ]
+    .iter()
+    .fold(tokens.to_string(), |text, (from, to)| {
+        text.replace(from, to)
+    })
+}
@@ -0,0 +1,76 @@
+use std::fs;
+
+use git2::Repository;
+
+/// The line `git commit -v` puts, after the comment prefix, above the diff.
+pub const SCISSORS: &str = "------------------------ >8 ------------------------";
+
+/// Reads the file configured as `commit.template`, if any.
+pub fn load(repo: &Repository) -> Option<String> {
+    let path = repo.config().ok()?.get_path("commit.template").ok()?;
+    let path = match repo.workdir() {
+        Some(workdir) if path.is_relative() => workdir.join(path),
+        _ => path,
+    };
+    let template = fs::read_to_string(path).ok()?;
+    if template.trim().is_empty() {
+        None
+    } else {
+        Some(template)
+    }
+}
+
+/// The prefix git uses for comment lines, from `core.commentString` or
+/// `core.commentChar`. `auto` resolves to `#` unless `existing` shows git picked
+/// another character.
+pub fn comment_prefix(repo: &Repository, existing: Option<&str>) -> String {
+    let configured = repo.config().ok().and_then(|config| {
+        config
+            .get_string("core.commentString")
+            .or_else(|_| config.get_string("core.commentChar"))
+            .ok()
+    });
+    match configured.as_deref() {
+        Some("auto") => existing
+            .and_then(detect_comment_char)
+            .unwrap_or_else(|| "#".to_string()),
+        Some(prefix) if !prefix.is_empty() => prefix.to_string(),
+        _ => "#".to_string(),
+    }
+}
+
+/// The comment character git picked for `existing`, from its last line above the diff
+/// `git commit -v` appends below the scissors line.
+fn detect_comment_char(contents: &str) -> Option<String> {
+    let message: Vec<&str> = contents
+        .lines()
+        .take_while(|line| !line.ends_with(SCISSORS))
+        .collect();
+    // Same candidates git tries for core.commentChar=auto
+    let last = message.iter().rev().find(|line| !line.trim().is_empty())?;
+    let first = last.chars().next()?;
+    "#;@!$%^&|:".contains(first).then(|| first.to_string())
+}
+
+/// Prompt section asking the model to follow the template's structure, with its
+/// comment lines passed along as guidance only.
+pub fn prompt_section(template: &str, comment_prefix: &str) -> String {
+    format!(
+        "Fill in the team's commit template below, keeping its structure and section headings. \
+Lines starting with {comment} are guidance for the author: follow them but do not copy them into the message.\n\n{}",
+        template.trim_end(),
+        comment = comment_prefix
+    )
+}
+
+/// Drops comment lines from a generated message. Git would strip them on commit
+/// anyway; they are usually template guidance the model echoed back.
+pub fn strip_comments(message: &str, comment_prefix: &str) -> String {
+    message
+        .lines()
+        .filter(|line| !line.starts_with(comment_prefix))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
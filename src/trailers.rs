@@ -0,0 +1,61 @@
+use git2::Repository;
+use regex::Regex;
+
+use crate::Result;
+
+pub const SIGNED_OFF_BY: &str = "Signed-off-by";
+
+/// Index of the first line of the trailing `Key: value` block, if the message ends with one.
+pub fn trailer_block_start(lines: &[&str]) -> Option<usize> {
+    let trailer = Regex::new(r"^[A-Za-z][A-Za-z0-9-]*: \S").unwrap();
+    let last_blank = lines.iter().rposition(|line| line.trim().is_empty())?;
+    let block = &lines[last_blank + 1..];
+    if !block.is_empty() && block.iter().all(|line| trailer.is_match(line)) {
+        Some(last_blank + 1)
+    } else {
+        None
+    }
+}
+
+pub fn signoff_line(repo: &Repository) -> Result<String> {
+    let signature = repo.signature()?;
+    Ok(format!(
+        "{}: {} <{}>",
+        SIGNED_OFF_BY,
+        signature.name().unwrap_or_default(),
+        signature.email().unwrap_or_default()
+    ))
+}
+
+/// Appends `trailer` the way `git commit -s` does: into the existing trailer block
+/// when there is one, and not at all when it is already the last trailer.
+pub fn append_trailer(message: &str, trailer: &str) -> String {
+    let message = message.trim_end();
+    let lines: Vec<&str> = message.lines().collect();
+
+    match trailer_block_start(&lines) {
+        Some(_) if lines.last() == Some(&trailer) => message.to_string(),
+        Some(_) => format!("{}\n{}", message, trailer),
+        None => format!("{}\n\n{}", message, trailer),
+    }
+}
+
+/// Lines of the trailing trailer block of `message`, e.g. `Signed-off-by` and `Change-Id`.
+pub fn parse_trailers(message: &str) -> Vec<String> {
+    let lines: Vec<&str> = message.trim_end().lines().collect();
+    match trailer_block_start(&lines) {
+        Some(start) => lines[start..].iter().map(|line| line.to_string()).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Re-attaches `trailers` to `message`, skipping any the message already carries.
+pub fn merge_trailers(message: &str, trailers: &[String]) -> String {
+    let present = parse_trailers(message);
+    trailers
+        .iter()
+        .filter(|trailer| !present.contains(trailer))
+        .fold(message.trim_end().to_string(), |message, trailer| {
+            append_trailer(&message, trailer)
+        })
+}
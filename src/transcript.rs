@@ -0,0 +1,115 @@
+// `--save-transcript` writes down everything a run asked and was told, for attaching to
+// a bug report about a bad message. Every exchange is appended as soon as it finishes,
+// so a run that fails halfway still leaves what led up to the failure.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::audit;
+use crate::openai::Message;
+use crate::{CommitGPTError, Result};
+
+/// A Markdown record of the requests of a run, their replies, and notes on how the
+/// replies were checked.
+pub struct Transcript {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: File,
+    requests: usize,
+}
+
+impl Transcript {
+    /// Starts the transcript at `path`, replacing any earlier one.
+    pub fn create(path: &Path, model: &str) -> Result<Self> {
+        let mut file = File::create(path).map_err(|e| write_error(path, e))?;
+        writeln!(
+            file,
+            "# commit-gpt {} transcript\n\n- Started: {}\n- Model: {}\n",
+            env!("CARGO_PKG_VERSION"),
+            audit::timestamp(SystemTime::now()),
+            model
+        )
+        .map_err(|e| write_error(path, e))?;
+        Ok(Transcript {
+            path: path.to_path_buf(),
+            state: Mutex::new(State { file, requests: 0 }),
+        })
+    }
+
+    /// Records a request for `n` replies from `model` and what came of it.
+    pub fn exchange(
+        &self,
+        model: &str,
+        messages: &[Message],
+        n: usize,
+        result: std::result::Result<&[String], &CommitGPTError>,
+    ) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.requests += 1;
+        let mut text = match n {
+            1 => format!("## Request {} to {}\n\n", state.requests, model),
+            n => format!(
+                "## Request {} to {} for {} replies\n\n",
+                state.requests, model, n
+            ),
+        };
+        for message in messages {
+            text.push_str(&block(&message.role, &message.content));
+        }
+        match result {
+            Ok(replies) if replies.len() == 1 => text.push_str(&block("Reply", &replies[0])),
+            Ok(replies) => {
+                for (index, reply) in replies.iter().enumerate() {
+                    text.push_str(&block(
+                        &format!("Reply {} of {}", index + 1, replies.len()),
+                        reply,
+                    ));
+                }
+            }
+            Err(e) => text.push_str(&block("Failed", &e.to_string())),
+        }
+        self.write(&mut state.file, &text);
+    }
+
+    /// Records something the run found out between requests, like the problems a check
+    /// found in a reply.
+    pub fn note(&self, title: &str, body: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            self.write(
+                &mut state.file,
+                &format!("## {}\n\n{}\n\n", title, body.trim_end()),
+            );
+        }
+    }
+
+    /// Failing to keep the transcript only warns, since it must not change the outcome
+    /// of the run.
+    fn write(&self, file: &mut File, text: &str) {
+        if let Err(e) = file.write_all(text.as_bytes()) {
+            eprintln!("Warning: {}", write_error(&self.path, e));
+        }
+    }
+}
+
+fn block(title: &str, text: &str) -> String {
+    format!("### {}\n\n{}\n\n", title, fenced(text))
+}
+
+/// `text` in a fenced block, with a fence longer than any run of backticks in it.
+pub fn fenced(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}text\n{}\n{}", fence, text.trim_end(), fence)
+}
+
+fn write_error(path: &Path, error: std::io::Error) -> CommitGPTError {
+    CommitGPTError::TranscriptWriteError(path.display().to_string(), error)
+}
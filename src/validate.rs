@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use regex::{Captures, Regex};
+
+use crate::changes::FileChange;
+use crate::language::{self, Language};
+
+/// How much the message says, scaled to the size of the change unless given with `--detail`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Detail {
+    /// Only a title line
+    Oneline,
+    /// A title and a few bullets
+    Brief,
+    /// A title and a bullet per notable change
+    Normal,
+    /// A title and thorough bullets, for large changes
+    Detailed,
+}
+
+impl Detail {
+    /// Picks the detail from the number of added and removed lines.
+    pub fn for_changes(changes: &[FileChange]) -> Self {
+        let lines: usize = changes.iter().map(|change| change.summaries.len()).sum();
+        match lines {
+            0..=2 => Detail::Oneline,
+            3..=40 => Detail::Brief,
+            41..=400 => Detail::Normal,
+            _ => Detail::Detailed,
+        }
+    }
+
+    fn instruction(self) -> &'static str {
+        match self {
+            Detail::Oneline => "This change is tiny: write only the title line, with no body.",
+            Detail::Brief => "This change is small: write the title and at most three short bullets.",
+            Detail::Normal => "Write the title and one bullet per notable change.",
+            Detail::Detailed => {
+                "This change is large: write the title and thorough bullets covering every notable change, grouped by area."
+            }
+        }
+    }
+
+    /// Completion tokens the message may take.
+    pub fn max_tokens(self) -> usize {
+        match self {
+            Detail::Oneline => 60,
+            Detail::Brief => 200,
+            Detail::Normal => 500,
+            Detail::Detailed => 1000,
+        }
+    }
+}
+
+/// The grammatical mood of the message, imperative unless given with `--mood`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mood {
+    /// "Add the cache", as git writes its own messages
+    #[default]
+    Imperative,
+    /// "Added the cache"
+    Past,
+    /// "Adds the cache", describing what the commit does
+    Descriptive,
+}
+
+/// Past tenses that do not end in -ed, of verbs titles often start with
+const IRREGULAR_PAST: &[&str] = &[
+    "began", "bound", "broke", "brought", "built", "chose", "drew", "drove", "found", "froze",
+    "gave", "got", "held", "hid", "kept", "knew", "laid", "left", "lost", "made", "met", "ran",
+    "rebuilt", "reran", "rewrote", "sent", "slid", "spun", "stood", "took", "tore", "undid",
+    "upheld", "went", "withdrew", "wrote",
+];
+/// Verbs whose past tense is spelled like the verb, which fit any mood
+const SAME_IN_PAST: &[&str] = &[
+    "cut", "let", "put", "read", "reset", "set", "shut", "split", "spread", "unset",
+];
+
+impl Mood {
+    /// The mood as the prompts ask for it.
+    pub fn phrase(self) -> &'static str {
+        match self {
+            Mood::Imperative => "the imperative mood",
+            Mood::Past => "the past tense",
+            Mood::Descriptive => "the third person present tense",
+        }
+    }
+
+    /// What is wrong with the mood of `title`, going by its first word after any
+    /// Conventional Commit prefix. Only clear mismatches count, such as "Added" in the
+    /// imperative, since a correction round costs a request.
+    fn problem(self, title: &str) -> Option<&'static str> {
+        let prefix = Regex::new(r"^[a-z]+(\([^)]*\))?!?:\s*").unwrap();
+        let title = prefix.replace(title.trim(), "");
+        let word = title.split_whitespace().next()?.to_lowercase();
+        if !word.chars().all(|c| c.is_ascii_alphabetic()) || SAME_IN_PAST.contains(&word.as_str()) {
+            return None;
+        }
+        let past = (word.ends_with("ed")
+            && !word.ends_with("eed")
+            && !matches!(word.as_str(), "embed" | "shred"))
+            || IRREGULAR_PAST.contains(&word.as_str());
+        match self {
+            Mood::Imperative if past => Some("Write the title in the imperative mood, like \"Add\" rather than \"Added\"."),
+            Mood::Past if !past => Some("Write the title in the past tense, like \"Added\" rather than \"Add\"."),
+            Mood::Descriptive if past || !word.ends_with('s') => {
+                Some("Write the title in the third person present tense, like \"Adds\" rather than \"Add\".")
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Constraints checked on every generated message; violations are fed back to the
+/// model as a correction request.
+#[derive(Default)]
+pub struct Rules {
+    pub max_bullets: Option<usize>,
+    pub conventional_type: Option<&'static str>,
+    /// Terms to avoid, mapped to the preferred term
+    pub glossary: BTreeMap<String, String>,
+    /// Words and patterns that must not appear, such as internal codenames
+    pub banned: Vec<Regex>,
+    pub detail: Option<Detail>,
+    pub mood: Mood,
+    /// The languages of the message, one section each, from `--languages`; English alone
+    /// when empty
+    pub languages: Vec<Language>,
+    /// Replaces the completion budget of the detail level
+    pub max_tokens: Option<usize>,
+    /// Replies are the parts of the message as JSON, see [`crate::structured`]
+    pub structured: bool,
+}
+
+impl Rules {
+    /// Prompt section describing the constraints up front, if there are any.
+    pub fn prompt_section(&self) -> Option<String> {
+        let mut constraints = Vec::new();
+        if let Some(detail) = self.detail {
+            constraints.push(detail.instruction().to_string());
+        }
+        // The conventional type is already requested by the intent section
+        if let Some(max_bullets) = self.max_bullets {
+            constraints.push(format!(
+                "Use at most {} bullet points in the body. When there are more changes than that, \
+prioritize behavior changes users notice over mechanical ones such as renames, formatting or import updates.",
+                max_bullets
+            ));
+        }
+        if !self.glossary.is_empty() {
+            let terms: Vec<String> = self
+                .glossary
+                .iter()
+                .map(|(avoided, preferred)| format!("\"{}\" instead of \"{}\"", preferred, avoided))
+                .collect();
+            constraints.push(format!(
+                "Use the project's terminology: {}.",
+                terms.join(", ")
+            ));
+        }
+        constraints.extend(language::instruction(&self.languages));
+        (!constraints.is_empty()).then(|| constraints.join("\n"))
+    }
+
+    pub fn problems(&self, message: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        // With several languages each section is a message of its own
+        let sections = match self.languages.len() {
+            0 | 1 => vec![(self.languages.first().copied(), message.to_string())],
+            _ => language::sections(message, &self.languages)
+                .into_iter()
+                .map(|(language, section)| (Some(language), section))
+                .collect(),
+        };
+        for (language, section) in sections {
+            for problem in self.section_problems(&section, language) {
+                if !problems.contains(&problem) {
+                    problems.push(problem);
+                }
+            }
+        }
+        problems.extend(language::problems(message, &self.languages));
+
+        if let Some(conventional_type) = self.conventional_type {
+            let title = message.lines().next().unwrap_or_default();
+            let prefix = Regex::new(&format!(r"^{}(\([^)]+\))?!?: \S", conventional_type)).unwrap();
+            if !prefix.is_match(title) {
+                problems.push(format!(
+                    "The title must start with the Conventional Commit type `{}: `.",
+                    conventional_type
+                ));
+            }
+        }
+
+        for (avoided, preferred) in &self.glossary {
+            if term_pattern(avoided).is_match(message) {
+                problems.push(format!("Say \"{}\" instead of \"{}\".", preferred, avoided));
+            }
+        }
+
+        for banned in self.banned_matches(message) {
+            problems.push(format!("Do not use \"{}\"; rephrase without it.", banned));
+        }
+
+        problems
+    }
+
+    /// The problems of the message, or its section, in `language`.
+    fn section_problems(&self, message: &str, language: Option<Language>) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Some(max_bullets) = self.max_bullets {
+            let bullets = count_bullets(message);
+            if bullets > max_bullets {
+                problems.push(format!(
+                    "The body has {} bullet points but at most {} are allowed; keep only the most user-facing changes.",
+                    bullets, max_bullets
+                ));
+            }
+        }
+
+        // Moods are told by English verb endings
+        if language.is_none_or(Language::is_english) {
+            if let Some(problem) = self
+                .mood
+                .problem(message.lines().next().unwrap_or_default())
+            {
+                problems.push(problem.to_string());
+            }
+        }
+
+        if self.detail == Some(Detail::Oneline)
+            && message.lines().skip(1).any(|line| !line.trim().is_empty())
+        {
+            problems.push("Write only the title line; this change needs no body.".to_string());
+        }
+        problems
+    }
+
+    /// Completion budget for a message under these rules, with room for each language.
+    pub fn max_tokens(&self) -> Option<usize> {
+        let languages = self.languages.len().max(1);
+        self.max_tokens
+            .or(self.detail.map(Detail::max_tokens))
+            .map(|tokens| tokens * languages)
+    }
+
+    /// The distinct banned words or phrases found in `message`.
+    pub fn banned_matches(&self, message: &str) -> Vec<String> {
+        let mut found: Vec<String> = Vec::new();
+        for pattern in &self.banned {
+            for banned in pattern.find_iter(message) {
+                if !found
+                    .iter()
+                    .any(|seen| seen.eq_ignore_ascii_case(banned.as_str()))
+                {
+                    found.push(banned.as_str().to_string());
+                }
+            }
+        }
+        found
+    }
+
+    /// Replaces avoided glossary terms with the preferred ones, keeping a leading capital.
+    pub fn substitute_glossary(&self, message: &str) -> String {
+        let mut message = message.to_string();
+        for (avoided, preferred) in &self.glossary {
+            message = term_pattern(avoided)
+                .replace_all(&message, |captures: &Captures| {
+                    let starts_upper = captures[0].starts_with(char::is_uppercase);
+                    let mut chars = preferred.chars();
+                    match chars.next() {
+                        Some(first) if starts_upper => first.to_uppercase().chain(chars).collect(),
+                        _ => preferred.clone(),
+                    }
+                })
+                .into_owned();
+        }
+        message
+    }
+}
+
+/// Compiles a configured banned pattern; matching ignores case.
+pub fn banned_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("(?i){}", pattern))
+}
+
+/// Matches `term` where it is not part of a longer word. At an end of the term that is a
+/// symbol, as in `C++` or `.NET`, that means a symbol, space or the end of the text
+/// next to it, which is where `\B` matches.
+fn term_pattern(term: &str) -> Regex {
+    let boundary = |c: Option<char>| match c {
+        Some(c) if c.is_alphanumeric() || c == '_' => r"\b",
+        _ => r"\B",
+    };
+    Regex::new(&format!(
+        r"(?i){}{}{}",
+        boundary(term.chars().next()),
+        regex::escape(term),
+        boundary(term.chars().next_back())
+    ))
+    .unwrap()
+}
+
+fn count_bullets(message: &str) -> usize {
+    let bullet = Regex::new(r"^\s*([-*+]|\d+[.)])\s").unwrap();
+    message
+        .lines()
+        .skip(1)
+        .filter(|line| bullet.is_match(line))
+        .count()
+}
@@ -0,0 +1,51 @@
+use serde::Deserialize;
+
+use crate::client::Client;
+use crate::injection;
+use crate::openai::{strip_code_fence, Message};
+use crate::prompt::fill;
+use crate::Result;
+
+const VERIFY_SYSTEM_PROMPT: &str = "You review Git commit messages for factual accuracy against the change they describe. Reply with JSON only.";
+const VERIFY_PROMPT_TEMPLATE: &str = "\
+Check the commit message below against the changes. List every statement the changes do not support and every significant change the message misdescribes. Do not comment on style. Reply with JSON of the form {\"issues\": [\"...\"]}, with an empty list if the message is accurate.
+
+{sandbox_instruction}
+
+Changes:
+{structured_changes}
+Commit message:
+{message}";
+
+#[derive(Deserialize)]
+struct Verdict {
+    issues: Vec<String>,
+}
+
+/// Asks `model` to point out inaccuracies of `message` with respect to the changes.
+pub fn find_inaccuracies(
+    client: &Client,
+    model: &str,
+    structured_changes: &str,
+    message: &str,
+) -> Result<Vec<String>> {
+    let prompt = fill(
+        VERIFY_PROMPT_TEMPLATE,
+        &[
+            ("sandbox_instruction", injection::SANDBOX_INSTRUCTION),
+            (
+                "structured_changes",
+                &injection::sandbox(structured_changes),
+            ),
+            ("message", message),
+        ],
+    );
+    let messages = [
+        Message::system(VERIFY_SYSTEM_PROMPT),
+        Message::user(&prompt),
+    ];
+
+    let reply = client.complete(model, &messages)?;
+    let verdict: Verdict = serde_json::from_str(strip_code_fence(&reply))?;
+    Ok(verdict.issues)
+}
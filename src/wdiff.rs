@@ -0,0 +1,58 @@
+use regex::Regex;
+
+use crate::style::Painter;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Removed,
+    Added,
+}
+
+/// Renders a word-level diff between two messages, in the style of `git diff --word-diff`.
+pub fn word_diff(painter: &Painter, old: &str, new: &str) -> String {
+    let token = Regex::new(r"\s+|\S+").unwrap();
+    let old_tokens: Vec<&str> = token.find_iter(old).map(|m| m.as_str()).collect();
+    let new_tokens: Vec<&str> = token.find_iter(new).map(|m| m.as_str()).collect();
+
+    // Longest common subsequence table, filled from the end
+    let (n, m) = (old_tokens.len(), new_tokens.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_tokens[i] == new_tokens[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<(Op, String)> = Vec::new();
+    let mut push = |op: Op, text: &str| match ops.last_mut() {
+        Some((last, run)) if *last == op => run.push_str(text),
+        _ => ops.push((op, text.to_string())),
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_tokens[i] == new_tokens[j] {
+            push(Op::Equal, old_tokens[i]);
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            push(Op::Added, new_tokens[j]);
+            j += 1;
+        } else {
+            push(Op::Removed, old_tokens[i]);
+            i += 1;
+        }
+    }
+
+    ops.iter()
+        .map(|(op, text)| match op {
+            Op::Equal => text.clone(),
+            Op::Removed => painter.removed(text),
+            Op::Added => painter.added(text),
+        })
+        .collect()
+}
@@ -0,0 +1,2702 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use wiremock::matchers::{body_string_contains, header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{stderr, stdout, Fixture};
+
+const MESSAGE: &str = "Add the greeting\n\n- Add hello.txt";
+
+fn completion(content: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "choices": [{ "message": { "role": "assistant", "content": content } }]
+    }))
+}
+
+fn error(status: u16, code: &str, message: &str) -> ResponseTemplate {
+    ResponseTemplate::new(status).set_body_json(json!({
+        "error": { "message": message, "type": "invalid_request_error", "code": code }
+    }))
+}
+
+fn staged_fixture() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+    fixture
+}
+
+async fn server() -> (MockServer, String) {
+    let server = MockServer::start().await;
+    let base_url = format!("{}/v1", server.uri());
+    (server, base_url)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prints_the_generated_message() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("authorization", "Bearer test-key"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_the_api_key_from_the_environment() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(header("authorization", "Bearer env-key"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command_without_key(&base_url)
+        .args(["--detail", "normal"])
+        .env("OPENAI_API_KEY", "env-key")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+
+    let output = fixture
+        .command_without_key(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("pass --api-key-path or set OPENAI_API_KEY"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_the_changes_without_streaming() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command(&base_url)
+        .args(["--model", "gpt-4o-mini", "--detail", "normal"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["model"], "gpt-4o-mini");
+    assert_eq!(body.get("stream"), None);
+    let prompt = body["messages"][1]["content"].as_str().unwrap();
+    assert!(prompt.contains("**hello.txt**: Added"), "{}", prompt);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streams_the_first_draft() {
+    let (server, base_url) = server().await;
+    let chunks: String = ["Add the ", "greeting\n\n", "- Add hello.txt"]
+        .iter()
+        .map(|text| {
+            format!(
+                "data: {}\n\n",
+                json!({ "choices": [{ "index": 0, "delta": { "content": text } }] })
+            )
+        })
+        .chain([
+            "data: {\"choices\":[]}\n\n".to_string(),
+            "data: [DONE]\n\n".to_string(),
+        ])
+        .collect();
+    Mock::given(method("POST"))
+        .and(body_string_contains("\"stream\":true"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(chunks, "text/event-stream"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--stream", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    assert!(stderr(&output).contains(MESSAGE), "{}", stderr(&output));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn saves_a_transcript_of_the_run() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(
+            "Add the greeting\n\nAdd hello.txt with a greeting for new users.",
+        ))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Add the greeting"))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let transcript = fixture.dir.path().join("transcript.md");
+    let output = fixture
+        .command(&base_url)
+        .arg("--save-transcript")
+        .arg(&transcript)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let transcript = fs::read_to_string(transcript).unwrap();
+    assert!(transcript.starts_with("# commit-gpt"), "{}", transcript);
+    assert!(
+        transcript.contains("## Request 1 to gpt-4\n\n### system\n"),
+        "{}",
+        transcript
+    );
+    assert!(
+        transcript.contains("**hello.txt**: Added"),
+        "{}",
+        transcript
+    );
+    assert!(
+        transcript.contains("## Validation\n\nThe reply breaks the rules:"),
+        "{}",
+        transcript
+    );
+    assert!(
+        transcript.contains("## Request 2 to gpt-4"),
+        "{}",
+        transcript
+    );
+    assert!(
+        transcript.ends_with("## Final message\n\n```text\nAdd the greeting\n```\n\n"),
+        "{}",
+        transcript
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn renders_structured_replies() {
+    let (server, base_url) = server().await;
+    let parts = json!({ "type": "feat", "scope": "greeting", "subject": "Add the greeting", "body_bullets": ["- Add hello.txt"] });
+    Mock::given(method("POST"))
+        .and(body_string_contains(r#""response_format":{"json_schema""#))
+        .respond_with(completion(&parts.to_string()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--structured", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output).trim(),
+        "feat(greeting): Add the greeting\n\n- Add hello.txt"
+    );
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(
+        body["response_format"]["json_schema"]["schema"]["required"][3],
+        "body_bullets"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shapes_requests_for_reasoning_models() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--model", "o1-mini", "--detail", "normal"])
+        .args(["--temperature", "0.2", "--top-p", "0.9"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let messages = body["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["role"], "user");
+    assert!(messages[0]["content"]
+        .as_str()
+        .unwrap()
+        .starts_with("You are a helpful assistant"));
+    assert_eq!(body.get("max_tokens"), None);
+    assert_eq!(body["max_completion_tokens"], 500 + 4096);
+    assert_eq!(body.get("temperature"), None);
+    assert_eq!(body.get("top_p"), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_malformed_responses() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{\"choices\": [ oops"))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture().command(&base_url).output().unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("Failed to parse API response"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn explains_rate_limiting() {
+    let (server, base_url) = server().await;
+    // Asked for three times: once, and again for each of the two retries
+    Mock::given(method("POST"))
+        .respond_with(
+            error(429, "rate_limit_exceeded", "Rate limit reached")
+                .insert_header("retry-after", "0"),
+        )
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture().command(&base_url).output().unwrap();
+
+    assert!(!output.status.success());
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains("gpt-4 responded 429 Too Many Requests; retrying in 0.0 s"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("429 Too Many Requests (rate_limit_exceeded): Rate limit reached"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Hint: requests are being rate limited"),
+        "{}",
+        stderr
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_after_a_server_error() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(error(503, "overloaded", "Try again"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "normal", "--no-history"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    assert!(
+        stderr(&output).contains("responded 503 Service Unavailable; retrying"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_up_on_a_request_past_the_timeout() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE).set_delay(Duration::from_secs(30)))
+        .mount(&server)
+        .await;
+
+    let started = Instant::now();
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "normal", "--no-history", "--timeout", "1"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert!(
+        stderr(&output).contains("The API did not answer within the --timeout of 1 s"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_requests_through_the_proxy_given() {
+    let proxy = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("host", "api.commit-gpt.invalid"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&proxy)
+        .await;
+
+    let output = staged_fixture()
+        .command("http://api.commit-gpt.invalid/v1")
+        .args(["--detail", "normal", "--proxy", &proxy.uri()])
+        .env_remove("NO_PROXY")
+        .env_remove("no_proxy")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[test]
+fn rejects_a_ca_certificate_file_without_certificates() {
+    let fixture = staged_fixture();
+    let ca_cert = fixture.dir.path().join("ca.pem");
+    fs::write(&ca_cert, "not a certificate\n").unwrap();
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .args(["--detail", "normal", "--ca-cert"])
+        .arg(&ca_cert)
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("No PEM certificate in"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn falls_back_to_the_next_model_when_rate_limited() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("\"model\":\"gpt-4\""))
+        .respond_with(
+            error(429, "rate_limit_exceeded", "Rate limit reached")
+                .insert_header("retry-after", "0"),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("\"model\":\"gpt-4o-mini\""))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--model", "gpt-4", "--fallback-model", "gpt-4o-mini"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains(MESSAGE));
+    assert!(
+        stderr(&output).contains("Warning: gpt-4 failed"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        stderr(&output).contains("falling back to gpt-4o-mini"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_with_a_smaller_prompt_when_the_context_is_exceeded() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(error(400, "context_length_exceeded", "Too long"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Context length exceeded; retrying"));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn skips_the_api_without_staged_changes() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let output = Fixture::new().command(&base_url).output().unwrap();
+
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("No staged changes detected"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exports_the_request_body_without_sending_it() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args([
+            "--model",
+            "gpt-4o-mini",
+            "export-prompt",
+            "--format",
+            "anthropic",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let body: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(body["model"], "gpt-4o-mini");
+    assert!(body["system"]
+        .as_str()
+        .unwrap()
+        .contains("Git commit messages"));
+    assert_eq!(body["messages"][0]["role"], "user");
+    assert!(body["max_tokens"].is_number());
+}
+
+#[test]
+fn defaults_to_a_model_of_the_provider() {
+    for (provider, model) in [
+        ("anthropic", "claude-3-5-sonnet-latest"),
+        ("gemini", "gemini-1.5-pro"),
+        ("ollama", "llama3.1"),
+    ] {
+        let output = staged_fixture()
+            .command("http://unused")
+            .args(["--provider", provider, "export-prompt"])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{}", stderr(&output));
+        let body: Value = serde_json::from_str(&stdout(&output)).unwrap();
+        assert_eq!(body["model"], model, "{}", provider);
+    }
+}
+
+#[test]
+fn picks_an_auto_model_of_the_provider_by_the_lines_changed() {
+    let model = |fixture: &Fixture| {
+        let output = fixture
+            .command("http://unused")
+            .args([
+                "--provider",
+                "anthropic",
+                "--model",
+                "auto",
+                "export-prompt",
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        let body: Value = serde_json::from_str(&stdout(&output)).unwrap();
+        body["model"].as_str().unwrap().to_string()
+    };
+
+    assert_eq!(model(&staged_fixture()), "claude-3-5-haiku-latest");
+    let fixture = Fixture::new();
+    fixture.write("notes.txt", "a line\n".repeat(250));
+    fixture.stage_all();
+    assert_eq!(model(&fixture), "claude-3-5-sonnet-latest");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fills_the_message_file_passed_by_pre_commit() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let message_file = fixture.dir.path().join("COMMIT_EDITMSG");
+    std::fs::write(&message_file, "\n# Please enter the commit message\n").unwrap();
+    let output = fixture
+        .command(&base_url)
+        .env("PRE_COMMIT_COMMIT_MSG_SOURCE", "")
+        .args([
+            "pre-commit-entry",
+            "--model",
+            "gpt-4o-mini",
+            "--detail",
+            "normal",
+        ])
+        .arg(&message_file)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let contents = std::fs::read_to_string(&message_file).unwrap();
+    assert_eq!(
+        contents,
+        format!("{}\n\n# Please enter the commit message\n", MESSAGE)
+    );
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["model"], "gpt-4o-mini");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn describes_only_the_files_on_stdin() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.write("other.txt", "other\n");
+    fixture.stage_all();
+    let message_file = fixture.dir.path().join("message");
+    let mut child = fixture
+        .command(&base_url)
+        .args(["--stdin-files", "--detail", "normal", "--output-file"])
+        .arg(&message_file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let files = format!("{}\n", fixture.workdir().join("hello.txt").display());
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(files.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        std::fs::read_to_string(&message_file).unwrap(),
+        format!("{}\n", MESSAGE)
+    );
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let prompt = body["messages"][1]["content"].as_str().unwrap();
+    assert!(prompt.contains("**hello.txt**"), "{}", prompt);
+    assert!(!prompt.contains("other.txt"), "{}", prompt);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn marks_files_as_context_only_or_excluded() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = Fixture::new();
+    fixture.write("excluded.txt", "leave me out\n");
+    fixture.write("helper.txt", "only context\n");
+    fixture.write("main.txt", "describe me\n");
+    fixture.stage_all();
+    let mut child = fixture
+        .command(&base_url)
+        .args([
+            "--interactive",
+            "--mark",
+            "--detail",
+            "normal",
+            "--no-history",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"x 1\nc 2\nd\na\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let prompt = body["messages"][1]["content"].as_str().unwrap();
+    assert!(!prompt.contains("excluded.txt"), "{}", prompt);
+    let (described, context) = prompt.split_once("For context only").expect(prompt);
+    assert!(described.contains("**main.txt**"), "{}", prompt);
+    assert!(context.contains("**helper.txt**"), "{}", prompt);
+    assert!(!described.contains("helper.txt"), "{}", prompt);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts_the_blank_lines_of_marked_hunks() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = Fixture::new();
+    let lines: Vec<String> = (1..=20).map(|line| format!("line {}\n", line)).collect();
+    fixture.write("notes.txt", lines.concat());
+    fixture.commit("Add notes");
+    let mut changed = lines.clone();
+    changed[1] = "line two\n\n".to_string();
+    changed[18] = "line nineteen\n".to_string();
+    fixture.write("notes.txt", changed.concat());
+    fixture.stage_all();
+    let mut child = fixture
+        .command(&base_url)
+        .args([
+            "--interactive",
+            "--mark",
+            "--detail",
+            "normal",
+            "--no-history",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"c 1.2\nd\na\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let prompt = body["messages"][1]["content"].as_str().unwrap();
+    let (described, context) = prompt.split_once("For context only").expect(prompt);
+    assert!(
+        described.contains("**notes.txt**: Modified (+2/-1, 1 hunk)"),
+        "{}",
+        prompt
+    );
+    assert!(
+        context.contains("**notes.txt**: Modified (+1/-1, 1 hunk)"),
+        "{}",
+        prompt
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prints_porcelain_lines() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(
+            "Add the greeting\n\n- Add hello.txt\n\n- Mention it\n\nRefs: #1",
+        ))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--porcelain", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "version: 1\ntitle: Add the greeting\nbody: - Add hello.txt\nbody:\nbody: - Mention it\ntrailer: Refs: #1\nstatus: ok\n"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_porcelain_errors_on_stdout() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(
+            error(429, "rate_limit_exceeded", "Rate limit reached")
+                .insert_header("retry-after", "0"),
+        )
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .arg("--porcelain")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stdout = stdout(&output);
+    assert!(
+        stdout.starts_with("version: 1\nstatus: error\nerror: API responded"),
+        "{}",
+        stdout
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prints_emacs_property_lists() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Add the \"greeting\"\n\n- Add hello.txt"))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--output", "emacs", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        concat!(
+            r#"(:body "- Add hello.txt" :message "Add the \"greeting\"\n\n- Add hello.txt" :title "Add the \"greeting\"" :trailers ())"#,
+            "\n"
+        )
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_multi_line_drafts_as_one_property_list_per_line() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let mut child = fixture
+        .command(&base_url)
+        .args([
+            "--server", "stdio", "--output", "emacs", "--detail", "normal",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(
+        stdin,
+        "{}",
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "generateMessage" })
+    )
+    .unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = stdout(&output);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(
+        lines
+            .iter()
+            .all(|line| line.starts_with('(') && line.ends_with(')')),
+        "{}",
+        stdout
+    );
+    let response = lines
+        .iter()
+        .find(|line| line.starts_with("(:id 1 "))
+        .expect(&stdout);
+    assert!(
+        response.contains(&format!(":message {:?}", MESSAGE)),
+        "{}",
+        stdout
+    );
+}
+
+fn serve(fixture: &Fixture, base_url: &str, requests: &[Value]) -> Vec<Value> {
+    let mut child = fixture
+        .command(base_url)
+        .args(["--server", "stdio", "--detail", "normal"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for request in requests {
+        writeln!(stdin, "{}", request).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    stdout(&output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn response(lines: &[Value], id: u64) -> &Value {
+    lines
+        .iter()
+        .find(|line| line["id"] == id && line.get("method").is_none())
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_and_revises_messages_over_stdio() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v1/models"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": [{ "id": "gpt-4o" }, { "id": "acme-chat" }]
+        })))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let lines = serve(
+        &fixture,
+        &base_url,
+        &[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "getConfig" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "listModels" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "generateMessage", "params": { "model": "gpt-4o-mini" } }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "shutdown" }),
+        ],
+    );
+
+    assert_eq!(response(&lines, 1)["result"]["model"], "gpt-4");
+    let models = response(&lines, 2)["result"].as_array().unwrap();
+    assert_eq!(models[0]["id"], "acme-chat");
+    assert_eq!(models[0]["contextWindow"], Value::Null);
+    assert_eq!(models[1]["id"], "gpt-4o");
+    assert_eq!(models[1]["contextWindow"], 128000);
+    assert_eq!(models[1]["inputPrice"], 2.5);
+    assert_eq!(response(&lines, 3)["result"]["title"], "Add the greeting");
+    let statuses: Vec<&Value> = lines
+        .iter()
+        .filter(|line| line["method"] == "status")
+        .collect();
+    assert_eq!(
+        statuses[0]["params"],
+        json!({ "id": 3, "status": "generating" })
+    );
+    assert_eq!(statuses[1]["params"], json!({ "id": 3, "status": "idle" }));
+
+    let received = server.received_requests().await.unwrap();
+    let completion = received
+        .iter()
+        .find(|request| request.method.as_str() == "POST")
+        .unwrap();
+    let body: Value = serde_json::from_slice(&completion.body).unwrap();
+    assert_eq!(body["model"], "gpt-4o-mini");
+}
+
+#[test]
+fn answers_requests_without_a_method_as_invalid() {
+    let fixture = staged_fixture();
+    let lines = serve(
+        &fixture,
+        "http://127.0.0.1:9",
+        &[
+            json!({ "jsonrpc": "2.0", "id": 7, "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "shutdown", "id": 8 }),
+        ],
+    );
+
+    assert_eq!(response(&lines, 7)["error"]["code"], -32600);
+    assert_eq!(response(&lines, 8)["result"], Value::Null);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn revises_the_last_draft_with_feedback() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let mut child = fixture
+        .command(&base_url)
+        .args(["--server", "stdio", "--detail", "normal"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+    let mut read_response = |id: u64| loop {
+        let mut line = String::new();
+        std::io::BufRead::read_line(&mut stdout, &mut line).unwrap();
+        let line: Value = serde_json::from_str(&line).unwrap();
+        if line["id"] == id && line.get("method").is_none() {
+            return line;
+        }
+    };
+
+    writeln!(
+        stdin,
+        "{}",
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "generateMessage" })
+    )
+    .unwrap();
+    assert_eq!(read_response(1)["result"]["message"], MESSAGE);
+    let revise = json!({ "jsonrpc": "2.0", "id": 2, "method": "generateMessage", "params": { "feedback": "Mention the farewell" } });
+    writeln!(stdin, "{}", revise).unwrap();
+    assert_eq!(read_response(2)["result"]["message"], MESSAGE);
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+
+    let received = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&received[1].body).unwrap();
+    assert_eq!(body["messages"][2]["content"], MESSAGE);
+    assert_eq!(body["messages"][3]["content"], "Mention the farewell");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancels_pending_requests() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE).set_delay(std::time::Duration::from_millis(500)))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let lines = serve(
+        &fixture,
+        &base_url,
+        &[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "generateMessage", "params": { "cancellationToken": "draft" } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "cancel", "params": { "cancellationToken": "draft" } }),
+        ],
+    );
+
+    let responses: Vec<&Value> = lines
+        .iter()
+        .filter(|line| line["id"] == 1 && line.get("method").is_none())
+        .collect();
+    assert_eq!(responses.len(), 1, "{:?}", lines);
+    assert_eq!(responses[0]["error"]["code"], -32800);
+    let cancel = response(&lines, 2);
+    assert!(cancel.get("result").is_some(), "{:?}", lines);
+    assert!(cancel.get("error").is_none(), "{:?}", lines);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_idle_once_no_request_is_pending() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE).set_delay(std::time::Duration::from_millis(200)))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let lines = serve(
+        &fixture,
+        &base_url,
+        &[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "generateMessage" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "generateMessage", "params": { "model": "gpt-4o-mini" } }),
+        ],
+    );
+
+    let statuses: Vec<&Value> = lines
+        .iter()
+        .filter(|line| line["method"] == "status")
+        .map(|line| &line["params"]["status"])
+        .collect();
+    assert_eq!(
+        statuses,
+        ["generating", "generating", "idle"],
+        "{:?}",
+        lines
+    );
+    assert_eq!(lines.last().unwrap()["params"]["status"], "idle");
+    assert_eq!(response(&lines, 1)["result"]["message"], MESSAGE);
+    assert_eq!(response(&lines, 2)["result"]["message"], MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_models_with_known_context_sizes_and_prices() {
+    let (server, base_url) = server().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/models"))
+        .and(header("authorization", "Bearer test-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": [
+                { "id": "gpt-4o-mini" },
+                { "id": "acme-chat" },
+                { "id": "davinci-002" },
+                { "id": "text-embedding-3-small" },
+                { "id": "whisper-1" }
+            ]
+        })))
+        .mount(&server)
+        .await;
+
+    let output = Fixture::new()
+        .command(&base_url)
+        .arg("models")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let lines: Vec<Vec<String>> = stdout(&output)
+        .lines()
+        .map(|line| {
+            line.split("  ")
+                .map(str::trim)
+                .filter(|cell| !cell.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .collect();
+    // Completion, embedding and speech models are left out
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert_eq!(
+        lines[1],
+        ["acme-chat", "-", "-", "-", "streaming,json,system"]
+    );
+    assert_eq!(
+        lines[2],
+        [
+            "gpt-4o-mini",
+            "128000",
+            "0.15",
+            "0.60",
+            "streaming,json,system"
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pings_the_provider() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Pong"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = Fixture::new()
+        .command(&base_url)
+        .arg("ping")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).starts_with("gpt-4 responded in "),
+        "{}",
+        stdout(&output)
+    );
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["max_tokens"], 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ping_exits_with_a_distinct_code_for_rejected_keys() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(error(401, "invalid_api_key", "Incorrect API key provided"))
+        .mount(&server)
+        .await;
+
+    let output = Fixture::new()
+        .command(&base_url)
+        .arg("ping")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Incorrect API key provided"));
+    assert!(
+        stderr(&output).contains("the provider's environment variable, like OPENAI_API_KEY"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forbids_network_requests() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .arg("--forbid-network")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("because of --forbid-network"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restores_private_paths_in_the_message() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Add file1 to dir1\n\n- Add dir1/file1.rs"))
+        .mount(&server)
+        .await;
+
+    let fixture = Fixture::new();
+    fixture.write("aurora/merger.rs", "pub fn close() {}\n");
+    fixture.stage_all();
+    let output = fixture
+        .command(&base_url)
+        .args(["--private-paths", "bucket", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output).trim(),
+        "Add merger to aurora\n\n- Add aurora/merger.rs"
+    );
+    let requests = server.received_requests().await.unwrap();
+    let body = String::from_utf8_lossy(&requests[0].body);
+    assert!(
+        !body.contains("aurora") && !body.contains("merger"),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_private_paths_out_of_the_previous_draft() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Add file1 to dir1"))
+        .mount(&server)
+        .await;
+
+    let fixture = Fixture::new();
+    fixture.write("aurora/merger.rs", "pub fn close() {}\n");
+    fixture.stage_all();
+    let output = fixture
+        .command(&base_url)
+        .args(["--private-paths", "bucket", "--incremental"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "Add merger to aurora");
+
+    fixture.write("aurora/vote.rs", "pub fn count() {}\n");
+    fixture.stage_all();
+    let output = fixture
+        .command(&base_url)
+        .args(["--private-paths", "hash", "--incremental", "--show-prompt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let prompt = stdout(&output);
+    assert!(prompt.contains("Previous draft:\nAdd "), "{}", prompt);
+    for name in ["aurora", "merger", "vote"] {
+        assert!(!prompt.contains(name), "{}", prompt);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_private_paths_out_of_revisions() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Add file1 to dir1"))
+        .mount(&server)
+        .await;
+
+    let fixture = Fixture::new();
+    fixture.write("aurora/merger.rs", "pub fn close() {}\n");
+    fixture.stage_all();
+    let mut child = fixture
+        .command(&base_url)
+        .args(["--server", "stdio", "--private-paths", "bucket"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+    let mut read_response = |id: u64| loop {
+        let mut line = String::new();
+        std::io::BufRead::read_line(&mut stdout, &mut line).unwrap();
+        let line: Value = serde_json::from_str(&line).unwrap();
+        if line["id"] == id && line.get("method").is_none() {
+            return line;
+        }
+    };
+
+    writeln!(
+        stdin,
+        "{}",
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "generateMessage" })
+    )
+    .unwrap();
+    assert_eq!(
+        read_response(1)["result"]["message"],
+        "Add merger to aurora"
+    );
+    let revise = json!({ "jsonrpc": "2.0", "id": 2, "method": "generateMessage", "params": { "feedback": "Say why merger moved into aurora" } });
+    writeln!(stdin, "{}", revise).unwrap();
+    read_response(2);
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let body: Value = serde_json::from_slice(&requests[1].body).unwrap();
+    assert_eq!(body["messages"][2]["content"], "Add file1 to dir1");
+    assert_eq!(
+        body["messages"][3]["content"],
+        "Say why file1 moved into dir1"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn substitutes_glossary_terms_that_end_in_symbols() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Build the .NET app with ASP.NET and C++"))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let config = fixture.dir.path().join("config/commit-gpt/config.toml");
+    std::fs::create_dir_all(config.parent().unwrap()).unwrap();
+    std::fs::write(
+        &config,
+        "[glossary]\n\".NET\" = \"dotnet\"\n\"C++\" = \"cpp\"\n",
+    )
+    .unwrap();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output).trim(),
+        "Build the dotnet app with ASP.NET and Cpp"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_payloads_in_the_audit_log() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let log = fixture.dir.path().join("audit.jsonl");
+    let config = fixture.dir.path().join("config/commit-gpt/config.toml");
+    std::fs::create_dir_all(config.parent().unwrap()).unwrap();
+    std::fs::write(
+        &config,
+        format!("[audit]\npath = {:?}\npayloads = \"full\"\n", log),
+    )
+    .unwrap();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let requests = server.received_requests().await.unwrap();
+    let sent = String::from_utf8_lossy(&requests[0].body).into_owned();
+    let entries: Vec<Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0]["destination"],
+        format!("{}/chat/completions", base_url)
+    );
+    assert_eq!(entries[0]["payload"], sent);
+    assert_eq!(entries[0]["bytes"], sent.len());
+    let sha256: String = ring::digest::digest(&ring::digest::SHA256, &requests[0].body)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(entries[0]["sha256"], sha256);
+    assert!(entries[0]["timestamp"].as_str().unwrap().ends_with('Z'));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ignores_audit_settings_from_the_repository() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let log = fixture.dir.path().join("audit.jsonl");
+    fixture.write(".commit-gpt.toml", format!("[audit]\npath = {:?}\n", log));
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("Warning: ignoring [audit]"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!log.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn searches_the_history_of_generated_messages() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let found = fixture
+        .command(&base_url)
+        .args(["history", "search", "GREETING", "hello.txt"])
+        .output()
+        .unwrap();
+    assert!(found.status.success(), "{}", stderr(&found));
+    assert!(
+        stdout(&found).contains("    Add the greeting\n\n    - Add hello.txt\n"),
+        "{}",
+        stdout(&found)
+    );
+
+    let missing = fixture
+        .command(&base_url)
+        .args(["history", "search", "farewell"])
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&missing), "");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exports_edited_messages_as_training_data() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    fixture.commit("Greet the world\n\nAdd hello.txt with the greeting.");
+
+    let exported = fixture
+        .command(&base_url)
+        .args(["feedback", "export"])
+        .output()
+        .unwrap();
+    assert!(exported.status.success(), "{}", stderr(&exported));
+    let exported = stdout(&exported);
+    let lines: Vec<&str> = exported.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", exported);
+    let example: Value = serde_json::from_str(lines[0]).unwrap();
+    let messages = example["messages"].as_array().unwrap();
+    assert_eq!(messages[0]["role"], "system");
+    assert!(messages[1]["content"]
+        .as_str()
+        .unwrap()
+        .contains("hello.txt"));
+    assert_eq!(
+        messages[2]["content"],
+        "Greet the world\n\nAdd hello.txt with the greeting."
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn does_not_count_the_signoff_as_an_edit() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let mut config = fixture.repo.config().unwrap();
+    config.set_str("user.name", "Fixture").unwrap();
+    config.set_str("user.email", "fixture@example.com").unwrap();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal", "--signoff"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).contains("Signed-off-by: "),
+        "{}",
+        stdout(&output)
+    );
+    fixture.commit(stdout(&output).trim());
+
+    let export = |include_unedited: bool| {
+        let mut command = fixture.command(&base_url);
+        command.args(["feedback", "export"]);
+        if include_unedited {
+            command.arg("--include-unedited");
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        stdout(&output).lines().count()
+    };
+    assert_eq!(export(false), 0);
+    assert_eq!(export(true), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compares_prompt_templates_on_past_commits() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("Describe this in one line"))
+        .respond_with(completion("Add the greeting"))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.commit("Add the greeting file");
+    fixture.write("bye.txt", "bye\n");
+    fixture.commit("Say goodbye");
+    fs::write(
+        fixture.dir.path().join("short.txt"),
+        "Describe this in one line.\n\n{structured_changes}",
+    )
+    .unwrap();
+
+    let output = fixture
+        .command(&base_url)
+        .args(["experiment", "--range", "HEAD~2..HEAD", "--prompts"])
+        .arg(fixture.dir.path().join("short.txt"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report = stdout(&output);
+    assert!(
+        report.contains(" Add the greeting file\n  short.txt gpt-4: Add the greeting\n"),
+        "{}",
+        report
+    );
+    assert!(report.contains(" Say goodbye\n"), "{}", report);
+    let summary = report.lines().last().unwrap();
+    assert!(summary.starts_with("short.txt  gpt-4"), "{}", summary);
+    // Three of four words shared with the first title, none with the second
+    assert!(
+        summary.ends_with("          2       0         0       16.0     0.38"),
+        "{}",
+        summary
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generates_with_ollama() {
+    let server = MockServer::start().await;
+    let chunks = [
+        json!({ "message": { "role": "assistant", "content": "Add the greeting\n\n" }, "done": false }),
+        json!({ "message": { "role": "assistant", "content": "- Add hello.txt" }, "done": true }),
+    ];
+    let streamed: String = chunks.iter().map(|chunk| format!("{}\n", chunk)).collect();
+    Mock::given(method("POST"))
+        .and(path("/api/chat"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(streamed))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command("http://unused")
+        .env("OLLAMA_HOST", server.uri())
+        .args([
+            "--provider",
+            "ollama",
+            "--model",
+            "llama3",
+            "--detail",
+            "normal",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["model"], "llama3");
+    assert_eq!(body["stream"], false);
+    assert_eq!(body["options"]["num_ctx"], 8192);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generates_with_anthropic() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/messages"))
+        .and(header("x-api-key", "test-key"))
+        .and(header("anthropic-version", "2023-06-01"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "type": "message",
+            "role": "assistant",
+            "content": [{ "type": "text", "text": MESSAGE }],
+            "stop_reason": "end_turn"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command("http://unused")
+        .env("ANTHROPIC_BASE_URL", &base_url)
+        .args([
+            "--provider",
+            "anthropic",
+            "--model",
+            "claude-3-5-sonnet-latest",
+            "--detail",
+            "normal",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert!(body["system"]
+        .as_str()
+        .unwrap()
+        .contains("Git commit messages"));
+    assert_eq!(body["messages"][0]["role"], "user");
+    assert!(body["max_tokens"].is_number());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_the_models_ollama_has_pulled() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/tags"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "models": [{ "name": "qwen2.5-coder:7b" }, { "name": "nomic-embed-text:latest" }, { "name": "llama3:latest" }]
+        })))
+        .mount(&server)
+        .await;
+
+    let output = Fixture::new()
+        .command("http://unused")
+        .env("OLLAMA_HOST", server.uri())
+        .args(["--provider", "ollama", "models"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let table = stdout(&output);
+    let models: Vec<&str> = table
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(models, ["llama3:latest", "qwen2.5-coder:7b"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_a_missing_ollama_model_without_a_terminal() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/chat"))
+        .respond_with(
+            ResponseTemplate::new(404).set_body_json(
+                json!({ "error": "model \"llama3\" not found, try pulling it first" }),
+            ),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command("http://unused")
+        .env("OLLAMA_HOST", server.uri())
+        .args(["--provider", "ollama", "--model", "llama3"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("model \"llama3\" not found"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generates_with_an_azure_deployment() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/openai/deployments/prod-gpt4/chat/completions"))
+        .and(query_param("api-version", "2024-06-01"))
+        .and(header("api-key", "test-key"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let config = fixture.dir.path().join("config/commit-gpt/config.toml");
+    fs::create_dir_all(config.parent().unwrap()).unwrap();
+    fs::write(
+        &config,
+        format!(
+            "[azure]\nendpoint = \"{}/\"\ndeployments = {{ gpt-4 = \"prod-gpt4\" }}\n",
+            server.uri()
+        ),
+    )
+    .unwrap();
+    let output = fixture
+        .command("http://unused")
+        .args(["--provider", "azure", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warms_up_the_model_alongside_generation() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("\"max_tokens\":1"))
+        .respond_with(completion("pong"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--warmup", "--verbose", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    assert!(
+        stderr(&output).contains("Warmed up gpt-4 in "),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_runs_share_one_request() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE).set_delay(std::time::Duration::from_millis(1000)))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let first = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    let second = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+    let first = first.wait_with_output().unwrap();
+
+    assert!(first.status.success(), "{}", stderr(&first));
+    assert!(second.status.success(), "{}", stderr(&second));
+    assert_eq!(stdout(&second), stdout(&first));
+    assert!(
+        stderr(&second).contains("Reusing the message a concurrent run generated"),
+        "{}",
+        stderr(&second)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generates_with_gemini() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/models/gemini-1.5-flash:generateContent"))
+        .and(header("x-goog-api-key", "test-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": MESSAGE }] } }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command("http://unused")
+        .env("GEMINI_BASE_URL", &base_url)
+        .args([
+            "--provider",
+            "gemini",
+            "--model",
+            "gemini-1.5-flash",
+            "--detail",
+            "normal",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert!(body["systemInstruction"]["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("Git commit messages"));
+    assert_eq!(body["contents"][0]["role"], "user");
+    assert!(body["contents"][0]["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("hello.txt"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_to_the_api_base_with_extra_headers() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/proxy/v1/chat/completions"))
+        .and(query_param("tenant", "tools"))
+        .and(header("x-team", "tools"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command("http://unused")
+        .arg("--api-base")
+        .arg(format!("{}/proxy/v1/?tenant=tools", server.uri()))
+        .args(["--header", "X-Team: tools", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn trades_the_github_token_for_a_copilot_token() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/copilot_internal/v2/token"))
+        .and(header("authorization", "token gho_editor"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "token": "tid=short-lived",
+            "expires_at": 4102444800u64,
+            "endpoints": { "api": format!("{}/copilot", server.uri()) }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/copilot/chat/completions"))
+        .and(header("authorization", "Bearer tid=short-lived"))
+        .and(header("copilot-integration-id", "vscode-chat"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let hosts = fixture.dir.path().join("config/github-copilot/hosts.json");
+    fs::create_dir_all(hosts.parent().unwrap()).unwrap();
+    fs::write(
+        &hosts,
+        r#"{ "github.com": { "user": "octocat", "oauth_token": "gho_editor" } }"#,
+    )
+    .unwrap();
+    let output = fixture
+        .command_without_key("http://unused")
+        .env("GITHUB_API_URL", server.uri())
+        .args([
+            "--provider",
+            "copilot",
+            "--model",
+            "gpt-4o",
+            "--detail",
+            "normal",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn signs_in_to_copilot_with_the_github_cli() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/copilot_internal/v2/token"))
+        .and(header("authorization", "token gho_cli"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "token": "tid=short-lived" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .and(header("authorization", "Bearer tid=short-lived"))
+        .and(header("copilot-integration-id", "acme-commits"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let config = fixture.dir.path().join("config/commit-gpt/config.toml");
+    fs::create_dir_all(config.parent().unwrap()).unwrap();
+    fs::write(&config, "[copilot]\nintegration_id = \"acme-commits\"\n").unwrap();
+    let bin = fixture.dir.path().join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    fs::write(
+        bin.join("gh"),
+        "#!/bin/sh\n[ \"$1 $2\" = \"auth token\" ] && echo gho_cli\n",
+    )
+    .unwrap();
+    fs::set_permissions(bin.join("gh"), fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let output = fixture
+        .command_without_key("http://unused")
+        .env("PATH", path)
+        .env("GITHUB_API_URL", server.uri())
+        .args([
+            "--provider",
+            "copilot",
+            "--api-base",
+            &server.uri(),
+            "--model",
+            "gpt-4o",
+            "--detail",
+            "normal",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signs_bedrock_requests_with_the_aws_profile() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(
+            "/model/anthropic.claude-3-haiku-20240307-v1%3A0/converse",
+        ))
+        .and(header("x-amz-security-token", "session"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "output": { "message": { "role": "assistant", "content": [{ "text": MESSAGE }] } },
+            "stopReason": "end_turn"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let credentials = fixture.dir.path().join(".aws/credentials");
+    fs::create_dir_all(credentials.parent().unwrap()).unwrap();
+    fs::write(
+        &credentials,
+        "[default]\naws_access_key_id = AKIDEXAMPLE\naws_secret_access_key = secret\naws_session_token = session\n",
+    )
+    .unwrap();
+    let output = fixture
+        .command_without_key("http://unused")
+        .env_remove("AWS_ACCESS_KEY_ID")
+        .env_remove("AWS_SECRET_ACCESS_KEY")
+        .env_remove("AWS_PROFILE")
+        .env_remove("AWS_SHARED_CREDENTIALS_FILE")
+        .env("AWS_REGION", "eu-west-1")
+        .env("AWS_ENDPOINT_URL_BEDROCK_RUNTIME", server.uri())
+        .args([
+            "--provider",
+            "bedrock",
+            "--model",
+            "anthropic.claude-3-haiku-20240307-v1:0",
+            "--detail",
+            "normal",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    let requests = server.received_requests().await.unwrap();
+    let authorization = requests[0]
+        .headers
+        .get("authorization")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(
+        authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"),
+        "{}",
+        authorization
+    );
+    assert!(
+        authorization.contains("/eu-west-1/bedrock-runtime/aws4_request"),
+        "{}",
+        authorization
+    );
+    assert!(
+        authorization.contains("SignedHeaders=host;x-amz-date;x-amz-security-token"),
+        "{}",
+        authorization
+    );
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert!(body["system"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("Git commit messages"));
+    assert!(body["messages"][0]["content"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("hello.txt"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn presets_default_to_the_vendors_model() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("authorization", "Bearer test-key"))
+        .and(body_string_contains(r#""model":"deepseek-chat""#))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command("http://unused")
+        .args([
+            "--provider",
+            "deepseek",
+            "--api-base",
+            &base_url,
+            "--detail",
+            "normal",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_openrouter_fallback_models_in_the_request() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("x-title", "commit-gpt"))
+        .and(header(
+            "http-referer",
+            "https://github.com/juliuskoskela/commit-gpt",
+        ))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let config = fixture.dir.path().join("config/commit-gpt/config.toml");
+    fs::create_dir_all(config.parent().unwrap()).unwrap();
+    fs::write(
+        &config,
+        "[openrouter]\nfallback_models = [\"openai/gpt-4o\", \"mistralai/mistral-large\"]\n",
+    )
+    .unwrap();
+    let output = fixture
+        .command("http://unused")
+        .args(["--provider", "openrouter", "--api-base", &base_url])
+        .args([
+            "--model",
+            "anthropic/claude-3.5-sonnet",
+            "--detail",
+            "normal",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["model"], "anthropic/claude-3.5-sonnet");
+    assert_eq!(
+        body["models"],
+        json!([
+            "anthropic/claude-3.5-sonnet",
+            "openai/gpt-4o",
+            "mistralai/mistral-large"
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_recorded_replies_with_the_mock_provider() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let replies = fixture.dir.path().join("replies.jsonl");
+    let recorded = fixture
+        .command(&base_url)
+        .args(["--detail", "normal", "--no-history", "--record-replies"])
+        .arg(&replies)
+        .output()
+        .unwrap();
+    assert!(recorded.status.success(), "{}", stderr(&recorded));
+
+    // Nothing listens on the discard port, so only the recording can answer
+    let replayed = fixture
+        .command_without_key("http://127.0.0.1:9")
+        .args([
+            "--provider",
+            "mock",
+            "--detail",
+            "normal",
+            "--no-history",
+            "--mock-replies",
+        ])
+        .arg(&replies)
+        .output()
+        .unwrap();
+
+    assert!(replayed.status.success(), "{}", stderr(&replayed));
+    assert_eq!(stdout(&replayed).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn picks_one_of_several_candidates() {
+    const OTHER: &str = "Add a greeting file";
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("\"n\":2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [
+                { "message": { "role": "assistant", "content": MESSAGE } },
+                { "message": { "role": "assistant", "content": OTHER } }
+            ]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let mut child = fixture
+        .command(&base_url)
+        .args([
+            "--candidates",
+            "2",
+            "--interactive",
+            "--detail",
+            "normal",
+            "--no-history",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"2\na\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), OTHER);
+    assert!(stderr(&output).contains("[1]"), "{}", stderr(&output));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_tokens_used() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": MESSAGE } }],
+            "usage": { "prompt_tokens": 1000, "completion_tokens": 50, "total_tokens": 1050 }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "normal", "--no-history", "--usage"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+    assert!(
+        stderr(&output).contains("gpt-4: 1000 prompt and 50 completion tokens, about $0.0330"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_the_sampling_parameters_given() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args([
+            "--detail",
+            "normal",
+            "--temperature",
+            "0",
+            "--top-p",
+            "0.5",
+            "--max-tokens",
+            "300",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["temperature"], 0.0);
+    assert_eq!(body["top_p"], 0.5);
+    assert_eq!(body["max_tokens"], 300);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn writes_the_title_in_the_mood_given() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Added the greeting\n\n- Added hello.txt"))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "normal", "--mood", "past"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output).trim(),
+        "Added the greeting\n\n- Added hello.txt"
+    );
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let body: Value = serde_json::from_slice(&requests[1].body).unwrap();
+    let messages = body["messages"].as_array().unwrap();
+    assert!(messages[0]["content"]
+        .as_str()
+        .unwrap()
+        .contains("in the past tense"));
+    assert!(messages.last().unwrap()["content"]
+        .as_str()
+        .unwrap()
+        .contains("like \"Added\" rather than \"Add\""));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn writes_a_section_in_each_language_given() {
+    let bilingual = "Add the greeting\n\n- Add hello.txt\n\n[中文]\n添加问候语\n\n- 添加 hello.txt";
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion(bilingual))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "normal", "--languages", "en,zh"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), bilingual);
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let body: Value = serde_json::from_slice(&requests[1].body).unwrap();
+    let messages = body["messages"].as_array().unwrap();
+    assert!(messages[1]["content"]
+        .as_str()
+        .unwrap()
+        .contains("a line of only `[中文]`, then the message in Chinese"));
+    assert!(messages.last().unwrap()["content"]
+        .as_str()
+        .unwrap()
+        .contains("Add the message in Chinese"));
+    assert_eq!(body["max_tokens"], 1000);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn writes_in_the_language_of_recent_commits() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(
+            "Begrüßung hinzugefügt\n\n- Die Datei hello.txt für die Begrüßung",
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = Fixture::new();
+    for (file, message) in [
+        ("a.txt", "Fehler beim Laden der Konfiguration behoben"),
+        ("b.txt", "Unterstützung für Proxys hinzugefügt"),
+        (
+            "c.txt",
+            "Veraltete Optionen entfernt, die nicht mehr genutzt werden",
+        ),
+    ] {
+        fixture.write(file, "a\n");
+        fixture.commit(message);
+    }
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Writing the message in German, like recent commits"));
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert!(body["messages"][1]["content"]
+        .as_str()
+        .unwrap()
+        .contains("Write the message in German."));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn escalates_when_the_judge_keeps_finding_inaccuracies() {
+    let escalated = "Add the greeting file\n\n- Add hello.txt with a greeting";
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("for factual accuracy"))
+        .respond_with(completion(r#"{"issues": ["hello.txt is not described"]}"#))
+        .up_to_n_times(3)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("for factual accuracy"))
+        .respond_with(completion(r#"{"issues": []}"#))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(body_string_contains(
+            "Summarize what the change to this file does",
+        ))
+        .respond_with(completion("Adds a file with a greeting."))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(body_string_contains(r#""model":"gpt-4.1""#))
+        .respond_with(completion(escalated))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args([
+            "--detail",
+            "normal",
+            "--verify",
+            "--escalation-model",
+            "gpt-4.1",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), escalated);
+    assert!(stderr(&output).contains("summarizing the files one by one"));
+    assert!(!stderr(&output).contains("possible inaccuracy"));
+    let requests = server.received_requests().await.unwrap();
+    let last = requests
+        .iter()
+        .map(|request| String::from_utf8_lossy(&request.body).into_owned())
+        .find(|body| body.contains("gpt-4.1"))
+        .unwrap();
+    assert!(last.contains("Adds a file with a greeting."));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_the_summaries_of_files_for_an_incremental_run() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("for factual accuracy"))
+        .respond_with(completion(r#"{"issues": ["hello.txt is not described"]}"#))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(body_string_contains(
+            "Summarize what the change to this file does",
+        ))
+        .respond_with(completion("Adds a file with a greeting."))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion(MESSAGE))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal", "--verify", "--incremental"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // Only the new file is given in full; the other one by its summary
+    fixture.write("farewell.txt", "goodbye\n");
+    fixture.stage_all();
+    let output = fixture
+        .command(&base_url)
+        .args(["--incremental", "--show-prompt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let prompt = stdout(&output);
+    assert!(
+        prompt.contains("- **hello.txt** (Added): Adds a file with a greeting."),
+        "{}",
+        prompt
+    );
+    assert!(prompt.contains("- **farewell.txt**: Added"), "{}", prompt);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_requests_to_a_named_endpoint() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("x-api-key", "test-key"))
+        .and(header("x-team", "tools"))
+        .and(body_string_contains("\"model\":\"llama-3.1-8b-instant\""))
+        .respond_with(completion(MESSAGE))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    let config = fixture.dir.path().join("config/commit-gpt/config.toml");
+    fs::create_dir_all(config.parent().unwrap()).unwrap();
+    fs::write(
+        &config,
+        format!(
+            "[endpoints.groq]\nbase_url = \"{}\"\nauth = \"header\"\nauth_header = \"x-api-key\"\n\
+             default_model = \"llama-3.1-8b-instant\"\nheaders = {{ X-Team = \"tools\" }}\n",
+            base_url
+        ),
+    )
+    .unwrap();
+    let output = fixture
+        .command("http://unused")
+        .args(["--endpoint", "groq", "--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_content_parts_from_compatible_servers() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "content": [{ "type": "text", "text": MESSAGE }] } }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), MESSAGE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn names_the_field_a_response_got_wrong() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "content": 42 } }]
+        })))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "normal"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("127.0.0.1 sent a response whose choices[0].message.content is a number instead of text"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn asks_again_for_fewer_bullets_than_max_bullets() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("at most 1 are allowed"))
+        .respond_with(completion("Add a greeting\n\n- Add hello.txt"))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion(
+            "Add a greeting\n\n- Add hello.txt\n- Say hello\n- End with a newline",
+        ))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "normal", "--max-bullets", "1"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "Add a greeting\n\n- Add hello.txt");
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let body = String::from_utf8_lossy(&requests[0].body);
+    assert!(
+        body.contains("Use at most 1 bullet points in the body."),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prefixes_the_title_with_the_type_of_the_intent() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("Conventional Commit type `fix: `."))
+        .respond_with(completion("fix: Greet with a newline"))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(completion("Greet with a newline"))
+        .mount(&server)
+        .await;
+
+    let output = staged_fixture()
+        .command(&base_url)
+        .args(["--detail", "oneline", "--intent", "fix"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "fix: Greet with a newline");
+    let requests = server.received_requests().await.unwrap();
+    let body = String::from_utf8_lossy(&requests[0].body);
+    assert!(body.contains("This change is a bug fix."), "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_the_comments_of_an_automatic_comment_char() {
+    let (server, base_url) = server().await;
+    Mock::given(method("POST"))
+        .respond_with(completion(
+            "Add a greeting\n\n; Mention why\n- Add hello.txt",
+        ))
+        .mount(&server)
+        .await;
+
+    let fixture = staged_fixture();
+    fixture
+        .repo
+        .config()
+        .unwrap()
+        .set_str("core.commentChar", "auto")
+        .unwrap();
+    // What `git commit -v` writes once auto picked ; as the comment character
+    let comments = "; Please enter the commit message for your changes.\n; ------------------------ >8 ------------------------\n; Do not modify or remove the line above.\ndiff --git a/hello.txt b/hello.txt\n+hello\n";
+    let message_file = fixture.workdir().join(".git/COMMIT_EDITMSG");
+    fs::write(&message_file, format!("\n{}", comments)).unwrap();
+    let output = fixture
+        .command(&base_url)
+        .args(["--detail", "normal", "hook", "run"])
+        .arg(&message_file)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(&message_file).unwrap(),
+        format!("Add a greeting\n\n- Add hello.txt\n\n{}", comments)
+    );
+}
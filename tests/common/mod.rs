@@ -0,0 +1,110 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use git2::{Repository, Signature};
+use tempfile::TempDir;
+
+/// A throwaway repository with an initial commit, plus an API key file next to it.
+pub struct Fixture {
+    pub dir: TempDir,
+    pub repo: Repository,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        let fixture = Fixture::empty();
+        fixture.write("README.md", "# Fixture\n");
+        fixture.commit("Initial commit");
+        fixture
+    }
+
+    /// A repository without any commits yet.
+    pub fn empty() -> Self {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path().join("repo")).unwrap();
+        fs::write(dir.path().join("key"), "test-key\n").unwrap();
+        Fixture { dir, repo }
+    }
+
+    pub fn workdir(&self) -> &Path {
+        self.repo.workdir().unwrap()
+    }
+
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) {
+        let path = self.workdir().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Stages everything in the working tree, including deletions.
+    pub fn stage_all(&self) {
+        let mut index = self.repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.update_all(["*"], None).unwrap();
+        index.write().unwrap();
+    }
+
+    pub fn commit(&self, message: &str) {
+        self.stage_all();
+        let mut index = self.repo.index().unwrap();
+        let tree = self.repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Fixture", "fixture@example.com").unwrap();
+        let parent = self
+            .repo
+            .head()
+            .ok()
+            .map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        self.repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap();
+    }
+
+    /// The binary, isolated from the user's config and pointed at `base_url`.
+    pub fn command(&self, base_url: &str) -> Command {
+        let mut command = self.command_without_key(base_url);
+        command
+            .arg("--api-key-path")
+            .arg(self.dir.path().join("key"));
+        command
+    }
+
+    /// Same, for providers that find their credentials themselves.
+    pub fn command_without_key(&self, base_url: &str) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_commit-gpt"));
+        command
+            .current_dir(self.workdir())
+            .env("HOME", self.dir.path())
+            .env("XDG_CONFIG_HOME", self.dir.path().join("config"))
+            .env("XDG_DATA_HOME", self.dir.path().join("data"))
+            .env("OPENAI_BASE_URL", base_url)
+            .env_remove("CI")
+            .env_remove("OPENAI_API_KEY")
+            .env_remove("ANTHROPIC_API_KEY")
+            .env_remove("GEMINI_API_KEY")
+            .env_remove("COMMIT_GPT_COLORS")
+            .arg("--color")
+            .arg("never");
+        command
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
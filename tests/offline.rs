@@ -0,0 +1,468 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{stderr, stdout, Fixture};
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[test]
+fn describes_the_changed_files_without_a_model() {
+    let fixture = Fixture::new();
+    fixture.write("src/lib.rs", "pub fn answer() -> u32 {\n    41\n}\n");
+    fixture.write("src/old_name.rs", "pub struct Widget;\n\nimpl Widget {\n    pub fn new() -> Self {\n        Widget\n    }\n}\n");
+    fixture.commit("Add answer");
+    fixture.write("src/lib.rs", "pub fn answer() -> u32 {\n    42\n}\n");
+    std::fs::remove_file(fixture.workdir().join("src/old_name.rs")).unwrap();
+    fixture.write("src/new_name.rs", "pub struct Widget;\n\nimpl Widget {\n    pub fn new() -> Self {\n        Widget\n    }\n}\n");
+    fixture.write("src/answer.txt", "42\n");
+    fixture.stage_all();
+
+    // Nothing listens on the discard port, so any request would fail
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .arg("--offline")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output).trim(),
+        "Update 3 files in src\n\n- Add src/answer.txt (+1)\n- Update src/lib.rs (+1 -1)\n- Rename src/old_name.rs to src/new_name.rs"
+    );
+}
+
+#[test]
+fn falls_back_to_the_changed_files_when_the_api_is_unreachable() {
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .args(["--yes", "--no-history"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output).trim(),
+        "Add hello.txt\n\n- Add hello.txt (+1)"
+    );
+    assert!(
+        stderr(&output).contains("could not reach the API"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn falls_back_to_the_changed_files_past_the_deadline() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+        .mount(&server)
+        .await;
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+
+    let started = Instant::now();
+    let output = fixture
+        .command(&format!("{}/v1", server.uri()))
+        .args(["--yes", "--no-history", "--deadline", "1"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(
+        stdout(&output).trim(),
+        "Add hello.txt\n\n- Add hello.txt (+1)"
+    );
+    assert!(
+        stderr(&output).contains("the API request ran past its deadline"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hook_falls_back_to_the_changed_files_when_the_api_fails() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(401).set_body_string("invalid key"))
+        .mount(&server)
+        .await;
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+    let message_file = fixture.workdir().join(".git/COMMIT_EDITMSG");
+    std::fs::write(&message_file, "\n# Please enter the commit message\n").unwrap();
+
+    let output = fixture
+        .command(&format!("{}/v1", server.uri()))
+        .args(["--yes", "--no-history", "hook", "run"])
+        .arg(&message_file)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        std::fs::read_to_string(&message_file).unwrap(),
+        "Add hello.txt\n\n- Add hello.txt (+1)\n\n# Please enter the commit message\n"
+    );
+    assert!(
+        stderr(&output).contains("writing a message from the changed files instead"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn hook_keeps_the_message_of_an_amended_commit() {
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.commit("Greet the reader");
+    fixture.write("hello.txt", "hello, world\n");
+    fixture.stage_all();
+    let message_file = fixture.workdir().join(".git/COMMIT_EDITMSG");
+    let amend = |message: &str| {
+        std::fs::write(&message_file, message).unwrap();
+        let output = fixture
+            .command("http://127.0.0.1:9")
+            .args(["--offline", "--no-history", "hook", "run"])
+            .arg(&message_file)
+            .args(["commit", "HEAD"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        std::fs::read_to_string(&message_file).unwrap()
+    };
+
+    assert_eq!(amend("Greet the reader\n"), "Greet the reader\n");
+
+    let config = fixture.dir.path().join("config/commit-gpt");
+    std::fs::create_dir_all(&config).unwrap();
+    std::fs::write(config.join("config.toml"), "hook_amend = true\n").unwrap();
+    assert_eq!(
+        amend("Greet the reader\n"),
+        "Add hello.txt\n\n- Add hello.txt (+1)\n"
+    );
+}
+
+#[cfg(not(feature = "builtin"))]
+#[test]
+fn explains_that_the_builtin_model_is_not_compiled_in() {
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .args(["--provider", "builtin"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("needs commit-gpt built with the builtin feature"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn answers_from_canned_replies_with_the_mock_provider() {
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+    let replies = fixture.dir.path().join("replies.jsonl");
+    std::fs::write(
+        &replies,
+        "{\"replies\": [\"Greet the world\\n\\n- Add hello.txt\"]}\n",
+    )
+    .unwrap();
+
+    let output = fixture
+        .command_without_key("http://127.0.0.1:9")
+        .args([
+            "--provider",
+            "mock",
+            "--detail",
+            "normal",
+            "--no-history",
+            "--mock-replies",
+        ])
+        .arg(&replies)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "Greet the world\n\n- Add hello.txt");
+}
+
+#[test]
+fn commits_the_first_commit_of_a_repository() {
+    let fixture = Fixture::empty();
+    let mut config = fixture.repo.config().unwrap();
+    config.set_str("user.name", "Fixture").unwrap();
+    config.set_str("user.email", "fixture@example.com").unwrap();
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .args(["--offline", "--commit", "--no-history"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let head = fixture.repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.summary(), Some("Add hello.txt"));
+    assert_eq!(head.parent_count(), 0);
+}
+
+#[test]
+fn commits_only_from_the_worktree_committed_in() {
+    let fixture = Fixture::new();
+    let mut config = fixture.repo.config().unwrap();
+    config.set_str("user.name", "Fixture").unwrap();
+    config.set_str("user.email", "fixture@example.com").unwrap();
+    let linked_path = fixture.dir.path().join("linked");
+    let worktree = fixture.repo.worktree("linked", &linked_path, None).unwrap();
+    let linked = git2::Repository::open_from_worktree(&worktree).unwrap();
+    std::fs::write(linked_path.join("hello.txt"), "hello\n").unwrap();
+    let mut index = linked.index().unwrap();
+    index.add_path(std::path::Path::new("hello.txt")).unwrap();
+    index.write().unwrap();
+
+    let refused = fixture
+        .command("http://127.0.0.1:9")
+        .args(["--offline", "--commit", "--no-history", "--workdir-path"])
+        .arg(&linked_path)
+        .output()
+        .unwrap();
+    assert!(!refused.status.success());
+    assert!(
+        stderr(&refused).contains("another worktree"),
+        "{}",
+        stderr(&refused)
+    );
+
+    let committed = fixture
+        .command("http://127.0.0.1:9")
+        .current_dir(&linked_path)
+        .args(["--offline", "--commit", "--no-history"])
+        .output()
+        .unwrap();
+    assert!(committed.status.success(), "{}", stderr(&committed));
+    let head = linked.head().unwrap();
+    assert_eq!(head.shorthand(), Some("linked"));
+    assert_eq!(
+        head.peel_to_commit().unwrap().summary(),
+        Some("Add hello.txt")
+    );
+    let main = fixture.repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(main.summary(), Some("Initial commit"));
+}
+
+#[test]
+fn rejects_banned_patterns_that_match_every_message() {
+    let fixture = Fixture::new();
+    fixture.write("hello.txt", "hello\n");
+    fixture.stage_all();
+    fixture.write(".commit-gpt.toml", "banned = [\"WIP\", \"\"]\n");
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .arg("--offline")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("Banned pattern \"\" in ")
+            && stderr(&output).contains("matches an empty string"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn chains_the_hook_it_replaces() {
+    let fixture = Fixture::new();
+    let hooks = fixture.workdir().join(".git/hooks");
+    std::fs::create_dir_all(&hooks).unwrap();
+    std::fs::write(hooks.join("prepare-commit-msg"), "#!/bin/sh\necho husky\n").unwrap();
+    let hook = |action: &str| {
+        fixture
+            .command("http://127.0.0.1:9")
+            .args(["hook", action])
+            .output()
+            .unwrap()
+    };
+
+    let installed = hook("install");
+    assert!(installed.status.success(), "{}", stderr(&installed));
+    let script = std::fs::read_to_string(hooks.join("prepare-commit-msg")).unwrap();
+    assert!(
+        script.contains("prepare-commit-msg.chained\" \"$@\""),
+        "{}",
+        script
+    );
+    assert_eq!(
+        std::fs::read_to_string(hooks.join("prepare-commit-msg.chained")).unwrap(),
+        "#!/bin/sh\necho husky\n"
+    );
+
+    let status = hook("status");
+    assert!(status.status.success(), "{}", stderr(&status));
+    let listed = stdout(&status);
+    assert!(
+        listed.contains(&format!(
+            "prepare-commit-msg: commit-gpt {}, runs",
+            env!("CARGO_PKG_VERSION")
+        )),
+        "{}",
+        listed
+    );
+    assert!(
+        listed.contains("prepare-commit-msg.chained: another tool's"),
+        "{}",
+        listed
+    );
+
+    let uninstalled = hook("uninstall");
+    assert!(uninstalled.status.success(), "{}", stderr(&uninstalled));
+    assert_eq!(
+        std::fs::read_to_string(hooks.join("prepare-commit-msg")).unwrap(),
+        "#!/bin/sh\necho husky\n"
+    );
+    assert!(!hooks.join("prepare-commit-msg.chained").exists());
+}
+
+#[test]
+fn installs_the_hook_for_the_provider_it_was_given() {
+    let fixture = Fixture::new();
+    let installed = fixture
+        .command("http://127.0.0.1:9")
+        .args(["--provider", "anthropic", "hook", "install"])
+        .output()
+        .unwrap();
+
+    assert!(installed.status.success(), "{}", stderr(&installed));
+    let script =
+        std::fs::read_to_string(fixture.workdir().join(".git/hooks/prepare-commit-msg")).unwrap();
+    assert!(
+        script.contains("'--provider' 'anthropic' '--model' 'claude-3-5-sonnet-latest' hook run"),
+        "{}",
+        script
+    );
+}
+
+#[test]
+fn runs_the_replaced_hook_after_its_own_when_configured() {
+    let fixture = Fixture::new();
+    let hooks = fixture.workdir().join(".git/hooks");
+    std::fs::create_dir_all(&hooks).unwrap();
+    std::fs::write(hooks.join("prepare-commit-msg"), "#!/bin/sh\necho husky\n").unwrap();
+    let config = fixture.dir.path().join("config/commit-gpt");
+    std::fs::create_dir_all(&config).unwrap();
+    std::fs::write(config.join("config.toml"), "hook_order = \"after\"\n").unwrap();
+    let hook = |action: &str| {
+        fixture
+            .command("http://127.0.0.1:9")
+            .args(["hook", action])
+            .output()
+            .unwrap()
+    };
+
+    let installed = hook("install");
+    assert!(installed.status.success(), "{}", stderr(&installed));
+    let script = std::fs::read_to_string(hooks.join("prepare-commit-msg")).unwrap();
+    assert!(
+        script.ends_with("hook run \"$@\" || exit $?\nexec \"$(dirname \"$0\")/prepare-commit-msg.chained\" \"$@\"\n"),
+        "{}",
+        script
+    );
+    let status = hook("status");
+    assert!(
+        stdout(&status).contains("prepare-commit-msg.chained after itself"),
+        "{}",
+        stdout(&status)
+    );
+}
+
+#[cfg(not(feature = "keychain"))]
+#[test]
+fn explains_that_auth_needs_the_keychain_feature() {
+    let fixture = Fixture::new();
+    let mut child = fixture
+        .command_without_key("http://127.0.0.1:9")
+        .args(["auth", "login"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(child.stdin.as_mut().unwrap(), b"sk-test\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("built with the keychain feature"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn reports_a_key_that_cannot_be_read() {
+    let fixture = Fixture::new();
+    let mut child = fixture
+        .command_without_key("http://127.0.0.1:9")
+        .args(["auth", "login"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(child.stdin.as_mut().unwrap(), b"sk-\xff\xfe\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("Failed to read API key from standard input"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn analyzes_the_staged_changes_as_json() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "src/lib.rs",
+        "pub fn answer() -> u32 {\n    41\n}\n\n// end\n\n\n\n\npub fn other() {}\n",
+    );
+    fixture.commit("Add answer");
+    fixture.write("src/lib.rs", "pub fn answer() -> u32 {\n    42\n}\n\n// end\n\n\n\n\npub fn other() {}\npub fn new() {}\n");
+    fixture.write("logo.png", "\u{0}\u{1}binary");
+    fixture.stage_all();
+
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .arg("analyze")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let files: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(files[0]["path"], "logo.png");
+    assert_eq!(files[0]["binary"], true);
+    assert_eq!(files[1]["path"], "src/lib.rs");
+    assert_eq!(files[1]["added"], 2);
+    assert_eq!(files[1]["removed"], 1);
+    assert_eq!(files[1]["hunks"], 2);
+    assert_eq!(files[1]["symbols"][0], "modified fn `answer`");
+}
@@ -0,0 +1,524 @@
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+
+use common::{stderr, stdout, Fixture};
+
+/// The prompt `--show-prompt` prints for the staged changes of `fixture`.
+fn prompt(fixture: &Fixture) -> String {
+    prompt_with(fixture, &[])
+}
+
+fn prompt_with(fixture: &Fixture, args: &[&str]) -> String {
+    fixture.stage_all();
+    // No request is made, so the URL is never contacted
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .arg("--show-prompt")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    stdout(&output)
+}
+
+#[test]
+fn modified_file() {
+    let fixture = Fixture::new();
+    fixture.write("src/lib.rs", "pub fn answer() -> u32 {\n    41\n}\n");
+    fixture.commit("Add answer");
+    fixture.write("src/lib.rs", "pub fn answer() -> u32 {\n    42\n}\n");
+
+    insta::assert_snapshot!(prompt(&fixture));
+}
+
+#[test]
+fn renamed_files() {
+    let fixture = Fixture::new();
+    fixture.write("src/old_name.rs", "pub struct Widget;\n\nimpl Widget {\n    pub fn new() -> Self {\n        Widget\n    }\n}\n");
+    fixture.write(
+        "docs/guide.md",
+        "# Guide\n\nUse the widget.\n\nIt is small.\n",
+    );
+    fixture.commit("Add widget");
+    std::fs::remove_file(fixture.workdir().join("src/old_name.rs")).unwrap();
+    fixture.write("src/new_name.rs", "pub struct Widget;\n\nimpl Widget {\n    pub fn new() -> Self {\n        Widget\n    }\n}\n");
+    std::fs::remove_file(fixture.workdir().join("docs/guide.md")).unwrap();
+    fixture.write(
+        "docs/manual.md",
+        "# Guide\n\nUse the widget.\n\nIt is tiny.\n",
+    );
+
+    insta::assert_snapshot!(prompt(&fixture));
+}
+
+#[test]
+fn renamed_file_listed_from_a_subdirectory() {
+    let fixture = Fixture::new();
+    let widget = "pub struct Widget;\n\nimpl Widget {\n    pub fn new() -> Self {\n        Widget\n    }\n}\n";
+    fixture.write("src/old_name.rs", widget);
+    fixture.write("src/other.rs", "pub struct Other;\n");
+    fixture.commit("Add widget");
+    std::fs::remove_file(fixture.workdir().join("src/old_name.rs")).unwrap();
+    fixture.write("src/widget/new_name.rs", widget);
+    fixture.write("src/other.rs", "pub struct Other(u8);\n");
+    fixture.stage_all();
+
+    // Only the new path is listed, relative to the directory the shell is in
+    let mut child = fixture
+        .command("http://127.0.0.1:9")
+        .current_dir(fixture.workdir().join("src"))
+        .args(["--workdir-path", "..", "--stdin-files", "--show-prompt"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"./widget/../widget/new_name.rs\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let prompt = stdout(&output);
+    assert!(
+        prompt.contains("- **src/widget/new_name.rs**: Renamed\n  - Renamed from src/old_name.rs"),
+        "{}",
+        prompt
+    );
+    assert!(!prompt.contains("src/other.rs"), "{}", prompt);
+}
+
+#[test]
+fn binary_files() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "assets/logo.png",
+        b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01",
+    );
+    fixture.commit("Add logo");
+    fixture.write(
+        "assets/logo.png",
+        b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x02",
+    );
+    fixture.write("assets/icon.ico", b"\0\0\x01\0\x01\0\x10\x10");
+    fixture.write("NOTES.txt", "Logo refreshed\n");
+
+    insta::assert_snapshot!(prompt(&fixture));
+}
+
+#[test]
+fn large_file_over_budget() {
+    let fixture = Fixture::new();
+    let table: String = (0..600)
+        .map(|row| {
+            format!(
+                "INSERT INTO readings VALUES ({}, 'sensor-{}', {});\n",
+                row,
+                row % 7,
+                row * 3
+            )
+        })
+        .collect();
+    fixture.write("db/seed.sql", table);
+    fixture.write("src/main.rs", "fn main() {\n    seed();\n}\n");
+
+    insta::assert_snapshot!(prompt(&fixture));
+}
+
+#[test]
+fn unicode_content_and_paths() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "docs/résumé.md",
+        "Überarbeitete Übersicht: Größenänderungen für Schlüsselwörter, Straßennamen und Äpfel — jetzt mit Emoji 🚀🚀🚀 am Ende der Zeile\n",
+    );
+    fixture.write("src/i18n/日本語.txt", "こんにちは世界\n");
+
+    insta::assert_snapshot!(prompt(&fixture));
+}
+
+#[test]
+fn many_files_grouped_per_directory() {
+    let fixture = Fixture::new();
+    for index in 0..12 {
+        fixture.write(
+            &format!("src/handlers/handler_{}.rs", index),
+            format!("pub fn handle_{}() {{}}\n", index),
+        );
+    }
+    for index in 0..3 {
+        fixture.write(
+            &format!("tests/case_{}.rs", index),
+            "#[test]\nfn works() {}\n",
+        );
+    }
+
+    insta::assert_snapshot!(prompt_with(&fixture, &["--group-threshold", "10"]));
+}
+
+#[test]
+fn bucketed_private_paths() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "project-aurora/acquisition/target.rs",
+        "pub const NAME: &str = \"x\";\n",
+    );
+    fixture.write("project-aurora/README.md", "Codename docs\n");
+    fixture.commit("Add aurora");
+    std::fs::remove_file(
+        fixture
+            .workdir()
+            .join("project-aurora/acquisition/target.rs"),
+    )
+    .unwrap();
+    fixture.write(
+        "project-aurora/acquisition/buyer.rs",
+        "pub const NAME: &str = \"x\";\n",
+    );
+
+    insta::assert_snapshot!(prompt_with(&fixture, &["--private-paths", "bucket"]));
+}
+
+#[test]
+fn hashed_private_paths() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "project-aurora/acquisition/target.rs",
+        "pub fn close() {}\n",
+    );
+    let prompt = prompt_with(&fixture, &["--private-paths", "hash"]);
+
+    for name in ["project-aurora", "acquisition", "target"] {
+        assert!(!prompt.contains(name), "{}", prompt);
+    }
+    assert!(prompt.contains(".rs**: Added"), "{}", prompt);
+}
+
+#[test]
+fn hashes_private_paths_with_a_salt_of_the_repository() {
+    let fixture = private_fixture();
+    let prompt = prompt_with(&fixture, &["--private-paths", "hash"]);
+
+    let salt = std::fs::read_to_string(fixture.repo.path().join("commit-gpt/salt")).unwrap();
+    assert_eq!(salt.len(), 32);
+    assert!(!prompt.contains(&salt), "{}", prompt);
+    // The same salt is used again, so the aliases stay the same
+    assert_eq!(prompt_with(&fixture, &["--private-paths", "hash"]), prompt);
+}
+
+#[test]
+fn private_paths_hidden_in_lines_and_items() {
+    let fixture = Fixture::new();
+    fixture.write("src/project_falcon/radar.rs", "pub fn sweep() {}\n");
+    fixture.write(
+        "src/lib.rs",
+        "mod project_falcon; // see project_falcon/radar.rs\n\npub use project_falcon::radar;\n",
+    );
+    let prompt = prompt_with(&fixture, &["--private-paths", "hash"]);
+
+    for name in ["project_falcon", "radar"] {
+        assert!(!prompt.contains(name), "{}", prompt);
+    }
+    assert!(prompt.contains("mod "), "{}", prompt);
+}
+
+/// A fixture with a change under `project-aurora/acquisition`, whose names must not reach the prompt.
+fn private_fixture() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.write(
+        "project-aurora/acquisition/target.rs",
+        "pub fn close() {}\n",
+    );
+    fixture
+}
+
+fn assert_private(prompt: &str) {
+    for name in ["aurora", "acquisition", "target"] {
+        assert!(!prompt.contains(name), "{}", prompt);
+    }
+}
+
+#[test]
+fn private_paths_hidden_in_the_context() {
+    let fixture = private_fixture();
+    let prompt = prompt_with(
+        &fixture,
+        &[
+            "--private-paths",
+            "hash",
+            "--context",
+            "Close the project-aurora acquisition of target",
+        ],
+    );
+
+    assert_private(&prompt);
+    assert!(prompt.contains("Close the "), "{}", prompt);
+}
+
+#[test]
+fn private_paths_hidden_in_the_project_context() {
+    let fixture = private_fixture();
+    fixture.write(
+        ".commit-gpt-context",
+        "Everything under project-aurora is the acquisition of target.\n",
+    );
+    let prompt = prompt_with(&fixture, &["--private-paths", "hash"]);
+
+    assert_private(&prompt);
+    assert!(prompt.contains("Everything under "), "{}", prompt);
+}
+
+#[test]
+fn private_paths_hidden_in_the_commit_template() {
+    let fixture = private_fixture();
+    fixture.write(
+        ".gitmessage",
+        "acquisition: <summary>\n\n# Scope is project-aurora or target\n",
+    );
+    fixture
+        .repo
+        .config()
+        .unwrap()
+        .set_str("commit.template", ".gitmessage")
+        .unwrap();
+    let prompt = prompt_with(&fixture, &["--private-paths", "hash"]);
+
+    assert_private(&prompt);
+    assert!(prompt.contains(": <summary>"), "{}", prompt);
+}
+
+#[test]
+fn private_paths_hidden_in_style_examples() {
+    let fixture = private_fixture();
+    let config = fixture.dir.path().join("config/commit-gpt/config.toml");
+    std::fs::create_dir_all(config.parent().unwrap()).unwrap();
+    std::fs::write(
+        &config,
+        "style_examples = [\"acquisition: Rename target\\n\\nproject-aurora needs it.\"]\n",
+    )
+    .unwrap();
+    let prompt = prompt_with(&fixture, &["--private-paths", "hash"]);
+
+    assert_private(&prompt);
+    assert!(prompt.contains(": Rename "), "{}", prompt);
+}
+
+#[test]
+fn masks_random_looking_strings() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "src/settings.rs",
+        "const PAYMENT_PROVIDER_TOKEN: &str = \"tok_9fQmZ2xW7vK4pL8sR1nB6yT3hJ5cD0aEgU\";\nconst CACHE_DIGEST: &str = \"3f9a1c7e5b2d8046af13c9e7b5d20864\";\n",
+    );
+    fixture.stage_all();
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .arg("--show-prompt")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let prompt = stdout(&output);
+    assert!(
+        !prompt.contains("9fQmZ2xW7vK4pL8sR1nB6yT3hJ5cD0aEgU"),
+        "{}",
+        prompt
+    );
+    assert!(
+        !prompt.contains("3f9a1c7e5b2d8046af13c9e7b5d20864"),
+        "{}",
+        prompt
+    );
+    assert!(
+        prompt.contains("const PAYMENT_PROVIDER_TOKEN: &str = \"[redacted]\";"),
+        "{}",
+        prompt
+    );
+    assert!(
+        stderr(&output).contains("Masked 2 random-looking strings"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn masks_keys_of_24_to_40_characters() {
+    let keys = [
+        "u8jzPde0IgxLd6GncfBAepfJ",
+        "Bd0Kh8oOOL8dKLzdocJ2isAjIhKt",
+        "J0RlgLKOmxgJTeKdNnFRIBXuDL7DxtpY",
+        "lSXpfKtHF4vUCsMehGAkWvj7FAc9QeWJKY40",
+        "uvSwMFLZDe1f8rESQedUStPKR0CsTy4Qwb8DwkNh",
+        "f16947ccf25ec84d8dbc7425",
+        "tDDb-xHKas1VOqg6YYZYn9ZhyiA4uoRgnatmUdjA",
+    ];
+    let fixture = Fixture::new();
+    let lines: Vec<String> = keys
+        .iter()
+        .enumerate()
+        .map(|(index, key)| format!("KEY_{}={}\n", index, key))
+        .collect();
+    fixture.write(
+        ".env",
+        format!("{}MAX_RETRIES_FOR_HTTP2_STREAMS=3\n", lines.concat()),
+    );
+    fixture.stage_all();
+    let output = fixture
+        .command("http://127.0.0.1:9")
+        .arg("--show-prompt")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let prompt = stdout(&output);
+    for key in keys {
+        assert!(!prompt.contains(key), "{}", prompt);
+    }
+    assert!(
+        prompt.contains("MAX_RETRIES_FOR_HTTP2_STREAMS=3"),
+        "{}",
+        prompt
+    );
+}
+
+#[test]
+fn style_examples_from_the_config() {
+    let fixture = Fixture::new();
+    fixture.write("src/lib.rs", "pub fn answer() -> u32 {\n    42\n}\n");
+    let config = fixture.dir.path().join("config/commit-gpt/config.toml");
+    std::fs::create_dir_all(config.parent().unwrap()).unwrap();
+    std::fs::write(&config, "style_examples = [\"parser: Accept trailing commas\\n\\nThe grammar allowed them already.\"]\n").unwrap();
+    let prompt = prompt(&fixture);
+
+    assert!(
+        prompt.contains("Style examples:\nMatch the tone"),
+        "{}",
+        prompt
+    );
+    assert!(
+        prompt
+            .contains("---\nparser: Accept trailing commas\n\nThe grammar allowed them already.\n"),
+        "{}",
+        prompt
+    );
+}
+
+#[test]
+fn changes_of_a_range() {
+    let fixture = Fixture::new();
+    fixture.write("src/lib.rs", "pub fn answer() -> u32 {\n    41\n}\n");
+    fixture.commit("Add answer");
+    fixture.write("src/lib.rs", "pub fn answer() -> u32 {\n    42\n}\n");
+    fixture.commit("Fix answer");
+    fixture.write("notes.txt", "staged, but not in the range\n");
+    let prompt = prompt_with(&fixture, &["--range", "HEAD~1..HEAD"]);
+
+    assert!(prompt.contains("**src/lib.rs**: Modified"), "{}", prompt);
+    assert!(prompt.contains("Added: 42"), "{}", prompt);
+    assert!(!prompt.contains("notes.txt"), "{}", prompt);
+}
+
+#[test]
+fn changes_of_a_unified_diff() {
+    let fixture = Fixture::new();
+    let patch = fixture.dir.path().join("change.patch");
+    std::fs::write(
+        &patch,
+        "diff --git a/src/lib.rs b/src/lib.rs\n--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn answer() -> u32 {\n-    41\n+    42\n }\n",
+    )
+    .unwrap();
+    let prompt = prompt_with(&fixture, &["--diff", patch.to_str().unwrap()]);
+
+    assert!(prompt.contains("**src/lib.rs**: Modified"), "{}", prompt);
+    assert!(prompt.contains("Removed: 41"), "{}", prompt);
+}
+
+#[test]
+fn changed_rust_items() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "src/stage.rs",
+        "pub enum Stage { Diff }\n\npub fn old() {}\n\nimpl std::fmt::Display for Stage {\n    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {\n        f.write_str(\"diff\")\n    }\n}\n",
+    );
+    fixture.commit("Add stage");
+    fixture.write(
+        "src/stage.rs",
+        "pub enum Stage { Diff }\n\npub fn new() {}\n\nimpl std::fmt::Display for Stage {\n    // Shown in errors\n    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {\n        f.write_str(\"the diff\")\n    }\n}\n",
+    );
+    let prompt = prompt(&fixture);
+
+    assert!(
+        prompt.contains("  - Items: added fn `new`; modified impl `std::fmt::Display for Stage` (modified fn `fmt`); removed fn `old`\n"),
+        "{}",
+        prompt
+    );
+}
+
+#[cfg(feature = "polyglot")]
+#[test]
+fn changed_python_and_go_items() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "app/user.py",
+        "class User:\n    def name(self):\n        return 'ada'\n",
+    );
+    fixture.write(
+        "user.go",
+        "package user\n\ntype User struct{}\n\nfunc (u *User) Name() string { return \"ada\" }\n",
+    );
+    fixture.commit("Add user");
+    fixture.write(
+        "app/user.py",
+        "class User:\n    def name(self):\n        return 'ada'\n\n    def login(self):\n        return True\n",
+    );
+    fixture.write(
+        "user.go",
+        "package user\n\ntype User struct{}\n\nfunc (u *User) Name() string { return \"grace\" }\n\nfunc New() *User { return &User{} }\n",
+    );
+    let prompt = prompt(&fixture);
+
+    assert!(
+        prompt.contains("  - Items: modified class `User` (added method `User.login`)\n"),
+        "{}",
+        prompt
+    );
+    assert!(
+        prompt.contains("  - Items: modified method `User.Name`; added function `New`\n"),
+        "{}",
+        prompt
+    );
+}
+
+#[test]
+fn mass_edits_described_once() {
+    let fixture = Fixture::new();
+    for name in ["a", "b", "c", "d"] {
+        fixture.write(
+            &format!("src/{}.rs", name),
+            format!("use crate::old_name::Thing;\n\npub fn {}() {{}}\n", name),
+        );
+    }
+    fixture.write("src/e.rs", "pub fn e() {}\n");
+    fixture.commit("Add modules");
+    for name in ["a", "b", "c", "d"] {
+        fixture.write(
+            &format!("src/{}.rs", name),
+            format!("use crate::new_name::Thing;\n\npub fn {}() {{}}\n", name),
+        );
+    }
+    fixture.write("src/e.rs", "pub fn e() {}\n\npub fn f() {}\n");
+    let prompt = prompt(&fixture);
+
+    assert!(
+        prompt.contains("- **The same edit in 4 files**: src/a.rs, src/b.rs, src/c.rs and 1 more\n  - Replaced `old_name` with `new_name`\n"),
+        "{}",
+        prompt
+    );
+    assert!(prompt.contains("- **src/e.rs**: Modified"), "{}", prompt);
+    assert!(!prompt.contains("**src/d.rs**"), "{}", prompt);
+}